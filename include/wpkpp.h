@@ -0,0 +1,49 @@
+/* C interface to wpkpp, built with
+ *   cargo rustc --release --lib --no-default-features --features cdylib --crate-type cdylib
+ * All functions are safe to call from several threads on separate programs.
+ */
+#ifndef WPKPP_H
+#define WPKPP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct WpkppProgram WpkppProgram;
+
+typedef struct WpkppRunResult {
+    int64_t runtime;
+    int64_t memory;
+} WpkppRunResult;
+
+/* Message of the last failed call on this thread, or NULL. Valid until the next failing call. */
+const char *wpkpp_last_error(void);
+
+/* Parses len bytes of script in format "wpk", "wpkm" or "wpkb". NULL on failure. */
+WpkppProgram *wpkpp_parse(const uint8_t *source, size_t len, const char *format);
+
+void wpkpp_program_free(WpkppProgram *program);
+
+/* Runs the program with input at the start of memory, then copies the first output_len bytes
+ * of memory into output. Bits are least significant first. result may be NULL.
+ * Returns 0 on success and -1 on failure. */
+int wpkpp_run(const WpkppProgram *program,
+              const uint8_t *input, size_t input_len,
+              uint8_t *output, size_t output_len,
+              WpkppRunResult *result);
+
+/* Grades the program against task (e.g. "2a") on the testcases generated from seed, returning
+ * the same report as `wpkpp grade --json`. A NULL seed uses WPKPP_SEED and
+ * WPKPP_SEED_NAMESPACE like the CLI. NULL on failure; free the report with wpkpp_string_free. */
+char *wpkpp_grade_json(const WpkppProgram *program, const char *task, const char *seed);
+
+void wpkpp_string_free(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* WPKPP_H */
@@ -0,0 +1,217 @@
+//! Assertions written in comments, e.g. `#assert ptr == 32` or `;assert mem[16..20] == 0b1010`,
+//! checked whenever execution reaches them. An assertion is reached once every instruction
+//! written before it has run, so a trailing `.wpk` comment sees the effect of its own line.
+
+use std::fmt;
+
+use crate::{
+    parse::ParseErrorKind,
+    vm::{cell_index, RunResult, Vm, VmUsize},
+};
+
+/// Word following the comment character of an assertion
+pub const ASSERT_STR: &str = "assert";
+
+/// Widest memory range an assertion can compare, in bits
+const MAX_RANGE_BITS: u64 = 64;
+
+/// What an assertion looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertSubject {
+    /// `ptr`: The pointer, relative to where it started
+    Pointer,
+    /// `reg`: The register, 0 or 1
+    Register,
+    /// `mem[start]` or `mem[start..end]`: Cells `start..end` read as a number, the cell at
+    /// `start` being the least significant bit, like task inputs and outputs
+    Memory { start: u64, end: u64 },
+}
+
+impl fmt::Display for AssertSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pointer => write!(f, "ptr"),
+            Self::Register => write!(f, "reg"),
+            Self::Memory { start, end } if *end == start + 1 => write!(f, "mem[{}]", start),
+            Self::Memory { start, end } => write!(f, "mem[{}..{}]", start, end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    pub subject: AssertSubject,
+    /// `==` when true, `!=` when false
+    pub equal: bool,
+    pub value: i128,
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.equal {
+            true => "==",
+            false => "!=",
+        };
+        write!(f, "{} {} {}", self.subject, op, self.value)
+    }
+}
+
+fn invalid(comment: &str) -> ParseErrorKind {
+    ParseErrorKind::InvalidAssertion(comment.to_string())
+}
+
+/// Reads a decimal, `0x` hex or `0b` binary number, optionally negative
+fn parse_value(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i128::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_address(text: &str) -> Option<u64> {
+    parse_value(text.trim()).and_then(|value| u64::try_from(value).ok())
+}
+
+fn parse_subject(text: &str) -> Option<AssertSubject> {
+    match text {
+        "ptr" => return Some(AssertSubject::Pointer),
+        "reg" => return Some(AssertSubject::Register),
+        _ => {}
+    }
+    let range = text.strip_prefix("mem[")?.strip_suffix(']')?;
+    let (start, end) = match range.split_once("..") {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => {
+            let start = parse_address(range)?;
+            (start, start.checked_add(1)?)
+        }
+    };
+    match start < end && end - start <= MAX_RANGE_BITS {
+        true => Some(AssertSubject::Memory { start, end }),
+        false => None,
+    }
+}
+
+impl Assertion {
+    /// Reads a comment, including its leading `#` / `;`, as an assertion; None if the comment
+    /// is not one
+    pub fn parse(comment: &str) -> Option<Result<Self, ParseErrorKind>> {
+        let mut chars = comment.chars();
+        chars.next()?;
+        let body = chars.as_str().trim_start().strip_prefix(ASSERT_STR)?;
+        if !body.starts_with(char::is_whitespace) {
+            return None;
+        }
+
+        let parsed = ["==", "!="].into_iter().find_map(|op| {
+            let (subject, value) = body.split_once(op)?;
+            Some((subject, op == "==", value))
+        });
+        let assertion = parsed.and_then(|(subject, equal, value)| {
+            Some(Self {
+                subject: parse_subject(&subject.split_whitespace().collect::<String>())?,
+                equal,
+                value: parse_value(value.trim())?,
+            })
+        });
+        Some(assertion.ok_or_else(|| invalid(comment)))
+    }
+
+    /// Current value of the assertion's subject
+    pub fn actual(&self, vm: &Vm) -> i128 {
+        match self.subject {
+            AssertSubject::Pointer => vm.memory_pointer.ptr_i as i128,
+            AssertSubject::Register => vm.register as i128,
+            AssertSubject::Memory { start, end } => (start..end)
+                .map(|address| vm.memory[cell_index(address as VmUsize)] as i128)
+                .enumerate()
+                .fold(0, |value, (bit, cell)| value | (cell << bit)),
+        }
+    }
+
+    pub fn holds(&self, vm: &Vm) -> bool {
+        (self.actual(vm) == self.value) == self.equal
+    }
+}
+
+/// An assertion which did not hold
+#[derive(Debug, Clone)]
+pub struct AssertFailure {
+    /// Instructions run before the assertion was checked
+    pub idx: usize,
+    pub assertion: Assertion,
+    pub actual: i128,
+}
+
+impl fmt::Display for AssertFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Assertion \"{}\" failed, {} is {}",
+            self.assertion, self.assertion.subject, self.actual
+        )
+    }
+}
+
+/// Checks a script's assertions as a VM runs, from a fresh start of the VM
+pub struct AssertChecker<'a> {
+    assertions: &'a [(usize, Assertion)],
+    next: usize,
+}
+
+impl<'a> AssertChecker<'a> {
+    /// `assertions` are paired with the instruction index they are checked at, in order, as
+    /// in `Script::assertions`
+    pub fn new(assertions: &'a [(usize, Assertion)]) -> Self {
+        Self {
+            assertions,
+            next: 0,
+        }
+    }
+
+    /// Checks every assertion at the instruction pointer; Call before each step and once more
+    /// after halting. Assertions which were stepped past unchecked are skipped.
+    pub fn check(&mut self, vm: &Vm) -> Result<(), AssertFailure> {
+        let ip = vm.intsruction_pointer;
+        while let Some((idx, assertion)) = self.assertions.get(self.next) {
+            if *idx > ip {
+                break;
+            }
+            self.next += 1;
+            if *idx == ip && !assertion.holds(vm) {
+                return Err(AssertFailure {
+                    idx: *idx,
+                    assertion: assertion.clone(),
+                    actual: assertion.actual(vm),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs to completion, stopping at the first assertion which does not hold
+    pub fn run(&mut self, vm: &mut Vm) -> Result<RunResult, AssertFailure> {
+        loop {
+            self.check(vm)?;
+            if vm.halted {
+                return Ok(vm.run_result());
+            }
+            let stop = self
+                .assertions
+                .get(self.next)
+                .map_or(usize::MAX, |(idx, _)| *idx);
+            vm.step();
+            while !vm.halted && vm.intsruction_pointer < stop {
+                vm.step();
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Fixtures and timing helpers behind `wpkpp bench`, public so that forks trying out other
+//! interpreters can time them on the same programs in the same way.
+
+use rand::{rngs::StdRng, Rng};
+use rand_seeder::Seeder;
+use std::fs;
+use tracing::info;
+
+use crate::{
+    error::{Result, WpkppError},
+    parse::{parse_script, parse_script_bytes, ScriptFormat},
+    task::Task,
+    util::ResetableTimer,
+    vm::{Instruction, Instructions, Vm, VmUsize, MEM_SIZE},
+};
+
+/// Default span of memory the synthetic program sweeps over, as a power of two
+pub const DEFAULT_MEM_BITS: u32 = 16;
+/// Instructions in the fixtures used by `wpkpp bench`
+pub const FIXTURE_LEN: usize = 10_000_000;
+/// Runs of the program, keeping the fastest
+pub const DEFAULT_RUNS: usize = 3;
+/// Resets timed to get the cost of one
+const RESETS: usize = 5;
+
+pub enum BenchProgram {
+    Synthetic,
+    File(String),
+}
+
+pub struct BenchOptions {
+    /// The synthetic program touches 2^mem_bits cells
+    pub mem_bits: u32,
+    pub program: BenchProgram,
+}
+
+/// INV / LOAD / INC 1 over each of 2^mem_bits cells, wrapping the pointer back to the first
+/// cell at the end of the span, until there are at least `len` instructions
+pub fn synthetic_program(mem_bits: u32, len: usize) -> Instructions {
+    let span = 1u64 << mem_bits;
+    let mut program = Instructions::new();
+    let mut cell = 0;
+    while program.len() < len {
+        program.push(Instruction::Inv);
+        program.push(Instruction::Load);
+        program.push(Instruction::Inc(1));
+        cell += 1;
+        if cell == span && span < MEM_SIZE {
+            program.push(Instruction::Inc((MEM_SIZE - span) as VmUsize));
+            cell = 0;
+        }
+    }
+    program
+}
+
+/// A program shaped like a solution to `task`, e.g. task 5: Blocks of INC / LOAD / CDEC / INV
+/// stepping at most the width of the task's input and output, with CDECs taken depending on
+/// the data. It computes nothing, but has the instruction mix and drifting memory footprint of
+/// real solutions, which the synthetic program lacks. The same seed gives the same program.
+pub fn task_style_program(task: Task, len: usize, seed: u64) -> Result<Instructions> {
+    let (input, output) = task.load_tc(0)?;
+    let width = (input.len() + output.len()) as VmUsize;
+    let mut rng: StdRng = Seeder::from(format!("WPKPP/bench/{:?}/{}", task, seed)).make_rng();
+    let mut program = Instructions::new();
+    while program.len() < len {
+        let step = rng.gen_range(1..=width);
+        program.push(Instruction::Inc(step));
+        program.push(Instruction::Load);
+        program.push(Instruction::Cdec(step));
+        program.push(Instruction::Inv);
+    }
+    Ok(program)
+}
+
+/// Writes the input of a task's first testcase into a reset VM, as data for task style programs
+pub fn load_task_input(vm: &mut Vm, task: Task) -> Result<()> {
+    let (input, _) = task.load_tc(0)?;
+    vm.memory[0..input.len()].copy_from_bitslice(&input);
+    Ok(())
+}
+
+/// Wall clock seconds over several runs
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub best: f64,
+    pub mean: f64,
+    pub runs: usize,
+}
+
+impl Timing {
+    /// `count` per second in the fastest run
+    pub fn per_second(&self, count: f64) -> f64 {
+        per_second(count, self.best)
+    }
+}
+
+fn per_second(count: f64, seconds: f64) -> f64 {
+    match seconds > 0.0 {
+        true => count / seconds,
+        false => f64::INFINITY,
+    }
+}
+
+/// Calls `f` `runs` times, timing each call; `prepare` runs before each call, untimed
+pub fn time_runs<T>(
+    runs: usize,
+    state: &mut T,
+    mut prepare: impl FnMut(&mut T),
+    mut f: impl FnMut(&mut T),
+) -> Timing {
+    let mut timer = ResetableTimer::new();
+    let mut best = f64::INFINITY;
+    let mut total = 0.0;
+    for _ in 0..runs {
+        prepare(state);
+        timer.seconds_since();
+        f(state);
+        let seconds = timer.seconds_since();
+        best = best.min(seconds);
+        total += seconds;
+    }
+    Timing {
+        best,
+        mean: total / runs as f64,
+        runs,
+    }
+}
+
+/// Times clearing the VM's memory
+pub fn time_reset(vm: &mut Vm, runs: usize) -> Timing {
+    time_runs(runs, vm, |_| (), |vm| vm.reset())
+}
+
+/// Times running the program to completion from a reset VM, with `prepare` applied untimed
+/// before each run, e.g. `load_task_input`
+pub fn time_run(vm: &mut Vm, runs: usize, mut prepare: impl FnMut(&mut Vm)) -> Timing {
+    let prepare = |vm: &mut Vm| {
+        vm.reset();
+        prepare(vm);
+    };
+    time_runs(runs, vm, prepare, |vm| {
+        vm.run();
+    })
+}
+
+/// Everything `wpkpp bench` measures
+#[derive(Debug, Clone, Copy)]
+pub struct BenchReport {
+    pub instructions: usize,
+    pub source_bytes: u64,
+    pub parse: f64,
+    pub setup: f64,
+    pub reset: Timing,
+    pub run: Timing,
+}
+
+impl BenchReport {
+    pub fn print(&self) {
+        println!(
+            "Parse: {:.3}s ({:.1} MB/s, {:.1}M instructions/s)",
+            self.parse,
+            per_second(self.source_bytes as f64 / 1e6, self.parse),
+            per_second(self.instructions as f64 / 1e6, self.parse)
+        );
+        println!("VM Setup: {:.3}s", self.setup);
+        println!("Reset: {:.3}s", self.reset.best);
+        println!(
+            "Run: {:.3}s ({:.1}M instructions/s)",
+            self.run.best,
+            self.run.per_second(self.instructions as f64 / 1e6)
+        );
+    }
+}
+
+/// Times VM setup, resets and runs of an already parsed program
+pub fn bench_instructions(
+    program: Instructions,
+    source_bytes: u64,
+    parse: f64,
+    runs: usize,
+) -> BenchReport {
+    let instructions = program.len();
+    info!(instructions, seconds = parse, "Parsed benchmark program");
+
+    let mut timer = ResetableTimer::new();
+    let mut vm = Vm::new(program);
+    let setup = timer.seconds_since();
+
+    BenchReport {
+        instructions,
+        source_bytes,
+        parse,
+        setup,
+        reset: time_reset(&mut vm, RESETS),
+        // Scripts have no jumps, so every run steps through each instruction exactly once
+        run: time_run(&mut vm, runs, |_| ()),
+    }
+}
+
+/// Times parsing an in-memory script, then running it
+pub fn bench_script(source: &[u8], format: ScriptFormat, runs: usize) -> Result<BenchReport> {
+    let mut timer = ResetableTimer::new();
+    let program = parse_script_bytes(source, format)?.instructions;
+    let parse = timer.seconds_since();
+    Ok(bench_instructions(
+        program,
+        source.len() as u64,
+        parse,
+        runs,
+    ))
+}
+
+/// Times reading and parsing a script file, then running it
+pub fn bench_file(path: &str, runs: usize) -> Result<BenchReport> {
+    let source_bytes = fs::metadata(path)?.len();
+    let mut timer = ResetableTimer::new();
+    let program = parse_script(path, false)?.instructions;
+    let parse = timer.seconds_since();
+    Ok(bench_instructions(program, source_bytes, parse, runs))
+}
+
+/// Times parsing, VM setup, memory resets and straight line execution, printing a summary
+pub fn do_bench(options: &BenchOptions) -> Result<()> {
+    if !(1..=32).contains(&options.mem_bits) {
+        return Err(WpkppError::InvalidArgument(format!(
+            "Memory span of 2^{} bits is out of range; expected 1 to 32",
+            options.mem_bits
+        )));
+    }
+
+    let report = match &options.program {
+        BenchProgram::Synthetic => {
+            let source = synthetic_program(options.mem_bits, FIXTURE_LEN).to_wpkm();
+            bench_script(source.as_bytes(), ScriptFormat::Wpkm, DEFAULT_RUNS)?
+        }
+        BenchProgram::File(path) => bench_file(path, DEFAULT_RUNS)?,
+    };
+
+    match &options.program {
+        BenchProgram::Synthetic => println!(
+            "Program: synthetic, {} instructions over 2^{} bits",
+            report.instructions, options.mem_bits
+        ),
+        BenchProgram::File(path) => {
+            println!("Program: {}, {} instructions", path, report.instructions)
+        }
+    }
+    report.print();
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! Parse cache: The instructions of large scripts, keyed by the SHA-256 of the file and how it
+//! was parsed, so that grading or compressing the same script again skips parsing it.
+//!
+//! Entries are .wpkb files under `$XDG_CACHE_HOME/wpkpp/parse`, or `~/.cache/wpkpp/parse`.
+//! Only scripts of nothing but instructions are stored, since .wpkb has no room for labels,
+//! comments, metadata or assertions. Any entry can be deleted at any time.
+
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::{
+    error::Result,
+    parse::{
+        is_container_path, parse_script_bytes_with, parse_script_with, parse_wpkb_exact,
+        write_wpkb, ParseOptions, Script, ScriptCompression, ScriptFormat, STDIN_PATH,
+    },
+};
+
+/// Scripts smaller than this parse about as fast as their cache entry is read
+pub const MIN_CACHED_SIZE: u64 = 1 << 20;
+
+/// `$XDG_CACHE_HOME/wpkpp/parse`, or `~/.cache/wpkpp/parse`
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_home.join("wpkpp").join("parse"))
+}
+
+/// Key of a script's bytes parsed as `format` with `options`, by this version of wpkpp, as hex;
+/// A collision would hand one script another's instructions, so this is SHA-256 rather than
+/// anything faster
+fn cache_key(bytes: &[u8], format: ScriptFormat, options: &ParseOptions) -> String {
+    let mut hasher = Sha256::new();
+    // NUL ends the variable length fields, so no two of them run into each other
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update([0]);
+    hasher.update(format.name().as_bytes());
+    hasher.update([0]);
+    hasher.update([
+        options.wrap_repeats as u8,
+        options.split_repeats as u8,
+        options.latin1 as u8,
+    ]);
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+fn store(dir: &Path, entry: &Path, script: &Script) -> Result<()> {
+    let mut bytes = vec![];
+    write_wpkb(&mut bytes, &script.instructions)?;
+    fs::create_dir_all(dir)?;
+    fs::write(entry, bytes)?;
+    Ok(())
+}
+
+/// Like `parse_script_with`, reusing the instructions of an earlier parse of the same bytes;
+/// Scripts from stdin, containers, compressed or small files, and parses wanting a source map
+/// or uncompressed instructions are always parsed
+pub fn parse_script_cached(
+    path: &str,
+    format: Option<ScriptFormat>,
+    check_size: bool,
+    options: ParseOptions,
+) -> Result<Script> {
+    let options = ParseOptions {
+        cache: false,
+        ..options
+    };
+    let cacheable = path != STDIN_PATH
+        && !is_container_path(path)
+        && ScriptCompression::split_path(path).1 == ScriptCompression::None
+        && options.compress
+        && !options.source_map;
+    let resolved = format.or_else(|| ScriptFormat::from_path(path));
+    let (format, dir) = match (cacheable, resolved, cache_dir()) {
+        (true, Some(format), Some(dir)) => (format, dir),
+        _ => return parse_script_with(path, format, check_size, options),
+    };
+    let size = fs::metadata(path)?.len();
+    if size < MIN_CACHED_SIZE || (check_size && size >= options.file_size_limit(format)) {
+        return parse_script_with(path, Some(format), check_size, options);
+    }
+
+    let bytes = fs::read(path)?;
+    let entry = dir.join(format!("{}.wpkb", cache_key(&bytes, format, &options)));
+    if let Ok(cached) = fs::read(&entry) {
+        match parse_wpkb_exact(&cached) {
+            Ok(instructions) => {
+                debug!(entry = ?entry, "Parse cache hit");
+                return Ok(Script::from_instructions(instructions));
+            }
+            Err(e) => debug!(entry = ?entry, error = ?e, "Unreadable parse cache entry"),
+        }
+    }
+
+    let script = parse_script_bytes_with(&bytes, format, options)?;
+    let plain = script.labels.is_empty()
+        && script.comments.is_empty()
+        && script.meta.is_none()
+        && script.assertions.is_empty();
+    if plain {
+        // The cache only ever saves time, so failing to write it is no reason to fail the parse
+        if let Err(e) = store(&dir, &entry, &script) {
+            debug!(entry = ?entry, error = ?e, "Could not write parse cache entry");
+        }
+    }
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{Instruction, VmUsize};
+
+    #[test]
+    fn keys_depend_on_bytes_format_and_options() {
+        let options = ParseOptions::default();
+        let key = cache_key(b"!>?", ScriptFormat::Wpkm, &options);
+        assert_eq!(key, cache_key(b"!>?", ScriptFormat::Wpkm, &options));
+        assert_ne!(key, cache_key(b"!>?!", ScriptFormat::Wpkm, &options));
+        assert_ne!(key, cache_key(b"!>?", ScriptFormat::Wpk, &options));
+        for changed in [
+            ParseOptions {
+                wrap_repeats: true,
+                ..options
+            },
+            ParseOptions {
+                split_repeats: true,
+                ..options
+            },
+            ParseOptions {
+                latin1: true,
+                ..options
+            },
+        ] {
+            assert_ne!(key, cache_key(b"!>?", ScriptFormat::Wpkm, &changed));
+        }
+        assert_eq!(key.len(), 64);
+
+        // Settings which never reach the cache do not split it
+        let jobs = ParseOptions {
+            jobs: Some(1),
+            ..options
+        };
+        assert_eq!(key, cache_key(b"!>?", ScriptFormat::Wpkm, &jobs));
+    }
+
+    #[test]
+    fn entries_read_back_exactly() {
+        let dir = env::temp_dir().join(format!("wpkpp-cache-test-{}", std::process::id()));
+        let entry = dir.join("entry.wpkb");
+        let options = ParseOptions {
+            split_repeats: true,
+            ..ParseOptions::default()
+        };
+        let script =
+            parse_script_bytes_with(b"8589934594>!3<?", ScriptFormat::Wpkm, options).unwrap();
+        store(&dir, &entry, &script).unwrap();
+        let cached = parse_wpkb_exact(&fs::read(&entry).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(cached, script.instructions);
+        assert_eq!(
+            cached[..3],
+            [
+                Instruction::Inc(VmUsize::MAX),
+                Instruction::Inc(VmUsize::MAX),
+                Instruction::Inc(4)
+            ]
+        );
+    }
+}
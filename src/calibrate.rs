@@ -0,0 +1,206 @@
+//! `wpkpp calibrate`: The reference solutions graded on this machine, keeping their scores and
+//! how long grading took, for features that score or time scripts relative to this machine.
+
+use miniserde::{json, Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::{grade_vm, GradeSummary, TESTCASES},
+    reference::reference_solution,
+    task::Task,
+    util::ResetableTimer,
+    vm::Vm,
+};
+
+/// Bumped whenever calibration files change shape
+pub const CALIBRATION_VERSION: &str = "1";
+/// Tasks with a reference solution, calibrated in this order
+pub const CALIBRATED_TASKS: [Task; 5] = [
+    Task::ZeroXor,
+    Task::OneAdd1,
+    Task::TwoAdd16,
+    Task::TwoSub16,
+    Task::ThreeMul16,
+];
+
+pub struct CalibrateOptions {
+    /// Gradings of each reference solution, keeping the fastest
+    pub runs: usize,
+    /// Where to save the calibration, instead of `calibration_path()`
+    pub output_path: Option<String>,
+}
+
+/// How a reference solution graded; Numbers are strings, as in `grade --json`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskCalibration {
+    pub task: String,
+    /// Most steps taken by any testcase
+    pub runtime: String,
+    /// Most memory cells spanned by any testcase
+    pub memory: String,
+    /// Instructions in the reference solution
+    pub instructions: String,
+    /// Seconds taken by the fastest grading of all testcases
+    pub seconds: String,
+}
+
+impl TaskCalibration {
+    pub fn seconds(&self) -> Option<f64> {
+        self.seconds.parse().ok()
+    }
+
+    /// Instructions run per second, every testcase running each instruction once
+    pub fn instructions_per_second(&self) -> Option<f64> {
+        let instructions: f64 = self.instructions.parse().ok()?;
+        let seconds = self.seconds()?;
+        match seconds > 0.0 {
+            true => Some(instructions * TESTCASES as f64 / seconds),
+            false => None,
+        }
+    }
+}
+
+/// Contents of a file written by `wpkpp calibrate`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Calibration {
+    pub version: String,
+    /// Version of wpkpp which made the calibration
+    pub wpkpp: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: String,
+    pub tasks: Vec<TaskCalibration>,
+}
+
+fn invalid(reason: impl Into<String>) -> WpkppError {
+    WpkppError::InvalidCalibration(reason.into())
+}
+
+/// `$XDG_DATA_HOME/wpkpp/calibration.json`, or `~/.local/share/wpkpp/calibration.json`
+pub fn calibration_path() -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(data_dir.join("wpkpp").join("calibration.json"))
+}
+
+/// Grades the reference solution of `task` `runs` times, keeping the fastest
+pub fn calibrate_task(task: Task, runs: usize) -> Result<TaskCalibration> {
+    let instructions = reference_solution(task).ok_or_else(|| {
+        WpkppError::InvalidArgument(format!("No reference solution for task {}", task.name()))
+    })?;
+    let instruction_count = instructions.len();
+    let mut vm = Vm::new(instructions);
+
+    let mut fastest = f64::INFINITY;
+    let mut summary = GradeSummary::default();
+    for _ in 0..runs.max(1) {
+        let mut timer = ResetableTimer::new();
+        summary = grade_vm(task, &Task::seed(), &mut vm, |_| true)?;
+        fastest = fastest.min(timer.seconds_since());
+    }
+    if !summary.passed() {
+        return Err(WpkppError::CheckFailed(format!(
+            "The reference solution for task {} only scored {}/{}",
+            task.name(),
+            summary.correct,
+            summary.total
+        )));
+    }
+
+    Ok(TaskCalibration {
+        task: task.name().to_string(),
+        runtime: summary.max_runtime.to_string(),
+        memory: summary.max_memory.to_string(),
+        instructions: instruction_count.to_string(),
+        seconds: format!("{:.6}", fastest),
+    })
+}
+
+impl Calibration {
+    /// Calibrates every task with a reference solution, handing each result to `on_task` as it
+    /// is done
+    pub fn new(runs: usize, mut on_task: impl FnMut(&TaskCalibration)) -> Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        let mut tasks = vec![];
+        for task in CALIBRATED_TASKS {
+            let calibration = calibrate_task(task, runs)?;
+            on_task(&calibration);
+            tasks.push(calibration);
+        }
+        Ok(Self {
+            version: CALIBRATION_VERSION.to_string(),
+            wpkpp: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: format!("{:.3}", timestamp),
+            tasks,
+        })
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let calibration: Self =
+            json::from_str(text).map_err(|_| invalid("not a wpkpp calibration"))?;
+        if calibration.version != CALIBRATION_VERSION {
+            return Err(invalid(format!(
+                "version {} is not supported; expected {}, so run `wpkpp calibrate` again",
+                calibration.version, CALIBRATION_VERSION
+            )));
+        }
+        Ok(calibration)
+    }
+
+    pub fn to_json(&self) -> String {
+        json::to_string(self)
+    }
+
+    /// The calibration saved at `calibration_path()`, if this machine has been calibrated
+    pub fn load() -> Result<Option<Self>> {
+        let path = match calibration_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => Self::from_json(&text).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn task(&self, task: Task) -> Option<&TaskCalibration> {
+        self.tasks.iter().find(|entry| entry.task == task.name())
+    }
+}
+
+/// Calibrates this machine and saves the result, printing one line per task
+pub fn do_calibrate(options: &CalibrateOptions) -> Result<()> {
+    let path = match &options.output_path {
+        Some(path) => PathBuf::from(path),
+        None => calibration_path().ok_or_else(|| {
+            WpkppError::InvalidArgument(
+                "Cannot find a home directory to save the calibration in; try --out".to_string(),
+            )
+        })?,
+    };
+
+    let calibration = Calibration::new(options.runs, |task| {
+        println!(
+            "Task {}: Instructions: {} / Memory Usage: {} / {}s to grade",
+            task.task, task.runtime, task.memory, task.seconds
+        );
+    })?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, calibration.to_json())?;
+    println!("Saved calibration to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::io::Write;
+
+use crate::{
+    error::{Result, WpkppError},
+    parse::{parse_script_as, ScriptFormat, STDOUT_PATH},
+    util::{fnv1a, FNV_OFFSET},
+    vm::{Instruction, Instructions},
+};
+
+pub struct CanonOptions {
+    /// Input format, overriding the input path's extension
+    pub format: Option<ScriptFormat>,
+    /// Print a hash of the canonical form instead of the form itself
+    pub hash: bool,
+}
+
+/// Instructions with zero length INC / CDEC dropped and every INC / CDEC merged as far as
+/// compression allows, ignoring labels
+pub fn canonical_instructions(instructions: &Instructions) -> Instructions {
+    let mut canonical = Instructions::with_capacity(instructions.len());
+    for instruction in instructions.iter() {
+        if !matches!(instruction, Instruction::Inc(0) | Instruction::Cdec(0)) {
+            canonical.push_compressed(*instruction, 0);
+        }
+    }
+    canonical
+}
+
+/// Canonical form of a script as a single .wpkm line: comments, labels and whitespace are
+/// gone, counts are written in decimal without leading zeros and only when above 1, and
+/// LOAD / INV are always "?" / "!". Scripts which compress to the same instructions get
+/// byte-identical forms, whatever format they were written in.
+pub fn canonical_bytes(instructions: &Instructions) -> Vec<u8> {
+    canonical_instructions(instructions).to_wpkm().into_bytes()
+}
+
+/// Hash of the canonical form, for spotting equivalent scripts
+pub fn canonical_hash(instructions: &Instructions) -> u64 {
+    fnv1a(FNV_OFFSET, &canonical_bytes(instructions))
+}
+
+/// Writes the canonical form of a script to `output_path`, or its hash to stdout
+pub fn do_canon(input_path: &str, output_path: &str, options: &CanonOptions) -> Result<()> {
+    if output_path != STDOUT_PATH {
+        if ScriptFormat::from_path(output_path) != Some(ScriptFormat::Wpkm) {
+            return Err(WpkppError::InvalidArgument(format!(
+                "Canonical output {} should end in \".wpkm\"",
+                output_path
+            )));
+        }
+        if input_path == output_path {
+            return Err(WpkppError::InvalidArgument(
+                "Input and output paths the same; aborting".to_string(),
+            ));
+        }
+    }
+    let script = parse_script_as(input_path, options.format, false)?;
+
+    if options.hash {
+        println!(
+            "{:016x}  {}",
+            canonical_hash(&script.instructions),
+            input_path
+        );
+        return Ok(());
+    }
+
+    let bytes = canonical_bytes(&script.instructions);
+    match output_path == STDOUT_PATH {
+        true => std::io::stdout().lock().write_all(&bytes)?,
+        false => std::fs::write(output_path, bytes)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! `wpkpp compare`: What changed between two `grade --json` reports, metric by metric.
+
+use std::fs;
+
+use crate::{error::Result, report::GradeReport};
+
+/// One metric in both reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricDelta {
+    pub name: &'static str,
+    pub old: i128,
+    pub new: i128,
+}
+
+impl MetricDelta {
+    pub fn delta(&self) -> i128 {
+        self.new - self.old
+    }
+
+    /// Change relative to the old value, in percent; None when the old value is 0
+    pub fn percent(&self) -> Option<f64> {
+        match self.old {
+            0 => None,
+            old => Some(self.delta() as f64 * 100.0 / old as f64),
+        }
+    }
+}
+
+/// Score, runtime, memory and opcounts of both reports, in that order
+pub fn compare_reports(old: &GradeReport, new: &GradeReport) -> Vec<MetricDelta> {
+    let metric = |name, value: fn(&GradeReport) -> i128| MetricDelta {
+        name,
+        old: value(old),
+        new: value(new),
+    };
+    vec![
+        metric("score", |report| report.score as i128),
+        metric("runtime", |report| report.runtime as i128),
+        metric("memory", |report| report.memory as i128),
+        metric("inc", |report| report.instructions.inc as i128),
+        metric("cdec", |report| report.instructions.cdec as i128),
+        metric("load", |report| report.instructions.load as i128),
+        metric("inv", |report| report.instructions.inv as i128),
+    ]
+}
+
+fn read_report(path: &str) -> Result<GradeReport> {
+    GradeReport::from_json(&fs::read_to_string(path)?)
+}
+
+/// Prints the deltas between two reports, one metric per line
+pub fn do_compare(old_path: &str, new_path: &str) -> Result<()> {
+    let old = read_report(old_path)?;
+    let new = read_report(new_path)?;
+
+    if old.memory_metric != new.memory_metric {
+        println!(
+            "Warning: memory is measured as {} in the old report but {} in the new one",
+            old.memory_metric.name(),
+            new.memory_metric.name()
+        );
+    }
+    if old.verdict() != new.verdict() {
+        println!("Verdict: {} -> {}", old.verdict(), new.verdict());
+    }
+    println!(
+        "{:<8} {:>14} {:>14} {:>14}",
+        "Metric", "Old", "New", "Delta"
+    );
+    for metric in compare_reports(&old, &new) {
+        let percent = match metric.percent() {
+            Some(percent) if metric.delta() != 0 => format!(" ({:+.1}%)", percent),
+            _ => String::new(),
+        };
+        println!(
+            "{:<8} {:>14} {:>14} {:>+14}{}",
+            metric.name,
+            metric.old,
+            metric.new,
+            metric.delta(),
+            percent
+        );
+    }
+    Ok(())
+}
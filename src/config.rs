@@ -0,0 +1,278 @@
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::VerdictStrings,
+    parse::ScriptFormat,
+    report::Verdict,
+    task::Task,
+};
+
+/// Per-project config, read from the working directory
+pub const PROJECT_CONFIG_PATH: &str = "wpkpp.toml";
+
+/// When to color terminal output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub const NAMES: [&'static str; 3] = ["auto", "always", "never"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "always" => Some(Self::Always),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+
+    /// Whether to color stdout; Auto only colors a terminal, and never when NO_COLOR is set
+    pub fn enabled(self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+/// The `[verdicts]` table, e.g. `OK = "Accepted"` and `emoji = false`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerdictConfig {
+    /// Text per verdict, later entries winning
+    pub text: Vec<(Verdict, String)>,
+    pub emoji: Option<bool>,
+}
+
+impl VerdictConfig {
+    fn parse(table: &toml::value::Table, invalid: impl Fn(String) -> WpkppError) -> Result<Self> {
+        let mut verdicts = VerdictConfig::default();
+        for (key, value) in table.iter() {
+            let expected = |kind: &str| invalid(format!("\"verdicts.{}\" should be {}", key, kind));
+            match (key.as_str(), Verdict::from_code(key)) {
+                ("emoji", _) => {
+                    verdicts.emoji = Some(value.as_bool().ok_or_else(|| expected("true or false"))?)
+                }
+                (_, Some(verdict)) => {
+                    let text = value.as_str().ok_or_else(|| expected("a string"))?;
+                    verdicts.text.push((verdict, text.to_string()));
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown verdict \"{}\"; expected OK, WA, TLE, MLE or emoji",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(verdicts)
+    }
+
+    fn overridden_by(mut self, other: Self) -> Self {
+        self.text.extend(other.text);
+        Self {
+            text: self.text,
+            emoji: other.emoji.or(self.emoji),
+        }
+    }
+
+    /// The default verdict strings with these settings applied
+    pub fn strings(&self) -> VerdictStrings {
+        let mut strings = VerdictStrings::default();
+        for (verdict, text) in self.text.iter() {
+            *strings.get_mut(*verdict) = text.clone();
+        }
+        if let Some(emoji) = self.emoji {
+            strings.emoji = emoji;
+        }
+        strings
+    }
+}
+
+/// A solution listed under `[solutions.<name>]`, as added by `wpkpp init`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    pub name: String,
+    pub task: Task,
+    pub path: String,
+}
+
+impl Solution {
+    fn parse(
+        name: &str,
+        value: &toml::Value,
+        invalid: impl Fn(String) -> WpkppError,
+    ) -> Result<Self> {
+        let table = value
+            .as_table()
+            .ok_or_else(|| invalid(format!("\"solutions.{}\" should be a table", name)))?;
+        let field = |key: &str| {
+            table
+                .get(key)
+                .and_then(|value| value.as_str())
+                .ok_or_else(|| {
+                    invalid(format!("\"solutions.{}.{}\" should be a string", name, key))
+                })
+        };
+        if let Some(key) = table
+            .keys()
+            .find(|key| !["task", "path"].contains(&key.as_str()))
+        {
+            return Err(invalid(format!(
+                "unknown setting \"solutions.{}.{}\"",
+                name, key
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            task: Task::from_str(field("task")?)
+                .map_err(|e| invalid(format!("\"solutions.{}.task\": {}", name, e)))?,
+            path: field("path")?.to_string(),
+        })
+    }
+}
+
+/// Defaults for command line flags, read from `~/.config/wpkpp/config.toml` and then
+/// `./wpkpp.toml`. Flags given on the command line always win.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub color: Option<ColorChoice>,
+    pub noprogress: Option<bool>,
+    /// Testcase seed, used when WPKPP_SEED is not set
+    pub seed: Option<String>,
+    /// Threads for parsing large .wpkm scripts
+    pub jobs: Option<usize>,
+    /// Output format for compress / convert when the output path does not give one
+    pub output_format: Option<ScriptFormat>,
+    /// What grade prints for each verdict
+    pub verdicts: VerdictConfig,
+    /// Solutions in the project, from the `[solutions]` table
+    pub solutions: Vec<Solution>,
+}
+
+impl Config {
+    /// Parses a config file; Unknown keys are rejected so that typos don't go unnoticed
+    pub fn parse(text: &str, path: &str) -> Result<Self> {
+        let invalid = |message: String| WpkppError::InvalidConfig(format!("{}: {}", path, message));
+        let table = match text.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(invalid("expected a table of settings".to_string())),
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+
+        let mut config = Config::default();
+        for (key, value) in table.iter() {
+            let expected = |kind: &str| invalid(format!("\"{}\" should be {}", key, kind));
+            match key.as_str() {
+                "color" => {
+                    config.color = Some(
+                        value
+                            .as_str()
+                            .and_then(ColorChoice::from_name)
+                            .ok_or_else(|| expected("\"auto\", \"always\" or \"never\""))?,
+                    )
+                }
+                // Older spelling of color = "never"
+                "nocolor" => {
+                    let nocolor = value.as_bool().ok_or_else(|| expected("true or false"))?;
+                    config.color = Some(match nocolor {
+                        true => ColorChoice::Never,
+                        false => ColorChoice::Auto,
+                    })
+                }
+                "noprogress" => {
+                    config.noprogress =
+                        Some(value.as_bool().ok_or_else(|| expected("true or false"))?)
+                }
+                "seed" => {
+                    config.seed = Some(match value {
+                        toml::Value::String(seed) => seed.clone(),
+                        toml::Value::Integer(seed) => seed.to_string(),
+                        _ => return Err(expected("a string or integer")),
+                    })
+                }
+                "jobs" => {
+                    config.jobs = Some(
+                        value
+                            .as_integer()
+                            .filter(|jobs| *jobs >= 1)
+                            .ok_or_else(|| expected("a positive integer"))?
+                            as usize,
+                    )
+                }
+                "output_format" => {
+                    config.output_format = Some(
+                        value
+                            .as_str()
+                            .and_then(ScriptFormat::from_name)
+                            .ok_or_else(|| expected("\"wpk\", \"wpkm\" or \"wpkb\""))?,
+                    )
+                }
+                "verdicts" => {
+                    let table = value.as_table().ok_or_else(|| expected("a table"))?;
+                    config.verdicts = VerdictConfig::parse(table, invalid)?;
+                }
+                "solutions" => {
+                    let table = value.as_table().ok_or_else(|| expected("a table"))?;
+                    for (name, solution) in table.iter() {
+                        config
+                            .solutions
+                            .push(Solution::parse(name, solution, invalid)?);
+                    }
+                }
+                _ => return Err(invalid(format!("unknown setting \"{}\"", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Settings from `other` where given, falling back to our own
+    fn overridden_by(self, other: Self) -> Self {
+        Self {
+            color: other.color.or(self.color),
+            noprogress: other.noprogress.or(self.noprogress),
+            seed: other.seed.or(self.seed),
+            jobs: other.jobs.or(self.jobs),
+            output_format: other.output_format.or(self.output_format),
+            verdicts: self.verdicts.overridden_by(other.verdicts),
+            solutions: self.solutions.into_iter().chain(other.solutions).collect(),
+        }
+    }
+
+    /// Reads the user config, then the project config over it; Missing files are skipped
+    pub fn load() -> Result<Self> {
+        let mut config = Config::default();
+        let paths = user_config_path()
+            .into_iter()
+            .chain(std::iter::once(PathBuf::from(PROJECT_CONFIG_PATH)));
+        for path in paths {
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            config = config.overridden_by(Config::parse(&text, &path.to_string_lossy())?);
+        }
+        Ok(config)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/wpkpp/config.toml`, or `~/.config/wpkpp/config.toml`
+fn user_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("wpkpp").join("config.toml"))
+}
@@ -0,0 +1,206 @@
+//! `wpkpp conformance`: Runs another woodpecker implementation on the programs and inputs of
+//! `wpkpp vectors`, and optionally on a script against a task's testcases, diffing what it
+//! reports against the built-in VM.
+//!
+//! The runner is started once per case. It is given three lines on stdin: the program as a
+//! single `.wpkm` line, the input memory as `0` / `1` from cell 0, and a number of cells N.
+//! It should run the program and print one line: the runtime, the address the pointer ended at
+//! and cells 0 to N-1 as `0` / `1`, separated by spaces.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::{
+    error::{Result, WpkppError},
+    parse::{parse_script_with, parse_wpkm_bytes, ParseOptions, ScriptFormat},
+    task::Task,
+    vectors::{bit_string, VM_PROGRAMS},
+    vm::{Instructions, Vm},
+};
+
+/// Fewest cells compared after every VM semantics program
+const VM_CELLS: usize = 8;
+
+pub struct ConformanceOptions {
+    /// Runner command, split on whitespace into the program and its arguments
+    pub runner: String,
+    /// Script to also run against every testcase of a task
+    pub script: Option<(Task, String)>,
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+}
+
+/// What a run came to, from the built-in VM or the runner
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Outcome {
+    runtime: i64,
+    ptr: u64,
+    memory: String,
+}
+
+/// The memory a program starts with and how many cells to compare afterwards
+struct Case {
+    name: String,
+    input: String,
+    cells: usize,
+}
+
+/// Runs a case on the built-in VM, whose program has already been set
+fn run_builtin(vm: &mut Vm, case: &Case) -> Outcome {
+    vm.reset();
+    for (cell, bit) in case.input.chars().enumerate() {
+        vm.memory.set(cell, bit == '1');
+    }
+    let result = vm.run();
+    Outcome {
+        runtime: result.runtime,
+        ptr: vm.memory_pointer.ptr as u64,
+        memory: bit_string(&vm.memory[..case.cells]),
+    }
+}
+
+/// Runs a case on the runner; Errors describe a runner which did not follow the protocol
+fn run_external(
+    runner: &[&str],
+    program: &str,
+    case: &Case,
+) -> Result<std::result::Result<Outcome, String>> {
+    let mut child = Command::new(runner[0])
+        .args(&runner[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            WpkppError::InvalidArgument(format!("Cannot start runner {}: {}", runner[0], e))
+        })?;
+    let request = format!("{}\n{}\n{}\n", program.trim_end(), case.input, case.cells);
+    // A runner which exits without reading its input is reported by its output below
+    let _ = child.stdin.take().unwrap().write_all(request.as_bytes());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Ok(Err(format!("runner exited with {}", output.status)));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.split_whitespace().collect();
+    let outcome = match fields[..] {
+        [runtime, ptr, memory] => match (runtime.parse(), ptr.parse()) {
+            (Ok(runtime), Ok(ptr)) => Some(Outcome {
+                runtime,
+                ptr,
+                memory: memory.to_string(),
+            }),
+            _ => None,
+        },
+        // Nothing to show for zero cells
+        [runtime, ptr] if case.cells == 0 => match (runtime.parse(), ptr.parse()) {
+            (Ok(runtime), Ok(ptr)) => Some(Outcome {
+                runtime,
+                ptr,
+                memory: String::new(),
+            }),
+            _ => None,
+        },
+        _ => None,
+    };
+    Ok(outcome.ok_or_else(|| format!("unreadable output {:?}", stdout.trim_end())))
+}
+
+/// How the runner's outcome differs from the built-in VM's, if at all
+fn describe_difference(expected: &Outcome, actual: &Outcome) -> Option<String> {
+    let mut differences = vec![];
+    if expected.runtime != actual.runtime {
+        differences.push(format!(
+            "runtime {}, expected {}",
+            actual.runtime, expected.runtime
+        ));
+    }
+    if expected.ptr != actual.ptr {
+        differences.push(format!("pointer {}, expected {}", actual.ptr, expected.ptr));
+    }
+    if expected.memory != actual.memory {
+        let cell = expected
+            .memory
+            .chars()
+            .zip(actual.memory.chars())
+            .position(|(expected, actual)| expected != actual)
+            .unwrap_or_else(|| expected.memory.len().min(actual.memory.len()));
+        differences.push(format!("memory differs from cell {}", cell));
+    }
+    match differences.is_empty() {
+        true => None,
+        false => Some(differences.join(", ")),
+    }
+}
+
+/// Runs every case of one program on both VMs, printing each disagreement; Returns how many
+/// cases agreed
+fn check_program(
+    vm: &mut Vm,
+    runner: &[&str],
+    instructions: Instructions,
+    program: &str,
+    cases: &[Case],
+) -> Result<usize> {
+    vm.program = instructions;
+    let mut agreed = 0;
+    for case in cases {
+        let expected = run_builtin(vm, case);
+        match run_external(runner, program, case)? {
+            Ok(actual) => match describe_difference(&expected, &actual) {
+                Some(difference) => println!("{}: {}", case.name, difference),
+                None => agreed += 1,
+            },
+            Err(problem) => println!("{}: {}", case.name, problem),
+        }
+    }
+    Ok(agreed)
+}
+
+/// Diffs an external woodpecker implementation against the built-in VM
+pub fn do_conformance(options: &ConformanceOptions) -> Result<()> {
+    let runner: Vec<&str> = options.runner.split_whitespace().collect();
+    if runner.is_empty() {
+        return Err(WpkppError::InvalidArgument(
+            "The runner command is empty".to_string(),
+        ));
+    }
+
+    let mut vm = Vm::new(Instructions::new());
+    let (mut agreed, mut total) = (0, 0);
+    for (name, program, input) in VM_PROGRAMS {
+        let case = Case {
+            name: name.to_string(),
+            input: input.to_string(),
+            cells: input.len().max(VM_CELLS),
+        };
+        let instructions = parse_wpkm_bytes(program.as_bytes())?;
+        agreed += check_program(&mut vm, &runner, instructions, program, &[case])?;
+        total += 1;
+    }
+
+    if let Some((task, path)) = options.script.as_ref() {
+        let script = parse_script_with(path, options.format, true, ParseOptions::default())?;
+        let program = script.instructions.to_wpkm();
+        let cases: Vec<Case> = task
+            .testcases(&Task::seed())?
+            .enumerate()
+            .map(|(tc_id, (input, answer))| Case {
+                name: format!("task {} testcase {}", task.name(), tc_id),
+                input: bit_string(&input),
+                cells: input.len() + answer.len(),
+            })
+            .collect();
+        agreed += check_program(&mut vm, &runner, script.instructions, &program, &cases)?;
+        total += cases.len();
+    }
+
+    println!("Conformance: {}/{} case(s) agree", agreed, total);
+    match agreed == total {
+        true => Ok(()),
+        false => Err(WpkppError::CheckFailed(format!(
+            "{} case(s) differ from the built-in VM",
+            total - agreed
+        ))),
+    }
+}
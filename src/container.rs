@@ -0,0 +1,170 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::error::{Result, WpkppError};
+use crate::parse::ScriptFormat;
+
+/// Leads every container, ahead of the script format and encoding names
+const CONTAINER_MAGIC: &str = "wpkpp";
+const CONTAINER_SEPARATOR: char = ':';
+
+/// Text encodings for the bytes of a script, safe to embed in JSON strings
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContainerEncoding {
+    Base64,
+    Hex,
+}
+
+impl ContainerEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "base64" => Some(Self::Base64),
+            "hex" => Some(Self::Hex),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Base64 => "base64",
+            Self::Hex => "hex",
+        }
+    }
+}
+
+/// Wraps a script's bytes as `wpkpp:<format>:<encoding>:<payload>`, so the script can be
+/// read back without knowing its file name
+pub fn encode_container(bytes: &[u8], format: ScriptFormat, encoding: ContainerEncoding) -> String {
+    let payload = match encoding {
+        ContainerEncoding::Base64 => STANDARD.encode(bytes),
+        ContainerEncoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+    format!(
+        "{magic}{sep}{}{sep}{}{sep}{}",
+        format.name(),
+        encoding.name(),
+        payload,
+        magic = CONTAINER_MAGIC,
+        sep = CONTAINER_SEPARATOR
+    )
+}
+
+/// Unwraps a container made by `encode_container`; Whitespace anywhere in the payload is
+/// ignored, so long payloads may be wrapped
+pub fn decode_container(text: &str) -> Result<(ScriptFormat, Vec<u8>)> {
+    let mut fields = text.trim().splitn(4, CONTAINER_SEPARATOR);
+    if fields.next() != Some(CONTAINER_MAGIC) {
+        return Err(WpkppError::InvalidContainer(format!(
+            "Not a script container; expected a \"{}{}\" header",
+            CONTAINER_MAGIC, CONTAINER_SEPARATOR
+        )));
+    }
+    let (format, encoding, payload) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(format), Some(encoding), Some(payload)) => (format, encoding, payload),
+        _ => {
+            return Err(WpkppError::InvalidContainer(
+                "Truncated script container header".to_string(),
+            ))
+        }
+    };
+    let format = ScriptFormat::from_name(format).ok_or_else(|| {
+        WpkppError::InvalidContainer(format!("Unknown script format \"{}\" in container", format))
+    })?;
+    let encoding = ContainerEncoding::from_name(encoding).ok_or_else(|| {
+        WpkppError::InvalidContainer(format!("Unknown container encoding \"{}\"", encoding))
+    })?;
+
+    let payload: Vec<u8> = payload
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let bytes = match encoding {
+        ContainerEncoding::Base64 => STANDARD.decode(payload).map_err(|e| {
+            WpkppError::InvalidContainer(format!("Invalid base64 in container: {}", e))
+        })?,
+        ContainerEncoding::Hex => decode_hex(&payload)?,
+    };
+    Ok((format, bytes))
+}
+
+fn decode_hex(payload: &[u8]) -> Result<Vec<u8>> {
+    if !payload.len().is_multiple_of(2) {
+        return Err(WpkppError::InvalidContainer(
+            "Odd number of hex digits in container".to_string(),
+        ));
+    }
+    let digit = |(pos, c): (usize, &u8)| match (*c as char).to_digit(16) {
+        Some(d) => Ok(d as u8),
+        None => Err(WpkppError::InvalidContainer(format!(
+            "Invalid hex digit '{}' at payload offset {}",
+            *c as char, pos
+        ))),
+    };
+    let digits = payload
+        .iter()
+        .enumerate()
+        .map(digit)
+        .collect::<Result<Vec<u8>>>()?;
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [ContainerEncoding; 2] = [ContainerEncoding::Base64, ContainerEncoding::Hex];
+
+    fn decode_error(text: &str) -> String {
+        decode_container(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn containers_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for format in [ScriptFormat::Wpk, ScriptFormat::Wpkm, ScriptFormat::Wpkb] {
+            for encoding in ENCODINGS {
+                for len in [0, 1, 2, 3, 256] {
+                    let text = encode_container(&bytes[..len], format, encoding);
+                    assert_eq!(
+                        decode_container(&text).unwrap(),
+                        (format, bytes[..len].to_vec())
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            encode_container(b"!?", ScriptFormat::Wpkm, ContainerEncoding::Hex),
+            "wpkpp:wpkm:hex:213f"
+        );
+    }
+
+    #[test]
+    fn wrapped_payloads_decode() {
+        let bytes = b"INC 3\nLOAD\nINV\n";
+        for encoding in ENCODINGS {
+            let text = encode_container(bytes, ScriptFormat::Wpk, encoding);
+            let (header, payload) = text.split_at(text.rfind(':').unwrap() + 1);
+            let wrapped: Vec<String> = payload
+                .as_bytes()
+                .chunks(5)
+                .map(|line| String::from_utf8(line.to_vec()).unwrap())
+                .collect();
+            let wrapped = format!("  {}{}\n", header, wrapped.join("\n  "));
+            assert_eq!(decode_container(&wrapped).unwrap().1, bytes);
+        }
+    }
+
+    #[test]
+    fn corrupt_containers_fail() {
+        assert!(decode_error("").contains("Not a script container"));
+        assert!(decode_error("wpk:wpkm:hex:21").contains("Not a script container"));
+        assert!(decode_error("wpkpp:wpkm:hex").contains("Truncated"));
+        assert!(decode_error("wpkpp:wpkx:hex:21").contains("Unknown script format"));
+        assert!(decode_error("wpkpp:wpkm:base32:21").contains("Unknown container encoding"));
+        assert!(decode_error("wpkpp:wpkm:base64:IT8=!").contains("Invalid base64"));
+        assert!(decode_error("wpkpp:wpkm:hex:213").contains("Odd number"));
+        assert!(decode_error("wpkpp:wpkm:hex:21g3").contains("offset 2"));
+    }
+}
@@ -0,0 +1,356 @@
+//! `wpkpp contest grade`: Every submission of a contest graded in one go, for organizers.
+//!
+//! Submissions are laid out one directory per user, with scripts named after the task map's
+//! keys, e.g. `submissions/ada/mul.wpkm` for `mul = "3"`. Each graded script gets a
+//! `grade --json` report under the output directory, and all users are ranked in
+//! `leaderboard.csv`: most tasks passed first, then least total runtime, then least total
+//! memory over the tasks passed.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::{grade_script, GradeSummary},
+    parse::{check_valid_extension, parse_script, ScriptCompression},
+    report::GradeReport,
+    task::Task,
+    util::ResetableTimer,
+};
+
+/// File name of the ranking written to the output directory
+pub const LEADERBOARD_PATH: &str = "leaderboard.csv";
+
+pub struct ContestOptions {
+    /// TOML file mapping script names to tasks
+    pub task_map_path: String,
+    /// Directory for reports and the leaderboard
+    pub out_dir: String,
+}
+
+/// Script names, without extension, paired with the task they solve, as in `mul = "3"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskMap(pub Vec<(String, Task)>);
+
+impl TaskMap {
+    pub fn parse(text: &str, path: &str) -> Result<Self> {
+        let invalid = |message: String| WpkppError::InvalidConfig(format!("{}: {}", path, message));
+        let table = match text.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(invalid("expected a table of script names".to_string())),
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+        let mut entries = vec![];
+        for (name, value) in table.iter() {
+            let task = value
+                .as_str()
+                .ok_or_else(|| invalid(format!("\"{}\" should be a task number", name)))?;
+            let task = Task::from_str(task).map_err(|e| invalid(format!("\"{}\": {}", name, e)))?;
+            entries.push((name.clone(), task));
+        }
+        if entries.is_empty() {
+            return Err(invalid("no tasks listed".to_string()));
+        }
+        Ok(Self(entries))
+    }
+}
+
+/// What came of one entry of the task map for one user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Submission {
+    Missing,
+    /// A script which could not be graded, e.g. for a syntax error
+    Invalid,
+    Graded(GradeSummary),
+}
+
+/// How one user did, with a submission for each entry of the task map
+pub struct Standing {
+    pub user: String,
+    pub submissions: Vec<Submission>,
+}
+
+impl Standing {
+    fn passed(&self) -> impl Iterator<Item = &GradeSummary> {
+        self.submissions
+            .iter()
+            .filter_map(|submission| match submission {
+                Submission::Graded(summary) if summary.passed() => Some(summary),
+                _ => None,
+            })
+    }
+
+    pub fn solved(&self) -> usize {
+        self.passed().count()
+    }
+
+    /// Total runtime over the tasks passed
+    pub fn runtime(&self) -> i64 {
+        self.passed().map(|summary| summary.max_runtime).sum()
+    }
+
+    /// Total memory over the tasks passed
+    pub fn memory(&self) -> i64 {
+        self.passed().map(|summary| summary.max_memory).sum()
+    }
+
+    fn compare(&self, other: &Self) -> Ordering {
+        other
+            .solved()
+            .cmp(&self.solved())
+            .then(self.runtime().cmp(&other.runtime()))
+            .then(self.memory().cmp(&other.memory()))
+    }
+}
+
+/// The script in a user's directory for a task map entry: `<name>.<ext>`, optionally
+/// compressed. Several matches are ambiguous, so none is taken.
+fn find_script(user_dir: &Path, name: &str) -> Result<Option<String>> {
+    let mut matches = vec![];
+    for entry in fs::read_dir(user_dir)? {
+        let path = entry?.path();
+        let path = path.to_string_lossy().to_string();
+        let file_name = Path::new(&path)
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+        let (uncompressed, _) = ScriptCompression::split_path(&file_name);
+        let stem = uncompressed.rsplit_once('.').map(|(stem, _)| stem);
+        if stem == Some(name) && check_valid_extension(&path) {
+            matches.push(path);
+        }
+    }
+    match matches.len() {
+        0 | 1 => Ok(matches.pop()),
+        _ => {
+            matches.sort();
+            eprintln!(
+                "{}: several scripts for \"{}\" ({}); skipping",
+                user_dir.display(),
+                name,
+                matches.join(", ")
+            );
+            Ok(None)
+        }
+    }
+}
+
+fn grade_path(task: Task, path: &str) -> Result<GradeReport> {
+    let mut timer = ResetableTimer::new();
+    let script = parse_script(path, true)?;
+    grade_script(task, &Task::seed(), &script, timer.seconds_since())
+}
+
+/// Quotes a CSV field if it needs it
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+/// Ranks standings in place, best first, returning each one's rank; Ties share a rank
+pub fn rank(standings: &mut [Standing]) -> Vec<usize> {
+    standings.sort_by(|a, b| a.compare(b).then(a.user.cmp(&b.user)));
+    let mut ranks: Vec<usize> = Vec::with_capacity(standings.len());
+    for i in 0..standings.len() {
+        let rank = match i > 0 && standings[i].compare(&standings[i - 1]) == Ordering::Equal {
+            true => ranks[i - 1],
+            false => i + 1,
+        };
+        ranks.push(rank);
+    }
+    ranks
+}
+
+/// `rank,user,solved,runtime,memory` and then one column per task map entry, holding the
+/// verdict and runtime, "ERR" for scripts which did not parse, or nothing when not submitted
+pub fn leaderboard_csv(task_map: &TaskMap, standings: &mut [Standing]) -> String {
+    let ranks = rank(standings);
+    let mut csv = String::from("rank,user,solved,runtime,memory");
+    for (name, _) in task_map.0.iter() {
+        csv.push(',');
+        csv.push_str(&csv_field(name));
+    }
+    csv.push('\n');
+    for (standing, rank) in standings.iter().zip(ranks) {
+        csv.push_str(&format!(
+            "{},{},{},{},{}",
+            rank,
+            csv_field(&standing.user),
+            standing.solved(),
+            standing.runtime(),
+            standing.memory()
+        ));
+        for submission in standing.submissions.iter() {
+            csv.push(',');
+            match submission {
+                Submission::Missing => {}
+                Submission::Invalid => csv.push_str("ERR"),
+                Submission::Graded(summary) => {
+                    let verdict = match summary.passed() {
+                        true => "OK",
+                        false => "WA",
+                    };
+                    csv.push_str(&format!("{} {}", verdict, summary.max_runtime));
+                }
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Grades every user's submissions, writing `<out>/<user>/<name>.json` reports and
+/// `<out>/leaderboard.csv`
+pub fn do_contest_grade(submissions_dir: &str, options: &ContestOptions) -> Result<()> {
+    let task_map = TaskMap::parse(
+        &fs::read_to_string(&options.task_map_path)?,
+        &options.task_map_path,
+    )?;
+    let out_dir = Path::new(&options.out_dir);
+
+    let mut users: Vec<_> = fs::read_dir(submissions_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    users.retain(|path| path.is_dir());
+    users.sort();
+
+    let mut standings = vec![];
+    let mut errors = 0;
+    for user_dir in users.iter() {
+        let user = user_dir
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+        let mut submissions = vec![];
+        for (name, task) in task_map.0.iter() {
+            let path = match find_script(user_dir, name)? {
+                Some(path) => path,
+                None => {
+                    submissions.push(Submission::Missing);
+                    continue;
+                }
+            };
+            match grade_path(*task, &path) {
+                Ok(report) => {
+                    println!(
+                        "{}: {} {}/{}, {} steps",
+                        path,
+                        report.verdict(),
+                        report.score,
+                        report.total,
+                        report.runtime
+                    );
+                    let report_dir = out_dir.join(&user);
+                    fs::create_dir_all(&report_dir)?;
+                    submissions.push(Submission::Graded(GradeSummary {
+                        correct: report.score,
+                        total: report.total,
+                        max_runtime: report.runtime,
+                        max_memory: report.memory,
+                    }));
+                    fs::write(
+                        report_dir.join(format!("{}.json", name)),
+                        report.into_json(),
+                    )?;
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    errors += 1;
+                    submissions.push(Submission::Invalid);
+                }
+            }
+        }
+        standings.push(Standing { user, submissions });
+    }
+
+    fs::create_dir_all(out_dir)?;
+    let leaderboard_path = out_dir.join(LEADERBOARD_PATH);
+    fs::write(
+        &leaderboard_path,
+        leaderboard_csv(&task_map, &mut standings),
+    )?;
+    println!(
+        "Graded {} users, {} scripts failed to grade; Leaderboard written to {}",
+        standings.len(),
+        errors,
+        leaderboard_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graded(correct: u64, max_runtime: i64, max_memory: i64) -> Submission {
+        Submission::Graded(GradeSummary {
+            correct,
+            total: 2,
+            max_runtime,
+            max_memory,
+        })
+    }
+
+    fn standing(user: &str, submissions: Vec<Submission>) -> Standing {
+        Standing {
+            user: user.to_string(),
+            submissions,
+        }
+    }
+
+    #[test]
+    fn task_maps_parse() {
+        let map = TaskMap::parse("mul = \"3\"\nadd = \"1\"\n", "tasks.toml").unwrap();
+        let names: Vec<_> = map.0.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["add", "mul"]);
+        assert_eq!(map.0[1].1, Task::from_str("3").unwrap());
+
+        for text in ["", "mul = 3", "mul = \"99\"", "= ="] {
+            let error = TaskMap::parse(text, "tasks.toml").unwrap_err();
+            assert!(
+                matches!(&error, WpkppError::InvalidConfig(message) if message.starts_with("tasks.toml: ")),
+                "{:?}: {}",
+                text,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn standings_rank_by_solved_then_runtime_then_memory() {
+        let mut standings = vec![
+            standing("slow", vec![graded(2, 50, 1), graded(2, 50, 1)]),
+            standing("fast", vec![graded(2, 10, 9), graded(2, 10, 9)]),
+            standing("lean", vec![graded(2, 10, 1), graded(2, 10, 1)]),
+            standing("one", vec![graded(2, 1, 1), graded(1, 1, 1)]),
+            standing("none", vec![Submission::Missing, Submission::Invalid]),
+            standing("also-lean", vec![graded(2, 10, 1), graded(2, 10, 1)]),
+        ];
+        let ranks = rank(&mut standings);
+        let users: Vec<_> = standings.iter().map(|s| s.user.as_str()).collect();
+        assert_eq!(users, ["also-lean", "lean", "fast", "slow", "one", "none"]);
+        assert_eq!(ranks, [1, 1, 3, 4, 5, 6]);
+
+        // Failed tasks count towards neither runtime nor memory
+        assert_eq!((standings[4].solved(), standings[4].runtime()), (1, 1));
+    }
+
+    #[test]
+    fn leaderboards_quote_fields() {
+        let task_map = TaskMap(vec![
+            ("mul".to_string(), Task::from_str("3").unwrap()),
+            ("a,b".to_string(), Task::from_str("1").unwrap()),
+        ]);
+        let mut standings = vec![
+            standing("say \"hi\"", vec![graded(2, 7, 3), Submission::Invalid]),
+            standing("bo", vec![graded(1, 4, 2), Submission::Missing]),
+        ];
+        assert_eq!(
+            leaderboard_csv(&task_map, &mut standings),
+            "rank,user,solved,runtime,memory,mul,\"a,b\"\n\
+             1,\"say \"\"hi\"\"\",1,7,3,OK 7,ERR\n\
+             2,bo,0,0,0,WA 4,\n"
+        );
+    }
+}
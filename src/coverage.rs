@@ -0,0 +1,127 @@
+use miniserde::{Deserialize, Serialize};
+
+use crate::{
+    parse::{format_location, format_source, Labels, SourceMap},
+    vm::{Coverage, Instruction, Instructions},
+};
+
+const MAX_PRINTED_REGIONS: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoverageRegion {
+    pub start: String,
+    pub end: String,
+    pub location: String,
+    pub source: Option<String>,
+    pub cdec: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoverageReport {
+    pub cdec_total: String,
+    pub cdec_never_taken: String,
+    pub cdec_always_taken: String,
+    pub cdec_mixed: String,
+    pub never_taken_regions: Vec<CoverageRegion>,
+}
+
+pub struct CoverageSummary {
+    pub cdec_total: u64,
+    pub cdec_never_taken: u64,
+    pub cdec_always_taken: u64,
+    pub cdec_mixed: u64,
+    /// Inclusive instruction index ranges where every CDEC executed with register=false,
+    /// paired with the number of CDECs inside the range
+    pub never_taken_regions: Vec<(usize, usize, u64)>,
+}
+
+impl CoverageSummary {
+    pub fn new(program: &Instructions, coverage: &Coverage) -> Self {
+        let mut summary = Self {
+            cdec_total: 0,
+            cdec_never_taken: 0,
+            cdec_always_taken: 0,
+            cdec_mixed: 0,
+            never_taken_regions: vec![],
+        };
+        let mut region: Option<(usize, usize, u64)> = None;
+
+        for (idx, instruction) in program.iter().enumerate() {
+            if let Instruction::Cdec(_) = instruction {
+                summary.cdec_total += 1;
+                let taken = coverage.register_true[idx];
+                let not_taken = coverage.register_false[idx];
+
+                match (taken, not_taken) {
+                    (0, _) => {
+                        summary.cdec_never_taken += 1;
+                        region = match region {
+                            Some((start, _, count)) => Some((start, idx, count + 1)),
+                            None => Some((idx, idx, 1)),
+                        };
+                    }
+                    (_, 0) => {
+                        summary.cdec_always_taken += 1;
+                        summary.never_taken_regions.extend(region.take());
+                    }
+                    _ => {
+                        summary.cdec_mixed += 1;
+                        summary.never_taken_regions.extend(region.take());
+                    }
+                }
+            }
+        }
+        summary.never_taken_regions.extend(region.take());
+
+        summary
+    }
+
+    /// `source` pairs the script path with its source map, to trace regions back to lines
+    pub fn to_report(&self, labels: &Labels, source: Option<(&str, &SourceMap)>) -> CoverageReport {
+        CoverageReport {
+            cdec_total: self.cdec_total.to_string(),
+            cdec_never_taken: self.cdec_never_taken.to_string(),
+            cdec_always_taken: self.cdec_always_taken.to_string(),
+            cdec_mixed: self.cdec_mixed.to_string(),
+            never_taken_regions: self
+                .never_taken_regions
+                .iter()
+                .map(|(start, end, count)| CoverageRegion {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                    location: format_location(labels, *start),
+                    source: source.and_then(|(path, map)| format_source(path, map, *start)),
+                    cdec: count.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn print(&self, labels: &Labels, source: Option<(&str, &SourceMap)>) {
+        println!(
+            "Coverage: CDEC {} / Never Taken {} / Always Taken {} / Mixed {}",
+            self.cdec_total, self.cdec_never_taken, self.cdec_always_taken, self.cdec_mixed
+        );
+        for (start, end, count) in self.never_taken_regions.iter().take(MAX_PRINTED_REGIONS) {
+            let from = match source.and_then(|(path, map)| format_source(path, map, *start)) {
+                Some(from) => format!(" from {}", from),
+                None => String::new(),
+            };
+            println!(
+                "  Never taken: instructions {}..={} ({} CDEC){}",
+                format_location(labels, *start),
+                format_location(labels, *end),
+                count,
+                from
+            );
+        }
+        if self.never_taken_regions.len() > MAX_PRINTED_REGIONS {
+            println!(
+                "  ... and {} more region(s)",
+                self.never_taken_regions.len() - MAX_PRINTED_REGIONS
+            );
+        }
+    }
+}
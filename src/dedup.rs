@@ -0,0 +1,59 @@
+//! `grade --seen-hashes`: Re-submissions of an already graded script, spotted by the
+//! canonical hash of its instructions.
+//!
+//! The hash file has one `<hash>  <path>` line per distinct script, the same lines
+//! `wpkpp canon --hash` prints, so it can be seeded from scripts graded earlier.
+
+use std::fs;
+use std::io::{self, Write};
+
+use crate::error::{Result, WpkppError};
+
+/// Where `grade` records the hashes of scripts it has seen, and what it does with repeats
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeenHashes {
+    pub path: String,
+    /// Fail instead of warning when a script was seen before
+    pub reject: bool,
+}
+
+/// Path of the script first recorded with `hash` in a hash file's text
+pub fn find_hash(text: &str, hash: u64) -> Option<&str> {
+    text.lines().find_map(|line| {
+        let (line_hash, path) = line.split_once(char::is_whitespace)?;
+        match u64::from_str_radix(line_hash, 16) {
+            Ok(line_hash) if line_hash == hash => Some(path.trim_start()),
+            _ => None,
+        }
+    })
+}
+
+impl SeenHashes {
+    /// Looks `hash` up, recording it when new; Warns about or rejects a script seen before,
+    /// returning the path it was first seen at
+    pub fn check(&self, hash: u64, script_path: &str) -> Result<Option<String>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(first_path) = find_hash(&text, hash) {
+            let message = format!(
+                "{} is a duplicate of {} (canonical hash {:016x})",
+                script_path, first_path, hash
+            );
+            if self.reject {
+                return Err(WpkppError::DuplicateSubmission(message));
+            }
+            eprintln!("Warning: {}", message);
+            return Ok(Some(first_path.to_string()));
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(format!("{:016x}  {}\n", hash, script_path).as_bytes())?;
+        Ok(None)
+    }
+}
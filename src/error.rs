@@ -0,0 +1,87 @@
+use std::io;
+use thiserror::Error;
+
+use crate::parse::{ParseError, MEGABYTE};
+
+/// Everything the library can fail with; The binary reports these through anyhow
+#[derive(Debug, Error)]
+pub enum WpkppError {
+    #[error("{0}")]
+    ParseError(#[from] ParseError),
+    /// A script over the size limit; `size` is unknown for streams cut off at the limit
+    #[error("{}", describe_too_large(.size, .limit))]
+    FileTooLarge { size: Option<u64>, limit: u64 },
+    #[error("Unknown task number {0}")]
+    UnknownTask(String),
+    #[error("Task {0} not implemented")]
+    TaskNotImplemented(String),
+    #[error("{0}")]
+    VmLimitExceeded(String),
+    #[error("{0}")]
+    IoError(io::Error),
+    #[error("{0}")]
+    InvalidContainer(String),
+    /// A compressed or optimized script which does not behave like its original
+    #[error("{0}")]
+    VerifyFailed(String),
+    /// Paths, formats or options which cannot be used together
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// A config file which cannot be read as settings
+    #[error("Invalid config {0}")]
+    InvalidConfig(String),
+    /// Checks over many scripts or lines which found problems, already printed
+    #[error("{0}")]
+    CheckFailed(String),
+    /// JSON which does not follow the `grade --json` schema
+    #[error("Invalid grade report: {0}")]
+    InvalidReport(String),
+    /// An `#assert` comment which did not hold when execution reached it
+    #[error("{0}")]
+    AssertionFailed(String),
+    /// A `;test` or `;layout` comment which cannot be read
+    #[error("Invalid test vector {0}")]
+    InvalidTestVector(String),
+    /// A `wpkpp record` file which cannot be replayed
+    #[error("Invalid recording: {0}")]
+    InvalidRecording(String),
+    /// A `wpkpp calibrate` file which cannot be read
+    #[error("Invalid calibration: {0}")]
+    InvalidCalibration(String),
+    /// A chat notification which could not be posted
+    #[error("{0}")]
+    NotifyFailed(String),
+    /// A script with the same canonical hash as one graded before
+    #[error("{0}")]
+    DuplicateSubmission(String),
+    /// Grading which was stopped before every testcase ran
+    #[error("Grading was cancelled")]
+    Cancelled,
+}
+
+pub type Result<T, E = WpkppError> = std::result::Result<T, E>;
+
+fn describe_too_large(size: &Option<u64>, limit: &u64) -> String {
+    let megabytes = |bytes: &u64| (*bytes as f64) / (MEGABYTE as f64);
+    match size {
+        Some(size) => format!(
+            "File size {:.2}/{:.2} MB is too large; try compressing your instructions",
+            megabytes(size),
+            megabytes(limit)
+        ),
+        None => format!(
+            "Script size is over {:.2} MB; try compressing your instructions",
+            megabytes(limit)
+        ),
+    }
+}
+
+/// Readers hand size limit errors back wrapped in io::Error, so unwrap those again
+impl From<io::Error> for WpkppError {
+    fn from(e: io::Error) -> Self {
+        match e.get_ref().is_some_and(|inner| inner.is::<WpkppError>()) {
+            true => *e.into_inner().unwrap().downcast::<WpkppError>().unwrap(),
+            false => Self::IoError(e),
+        }
+    }
+}
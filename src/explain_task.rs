@@ -0,0 +1,119 @@
+//! `wpkpp explain-task`: What a task asks for, with its memory layout and a worked example
+//! generated by the same code the grader uses, so it can never drift from the real testcases.
+
+use crate::{error::Result, task::Task};
+
+pub struct ExplainTaskOptions {
+    /// Testcase to show as the example
+    pub tc_id: i8,
+    /// Seed to generate the example from, instead of WPKPP_SEED
+    pub seed: Option<String>,
+}
+
+/// A value of a testcase with where it sits in memory
+struct Field {
+    name: &'static str,
+    value: u64,
+    start: u64,
+    width: u64,
+}
+
+/// A modulus as a power of two and an offset, e.g. "2^16 - 17"
+fn describe_modulus(modulus: u64) -> String {
+    let bits = u64::BITS - (modulus - 1).leading_zeros();
+    let below = (1_u128 << bits) - modulus as u128;
+    match below {
+        0 => format!("{} (2^{})", modulus, bits),
+        below => format!("{} (2^{} - {})", modulus, bits, below),
+    }
+}
+
+/// Bits of a value least significant first, as they sit in memory
+fn bits(value: u64, width: u64) -> String {
+    (0..width)
+        .map(|bit| match (value >> bit) & 1 {
+            0 => '0',
+            _ => '1',
+        })
+        .collect()
+}
+
+/// Prints a task's description, modulus, memory layout and an example testcase
+pub fn do_explain_task(task: Task, options: &ExplainTaskOptions) -> Result<()> {
+    let seed = options.seed.clone().unwrap_or_else(Task::seed);
+    let (input, output) = task.testcase_layout_seeded(&seed, options.tc_id)?;
+
+    let input_names: &[&'static str] = match input.len() {
+        1 => &["a"],
+        _ => &["a", "b", "c", "d"],
+    };
+    let mut fields = vec![];
+    let mut start = 0;
+    for (i, (value, width)) in input.iter().enumerate() {
+        fields.push(Field {
+            name: input_names.get(i).copied().unwrap_or("input"),
+            value: *value,
+            start,
+            width: *width,
+        });
+        start += width;
+    }
+    let input_len = fields.len();
+    for (value, width) in output.iter() {
+        fields.push(Field {
+            name: "output",
+            value: *value,
+            start,
+            width: *width,
+        });
+        start += width;
+    }
+    let cells = |field: &Field| format!("{}-{}", field.start, field.start + field.width - 1);
+    let cells_width = fields
+        .iter()
+        .map(|field| cells(field).len())
+        .max()
+        .unwrap_or(0);
+    let name_width = fields
+        .iter()
+        .map(|field| field.name.len())
+        .max()
+        .unwrap_or(0);
+
+    println!("Task {}: {}", task.name(), task.description());
+    if let Some(modulus) = task.modulus() {
+        println!("Reduction modulus: {}", describe_modulus(modulus));
+    }
+    println!();
+    println!("Memory layout, each value least significant bit first:");
+    for (i, field) in fields.iter().enumerate() {
+        let role = match i < input_len {
+            true => "input",
+            false => "written by the script",
+        };
+        println!(
+            "  cells {:>cells_width$}  {:<name_width$}  {} bit(s), {}",
+            cells(field),
+            field.name,
+            field.width,
+            role,
+            cells_width = cells_width,
+            name_width = name_width
+        );
+    }
+    println!("Every other cell starts at 0, with the pointer at cell 0 and the register at 0.");
+    println!();
+    println!("Example: testcase {} with seed {}", options.tc_id, seed);
+    for field in fields.iter() {
+        println!(
+            "  {:<name_width$} = {:<20} cells {:>cells_width$}  {}",
+            field.name,
+            format!("{} ({:#x})", field.value, field.value),
+            cells(field),
+            bits(field.value, field.width),
+            cells_width = cells_width,
+            name_width = name_width
+        );
+    }
+    Ok(())
+}
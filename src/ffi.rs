@@ -0,0 +1,197 @@
+//! C bindings, see `include/wpkpp.h`. Every function catches panics and reports failure
+//! through its return value, with a message left for `wpkpp_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::grade_script,
+    parse::{parse_script_bytes, Script, ScriptFormat},
+    task::Task,
+    util::ResetableTimer,
+    vm::Vm,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A parsed script, owned by the caller until passed to `wpkpp_program_free`
+pub struct WpkppProgram {
+    script: Script,
+    parse_time: f64,
+}
+
+/// Mirrors `RunResult` for C callers
+#[repr(C)]
+pub struct WpkppRunResult {
+    pub runtime: i64,
+    pub memory: i64,
+}
+
+fn set_last_error(message: String) {
+    // Messages never contain NUL bytes in practice, but don't let one hide the error
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning errors and panics into `on_error` with the message kept for
+/// `wpkpp_last_error`
+fn guard<T>(on_error: T, f: impl FnOnce() -> Result<T>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            on_error
+        }
+        Err(_) => {
+            set_last_error("wpkpp panicked".to_string());
+            on_error
+        }
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8]> {
+    match (data.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(WpkppError::InvalidArgument("Null buffer".to_string())),
+        (false, _) => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn c_str<'a>(text: *const c_char) -> Result<&'a str> {
+    if text.is_null() {
+        return Err(WpkppError::InvalidArgument("Null string".to_string()));
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|_| WpkppError::InvalidArgument("String is not valid UTF-8".to_string()))
+}
+
+unsafe fn program_ref<'a>(program: *const WpkppProgram) -> Result<&'a WpkppProgram> {
+    program
+        .as_ref()
+        .ok_or_else(|| WpkppError::InvalidArgument("Null program".to_string()))
+}
+
+/// Message of the last failed call on this thread, or NULL; Valid until the next failing call
+#[no_mangle]
+pub extern "C" fn wpkpp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Parses `len` bytes of script in `format` ("wpk", "wpkm" or "wpkb"); NULL on failure
+///
+/// # Safety
+/// `source` must point to `len` readable bytes and `format` to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wpkpp_parse(
+    source: *const u8,
+    len: usize,
+    format: *const c_char,
+) -> *mut WpkppProgram {
+    guard(ptr::null_mut(), || {
+        let format = c_str(format)?;
+        let format = ScriptFormat::from_name(format).ok_or_else(|| {
+            WpkppError::InvalidArgument(format!("Unknown script format \"{}\"", format))
+        })?;
+        let mut timer = ResetableTimer::new();
+        let script = parse_script_bytes(bytes(source, len)?, format)?;
+        let parse_time = timer.seconds_since();
+        Ok(Box::into_raw(Box::new(WpkppProgram { script, parse_time })))
+    })
+}
+
+/// # Safety
+/// `program` must come from `wpkpp_parse` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn wpkpp_program_free(program: *mut WpkppProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// Runs the program with `input` at the start of memory, then copies the first `output_len`
+/// bytes of memory into `output`; Bits are least significant first. Returns 0 on success.
+///
+/// # Safety
+/// `input` and `output` must point to `input_len` and `output_len` bytes, and `result` must be
+/// NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn wpkpp_run(
+    program: *const WpkppProgram,
+    input: *const u8,
+    input_len: usize,
+    output: *mut u8,
+    output_len: usize,
+    result: *mut WpkppRunResult,
+) -> c_int {
+    guard(-1, || {
+        let program = program_ref(program)?;
+        let input = bytes(input, input_len)?;
+        if output.is_null() && output_len > 0 {
+            return Err(WpkppError::InvalidArgument("Null buffer".to_string()));
+        }
+
+        let mut vm = Vm::new(program.script.instructions.clone());
+        vm.memory.as_raw_mut_slice()[..input.len()].copy_from_slice(input);
+        let run_stats = vm.run();
+
+        if output_len > 0 {
+            let memory = &vm.memory.as_raw_slice()[..output_len];
+            slice::from_raw_parts_mut(output, output_len).copy_from_slice(memory);
+        }
+        if let Some(result) = result.as_mut() {
+            *result = WpkppRunResult {
+                runtime: run_stats.runtime,
+                memory: run_stats.memory,
+            };
+        }
+        Ok(0)
+    })
+}
+
+/// Grades the program against `task` (e.g. "2a") on the testcases generated from `seed`,
+/// returning the same report as `wpkpp grade --json`; NULL on failure, otherwise free with
+/// `wpkpp_string_free`. A NULL `seed` uses WPKPP_SEED and WPKPP_SEED_NAMESPACE like the CLI.
+///
+/// # Safety
+/// `program` must come from `wpkpp_parse`, `task` must be a NUL-terminated string and `seed`
+/// either NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wpkpp_grade_json(
+    program: *const WpkppProgram,
+    task: *const c_char,
+    seed: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let program = program_ref(program)?;
+        let task = Task::from_str(c_str(task)?)?;
+        let seed = match seed.is_null() {
+            true => Task::seed(),
+            false => c_str(seed)?.to_string(),
+        };
+        let report = grade_script(task, &seed, &program.script, program.parse_time)?;
+        let report = CString::new(report.into_json()).unwrap();
+        Ok(report.into_raw())
+    })
+}
+
+/// Frees a string returned by wpkpp
+///
+/// # Safety
+/// `text` must come from `wpkpp_grade_json` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn wpkpp_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
@@ -0,0 +1,103 @@
+//! `grade --first-touch`: Which instruction first touches the input, the output and scratch
+//! memory in each testcase, and which LOADs read scratch cells nothing has written yet, i.e.
+//! count on memory starting clear.
+
+use std::collections::HashSet;
+
+use crate::{
+    grader::LoadedTestcase,
+    parse::{format_location, Labels},
+    vm::{cell_index, Instruction, Vm},
+};
+
+/// Part of memory a cell belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Input,
+    Output,
+    /// Every cell past the output
+    Scratch,
+}
+
+/// A LOAD of a scratch cell which no INV had written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwrittenRead {
+    /// Index of the LOAD instruction
+    pub idx: usize,
+    pub cell: usize,
+}
+
+/// Instructions which first touched each region in one testcase; Touching is reading with LOAD
+/// or writing with INV, as with `--memory-metric touched`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FirstTouch {
+    pub input: Option<usize>,
+    pub output: Option<usize>,
+    pub scratch: Option<usize>,
+    /// The first read of every scratch cell read before it was written, in the order they ran
+    pub unwritten_reads: Vec<UnwrittenRead>,
+}
+
+impl FirstTouch {
+    /// Runs a loaded testcase to completion, noting where each region is first touched
+    pub fn run(vm: &mut Vm, testcase: &LoadedTestcase) -> Self {
+        let (input_end, output_end) = testcase.regions();
+        let mut touch = Self::default();
+        let mut written: HashSet<usize> = HashSet::new();
+        let mut flagged: HashSet<usize> = HashSet::new();
+        while !vm.halted {
+            let idx = vm.intsruction_pointer;
+            let instruction = vm.program[idx];
+            if matches!(instruction, Instruction::Load | Instruction::Inv) {
+                let cell = cell_index(vm.memory_pointer.ptr);
+                let region = match cell {
+                    cell if cell < input_end => Region::Input,
+                    cell if cell < output_end => Region::Output,
+                    _ => Region::Scratch,
+                };
+                let first = match region {
+                    Region::Input => &mut touch.input,
+                    Region::Output => &mut touch.output,
+                    Region::Scratch => &mut touch.scratch,
+                };
+                first.get_or_insert(idx);
+                if region == Region::Scratch {
+                    match instruction {
+                        Instruction::Inv => {
+                            written.insert(cell);
+                        }
+                        _ if !written.contains(&cell) && flagged.insert(cell) => {
+                            touch.unwritten_reads.push(UnwrittenRead { idx, cell });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            vm.step();
+        }
+        touch
+    }
+
+    /// One line, e.g. "input 0, output 12 (@add+3), scratch never"
+    pub fn describe(&self, labels: &Labels) -> String {
+        let location = |idx: Option<usize>| match idx {
+            Some(idx) => format_location(labels, idx),
+            None => "never".to_string(),
+        };
+        let mut line = format!(
+            "input {}, output {}, scratch {}",
+            location(self.input),
+            location(self.output),
+            location(self.scratch)
+        );
+        if let Some(read) = self.unwritten_reads.first() {
+            line.push_str(&format!(
+                "; {} scratch cell(s) read before written, first cell {} by instruction {}",
+                self.unwritten_reads.len(),
+                read.cell,
+                format_location(labels, read.idx)
+            ));
+        }
+        line
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+
+use crate::{
+    error::{Result, WpkppError},
+    parse::{
+        parse_script_bytes, ScriptCompression, ScriptFormat, BLOCK_CLOSE_STR, BLOCK_OPEN_STR,
+        COMMENT_STR, COMMENT_STR_ALT, INV_M_STR, INV_M_STR_ALT, LABEL_END, LABEL_START, LOAD_M_STR,
+        LOAD_M_STR_ALT, REPEAT_STR,
+    },
+    seal::{is_sealed, seal_source, unseal_source},
+};
+
+pub const DEFAULT_WIDTH: usize = 80;
+const INDENT: &str = "    ";
+
+pub struct FmtOptions {
+    /// Column to wrap .wpkm output at
+    pub width: usize,
+}
+
+/// Canonical layout of a script: .wpk scripts get one instruction per line indented by
+/// REPEAT depth, .wpkm scripts are packed into lines of at most `width` characters.
+/// Formatting formatted output changes nothing.
+pub fn format_script(source: &str, format: ScriptFormat, width: usize) -> Result<String> {
+    // Only valid scripts are formatted, so the token passes below can trust their input
+    parse_script_bytes(source.as_bytes(), format)?;
+
+    match format {
+        ScriptFormat::Wpk => Ok(format_wpk(source)),
+        ScriptFormat::Wpkm => Ok(format_wpkm(source, width)),
+        ScriptFormat::Wpkb => Err(WpkppError::InvalidArgument(
+            ".wpkb scripts have no text to format".to_string(),
+        )),
+    }
+}
+
+fn format_wpk(source: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut depth: usize = 0;
+
+    for raw_line in source.lines() {
+        let (code, comment) = match raw_line.find([COMMENT_STR, COMMENT_STR_ALT]) {
+            Some(idx) => (&raw_line[..idx], Some(raw_line[idx..].trim_end())),
+            None => (raw_line, None),
+        };
+        let mut tokens = code.split_whitespace().collect::<Vec<_>>();
+
+        if tokens.is_empty() && comment.is_none() {
+            // Runs of blank lines collapse into one
+            if lines.last().is_some_and(|line| !line.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+
+        // Labels always go on their own line
+        while tokens
+            .first()
+            .is_some_and(|token| token.starts_with(LABEL_START))
+        {
+            lines.push(tokens.remove(0).to_string());
+        }
+
+        if tokens.as_slice() == [BLOCK_CLOSE_STR] {
+            depth = depth.saturating_sub(1);
+        }
+        let mut line = INDENT.repeat(depth);
+        line.push_str(&tokens.join(" "));
+        if let Some(comment) = comment {
+            if !tokens.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(comment);
+        }
+        if tokens.first() == Some(&REPEAT_STR) && tokens.last() == Some(&BLOCK_OPEN_STR) {
+            depth += 1;
+        }
+
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.iter().map(|line| line.to_string() + "\n").collect()
+}
+
+fn format_wpkm(source: &str, width: usize) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            COMMENT_STR | COMMENT_STR_ALT => {
+                let mut comment = c.to_string();
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    comment.push(c);
+                }
+                // Comments end the line they are on
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(comment.trim_end());
+                lines.push(std::mem::take(&mut line));
+                continue;
+            }
+            LABEL_START => {
+                let mut label = c.to_string();
+                for c in chars.by_ref() {
+                    label.push(c);
+                    if c == LABEL_END {
+                        break;
+                    }
+                }
+                label
+            }
+            '0'..='9' => {
+                // Repeat counts may be split by whitespace, which the parser skips over
+                let mut count = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || c.is_whitespace()) {
+                    if c.is_ascii_digit() {
+                        count.push(c);
+                    }
+                }
+                let count: u64 = count.parse().unwrap();
+                let op = chars.next().map(normalize_wpkm_char).unwrap();
+                format!("{}{}", count, op)
+            }
+            c if c.is_whitespace() => continue,
+            c => normalize_wpkm_char(c).to_string(),
+        };
+
+        if !line.is_empty() && line.chars().count() + token.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        line.push_str(&token);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines.iter().map(|line| line.to_string() + "\n").collect()
+}
+
+/// Replaces the alternate "v" / "^" spellings with "?" / "!"
+fn normalize_wpkm_char(c: char) -> char {
+    match c {
+        LOAD_M_STR_ALT => LOAD_M_STR,
+        INV_M_STR_ALT => INV_M_STR,
+        c => c,
+    }
+}
+
+/// Formats a .wpk / .wpkm script in place
+pub fn do_fmt(path: &str, options: &FmtOptions) -> Result<()> {
+    if ScriptCompression::split_path(path).1 != ScriptCompression::None {
+        return Err(WpkppError::InvalidArgument(format!(
+            "Compressed scripts cannot be formatted in place: {}",
+            path
+        )));
+    }
+    let format = ScriptFormat::from_path(path)
+        .ok_or_else(|| WpkppError::InvalidArgument(format!("Unknown file type {}", path)))?;
+
+    let source = fs::read_to_string(path)?;
+    // Sealed scripts are checked as written, then resealed to match their new layout
+    let formatted = match is_sealed(&source) {
+        true => {
+            parse_script_bytes(source.as_bytes(), format)?;
+            seal_source(&format_script(
+                &unseal_source(&source),
+                format,
+                options.width,
+            )?)
+        }
+        false => format_script(&source, format, options.width)?,
+    };
+
+    if formatted == source {
+        eprintln!("{} is already formatted", path);
+    } else {
+        fs::write(path, formatted)?;
+        eprintln!("Formatted {}", path);
+    }
+    Ok(())
+}
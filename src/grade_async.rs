@@ -0,0 +1,149 @@
+use std::panic;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::{check_testcase, load_testcase_seeded, GradeSummary, TESTCASES},
+    report::CaseResult,
+    task::Task,
+    vm::{Instructions, Vm},
+};
+
+/// Steps run between checks of the cancellation flag, so a dropped future stops even a
+/// testcase which would take minutes
+const CANCEL_CHECK_STEPS: u64 = 1 << 16;
+
+/// Raises the flag when the grading future is dropped, so the blocking task stops as well
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Grades `instructions` against the testcases of `task` generated from `seed` on tokio's
+/// blocking thread pool.
+///
+/// Each testcase result is sent to `progress` as soon as it is known; wrap the receiver in
+/// `tokio_stream::wrappers::UnboundedReceiverStream` to use it as a `Stream`. Dropping the
+/// returned future (e.g. losing a `tokio::select!` or timing out) stops grading within
+/// `CANCEL_CHECK_STEPS` steps, even part way through a testcase.
+pub async fn grade_async(
+    task: Task,
+    seed: String,
+    instructions: Instructions,
+    progress: Option<UnboundedSender<CaseResult>>,
+) -> Result<GradeSummary> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let _guard = CancelOnDrop(cancelled.clone());
+
+    let grading = tokio::task::spawn_blocking(move || {
+        grade_blocking(task, &seed, instructions, progress, &cancelled)
+    });
+
+    match grading.await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(WpkppError::Cancelled),
+    }
+}
+
+/// Body of the blocking task; Returns `Cancelled` as soon as `cancelled` is seen raised
+fn grade_blocking(
+    task: Task,
+    seed: &str,
+    instructions: Instructions,
+    progress: Option<UnboundedSender<CaseResult>>,
+    cancelled: &AtomicBool,
+) -> Result<GradeSummary> {
+    let mut vm = Vm::new(instructions);
+    let mut summary = GradeSummary::default();
+    for tc_id in 0..TESTCASES as i8 {
+        let testcase = load_testcase_seeded(task, seed, &mut vm, tc_id)?;
+        let keep_going = || !cancelled.load(Ordering::Relaxed);
+        if vm.run_while(CANCEL_CHECK_STEPS, keep_going).is_none() || !keep_going() {
+            return Err(WpkppError::Cancelled);
+        }
+
+        let result = check_testcase(&vm, &testcase);
+        summary.add(&result);
+        if let Some(progress) = progress.as_ref() {
+            // Nobody listening is fine, the summary is still returned
+            let _ = progress.send(result);
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::vm::Instruction;
+
+    const SEED: &str = "grade-async";
+
+    /// A script taking far longer than the tests wait on every testcase
+    fn slow_script() -> Instructions {
+        vec![Instruction::Load; 20_000_000].into()
+    }
+
+    #[tokio::test]
+    async fn progress_arrives_in_testcase_order() {
+        let (progress, mut results) = unbounded_channel();
+        let summary = grade_async(
+            Task::ZeroXor,
+            SEED.to_string(),
+            Instructions::new(),
+            Some(progress),
+        )
+        .await
+        .unwrap();
+
+        let mut tc_ids = vec![];
+        while let Some(result) = results.recv().await {
+            tc_ids.push(result.tc_id);
+        }
+        assert_eq!(tc_ids, (0..TESTCASES as i8).collect::<Vec<_>>());
+        assert_eq!(summary.total, TESTCASES as u64);
+    }
+
+    #[test]
+    fn raised_flag_stops_grading_part_way_through_a_testcase() {
+        let (progress, mut results) = unbounded_channel();
+        let cancelled = AtomicBool::new(true);
+        let graded = grade_blocking(
+            Task::ZeroXor,
+            SEED,
+            slow_script(),
+            Some(progress),
+            &cancelled,
+        );
+        assert!(matches!(graded, Err(WpkppError::Cancelled)), "{:?}", graded);
+        assert!(results.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn dropping_the_future_stops_the_worker() {
+        let (progress, mut results) = unbounded_channel();
+        let grading = grade_async(
+            Task::ZeroXor,
+            SEED.to_string(),
+            slow_script(),
+            Some(progress),
+        );
+        // Timing out drops the future long before the first testcase could finish
+        let timed_out = tokio::time::timeout(Duration::from_millis(10), grading).await;
+        assert!(timed_out.is_err());
+
+        // The worker drops its sender once it stops, without finishing a testcase
+        assert!(results.recv().await.is_none());
+    }
+}
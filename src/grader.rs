@@ -1,141 +1,852 @@
-use anyhow::Result;
+use bitvec::prelude::*;
+#[cfg(feature = "cli")]
 use colored::Colorize;
-use miniserde::{json, Deserialize, Serialize};
+use miniserde::{Deserialize, Serialize};
+use rand::{rngs::StdRng, Rng};
+use rand_seeder::Seeder;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
 use std::io;
 use std::{cmp::max, io::Write};
+use tracing::{debug, info, info_span};
 
 use crate::{
-    parse::parse_file,
+    assertion::{AssertChecker, AssertFailure},
+    canon::canonical_hash,
+    coverage::{CoverageReport, CoverageSummary},
+    dedup::SeenHashes,
+    error::{Result, WpkppError},
+    first_touch::FirstTouch,
+    html_report::render_report,
+    notify::Notifier,
+    parse::{format_location, parse_script_with, Labels, ParseOptions, Script, ScriptFormat},
+    report::{CaseResult, GradeReport, RuntimeAggregates, RuntimePercentiles, Timings, Verdict},
+    runlog::{append_record, RunLog, RunLogRecord},
+    stats::{Complexity, ComplexityReport},
     task::Task,
-    util::ResetableTimer,
-    vm::{Vm, WpkOpcount},
+    util::{fnv1a, InstructionCount, ResetableTimer, FNV_OFFSET},
+    vm::{cell_index, Instructions, MemoryMetric, Vm, VmUsize, MEMORY_BITS},
+    window::{self, ContestWindow},
 };
 
 #[derive(Serialize, Deserialize, Debug)]
-struct InstructionCount {
-    inc: String,
-    cdec: String,
-    load: String,
-    inv: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeTaken {
+    pub parse: f64,
+    pub vm: f64,
+    pub grade: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct TimeTaken {
-    parse: f64,
-    vm: f64,
-    grade: f64,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TranscriptCase {
+    pub tc_id: String,
+    pub hashes: Vec<String>,
 }
 
+/// Report printed by `grade --json`
 #[derive(Serialize, Deserialize, Debug)]
-struct GradeResult {
-    verdict: String,
-    score: String,
-    total: String,
-    runtime: String,
-    memory: String,
-    instructions: InstructionCount,
-    time_taken: TimeTaken,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GradeResult {
+    pub verdict: String,
+    pub score: String,
+    pub total: String,
+    pub runtime: String,
+    /// Mean, median and total runtime over the testcases
+    pub runtime_mean: Option<String>,
+    pub runtime_median: Option<String>,
+    pub runtime_total: Option<String>,
+    /// Runtime percentiles over the testcases, by nearest rank
+    pub runtime_p50: Option<String>,
+    pub runtime_p90: Option<String>,
+    pub runtime_p99: Option<String>,
+    pub memory: String,
+    pub instructions: InstructionCount,
+    pub time_taken: TimeTaken,
+    pub coverage: Option<CoverageReport>,
+    pub transcript: Option<Vec<TranscriptCase>>,
+    pub meta: Option<BTreeMap<String, String>>,
+    /// Canonical hash of the script, as printed by `wpkpp canon --hash`
+    pub hash: Option<String>,
+    /// Ceiling passed to `--max-instructions`
+    pub max_instructions: Option<String>,
+    /// Static complexity metrics, with `--complexity`
+    pub complexity: Option<ComplexityReport>,
+    /// Graded on every possible input rather than seeded testcases, with `--exhaustive`
+    pub exhaustive: Option<bool>,
+    /// How `memory` was measured, "span" or "touched"; None in reports from older versions,
+    /// which always measured the span
+    pub memory_metric: Option<String>,
+    /// Testcases failed unless memory past the output was clear, with `--strict-clean`
+    pub strict_clean: Option<bool>,
+    /// Memory other than the input started with random bits, with `--dirty-scratch`
+    pub dirty_scratch: Option<bool>,
 }
 
-pub fn do_grade(task: Task, wpk_path: &str, progress: bool, color: bool, json: bool) -> Result<()> {
+/// Testcases in every task
+pub const TESTCASES: usize = 100;
+
+/// Scores over the testcases run so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GradeSummary {
+    pub correct: u64,
+    pub total: u64,
+    pub max_runtime: i64,
+    pub max_memory: i64,
+}
+
+impl GradeSummary {
+    pub fn add(&mut self, result: &CaseResult) {
+        self.max_runtime = max(self.max_runtime, result.runtime);
+        self.max_memory = max(self.max_memory, result.memory);
+        self.total += 1;
+        if result.correct {
+            self.correct += 1;
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.correct == self.total
+    }
+}
+
+/// A testcase loaded into the VM, waiting to be run
+pub struct LoadedTestcase {
+    pub tc_id: i8,
+    input_len: usize,
+    answer: BitVec<u8>,
+}
+
+impl LoadedTestcase {
+    /// Ends of the input, which starts at cell 0, and of the output right after it
+    pub fn regions(&self) -> (usize, usize) {
+        (self.input_len, self.input_len + self.answer.len())
+    }
+
+    /// Expected value of output bit `bit`, if the output has that many bits
+    pub fn answer_bit(&self, bit: usize) -> Option<bool> {
+        self.answer.get(bit).map(|bit| *bit)
+    }
+}
+
+/// Resets `vm` and writes the input of testcase `tc_id` into its memory, generating it from the
+/// seed the command line defaults to
+pub fn load_testcase(task: Task, vm: &mut Vm, tc_id: i8) -> Result<LoadedTestcase> {
+    load_testcase_seeded(task, &Task::seed(), vm, tc_id)
+}
+
+/// Like `load_testcase`, generating the testcase from `seed` instead of WPKPP_SEED
+pub fn load_testcase_seeded(
+    task: Task,
+    seed: &str,
+    vm: &mut Vm,
+    tc_id: i8,
+) -> Result<LoadedTestcase> {
+    let (input_mem, answer) = task.load_tc_seeded(seed, tc_id)?;
+    Ok(load_testcase_memory(vm, tc_id, &input_mem, answer))
+}
+
+/// Resets `vm` and writes an already generated input into its memory, e.g. one of
+/// `Task::exhaustive_testcases`
+pub fn load_testcase_memory(
+    vm: &mut Vm,
+    tc_id: i8,
+    input_mem: &BitSlice<u8>,
+    answer: BitVec<u8>,
+) -> LoadedTestcase {
+    vm.reset();
+    vm.memory[0..input_mem.len()].copy_from_bitslice(input_mem);
+    LoadedTestcase {
+        tc_id,
+        input_len: input_mem.len(),
+        answer,
+    }
+}
+
+/// Runs a loaded testcase and checks the VM's output against its answer, hashing memory every
+/// `hash_every` steps if given
+pub fn run_testcase(
+    vm: &mut Vm,
+    testcase: &LoadedTestcase,
+    hash_every: Option<i64>,
+) -> (CaseResult, Option<Vec<u64>>) {
+    let hashes = match hash_every {
+        Some(every) => Some(vm.run_hashed(every).1),
+        None => {
+            vm.run();
+            None
+        }
+    };
+    (check_testcase(vm, testcase), hashes)
+}
+
+/// Checks the output of a halted VM against a loaded testcase's answer
+pub fn check_testcase(vm: &Vm, testcase: &LoadedTestcase) -> CaseResult {
+    let run_stats = vm.run_result();
+    let output_start = testcase.input_len;
+    let output_mem = &vm.memory[output_start..(output_start + testcase.answer.len())];
+    let result = CaseResult {
+        tc_id: testcase.tc_id,
+        correct: output_mem == testcase.answer,
+        runtime: run_stats.runtime,
+        memory: run_stats.memory,
+    };
+    debug!(
+        tc_id = result.tc_id,
+        correct = result.correct,
+        runtime = result.runtime,
+        memory = result.memory,
+        "Ran testcase"
+    );
+    result
+}
+
+/// String the RNG filling a testcase's scratch memory is seeded with, e.g.
+/// "WPKPP/NOSEED/FiveMul16Mod/17/scratch"
+pub fn scratch_seed(task: Task, seed: &str, tc_id: i8) -> String {
+    format!("{}/scratch", task.testcase_seed(seed, tc_id))
+}
+
+/// Fills every cell the script could reach, other than the input, with random bits generated
+/// from `seed`, so that scripts counting on memory starting clear go wrong
+pub fn fill_scratch(vm: &mut Vm, testcase: &LoadedTestcase, seed: &str) {
+    let mut rng: StdRng = Seeder::from(seed).make_rng();
+    let (inc, cdec, _, _) = vm.program.opcount();
+    let input = vm.memory[..testcase.input_len].to_bitvec();
+    let bytes = vm.memory.as_raw_mut_slice();
+    match inc + cdec + 1 {
+        reach if reach >= MEMORY_BITS as u64 => rng.fill(bytes),
+        reach => {
+            // Every CDEC moving back and every INC forward is as far as the pointer can get, and
+            // memory wraps around
+            let lowest = cell_index((cdec as VmUsize).wrapping_neg());
+            let count = (reach as usize).div_ceil(8) + 1;
+            for i in 0..count.min(bytes.len()) {
+                let byte = (lowest / 8 + i) % bytes.len();
+                bytes[byte] = rng.gen();
+            }
+        }
+    }
+    vm.memory[..testcase.input_len].copy_from_bitslice(&input);
+}
+
+/// First cell past the output region left set by a halted VM; The input may be left as the
+/// script likes, and every other cell started clear
+pub fn first_dirty_cell(vm: &Vm, testcase: &LoadedTestcase) -> Option<usize> {
+    let scratch_start = testcase.input_len + testcase.answer.len();
+    // Only cells the pointer reached can have been written, which may wrap around memory
+    let pointer = &vm.memory_pointer;
+    let span = (pointer.ptr_ub - pointer.ptr_lb + 1) as usize;
+    let (wrapped, reached) = match span >= MEMORY_BITS {
+        true => (0..0, 0..MEMORY_BITS),
+        false => {
+            let lowest = cell_index(pointer.ptr_lb as VmUsize);
+            let end = lowest + span;
+            (
+                0..end.saturating_sub(MEMORY_BITS),
+                lowest..end.min(MEMORY_BITS),
+            )
+        }
+    };
+    [wrapped, reached].into_iter().find_map(|cells| {
+        let start = cells.start.max(scratch_start);
+        match start < cells.end {
+            true => vm.memory[start..cells.end]
+                .first_one()
+                .map(|cell| start + cell),
+            false => None,
+        }
+    })
+}
+
+/// Runs every testcase of `task` generated from `seed` on `vm`, handing each result to
+/// `on_testcase`; Stops early, keeping the testcases run so far, once it returns false
+pub fn grade_vm(
+    task: Task,
+    seed: &str,
+    vm: &mut Vm,
+    mut on_testcase: impl FnMut(&CaseResult) -> bool,
+) -> Result<GradeSummary> {
+    let mut summary = GradeSummary::default();
+    for tc_id in 0..TESTCASES as i8 {
+        let testcase = load_testcase_seeded(task, seed, vm, tc_id)?;
+        let (result, _) = run_testcase(vm, &testcase, None);
+        summary.add(&result);
+        if !on_testcase(&result) {
+            break;
+        }
+    }
+    Ok(summary)
+}
+
+/// Grades an already parsed script on the testcases generated from `seed`, building the
+/// `grade --json` report without coverage or transcripts
+pub fn grade_script(
+    task: Task,
+    seed: &str,
+    script: &Script,
+    parse_time: f64,
+) -> Result<GradeReport> {
+    let mut timer = ResetableTimer::new();
+    let instructions = &script.instructions;
+    let mut vm = Vm::new(instructions.clone());
+    let vm_time = timer.seconds_since();
+    let mut runtimes = Vec::with_capacity(TESTCASES);
+    let summary = grade_vm(task, seed, &mut vm, |result| {
+        runtimes.push(result.runtime);
+        true
+    })?;
+    let grade_time = timer.seconds_since();
+
+    Ok(GradeReport {
+        passed: summary.passed(),
+        score: summary.correct,
+        total: summary.total,
+        runtime: summary.max_runtime,
+        runtime_aggregates: RuntimeAggregates::new(&runtimes),
+        runtime_percentiles: RuntimePercentiles::new(&runtimes),
+        memory: summary.max_memory,
+        instructions: instructions.opcount().into(),
+        timings: Timings {
+            parse: parse_time,
+            vm: vm_time,
+            grade: grade_time,
+        },
+        coverage: None,
+        transcript: None,
+        meta: script.meta.as_ref().map(|meta| meta.to_map()),
+        hash: Some(canonical_hash(instructions)),
+        max_instructions: None,
+        late: false,
+        complexity: None,
+        exhaustive: false,
+        memory_metric: MemoryMetric::Span,
+        strict_clean: false,
+        dirty_scratch: false,
+    })
+}
+
+/// What `grade` prints for each verdict, e.g. to match the wording a judge or bot expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerdictStrings {
+    pub ok: String,
+    pub wrong_answer: String,
+    pub time_limit_exceeded: String,
+    pub memory_limit_exceeded: String,
+    /// Follow each verdict with an emoji
+    pub emoji: bool,
+}
+
+impl Default for VerdictStrings {
+    fn default() -> Self {
+        Self {
+            ok: "OK".to_string(),
+            wrong_answer: "WA".to_string(),
+            time_limit_exceeded: "TLE".to_string(),
+            memory_limit_exceeded: "MLE".to_string(),
+            emoji: true,
+        }
+    }
+}
+
+impl VerdictStrings {
+    pub fn get_mut(&mut self, verdict: Verdict) -> &mut String {
+        match verdict {
+            Verdict::Ok => &mut self.ok,
+            Verdict::WrongAnswer => &mut self.wrong_answer,
+            Verdict::TimeLimitExceeded => &mut self.time_limit_exceeded,
+            Verdict::MemoryLimitExceeded => &mut self.memory_limit_exceeded,
+        }
+    }
+
+    pub fn render(&self, verdict: Verdict) -> String {
+        let (text, emoji) = match verdict {
+            Verdict::Ok => (&self.ok, "🎉"),
+            Verdict::WrongAnswer => (&self.wrong_answer, "❌"),
+            Verdict::TimeLimitExceeded => (&self.time_limit_exceeded, "⏰"),
+            Verdict::MemoryLimitExceeded => (&self.memory_limit_exceeded, "💾"),
+        };
+        match self.emoji {
+            true => format!("{} {}", text, emoji),
+            false => text.clone(),
+        }
+    }
+
+    /// Verdict of a whole grade; A late script is LATE whatever its score, as in `grade --json`
+    pub fn render_report(&self, report: &GradeReport) -> String {
+        match (report.late, report.passed) {
+            (true, _) => "LATE (submitted outside the contest window)".to_string(),
+            (false, true) => self.render(Verdict::Ok),
+            (false, false) => self.render(Verdict::WrongAnswer),
+        }
+    }
+}
+
+pub struct GradeOptions {
+    pub progress: bool,
+    pub color: bool,
+    pub json: bool,
+    pub coverage: bool,
+    /// Hash the touched memory region every N steps of each testcase
+    pub hash_every: Option<i64>,
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Largest script file in bytes, replacing the 10 MB / 5 MB defaults
+    pub max_file_size: Option<u64>,
+    /// Read scripts which are not valid UTF-8 as Latin-1
+    pub latin1: bool,
+    /// Threads for parsing large .wpkm scripts; None uses every core
+    pub jobs: Option<usize>,
+    /// Append a JSON line describing the run to a log file
+    pub log: Option<RunLog>,
+    /// Verdict text for the plain output; `--json` always uses the short codes
+    pub verdicts: VerdictStrings,
+    /// Check `#assert` comments, stopping at the first which does not hold
+    pub assert: bool,
+    /// Write a self-contained HTML report to this path
+    pub html: Option<String>,
+    /// Print a markdown badge instead of the plain output
+    pub badge: bool,
+    /// Post a summary to a chat webhook once grading finishes
+    pub notify: Option<Notifier>,
+    /// Record the script's canonical hash, flagging or rejecting scripts seen before
+    pub seen_hashes: Option<SeenHashes>,
+    /// Reject scripts with more compressed instructions or total steps than this before running
+    /// them
+    pub max_instructions: Option<u64>,
+    /// Refuse tasks outside this contest, and mark submissions outside its window as late
+    pub contest: Option<ContestWindow>,
+    /// Include static complexity metrics of the script
+    pub complexity: bool,
+    /// Draw the runtime and memory of every testcase as sparklines in the plain output
+    pub sparkline: bool,
+    /// Write the runtime and memory of every testcase to this CSV file
+    pub series: Option<String>,
+    /// Grade on every possible input instead of the seeded testcases, for tasks with few enough
+    /// input bits
+    pub exhaustive: bool,
+    /// How memory usage is measured
+    pub memory_metric: MemoryMetric,
+    /// Print the seed of each failed testcase and a command running just that testcase
+    pub show_seeds: bool,
+    /// Fail testcases which leave any cell past the output set
+    pub strict_clean: bool,
+    /// Start every testcase with random bits in every cell but the input
+    pub dirty_scratch: bool,
+    /// Print which instruction first touches the input, output and scratch memory in each
+    /// testcase
+    pub first_touch: bool,
+    /// Reuse the instructions of an earlier parse of the same script from the parse cache
+    pub cache: bool,
+    /// Seed testcases are generated from; `Task::seed()` on the command line, or
+    /// `Task::namespaced_seed(seed, user)` for each user of a server
+    pub seed: String,
+}
+
+/// Fails when the compressed script, or the steps it takes on every testcase, are over `limit`
+pub fn check_instruction_limit(instructions: &Instructions, limit: u64) -> Result<()> {
+    let count = instructions.len() as u64;
+    if count > limit {
+        return Err(WpkppError::VmLimitExceeded(format!(
+            "Script has {} instructions, over the limit of {}",
+            count, limit
+        )));
+    }
+    let steps = instructions.total_steps();
+    if steps > limit {
+        return Err(WpkppError::VmLimitExceeded(format!(
+            "Script takes {} steps, over the limit of {}",
+            steps, limit
+        )));
+    }
+    Ok(())
+}
+
+/// Error for an assertion which failed in a testcase
+pub fn assertion_failed(tc_id: i8, labels: &Labels, failure: AssertFailure) -> WpkppError {
+    WpkppError::AssertionFailed(format!(
+        "Testcase {}, instruction {}: {}",
+        tc_id,
+        format_location(labels, failure.idx),
+        failure
+    ))
+}
+
+/// Colors a verdict green or red; Without the `cli` feature, text is never colored
+#[cfg(feature = "cli")]
+fn paint(text: &str, ok: bool, color: bool) -> String {
+    match (color, ok) {
+        (false, _) => text.to_string(),
+        (true, true) => text.green().to_string(),
+        (true, false) => text.red().to_string(),
+    }
+}
+
+#[cfg(not(feature = "cli"))]
+fn paint(text: &str, _ok: bool, _color: bool) -> String {
+    text.to_string()
+}
+
+/// Quotes a word for POSIX shells, unless it is plain enough to paste as is
+fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c);
+    match !word.is_empty() && word.chars().all(plain) {
+        true => word.to_string(),
+        false => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
+
+/// Bars of a sparkline, lowest first
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One bar per testcase, scaled between the smallest and largest value; Wrong testcases are
+/// drawn in red
+fn sparkline(cases: &[CaseResult], value: fn(&CaseResult) -> i64, color: bool) -> String {
+    let low = cases.iter().map(value).min().unwrap_or(0);
+    let high = cases.iter().map(value).max().unwrap_or(0);
+    let top = SPARK_LEVELS.len() as i128 - 1;
+    let bars: String = cases
+        .iter()
+        .map(|case| {
+            let level = match high - low {
+                0 => 0,
+                range => (value(case) - low) as i128 * top / range as i128,
+            };
+            let bar = SPARK_LEVELS[level as usize].to_string();
+            match case.correct {
+                true => bar,
+                false => paint(&bar, false, color),
+            }
+        })
+        .collect();
+    format!("{} {} to {}", bars, low, high)
+}
+
+/// `testcase,correct,runtime,memory` and a row per testcase, ready for a spreadsheet or gnuplot
+fn series_csv(cases: &[CaseResult]) -> String {
+    let mut csv = String::from("testcase,correct,runtime,memory\n");
+    for case in cases {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            case.tc_id, case.correct as u8, case.runtime, case.memory
+        ));
+    }
+    csv
+}
+
+pub fn do_grade(task: Task, wpk_path: &str, options: &GradeOptions) -> Result<()> {
+    let GradeOptions {
+        progress,
+        color,
+        json,
+        coverage,
+        hash_every,
+        format,
+        max_file_size,
+        latin1,
+        jobs,
+        ref log,
+        ref verdicts,
+        assert,
+        ref html,
+        badge,
+        ref notify,
+        ref seen_hashes,
+        max_instructions,
+        ref contest,
+        complexity,
+        sparkline: show_sparkline,
+        ref series,
+        exhaustive,
+        memory_metric,
+        show_seeds,
+        strict_clean,
+        dirty_scratch,
+        first_touch,
+        cache,
+        ref seed,
+    } = *options;
+    if strict_clean && dirty_scratch {
+        return Err(WpkppError::InvalidArgument(
+            "Scratch memory cannot be checked clear when it starts dirty".to_string(),
+        ));
+    }
+    if assert && hash_every.is_some() {
+        return Err(WpkppError::InvalidArgument(
+            "Assertions cannot be checked while hashing memory".to_string(),
+        ));
+    }
+    if first_touch && (assert || hash_every.is_some()) {
+        return Err(WpkppError::InvalidArgument(
+            "First touches cannot be tracked while checking assertions or hashing memory"
+                .to_string(),
+        ));
+    }
+    if show_seeds && exhaustive {
+        return Err(WpkppError::InvalidArgument(
+            "Exhaustive testcases are not generated from a seed".to_string(),
+        ));
+    }
+    let exhaustive_testcases = match exhaustive {
+        true => Some(task.exhaustive_testcases().ok_or_else(|| {
+            WpkppError::InvalidArgument(format!(
+                "Task {} has too many possible inputs to grade exhaustively",
+                task.name()
+            ))
+        })?),
+        false => None,
+    };
+    let _span = info_span!("grade", task = ?task, path = wpk_path).entered();
+    let submitted = window::now();
+    if let Some(contest) = contest {
+        contest.check_task(task)?;
+    }
+    let late = contest
+        .as_ref()
+        .is_some_and(|contest| contest.is_late(submitted));
+
     let mut timer = ResetableTimer::new();
     let mut parse_time: f64 = 0.0;
     let mut vm_time: f64 = 0.0;
     let mut grade_time: f64 = 0.0;
 
-    let instructions = parse_file(wpk_path, true)?;
+    // Source maps are only needed to trace coverage regions back to the script
+    let parse_options = ParseOptions {
+        source_map: coverage,
+        max_file_size,
+        latin1,
+        jobs,
+        cache,
+        ..Default::default()
+    };
+    let script = parse_script_with(wpk_path, format, true, parse_options)?;
+    let instructions = script.instructions;
+    let labels = script.labels;
+    let source_map = script.source_map;
+    let meta = script.meta;
+    let assertions = script.assertions;
+    let source = source_map.as_ref().map(|map| (wpk_path, map));
     let opcounts = instructions.opcount();
+    let hash = canonical_hash(&instructions);
+    let complexity = complexity.then(|| Complexity::new(&instructions));
+    if let Some(limit) = max_instructions {
+        check_instruction_limit(&instructions, limit)?;
+    }
+    if let Some(seen_hashes) = seen_hashes {
+        seen_hashes.check(hash, wpk_path)?;
+    }
 
     parse_time += timer.seconds_since();
+    info!(seconds = parse_time, "Parsed script");
 
     let mut vm = Vm::new(instructions);
+    if coverage {
+        vm.enable_coverage();
+    }
+    vm.set_memory_metric(memory_metric);
 
     vm_time += timer.seconds_since();
+    info!(seconds = vm_time, "Set up VM");
 
-    let mut max_runtime: i64 = 0;
-    let mut max_memory: i64 = 0;
-    let mut total: u64 = 0;
-    let mut correct: u64 = 0;
+    let mut summary = GradeSummary::default();
+    let mut transcript: Vec<TranscriptCase> = vec![];
+    let mut transcript_hash = FNV_OFFSET;
+    let mut cases: Vec<CaseResult> = vec![];
+    let mut runtimes: Vec<i64> = Vec::with_capacity(TESTCASES);
+    let mut failed: Vec<i8> = vec![];
+    let mut dirty: Vec<(i8, usize)> = vec![];
+    let mut touches: Vec<(i8, FirstTouch)> = vec![];
 
-    for tc_id in 0..100 {
-        let (input_mem, ans_mem) = task.load_tc(tc_id)?;
-        vm.reset();
-        vm.memory[0..input_mem.len()].copy_from_bitslice(&input_mem);
+    let testcase_count = exhaustive_testcases.as_ref().map_or(TESTCASES, Vec::len);
+    for tc_id in 0..testcase_count as i8 {
+        let testcase = match exhaustive_testcases.as_ref() {
+            Some(testcases) => {
+                let (input_mem, answer) = &testcases[tc_id as usize];
+                load_testcase_memory(&mut vm, tc_id, input_mem, answer.clone())
+            }
+            None => load_testcase_seeded(task, seed, &mut vm, tc_id)?,
+        };
+        if dirty_scratch {
+            fill_scratch(&mut vm, &testcase, &scratch_seed(task, seed, tc_id));
+        }
         vm_time += timer.seconds_since();
 
-        let run_stats = vm.run();
-
-        let output_mem = &vm.memory[input_mem.len()..(input_mem.len() + ans_mem.len())];
-
-        let res = output_mem == ans_mem;
-
-        max_runtime = max(max_runtime, run_stats.runtime);
-        max_memory = max(max_memory, run_stats.memory);
-
-        total += 1;
-        if res {
-            correct += 1;
+        let (mut result, hashes) = match (assert, first_touch) {
+            (true, _) => {
+                AssertChecker::new(&assertions)
+                    .run(&mut vm)
+                    .map_err(|failure| assertion_failed(tc_id, &labels, failure))?;
+                (check_testcase(&vm, &testcase), None)
+            }
+            (false, true) => {
+                touches.push((tc_id, FirstTouch::run(&mut vm, &testcase)));
+                (check_testcase(&vm, &testcase), None)
+            }
+            (false, false) => run_testcase(&mut vm, &testcase, hash_every),
+        };
+        if strict_clean {
+            if let Some(cell) = first_dirty_cell(&vm, &testcase) {
+                result.correct = false;
+                dirty.push((tc_id, cell));
+            }
+        }
+        if let Some(hashes) = hashes {
+            transcript_hash = fnv1a(transcript_hash, &hashes.last().unwrap().to_le_bytes());
+            transcript.push(TranscriptCase {
+                tc_id: tc_id.to_string(),
+                hashes: hashes.iter().map(|h| format!("{:016x}", h)).collect(),
+            });
+        }
+        summary.add(&result);
+        runtimes.push(result.runtime);
+        if !result.correct {
+            failed.push(tc_id);
+        }
+        if log.is_some() || html.is_some() || show_sparkline || series.is_some() {
+            cases.push(result);
         }
 
-        if progress && !json {
-            let mut res_text = match res {
-                true => "O".green(),
-                false => "X".red(),
+        if progress && !json && !badge {
+            let res_text = match result.correct {
+                true => paint("O", true, color),
+                false => paint("X", false, color),
             };
-            if !color {
-                res_text = res_text.clear();
-            }
 
-            print!("{}", res_text);
-            io::stdout().flush().unwrap();
+            eprint!("{}", res_text);
+            io::stderr().flush().unwrap();
         }
         grade_time += timer.seconds_since();
     }
 
-    if progress && !json {
-        println!("");
-    }
-
-    if json {
-        let gr = GradeResult {
-            verdict: format!(
-                "{}",
-                match total == correct {
-                    true => "OK",
-                    false => "WA",
-                }
-            ),
-            score: correct.to_string(),
-            total: total.to_string(),
-            runtime: max_runtime.to_string(),
-            memory: max_memory.to_string(),
-            instructions: InstructionCount {
-                inc: opcounts.0.to_string(),
-                cdec: opcounts.1.to_string(),
-                load: opcounts.2.to_string(),
-                inv: opcounts.3.to_string(),
-            },
-            time_taken: TimeTaken {
-                parse: parse_time,
-                vm: vm_time,
-                grade: grade_time,
-            },
+    if progress && !json && !badge {
+        eprintln!();
+    }
+    let GradeSummary {
+        correct,
+        total,
+        max_runtime,
+        max_memory,
+    } = summary;
+    info!(
+        score = correct,
+        total,
+        seconds = grade_time,
+        "Graded testcases"
+    );
+
+    if let Some(log) = log {
+        let time_taken = TimeTaken {
+            parse: parse_time,
+            vm: vm_time,
+            grade: grade_time,
         };
+        let record = RunLogRecord::new(&log.arguments, task, wpk_path, seed, &cases, time_taken);
+        append_record(&log.path, &record)?;
+    }
+
+    let coverage_summary = vm
+        .coverage
+        .as_ref()
+        .map(|c| CoverageSummary::new(&vm.program, c));
+
+    let report = GradeReport {
+        passed: total == correct,
+        score: correct,
+        total,
+        runtime: max_runtime,
+        runtime_aggregates: RuntimeAggregates::new(&runtimes),
+        runtime_percentiles: RuntimePercentiles::new(&runtimes),
+        memory: max_memory,
+        instructions: opcounts.into(),
+        timings: Timings {
+            parse: parse_time,
+            vm: vm_time,
+            grade: grade_time,
+        },
+        coverage: coverage_summary
+            .as_ref()
+            .map(|c| c.to_report(&labels, source)),
+        transcript: hash_every.map(|_| transcript),
+        meta: meta.as_ref().map(|meta| meta.to_map()),
+        hash: Some(hash),
+        max_instructions,
+        late,
+        complexity,
+        exhaustive,
+        memory_metric,
+        strict_clean,
+        dirty_scratch,
+    };
+    if let Some(series_path) = series {
+        fs::write(series_path, series_csv(&cases))?;
+    }
+    if let Some(html_path) = html {
+        fs::write(html_path, render_report(task, wpk_path, &report, &cases))?;
+    }
+    if let Some(notifier) = notify {
+        notifier.notify(task, wpk_path, &report, verdicts)?;
+    }
 
-        println!("{}", json::to_string(&gr));
+    if badge {
+        println!("{}", report.badge(task));
+    } else if json {
+        println!("{}", report.into_json());
     } else {
-        let mut res_text = match total == correct {
-            true => "OK 🎉".green(),
-            false => "WA ❌".red(),
-        };
-        if !color {
-            res_text = res_text.clear();
-        }
+        let res_text = paint(
+            &verdicts.render_report(&report),
+            report.passed && !late,
+            color,
+        );
 
+        if let Some(meta) = meta.as_ref() {
+            println!("Script: {}", meta);
+        }
         println!("Verdict: {}", res_text);
         println!("Score: {}/{}", correct, total);
+        if exhaustive {
+            let domain = match total == correct {
+                true => "correct on every possible input",
+                false => "wrong on some possible inputs",
+            };
+            println!("Exhaustive: {}", paint(domain, total == correct, color));
+        }
+        if dirty_scratch {
+            println!("Scratch: started with random bits in every cell but the input");
+        }
+        if strict_clean {
+            match dirty.first() {
+                None => println!("Scratch: {}", paint("clear on every testcase", true, color)),
+                Some((tc_id, cell)) => println!(
+                    "Scratch: {}, first at cell {} in testcase {}",
+                    paint(
+                        &format!("left set on {} testcase(s)", dirty.len()),
+                        false,
+                        color
+                    ),
+                    cell,
+                    tc_id
+                ),
+            }
+        }
         println!("Instructions: {}", max_runtime);
-        println!("Memory Usage: {}", max_memory);
+        if let Some(aggregates) = report.runtime_aggregates {
+            println!("Runtime Aggregates: {}", aggregates);
+        }
+        if let Some(percentiles) = report.runtime_percentiles {
+            println!("Runtime Percentiles: {}", percentiles);
+        }
+        match memory_metric {
+            MemoryMetric::Span => println!("Memory Usage: {}", max_memory),
+            MemoryMetric::Touched => {
+                println!("Memory Usage: {} distinct cells touched", max_memory)
+            }
+        }
+        if show_sparkline {
+            println!("Runtime: {}", sparkline(&cases, |case| case.runtime, color));
+            println!("Memory: {}", sparkline(&cases, |case| case.memory, color));
+        }
         println!(
             "Instruction Counts: INC {} / CDEC {} / LOAD {} / INV {}",
             opcounts.0, opcounts.1, opcounts.2, opcounts.3
@@ -144,7 +855,91 @@ pub fn do_grade(task: Task, wpk_path: &str, progress: bool, color: bool, json: b
             "Time: Parse {:.3}s / VM Setup {:.3}s / Grading {:.3}s",
             parse_time, vm_time, grade_time
         );
+        if let Some(c) = coverage_summary.as_ref() {
+            c.print(&labels, source);
+        }
+        println!("Script Hash: {:016x}", hash);
+        if let Some(complexity) = complexity {
+            println!("Complexity: {}", complexity);
+        }
+        if hash_every.is_some() {
+            println!("Transcript Hash: {:016x}", transcript_hash);
+        }
+        if first_touch {
+            println!("First Touch, by instruction:");
+            for (tc_id, touch) in touches.iter() {
+                println!("  Testcase {}: {}", tc_id, touch.describe(&labels));
+            }
+        }
+        if show_seeds {
+            // Every option which changes a testcase's result, so `check` reproduces it exactly
+            let mut flags = String::new();
+            if let Some(format) = format {
+                write!(flags, " --format {}", format.name()).unwrap();
+            }
+            if let Some(size) = max_file_size {
+                write!(flags, " --max-file-size {}", size).unwrap();
+            }
+            if latin1 {
+                flags.push_str(" --latin1");
+            }
+            if memory_metric != MemoryMetric::Span {
+                write!(flags, " --memory-metric {}", memory_metric.name()).unwrap();
+            }
+            if strict_clean {
+                flags.push_str(" --strict-clean");
+            }
+            if dirty_scratch {
+                flags.push_str(" --dirty-scratch");
+            }
+            for tc_id in failed {
+                println!(
+                    "Failed testcase {}: seed {}; reproduce with: \
+                     wpkpp check {} {} --tc {} --seed {}{}",
+                    tc_id,
+                    task.testcase_seed(seed, tc_id),
+                    task.name(),
+                    shell_quote(wpk_path),
+                    tc_id,
+                    shell_quote(seed),
+                    flags
+                );
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{Instruction, Instruction::*};
+
+    /// Where a script leaves scratch memory dirty, with two input and two output cells
+    fn dirty_after(program: Vec<Instruction>) -> Option<usize> {
+        let testcase = LoadedTestcase {
+            tc_id: 0,
+            input_len: 2,
+            answer: BitVec::repeat(false, 2),
+        };
+        let mut vm = Vm::new(program.into());
+        vm.run();
+        first_dirty_cell(&vm, &testcase)
+    }
+
+    #[test]
+    fn dirty_cells_are_found_where_the_pointer_reached() {
+        assert_eq!(dirty_after(vec![Inv, Inc(3), Inv, Inv]), None);
+        assert_eq!(dirty_after(vec![Inc(10), Inv, Inc(5), Inv]), Some(10));
+        // Moving back from cell 0 wraps around to the end of memory
+        assert_eq!(
+            dirty_after(vec![Inv, Load, Cdec(3), Inv, Inc(3), Inv]),
+            Some(MEMORY_BITS - 3)
+        );
+        assert_eq!(
+            dirty_after(vec![Inv, Load, Cdec(3), Inv, Inc(13), Inv]),
+            Some(10)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! `grade --html`: A grade as a single self-contained HTML page, with no scripts or external
+//! files, for sharing with people who don't run wpkpp.
+
+use std::fmt::Write;
+
+use crate::{
+    render::escape_html,
+    report::{CaseResult, GradeReport},
+    task::Task,
+};
+
+const CHART_WIDTH: usize = 600;
+const CHART_HEIGHT: usize = 120;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; } \
+    table { border-collapse: collapse; } \
+    td, th { padding: 0.2em 1em; text-align: right; } \
+    td.name, th.name { text-align: left; } \
+    .ok { color: #1a7f37; } .wrong { color: #cf222e; } \
+    rect.bar { fill: #0969da; } rect.wrong { fill: #cf222e; } \
+    svg { background: #f6f8fa; }";
+
+/// One bar per testcase, scaled to the largest value; Wrong testcases are drawn in red
+fn bar_chart(out: &mut String, title: &str, cases: &[CaseResult], value: fn(&CaseResult) -> i64) {
+    let max = cases.iter().map(value).max().unwrap_or(0).max(1);
+    let bar_width = CHART_WIDTH / cases.len().max(1);
+    writeln!(out, "<h3>{}</h3>", title).unwrap();
+    writeln!(
+        out,
+        "<svg width=\"{}\" height=\"{}\" role=\"img\" aria-label=\"{}\">",
+        CHART_WIDTH, CHART_HEIGHT, title
+    )
+    .unwrap();
+    for (i, case) in cases.iter().enumerate() {
+        let height = (value(case) as f64 / max as f64 * CHART_HEIGHT as f64).round() as usize;
+        let class = match case.correct {
+            true => "bar",
+            false => "bar wrong",
+        };
+        writeln!(
+            out,
+            "<rect class=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\">\
+             <title>Testcase {}: {}</title></rect>",
+            class,
+            i * bar_width,
+            CHART_HEIGHT - height,
+            bar_width.saturating_sub(1).max(1),
+            height,
+            case.tc_id,
+            value(case)
+        )
+        .unwrap();
+    }
+    writeln!(out, "</svg><p>Largest: {}</p>", max).unwrap();
+}
+
+/// Steps spent on each kind of instruction, with their share of the total
+fn opcode_table(out: &mut String, report: &GradeReport) {
+    let counts = report.instructions;
+    let rows = [
+        ("INC", counts.inc),
+        ("CDEC", counts.cdec),
+        ("LOAD", counts.load),
+        ("INV", counts.inv),
+    ];
+    let total: u64 = rows.iter().map(|(_, count)| count).sum();
+    writeln!(out, "<h2>Instructions</h2><table>").unwrap();
+    writeln!(
+        out,
+        "<tr><th class=\"name\">Instruction</th><th>Steps</th><th>Share</th></tr>"
+    )
+    .unwrap();
+    for (name, count) in rows {
+        let share = match total {
+            0 => 0.0,
+            total => count as f64 * 100.0 / total as f64,
+        };
+        writeln!(
+            out,
+            "<tr><td class=\"name\">{}</td><td>{}</td><td>{:.1}%</td></tr>",
+            name, count, share
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+}
+
+/// The whole report page: Verdict and totals, charts and a table of every testcase, and the
+/// opcode breakdown
+pub fn render_report(task: Task, path: &str, report: &GradeReport, cases: &[CaseResult]) -> String {
+    let mut out = String::new();
+    let title = format!("Task {}: {}", task.name(), escape_html(path));
+    let verdict_class = match report.passed {
+        true => "ok",
+        false => "wrong",
+    };
+
+    writeln!(out, "<!DOCTYPE html>").unwrap();
+    writeln!(
+        out,
+        "<html><head><meta charset=\"utf-8\"><title>{}</title><style>{}</style></head><body>",
+        title, STYLE
+    )
+    .unwrap();
+    writeln!(out, "<h1>{}</h1>", title).unwrap();
+    writeln!(out, "<p>{}</p>", escape_html(task.description())).unwrap();
+    if let Some(meta) = report.meta.as_ref() {
+        let fields: Vec<String> = meta
+            .iter()
+            .map(|(key, value)| format!("{}={}", escape_html(key), escape_html(value)))
+            .collect();
+        writeln!(out, "<p>Script: {}</p>", fields.join(", ")).unwrap();
+    }
+    writeln!(out, "<table>").unwrap();
+    writeln!(
+        out,
+        "<tr><td class=\"name\">Verdict</td><td class=\"{}\">{}</td></tr>",
+        verdict_class,
+        report.verdict()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<tr><td class=\"name\">Score</td><td>{}/{}</td></tr>",
+        report.score, report.total
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<tr><td class=\"name\">Instructions</td><td>{}</td></tr>",
+        report.runtime
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<tr><td class=\"name\">Memory Usage</td><td>{}</td></tr>",
+        report.memory
+    )
+    .unwrap();
+    writeln!(out, "</table>").unwrap();
+
+    writeln!(out, "<h2>Testcases</h2>").unwrap();
+    bar_chart(&mut out, "Instructions", cases, |case| case.runtime);
+    bar_chart(&mut out, "Memory Usage", cases, |case| case.memory);
+    writeln!(out, "<table>").unwrap();
+    writeln!(
+        out,
+        "<tr><th>Testcase</th><th class=\"name\">Result</th><th>Instructions</th>\
+         <th>Memory Usage</th></tr>"
+    )
+    .unwrap();
+    for case in cases {
+        let (class, result) = match case.correct {
+            true => ("ok", "Correct"),
+            false => ("wrong", "Wrong"),
+        };
+        writeln!(
+            out,
+            "<tr><td>{}</td><td class=\"name {}\">{}</td><td>{}</td><td>{}</td></tr>",
+            case.tc_id, class, result, case.runtime, case.memory
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+
+    opcode_table(&mut out, report);
+    writeln!(
+        out,
+        "<p>Graded by wpkpp {} in {:.3}s</p></body></html>",
+        env!("CARGO_PKG_VERSION"),
+        report.timings.parse + report.timings.vm + report.timings.grade
+    )
+    .unwrap();
+    out
+}
@@ -0,0 +1,166 @@
+//! `wpkpp init`: A skeleton `.wpk` solution documenting its task's memory layout, registered in
+//! the project's `wpkpp.toml`.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{
+    config::{Config, PROJECT_CONFIG_PATH},
+    error::{Result, WpkppError},
+    meta::META_STR,
+    script_tests::{LAYOUT_STR, TEST_STR},
+    task::{MemoryLayout, Task},
+};
+
+/// Fixed testcases at the start of every task, written into the skeleton as `;test` vectors
+const SKELETON_TESTS: i8 = 4;
+
+pub struct InitOptions {
+    /// Solution name, giving the script path `<name>.wpk`
+    pub name: String,
+}
+
+/// Names of the values in a layout: a, b, ... for inputs, and out or out0, out1, ... for outputs
+fn value_names(layout: &MemoryLayout, output: bool) -> Vec<String> {
+    (0..layout.len())
+        .map(|i| match (output, layout.len()) {
+            (true, 1) => "out".to_string(),
+            (true, _) => format!("out{}", i),
+            (false, _) => ((b'a' + i as u8) as char).to_string(),
+        })
+        .collect()
+}
+
+/// Writes `name: cells start..end` lines for each value of a layout, starting at cell `start`
+fn describe_layout(skeleton: &mut String, layout: &MemoryLayout, output: bool, mut start: u64) {
+    for (name, (_, width)) in value_names(layout, output).iter().zip(layout.iter()) {
+        skeleton.push_str(&format!(
+            "#   {}: cells {}..{} ({} bits)\n",
+            name,
+            start,
+            start + width,
+            width
+        ));
+        start += width;
+    }
+}
+
+fn list(layout: &MemoryLayout, item: impl Fn(&(u64, u64)) -> String) -> String {
+    layout.iter().map(item).collect::<Vec<_>>().join(",")
+}
+
+/// A script with no instructions yet: Metadata, the task's layout as comments, and its first
+/// testcases as `;test` vectors for `wpkpp test`
+pub fn skeleton(task: Task, name: &str) -> Result<String> {
+    let (input, output) = task.testcase_layout(0)?;
+    let input_width: u64 = input.iter().map(|(_, width)| width).sum();
+
+    let mut skeleton = format!("{} task={}, version=1\n", META_STR, task.name());
+    skeleton.push_str(&format!("# {}: {}\n#\n", name, task.description()));
+    skeleton.push_str(
+        "# Memory starts with the input from cell 0, each value least significant bit first:\n",
+    );
+    describe_layout(&mut skeleton, &input, false, 0);
+    skeleton.push_str("# The answer is read right after the input:\n");
+    describe_layout(&mut skeleton, &output, true, input_width);
+    skeleton.push_str(&format!(
+        "#\n# Try the vectors below with `wpkpp test {}.wpk`,\n",
+        name
+    ));
+    skeleton.push_str(&format!(
+        "# then grade with `wpkpp grade {} {}.wpk`\n",
+        task.name(),
+        name
+    ));
+
+    let width = |(_, width): &(u64, u64)| width.to_string();
+    skeleton.push_str(&format!(
+        ";{} in={} out={}\n",
+        LAYOUT_STR,
+        list(&input, width),
+        list(&output, width)
+    ));
+    for tc_id in 0..SKELETON_TESTS {
+        let (input, output) = task.testcase_layout(tc_id)?;
+        let value = |(value, _): &(u64, u64)| format!("{:#x}", value);
+        skeleton.push_str(&format!(
+            ";{} in={} out={}\n",
+            TEST_STR,
+            list(&input, value),
+            list(&output, value)
+        ));
+    }
+    skeleton.push('\n');
+    Ok(skeleton)
+}
+
+/// The `[solutions.<name>]` table registering a solution in `wpkpp.toml`
+pub fn manifest_entry(task: Task, name: &str, path: &str) -> String {
+    format!(
+        "\n[solutions.{}]\ntask = \"{}\"\npath = \"{}\"\n",
+        name,
+        task.name(),
+        path
+    )
+}
+
+/// Creates `<name>.wpk` for a task and adds it to `wpkpp.toml`, refusing to overwrite either
+/// an existing script or an existing entry
+pub fn do_init(task: Task, options: &InitOptions) -> Result<()> {
+    let name = options.name.as_str();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(WpkppError::InvalidArgument(format!(
+            "Invalid solution name \"{}\"; use letters, digits, \"_\" and \"-\"",
+            name
+        )));
+    }
+
+    let config = match fs::read_to_string(PROJECT_CONFIG_PATH) {
+        Ok(text) => Config::parse(&text, PROJECT_CONFIG_PATH)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(e.into()),
+    };
+    if config
+        .solutions
+        .iter()
+        .any(|solution| solution.name == name)
+    {
+        return Err(WpkppError::InvalidArgument(format!(
+            "{} already lists a solution named \"{}\"",
+            PROJECT_CONFIG_PATH, name
+        )));
+    }
+
+    let path = format!("{}.wpk", name);
+    if Path::new(&path).exists() {
+        return Err(WpkppError::InvalidArgument(format!(
+            "{} already exists",
+            path
+        )));
+    }
+    let skeleton = skeleton(task, name)?;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?
+        .write_all(skeleton.as_bytes())?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(PROJECT_CONFIG_PATH)?
+        .write_all(manifest_entry(task, name, &path).as_bytes())?;
+
+    println!(
+        "Created {} for task {} ({}) and added it to {}",
+        path,
+        task.name(),
+        task.description(),
+        PROJECT_CONFIG_PATH
+    );
+    Ok(())
+}
@@ -1,9 +1,107 @@
-pub mod vm;
+// miniserde's derives expand to impls inside a const block, which newer compilers warn about
+#![allow(non_local_definitions)]
+
+pub mod assertion;
+pub mod bench;
+pub mod cache;
+pub mod calibrate;
+pub mod canon;
+pub mod compare;
+#[cfg(feature = "cli")]
+pub mod config;
+pub mod conformance;
+pub mod container;
+#[cfg(feature = "cli")]
+pub mod contest;
+pub mod coverage;
+pub mod dedup;
+pub mod error;
+pub mod explain_task;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+pub mod first_touch;
+pub mod format;
+#[cfg(feature = "async")]
+pub mod grade_async;
+pub mod grader;
+pub mod html_report;
+#[cfg(feature = "cli")]
+pub mod init;
+pub mod lint;
+pub mod lsp;
+pub mod meta;
+pub mod notify;
+pub mod optimize;
 pub mod parse;
+pub mod peephole;
+pub mod predict;
+pub mod record;
+pub mod reference;
+pub mod render;
+pub mod repl;
+pub mod report;
+pub mod run;
+pub mod runlog;
+pub mod script_tests;
+pub mod seal;
+pub mod selftest;
+pub mod session;
+pub mod similarity;
+pub mod stats;
 pub mod task;
-pub mod grader;
+pub mod tc;
+#[cfg(test)]
+mod test_util;
+pub mod tokens;
 pub mod util;
+pub mod vectors;
+pub mod verify;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod when;
+pub mod window;
 
+pub use bench::do_bench;
+pub use calibrate::do_calibrate;
+pub use canon::do_canon;
+pub use compare::do_compare;
+pub use conformance::do_conformance;
+pub use container::encode_container;
+pub use error::WpkppError;
+pub use explain_task::do_explain_task;
+pub use format::do_fmt;
+#[cfg(feature = "async")]
+pub use grade_async::grade_async;
 pub use grader::do_grade;
+pub use lint::do_lint;
+pub use lsp::do_lsp;
+pub use parse::check_valid_extension;
 pub use parse::do_compress;
-pub use parse::check_valid_extension;
\ No newline at end of file
+pub use parse::do_compress_batch;
+pub use parse::do_convert;
+pub use parse::parse_container_str;
+pub use parse::parse_wpk_str;
+pub use parse::parse_wpkb_bytes;
+pub use parse::parse_wpkm_bytes;
+pub use parse::ParseError;
+pub use parse::ParseErrorKind;
+pub use predict::do_predict;
+pub use record::{do_record, do_replay};
+pub use reference::do_reference;
+pub use render::do_render;
+pub use repl::do_repl;
+pub use report::{
+    CaseResult, GradeReport, OpCounts, RuntimeAggregates, RuntimePercentiles, Timings, Verdict,
+};
+pub use run::do_run;
+pub use script_tests::do_test;
+pub use seal::do_seal;
+pub use selftest::do_selftest;
+pub use session::Session;
+pub use similarity::do_similarity;
+pub use stats::do_stats;
+pub use tc::do_tc;
+pub use tokens::do_tokens;
+pub use vectors::do_vectors;
+pub use when::do_when;
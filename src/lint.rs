@@ -0,0 +1,210 @@
+use std::fs;
+
+use crate::{
+    error::{Result, WpkppError},
+    parse::{
+        format_location, format_source, parse_script_with, Labels, ParseOptions, ScriptCompression,
+        ScriptFormat, SourceMap, SourcePos, COMMENT_STR, COMMENT_STR_ALT, LABEL_END, LABEL_START,
+        STDIN_PATH,
+    },
+    task::Task,
+    vm::{Instruction, Instructions},
+};
+
+pub struct LintOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+}
+
+/// One suspicious spot in a script
+pub struct LintWarning {
+    /// Instruction the warning is about, if it is about one
+    pub idx: Option<usize>,
+    /// Source position, for warnings about the text rather than an instruction
+    pub pos: Option<SourcePos>,
+    pub message: String,
+}
+
+impl LintWarning {
+    fn at(idx: usize, message: String) -> Self {
+        Self {
+            idx: Some(idx),
+            pos: None,
+            message,
+        }
+    }
+}
+
+/// End of the largest memory layout among the implemented tasks
+fn largest_layout() -> u64 {
+    Task::ALL
+        .iter()
+        .filter_map(|task| task.output_region().ok())
+        .map(|(_, end)| end)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Flags instructions which are legal but probably not what was meant
+pub fn lint_instructions(instructions: &Instructions) -> Vec<LintWarning> {
+    let mut warnings = vec![];
+    let layout_end = largest_layout();
+    // Highest address the pointer can be at, taking every CDEC as not taken
+    let mut hi: i64 = 0;
+    let mut register_false = true;
+
+    for (idx, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Inc(x) => {
+                if *x as u64 > layout_end {
+                    warnings.push(LintWarning::at(
+                        idx,
+                        format!(
+                            "INC {} jumps past every task's memory layout ({} bits)",
+                            x, layout_end
+                        ),
+                    ));
+                }
+                hi += *x as i64;
+            }
+            Instruction::Cdec(x) if register_false => warnings.push(LintWarning::at(
+                idx,
+                format!("CDEC {} comes before any LOAD, so it is never taken", x),
+            )),
+            Instruction::Cdec(x) if (*x as i64) > hi => warnings.push(LintWarning::at(
+                idx,
+                format!(
+                    "CDEC {} moves the pointer below address 0 whenever it is taken",
+                    x
+                ),
+            )),
+            Instruction::Load => register_false = false,
+            _ => {}
+        }
+    }
+
+    match instructions.iter().rposition(|i| *i == Instruction::Inv) {
+        None if !instructions.is_empty() => warnings.push(LintWarning::at(
+            0,
+            "Script has no INV, so it never writes memory".to_string(),
+        )),
+        Some(last_inv) if last_inv + 1 < instructions.len() => warnings.push(LintWarning::at(
+            last_inv + 1,
+            format!(
+                "{} instruction(s) after the last INV cannot change memory",
+                instructions.len() - last_inv - 1
+            ),
+        )),
+        _ => {}
+    }
+
+    warnings
+}
+
+/// Flags .wpkm repeat counts with whitespace inside or after them, which the parser skips
+/// over, e.g. "1 2>" is "12>"
+pub fn lint_wpkm_source(source: &str) -> Vec<LintWarning> {
+    let mut warnings = vec![];
+    let mut chars = source.chars().peekable();
+    let mut pos = SourcePos { line: 1, column: 1 };
+    let advance = |pos: &mut SourcePos, c: char| match c {
+        '\n' => {
+            pos.line += 1;
+            pos.column = 1;
+        }
+        _ => pos.column += 1,
+    };
+
+    while let Some(c) = chars.next() {
+        let start = pos;
+        advance(&mut pos, c);
+        match c {
+            COMMENT_STR | COMMENT_STR_ALT => {
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    advance(&mut pos, c);
+                }
+            }
+            LABEL_START => {
+                while let Some(c) = chars.next_if(|c| *c != LABEL_END) {
+                    advance(&mut pos, c);
+                }
+            }
+            '0'..='9' => {
+                let mut split = false;
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || c.is_whitespace()) {
+                    split |= c.is_whitespace();
+                    advance(&mut pos, c);
+                }
+                if split {
+                    warnings.push(LintWarning {
+                        idx: None,
+                        pos: Some(start),
+                        message: "Repeat count is split by whitespace from what it repeats"
+                            .to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    warnings
+}
+
+fn describe(
+    warning: &LintWarning,
+    path: &str,
+    labels: &Labels,
+    source_map: Option<&SourceMap>,
+) -> String {
+    match (warning.idx, warning.pos) {
+        (Some(idx), _) => {
+            let from = source_map.and_then(|map| format_source(path, map, idx));
+            format!(
+                "{}: instruction {}: {}",
+                from.unwrap_or_else(|| path.to_string()),
+                format_location(labels, idx),
+                warning.message
+            )
+        }
+        (None, Some(pos)) => format!("{}:{}:{}: {}", path, pos.line, pos.column, warning.message),
+        (None, None) => format!("{}: {}", path, warning.message),
+    }
+}
+
+/// Prints every lint warning for a script, failing if there were any
+pub fn do_lint(path: &str, options: &LintOptions) -> Result<()> {
+    let parse_options = ParseOptions {
+        source_map: true,
+        ..Default::default()
+    };
+    let script = parse_script_with(path, options.format, false, parse_options)?;
+
+    let mut warnings = vec![];
+    let format = options.format.or_else(|| ScriptFormat::from_path(path));
+    // The source checks need the text as written, which stdin and compressed files don't keep
+    if format == Some(ScriptFormat::Wpkm)
+        && path != STDIN_PATH
+        && ScriptCompression::split_path(path).1 == ScriptCompression::None
+    {
+        warnings.extend(lint_wpkm_source(&fs::read_to_string(path)?));
+    }
+    warnings.extend(lint_instructions(&script.instructions));
+
+    for warning in warnings.iter() {
+        println!(
+            "Warning: {}",
+            describe(warning, path, &script.labels, script.source_map.as_ref())
+        );
+    }
+    match warnings.len() {
+        0 => {
+            println!("No warnings");
+            Ok(())
+        }
+        n => Err(WpkppError::CheckFailed(format!(
+            "{} warning(s) in {}",
+            n, path
+        ))),
+    }
+}
@@ -0,0 +1,426 @@
+//! `wpkpp lsp`: A minimal language server for .wpk / .wpkm scripts over stdio. Editors get
+//! syntax errors and lint warnings as diagnostics, `wpkpp fmt` as document formatting, and the
+//! pointer's cumulative offset when hovering over an instruction.
+//!
+//! Documents are synced in full on every change. Positions are counted in characters, which
+//! is what editors send as UTF-16 offsets for the ASCII woodpecker scripts are written in.
+
+use miniserde::json::{self, Array, Number, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    error::{Result, WpkppError},
+    format::{format_script, DEFAULT_WIDTH},
+    lint::{lint_instructions, lint_wpkm_source},
+    parse::{
+        check_script_bytes, parse_script_bytes_with, ParseOptions, ScriptFormat, SourcePos,
+        SourceSpan,
+    },
+    vm::Instruction,
+};
+
+/// Diagnostic severities
+const ERROR: u64 = 1;
+const WARNING: u64 = 2;
+
+/// JSON-RPC error code for requests the server does not handle
+const METHOD_NOT_FOUND: i64 = -32601;
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn number(n: u64) -> Value {
+    Value::Number(Number::U64(n))
+}
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+/// Value at a path of object keys
+fn field<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| match value {
+        Value::Object(object) => object.get(*key),
+        _ => None,
+    })
+}
+
+fn field_str<'a>(value: &'a Value, path: &[&str]) -> Option<&'a str> {
+    match field(value, path)? {
+        Value::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn field_u64(value: &Value, path: &[&str]) -> Option<u64> {
+    match field(value, path)? {
+        Value::Number(Number::U64(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+/// An LSP range from an inclusive, 1-based span
+fn range(span: SourceSpan) -> Value {
+    let position = |line: usize, character: usize| {
+        object(vec![
+            ("line", number(line.saturating_sub(1) as u64)),
+            ("character", number(character as u64)),
+        ])
+    };
+    object(vec![
+        (
+            "start",
+            position(span.start.line, span.start.column.saturating_sub(1)),
+        ),
+        ("end", position(span.end.line, span.end.column)),
+    ])
+}
+
+fn diagnostic(span: SourceSpan, severity: u64, message: &str) -> Value {
+    object(vec![
+        ("range", range(span)),
+        ("severity", number(severity)),
+        ("source", string("wpkpp")),
+        ("message", string(message)),
+    ])
+}
+
+/// Syntax errors of a script or, once it parses, its lint warnings
+fn diagnostics(text: &str, format: ScriptFormat) -> Vec<Value> {
+    let point = |line: usize, column: usize| {
+        let pos = SourcePos { line, column };
+        SourceSpan {
+            start: pos,
+            end: pos,
+        }
+    };
+    let errors = check_script_bytes(text.as_bytes(), format);
+    if !errors.is_empty() {
+        return errors
+            .iter()
+            .map(|e| diagnostic(point(e.line, e.column), ERROR, &e.kind.to_string()))
+            .collect();
+    }
+
+    let options = ParseOptions {
+        source_map: true,
+        ..Default::default()
+    };
+    let script = match parse_script_bytes_with(text.as_bytes(), format, options) {
+        Ok(script) => script,
+        Err(e) => return vec![diagnostic(point(1, 1), ERROR, &e.to_string())],
+    };
+    let mut warnings = vec![];
+    if format == ScriptFormat::Wpkm {
+        warnings.extend(lint_wpkm_source(text));
+    }
+    warnings.extend(lint_instructions(&script.instructions));
+    warnings
+        .iter()
+        .map(|warning| {
+            let span = match (warning.idx, warning.pos) {
+                (Some(idx), _) => script
+                    .source_map
+                    .as_ref()
+                    .and_then(|map| map.get(idx).copied()),
+                (None, Some(pos)) => Some(point(pos.line, pos.column)),
+                (None, None) => None,
+            };
+            diagnostic(span.unwrap_or(point(1, 1)), WARNING, &warning.message)
+        })
+        .collect()
+}
+
+/// Where the pointer is after the instruction at a 0-based position, e.g. "+40 if no CDEC is
+/// taken, +8 if every CDEC is"
+fn hover(text: &str, format: ScriptFormat, line: u64, character: u64) -> Option<String> {
+    // Uncompressed, so that every instruction maps to exactly the text it was written as
+    let options = ParseOptions {
+        source_map: true,
+        compress: false,
+        ..Default::default()
+    };
+    let script = parse_script_bytes_with(text.as_bytes(), format, options).ok()?;
+    let map = script.source_map.as_ref()?;
+    let pos = SourcePos {
+        line: line as usize + 1,
+        column: character as usize + 1,
+    };
+    let covers = |span: &SourceSpan| span.start <= pos && pos <= span.end;
+    let idx = map.iter().position(covers)?;
+    let runs = map.iter().filter(|span| covers(span)).count();
+
+    let (mut inc, mut cdec) = (0_i128, 0_i128);
+    for instruction in script.instructions[..=idx].iter() {
+        match instruction {
+            Instruction::Inc(x) => inc += *x as i128,
+            Instruction::Cdec(x) => cdec += *x as i128,
+            _ => {}
+        }
+    }
+    let mut description = format!(
+        "Pointer after instruction {}: {:+} if no CDEC is taken, {:+} if every CDEC is \
+         (INC {} / CDEC {} so far)",
+        idx,
+        inc,
+        inc - cdec,
+        inc,
+        cdec
+    );
+    if runs > 1 {
+        description.push_str(&format!("; the first of {} runs through REPEAT", runs));
+    }
+    Some(description)
+}
+
+/// `wpkpp fmt` of a whole document as a single edit, or null when it does not parse
+fn formatting(text: &str, format: ScriptFormat) -> Value {
+    let formatted = match format_script(text, format, DEFAULT_WIDTH) {
+        Ok(formatted) => formatted,
+        Err(_) => return Value::Null,
+    };
+    let whole = SourceSpan {
+        start: SourcePos { line: 1, column: 1 },
+        end: SourcePos {
+            line: text.split('\n').count() + 1,
+            column: 0,
+        },
+    };
+    let edit = object(vec![
+        ("range", range(whole)),
+        ("newText", string(&formatted)),
+    ]);
+    Value::Array(Array::from_iter([edit]))
+}
+
+fn capabilities() -> Value {
+    object(vec![
+        (
+            "capabilities",
+            object(vec![
+                // Full document sync
+                ("textDocumentSync", number(1)),
+                ("hoverProvider", Value::Bool(true)),
+                ("documentFormattingProvider", Value::Bool(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            object(vec![
+                ("name", string("wpkpp")),
+                ("version", string(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+/// Reads one message; None once the client closes the stream, null for unreadable JSON
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        WpkppError::InvalidArgument("LSP message without a Content-Length header".to_string())
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(
+        json::from_str(&String::from_utf8_lossy(&body)).unwrap_or(Value::Null),
+    ))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = json::to_string(message);
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn publish_diagnostics(output: &mut impl Write, uri: &str, diagnostics: Vec<Value>) -> Result<()> {
+    let params = object(vec![
+        ("uri", string(uri)),
+        (
+            "diagnostics",
+            Value::Array(diagnostics.into_iter().collect()),
+        ),
+    ]);
+    write_message(
+        output,
+        &object(vec![
+            ("jsonrpc", string("2.0")),
+            ("method", string("textDocument/publishDiagnostics")),
+            ("params", params),
+        ]),
+    )
+}
+
+/// Serves one client over stdin / stdout until it exits
+pub fn do_lsp() -> Result<()> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let null = Value::Null;
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = field_str(&message, &["method"]).unwrap_or_default();
+        let params = field(&message, &["params"]).unwrap_or(&null);
+        let uri = field_str(params, &["textDocument", "uri"]).unwrap_or_default();
+        let document = documents
+            .get(uri)
+            .zip(ScriptFormat::from_path(uri))
+            .map(|(text, format)| (text.clone(), format));
+
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => Some(Value::Null),
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => field_str(params, &["textDocument", "text"]),
+                    // Full sync sends the whole document as the only change
+                    _ => match field(params, &["contentChanges"]) {
+                        Some(Value::Array(changes)) => changes
+                            .last()
+                            .and_then(|change| field_str(change, &["text"])),
+                        _ => None,
+                    },
+                };
+                if let Some(text) = text {
+                    documents.insert(uri.to_string(), text.to_string());
+                    if let Some(format) = ScriptFormat::from_path(uri) {
+                        publish_diagnostics(&mut output, uri, diagnostics(text, format))?;
+                    }
+                }
+                None
+            }
+            "textDocument/didClose" => {
+                documents.remove(uri);
+                publish_diagnostics(&mut output, uri, vec![])?;
+                None
+            }
+            "textDocument/hover" => Some(
+                document
+                    .and_then(|(text, format)| {
+                        let line = field_u64(params, &["position", "line"])?;
+                        let character = field_u64(params, &["position", "character"])?;
+                        hover(&text, format, line, character)
+                    })
+                    .map_or(Value::Null, |text| {
+                        object(vec![(
+                            "contents",
+                            object(vec![
+                                ("kind", string("plaintext")),
+                                ("value", string(&text)),
+                            ]),
+                        )])
+                    }),
+            ),
+            "textDocument/formatting" => {
+                Some(document.map_or(Value::Null, |(text, format)| formatting(&text, format)))
+            }
+            _ => None,
+        };
+
+        // Notifications have no id and get no response
+        let id = match field(&message, &["id"]) {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let response = match result {
+            Some(result) => object(vec![
+                ("jsonrpc", string("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            None => object(vec![
+                ("jsonrpc", string("2.0")),
+                ("id", id),
+                (
+                    "error",
+                    object(vec![
+                        ("code", Value::Number(Number::I64(METHOD_NOT_FOUND))),
+                        ("message", string(&format!("Unhandled method {}", method))),
+                    ]),
+                ),
+            ]),
+        };
+        write_message(&mut output, &response)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_errors_become_error_diagnostics() {
+        let diagnostics = diagnostics("INC 1\n  FOO 2\n", ScriptFormat::Wpk);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(field_u64(diagnostic, &["severity"]), Some(ERROR));
+        assert_eq!(field_u64(diagnostic, &["range", "start", "line"]), Some(1));
+        assert_eq!(
+            field_u64(diagnostic, &["range", "start", "character"]),
+            Some(2)
+        );
+        assert_eq!(
+            field_str(diagnostic, &["message"]),
+            Some("Unknown instruction")
+        );
+    }
+
+    #[test]
+    fn clean_scripts_have_no_errors() {
+        for diagnostic in diagnostics("INC 1\nLOAD\nINV\n", ScriptFormat::Wpk) {
+            assert_eq!(field_u64(&diagnostic, &["severity"]), Some(WARNING));
+        }
+        assert!(diagnostics("", ScriptFormat::Wpkm).is_empty());
+    }
+
+    #[test]
+    fn hover_sums_the_pointer_offset() {
+        let text = "INC 40\nCDEC 32\nLOAD\n";
+        let description = hover(text, ScriptFormat::Wpk, 1, 2).unwrap();
+        assert!(
+            description.starts_with("Pointer after instruction 1: +40 if no CDEC is taken, +8"),
+            "{}",
+            description
+        );
+        assert_eq!(hover(text, ScriptFormat::Wpk, 5, 0), None);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let message = object(vec![("id", number(7)), ("method", string("shutdown"))]);
+        let mut bytes = vec![];
+        write_message(&mut bytes, &message).unwrap();
+        assert!(bytes.starts_with(b"Content-Length: "));
+
+        let mut input = &bytes[..];
+        let read = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(field_u64(&read, &["id"]), Some(7));
+        assert_eq!(field_str(&read, &["method"]), Some("shutdown"));
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        assert!(read_message(&mut &b"Content-Type: x\r\n\r\n{}"[..]).is_err());
+    }
+}
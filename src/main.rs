@@ -1,7 +1,54 @@
-use clap::{Parser, Args, Subcommand};
+use anyhow::{anyhow, Result};
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    Args, CommandFactory, Parser, Subcommand,
+};
+use clap_complete::Shell;
 use std::process;
 
-use wpkpp::{do_compress, do_grade, check_valid_extension, task::Task};
+use wpkpp::{
+    bench::{BenchOptions, BenchProgram, DEFAULT_MEM_BITS, DEFAULT_RUNS},
+    calibrate::CalibrateOptions,
+    canon::CanonOptions,
+    check_valid_extension,
+    config::{ColorChoice, Config},
+    conformance::ConformanceOptions,
+    container::ContainerEncoding,
+    contest::{do_contest_grade, ContestOptions},
+    dedup::SeenHashes,
+    do_bench, do_calibrate, do_canon, do_compare, do_compress, do_compress_batch, do_conformance,
+    do_convert, do_explain_task, do_fmt, do_grade, do_lint, do_lsp, do_predict, do_record,
+    do_reference, do_render, do_repl, do_replay, do_run, do_seal, do_selftest, do_similarity,
+    do_stats, do_tc, do_test, do_tokens, do_vectors, do_when,
+    explain_task::ExplainTaskOptions,
+    format::{FmtOptions, DEFAULT_WIDTH},
+    grader::GradeOptions,
+    init::{do_init, InitOptions},
+    lint::LintOptions,
+    notify::{ChatService, Notifier},
+    optimize::MAX_OPTIMIZE_LEVEL,
+    parse::{
+        CompressOptions, ConvertOptions, ScriptCompression, ScriptFormat, STDIN_PATH, STDOUT_PATH,
+    },
+    predict::{PredictOptions, DEFAULT_SAMPLES},
+    record::RecordOptions,
+    render::RenderOptions,
+    repl::ReplOptions,
+    run::RunOptions,
+    runlog::RunLog,
+    script_tests::TestOptions,
+    selftest::SelftestOptions,
+    similarity::{SimilarityOptions, DEFAULT_K, DEFAULT_THRESHOLD, DEFAULT_WINDOW},
+    stats::StatsOptions,
+    task::Task,
+    tc::TcOptions,
+    tokens::TokensOptions,
+    vectors::VectorsOptions,
+    verify::VerifyTarget,
+    vm::MemoryMetric,
+    when::WhenOptions,
+    window::ContestWindow,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -9,12 +56,51 @@ use wpkpp::{do_compress, do_grade, check_valid_extension, task::Task};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Log progress to stderr; Repeat for more detail, e.g. -vv for every testcase
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// When to color output; "auto" colors terminals only, unless NO_COLOR is set
+    #[arg(long, value_name = "auto|always|never", global = true, value_parser = color_parser())]
+    color: Option<ColorChoice>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Grade(Grade),
-    Compress(Compress)
+    Compress(Compress),
+    Convert(Convert),
+    Fmt(Fmt),
+    Optimize(Optimize),
+    Canon(Canon),
+    Seal(Seal),
+    Similarity(Similarity),
+    Lint(Lint),
+    Lsp(Lsp),
+    Tokens(Tokens),
+    Render(Render),
+    Completions(Completions),
+    Bench(Bench),
+    Calibrate(Calibrate),
+    Record(Record),
+    Replay(Replay),
+    Compare(Compare),
+    Repl(Repl),
+    Run(Run),
+    When(When),
+    Predict(Predict),
+    Stats(Stats),
+    Vectors(Vectors),
+    Conformance(Conformance),
+    Selftest(Selftest),
+    ExplainTask(ExplainTask),
+    Tc(Tc),
+    Test(Test),
+    Init(Init),
+    Reference(Reference),
+    #[command(subcommand)]
+    Contest(ContestCommand),
 }
 
 #[derive(Args)]
@@ -29,23 +115,116 @@ enum Commands {
 ///   4  : 16 bit addition modulo 2**16 - 17
 ///   4a : 16 bit subtraction modulo 2**16 - 17
 ///   5  : 16 bit multiplication modulo 2**16 - 17
-///   5a : 16 bit multiplicative inverse modulo 2**16 - 17 
+///   5a : 16 bit multiplicative inverse modulo 2**16 - 17
 struct Grade {
     /// Task number [0..5]
-    #[arg(value_name = "task", value_parser = parse_task_name)]
+    #[arg(value_name = "task", value_parser = task_parser())]
     task: Task,
-    /// Solution path
-    #[arg(value_name = "script.(wpk|wpkm)", value_parser = parse_script_name)]
+    /// Solution path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
     wpk_path: String,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
     /// Hide progress bar
     #[arg(long)]
     noprogress: bool,
-    /// Disable color
-    #[arg(long)]
+    /// Same as --color never
+    #[arg(long, hide = true)]
     nocolor: bool,
     /// JSON ouptut
     #[arg(long)]
     json: bool,
+    /// Report which CDEC instructions were taken across all testcases
+    #[arg(long)]
+    coverage: bool,
+    /// Hash touched memory every N steps and include the hash chain in the JSON transcript
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i64).range(1..))]
+    hash_every: Option<i64>,
+    /// Largest script file accepted, in bytes or with a k / M suffix; Defaults to 10M for
+    /// .wpk and 5M for .wpkm / .wpkb
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    max_file_size: Option<u64>,
+    /// Read scripts which are not valid UTF-8 as Latin-1 instead of rejecting them
+    #[arg(long)]
+    latin1: bool,
+    /// Threads for parsing large .wpkm scripts; Defaults to every core
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..).map(|n| n as usize))]
+    jobs: Option<usize>,
+    /// Append a JSON line recording the arguments, seed, testcase results and timings of this
+    /// run to a log file
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+    /// Check "#assert" comments as execution reaches them, failing on the first which does
+    /// not hold
+    #[arg(long, conflicts_with = "hash_every")]
+    assert: bool,
+    /// Also write a self-contained HTML report with the verdict, every testcase and the
+    /// instruction breakdown, for sharing
+    #[arg(long, value_name = "report.html")]
+    html: Option<String>,
+    /// Print a markdown badge such as "task 5: OK, 1.2M steps" instead of the usual output,
+    /// for a solutions repository's README
+    #[arg(long, conflicts_with = "json")]
+    badge: bool,
+    /// Post a summary with the verdict, score and runtime to a team chat once grading finishes
+    #[arg(long, value_name = "slack|discord", requires = "webhook", value_parser = chat_service_parser())]
+    notify: Option<ChatService>,
+    /// Incoming webhook URL of the channel to notify
+    #[arg(long, value_name = "URL", requires = "notify")]
+    webhook: Option<String>,
+    /// Reject scripts with more than N instructions after compression, or which take more than
+    /// N steps, before running any testcase
+    #[arg(long, value_name = "N")]
+    max_instructions: Option<u64>,
+    /// How memory usage is measured: "span" counts cells from the lowest to the highest address
+    /// the pointer reached, "touched" counts distinct cells read by LOAD or written by INV
+    #[arg(long, value_name = "span|touched", default_value = "span", value_parser = memory_metric_parser())]
+    memory_metric: MemoryMetric,
+    /// Grade on every possible input instead of the 100 seeded testcases; Tasks 0 and 1 only
+    #[arg(long)]
+    exhaustive: bool,
+    /// Print the seed of each failed testcase along with a `wpkpp run` command reproducing it
+    #[arg(long, conflicts_with = "exhaustive")]
+    show_seeds: bool,
+    /// Fail testcases which leave any cell past the output set, for contests requiring scratch
+    /// space to be cleaned up; The input may be left changed
+    #[arg(long, conflicts_with = "dirty_scratch")]
+    strict_clean: bool,
+    /// Start every testcase with seeded random bits in every cell but the input, catching
+    /// scripts which count on memory starting clear
+    #[arg(long)]
+    dirty_scratch: bool,
+    /// Print which instruction first touches the input, output and scratch memory in each
+    /// testcase, flagging LOADs of scratch cells nothing has written yet
+    #[arg(long, conflicts_with_all = ["assert", "hash_every"])]
+    first_touch: bool,
+    /// Always parse the script, instead of reusing the instructions of an earlier parse of the
+    /// same file from the parse cache
+    #[arg(long)]
+    no_cache: bool,
+    /// Draw every testcase's runtime and memory usage as a sparkline, with wrong testcases in
+    /// red
+    #[arg(long)]
+    sparkline: bool,
+    /// Write every testcase's result, runtime and memory usage to a CSV file, e.g. for gnuplot
+    #[arg(long, value_name = "series.csv")]
+    series: Option<String>,
+    /// Include complexity metrics of the script: Distinct INC / CDEC run lengths, their
+    /// entropy, LOAD density and INV clustering
+    #[arg(long)]
+    complexity: bool,
+    /// Contest settings with the round's start and end times and tasks; Submissions outside
+    /// the window are still graded, with verdict LATE
+    #[arg(long, value_name = "contest.toml")]
+    contest: Option<String>,
+    /// Record the script's canonical hash in this file, warning when the same instructions
+    /// were graded before, whatever their format, comments or whitespace
+    #[arg(long, value_name = "PATH")]
+    seen_hashes: Option<String>,
+    /// Fail instead of warning on a script listed in --seen-hashes
+    #[arg(long, requires = "seen_hashes")]
+    reject_duplicates: bool,
 }
 
 #[derive(Args)]
@@ -53,47 +232,1078 @@ struct Grade {
 /// Compress your woodpecker scripts to use repeating INC / CDEC instructions
 /// *.wpk format uses "INC [?n]" / "CDEC [?n]" / "LOAD" / "INV"  
 /// *.wpkm format uses "[?n]>" / "[?n]<" / "?" or "v" / "!" or "^"
+/// *.wpkb format is a binary container of varint encoded instructions
+/// *.wpkc files wrap a script of any format as base64 or hex text
 struct Compress {
+    /// Input file path, or "-" to read from stdin; With --out-dir, a glob pattern or directory
+    #[arg(value_name = "infile.(wpk|wpkm|wpkb)", value_parser = parse_input_name)]
+    input_path: String,
+
+    /// Output file path, or "-" for stdout; Optional, defaults to [infile]-compress.(wpk|wpkm|wpkb)
+    #[arg(value_name = "outfile.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    output_path: Option<String>,
+
+    /// Write the compressed script to stdout, same as passing "-" as the output path
+    #[arg(long, conflicts_with = "output_path")]
+    stdout: bool,
+
+    /// Compress every script matching the input glob or inside the input directory into DIR,
+    /// keeping their relative names
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output_path", "stdout", "check"])]
+    out_dir: Option<String>,
+
+    /// Preserve "#" / ";" comments in the output instead of stripping them
+    #[arg(long)]
+    keep_comments: bool,
+
+    /// Input format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+
+    /// Output format; Defaults to the input format when writing to stdout
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    output_format: Option<ScriptFormat>,
+
+    /// Only check the input, reporting every syntax error instead of stopping at the first
+    #[arg(long)]
+    check: bool,
+
+    /// Only report instruction counts and output size, without writing any output
+    #[arg(long, conflicts_with = "check")]
+    dry_run: bool,
+
+    /// Print a JSON summary instead of progress messages
+    #[arg(long, conflicts_with = "check")]
+    json: bool,
+
+    /// Print stable tab-separated result lines instead of progress messages, for scripts
+    #[arg(long, conflicts_with_all = ["check", "json"])]
+    porcelain: bool,
+
+    /// Before writing, check the compressed script gives the same memory and runtime as the
+    /// input on random memory states, or on a task's testcases when given
+    #[arg(long, value_name = "task", num_args = 0..=1, value_parser = task_parser())]
+    verify: Option<Option<Task>>,
+
+    /// Remove redundant instructions; 0 = off, 1 = INV pairs and zero length INC / CDEC,
+    /// 2 = also repeated LOADs, 3 = also CDECs before the first LOAD
+    #[arg(long, value_name = "LEVEL", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=MAX_OPTIMIZE_LEVEL as i64))]
+    optimize: u8,
+
+    /// Break .wpkm output into lines of at most N characters
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..).map(|n| n as usize))]
+    wrap: Option<usize>,
+
+    /// Reduce INC / CDEC counts of 2^32 or more modulo 2^32 instead of rejecting them
+    #[arg(long)]
+    wrap_repeats: bool,
+
+    /// Split INC / CDEC counts of 2^32 or more into several instructions instead of rejecting
+    /// them, keeping the step count exact
+    #[arg(long, conflicts_with = "wrap_repeats")]
+    split_repeats: bool,
+
+    /// Write the output as a "wpkpp:<format>:<encoding>:" text container, safe to embed in
+    /// JSON; Output paths ending in .wpkc default to base64
+    #[arg(long, value_name = "base64|hex", value_parser = parse_container_name)]
+    container: Option<ContainerEncoding>,
+
+    /// Read scripts which are not valid UTF-8 as Latin-1 instead of rejecting them
+    #[arg(long)]
+    latin1: bool,
+
+    /// Threads for parsing large .wpkm scripts; Defaults to every core
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..).map(|n| n as usize))]
+    jobs: Option<usize>,
+
+    /// Always parse the input, instead of reusing the instructions of an earlier parse of the
+    /// same file from the parse cache
+    #[arg(long)]
+    no_cache: bool,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Convert a woodpecker script between the .wpk, .wpkm and .wpkb formats
+/// Comments and labels are kept where the output format supports them
+struct Convert {
+    /// Input file path, or "-" to read from stdin
+    #[arg(value_name = "infile.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    input_path: String,
+
+    /// Output file path, or "-" for stdout
+    #[arg(value_name = "outfile.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    output_path: String,
+
+    /// Keep instructions one for one instead of merging repeated INC / CDEC
+    #[arg(long)]
+    no_compress: bool,
+
+    /// Input format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+
+    /// Output format; Required when writing to stdout
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    output_format: Option<ScriptFormat>,
+
+    /// Reduce INC / CDEC counts of 2^32 or more modulo 2^32 instead of rejecting them
+    #[arg(long)]
+    wrap_repeats: bool,
+
+    /// Split INC / CDEC counts of 2^32 or more into several instructions instead of rejecting
+    /// them, keeping the step count exact
+    #[arg(long, conflicts_with = "wrap_repeats")]
+    split_repeats: bool,
+
+    /// Read scripts which are not valid UTF-8 as Latin-1 instead of rejecting them
+    #[arg(long)]
+    latin1: bool,
+
+    /// Threads for parsing large .wpkm scripts; Defaults to every core
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..).map(|n| n as usize))]
+    jobs: Option<usize>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Format a woodpecker script in place
+/// *.wpk scripts get one instruction per line, indented inside REPEAT blocks
+/// *.wpkm scripts are wrapped at a column width, with "v" / "^" written as "?" / "!"
+struct Fmt {
+    /// Script path
+    #[arg(value_name = "script.(wpk|wpkm)", value_parser = parse_script_name)]
+    path: String,
+
+    /// Column to wrap .wpkm scripts at
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_WIDTH, value_parser = clap::value_parser!(u64).range(1..).map(|n| n as usize))]
+    width: usize,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Remove instructions which cannot change a woodpecker script's result
+/// With --task, also remove trailing instructions which cannot reach the task's output
+struct Optimize {
     /// Input file path
-    #[arg(value_name = "infile.(wpk|wpkm)", value_parser = parse_script_name)]
+    #[arg(value_name = "infile.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
     input_path: String,
 
-    /// Output file path; Optional, defaults to [infile]-compress.(wpk|wpkm)
-    #[arg(value_name = "outfile.(wpk|wpkm)", value_parser = parse_script_name)]
+    /// Output file path, or "-" for stdout; Defaults to [infile]-optimize.(wpk|wpkm|wpkb)
+    #[arg(value_name = "outfile.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
     output_path: Option<String>,
+
+    /// Optimization level, as for compress --optimize
+    #[arg(long, value_name = "LEVEL", default_value_t = MAX_OPTIMIZE_LEVEL, value_parser = clap::value_parser!(u8).range(0..=MAX_OPTIMIZE_LEVEL as i64))]
+    level: u8,
+
+    /// Task whose output region the script has to keep
+    #[arg(long, value_name = "task", value_parser = task_parser())]
+    task: Option<Task>,
+
+    /// Preserve "#" / ";" comments in the output instead of stripping them
+    #[arg(long)]
+    keep_comments: bool,
+
+    /// Before writing, check the optimized script gives the same result as the input on the
+    /// task's testcases, or on random memory states without --task
+    #[arg(long)]
+    verify: bool,
+
+    /// Only report what would be removed, without writing any output
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Print the canonical .wpkm form of a woodpecker script
+/// Scripts which compress to the same instructions get byte-identical forms, so their
+/// hashes match no matter how they were written
+struct Canon {
+    /// Input file path, or "-" to read from stdin
+    #[arg(value_name = "infile.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    input_path: String,
+
+    /// Output file path; Defaults to stdout
+    #[arg(value_name = "outfile.wpkm", default_value = STDOUT_PATH)]
+    output_path: String,
+
+    /// Input format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+
+    /// Print a 64-bit FNV-1a hash of the canonical form instead of the form itself
+    #[arg(long, conflicts_with = "output_path")]
+    hash: bool,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Append a ";#sha256=..." checksum footer to a woodpecker script in place
+/// Sealed scripts fail to parse if they are later truncated or corrupted
+struct Seal {
+    /// Script path
+    #[arg(value_name = "script.(wpk|wpkm)", value_parser = parse_script_name)]
+    path: String,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Warn about likely mistakes in a woodpecker script
+/// Flags CDECs before any LOAD, CDECs which always take the pointer below 0, INCs past every
+/// task's memory layout, instructions after the last INV and split .wpkm repeat counts
+struct Lint {
+    /// Script path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    path: String,
+
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Print an annotated listing of a woodpecker script
+/// Each instruction is shown with its index, its byte offset in the file and the pointer
+/// offset after it when no CDEC is taken
+struct Render {
+    /// Script path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    path: String,
+
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+
+    /// Write an HTML page instead of plain text
+    #[arg(long)]
+    html: bool,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Print a shell completion script
+/// e.g. wpkpp completions bash > ~/.local/share/bash-completion/completions/wpkpp
+struct Completions {
+    /// Shell to complete for
+    #[arg(value_name = "bash|zsh|fish|powershell|elvish")]
+    shell: Shell,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Measure parse speed, VM setup and reset cost, and VM throughput on this machine
+/// Compare the printed numbers across versions to spot performance regressions
+struct Bench {
+    /// Span of memory the synthetic program sweeps over, as a power of two
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MEM_BITS, value_parser = clap::value_parser!(u32).range(1..=32))]
+    mem_bits: u32,
+
+    /// "synthetic" for a generated program of 10M instructions, or a script path
+    #[arg(long, value_name = "synthetic|file", default_value = "synthetic")]
+    program: String,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Grade the reference solutions of tasks 0 to 3 on this machine, saving their scores and grading
+/// times to ~/.local/share/wpkpp/calibration.json for scoring and time estimates
+struct Calibrate {
+    /// Gradings of each reference solution, keeping the fastest
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_RUNS, value_parser = clap::value_parser!(u64).range(1..).map(|runs| runs as usize))]
+    runs: usize,
+    /// Save the calibration here instead
+    #[arg(long, value_name = "calibration.json")]
+    out: Option<String>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Grade a script and save everything the grade depends on to a file: The script itself, its
+/// SHA-256, the task, the seed and settings, along with each testcase's result and memory hash
+/// Anyone can then check the grade with `wpkpp replay`
+struct Record {
+    /// Task number [0..5]
+    #[arg(value_name = "task", value_parser = task_parser())]
+    task: Task,
+    /// Solution path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    wpk_path: String,
+    /// Recording path
+    #[arg(value_name = "recording.json")]
+    output_path: String,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+    /// Seed to generate testcases from; Defaults to WPKPP_SEED or the config's seed
+    #[arg(long)]
+    seed: Option<String>,
+    /// Hash touched memory every N steps as well as at the end of each testcase, so replays
+    /// also compare the memory along the way
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i64).range(1..))]
+    hash_every: Option<i64>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Dump conformance test vectors as JSON for other woodpecker implementations: The input and
+/// expected output of every testcase of every task, and small programs pinning down the VM
+struct Vectors {
+    /// Write the vectors here instead of stdout
+    #[arg(long, value_name = "vectors.json")]
+    out: Option<String>,
+    /// Seed to generate testcases from; Defaults to WPKPP_SEED or the config's seed
+    #[arg(long)]
+    seed: Option<String>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Run another woodpecker implementation on the programs of `wpkpp vectors`, and optionally on
+/// a script against a task's testcases, diffing its runtime, pointer and memory against wpkpp
+/// The runner gets the program as one .wpkm line, the input as 0 / 1 and a cell count N on
+/// stdin, and prints "<runtime> <pointer> <cells 0..N as 0 / 1>"
+struct Conformance {
+    /// Command running the other implementation, split on whitespace
+    #[arg(long, value_name = "./other-vm")]
+    runner: String,
+    /// Script to also run against every testcase of --task
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name, requires = "task")]
+    wpk_path: Option<String>,
+    /// Task whose testcases the script runs against
+    #[arg(long, value_name = "task", value_parser = task_parser(), requires = "wpk_path")]
+    task: Option<Task>,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Check this build behaves correctly on this machine: Reference solutions must survive every
+/// format, compression and grading with full marks, small programs must give known results
+/// and testcase generation must be deterministic for each task and seed
+struct Selftest {
+    /// Compare testcase digests against ones saved on another machine with --save-digests
+    #[arg(long, value_name = "digests.txt")]
+    digests: Option<String>,
+    /// Save this machine's testcase digests
+    #[arg(long, value_name = "digests.txt")]
+    save_digests: Option<String>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Explain a task: What it computes, its reduction modulus, where each value sits in memory and
+/// a worked example testcase, generated by the same code the grader uses
+struct ExplainTask {
+    /// Task number [0..5]
+    #[arg(value_name = "task", value_parser = task_parser())]
+    task: Task,
+    /// Testcase to show as the example [0..99]
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(i8).range(0..100))]
+    tc: i8,
+    /// Seed to generate the example from; Defaults to WPKPP_SEED or the config's seed
+    #[arg(long)]
+    seed: Option<String>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Print the input and expected output of one testcase in binary and hex, exactly as the grader
+/// will load it, to trace a script by hand against
+struct Tc {
+    /// Task number [0..5]
+    #[arg(value_name = "task", value_parser = task_parser())]
+    task: Task,
+    /// Testcase [0..99]
+    #[arg(value_name = "id", value_parser = clap::value_parser!(i8).range(0..100))]
+    id: i8,
+    /// Seed to generate the testcase from; Defaults to WPKPP_SEED or the config's seed
+    #[arg(long)]
+    seed: Option<String>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Run a language server for .wpk / .wpkm scripts over stdin / stdout, giving editors syntax
+/// errors and lint warnings, `wpkpp fmt` formatting and the pointer's offset on hover
+struct Lsp {}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Print the tokens of a .wpk / .wpkm script with their byte ranges, for editors and viewers to
+/// highlight scripts with
+struct Tokens {
+    /// Script path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm)", value_parser = parse_script_name)]
+    path: String,
+
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+
+    /// Print a JSON array of {kind, start, end, text} instead of a line per token
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Rerun a grade saved by `wpkpp record` with the same script, task, seed and settings
+/// Prints each testcase which came out differently, failing if there are any
+struct Replay {
+    /// Recording path
+    #[arg(value_name = "recording.json")]
+    path: String,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Type woodpecker instructions in .wpk or .wpkm syntax and run them right away on a live VM
+/// Commands: ":mem [N]" shows memory around the pointer, ":undo" takes back the last line,
+/// ":reset" starts over and ":dump [PATH]" prints or saves the session as a script
+struct Repl {
+    /// Syntax of typed instructions; Defaults to .wpk for lines starting with a keyword such
+    /// as "INC", and .wpkm otherwise
+    #[arg(long, value_name = "wpk|wpkm", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Compare two reports saved from `wpkpp grade --json`, printing how score, runtime, memory and
+/// each opcount changed
+struct Compare {
+    /// Report before the change
+    #[arg(value_name = "old.json")]
+    old_path: String,
+    /// Report after the change
+    #[arg(value_name = "new.json")]
+    new_path: String,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment, visible_alias = "check")]
+/// Run a woodpecker script on a single testcase of a task
+/// With --explain, each step is narrated in plain English, e.g. "register is 1, so CDEC 3 moves
+/// the pointer back from 15 to 12". Takes the grade options which change a testcase's result,
+/// so that `grade --show-seeds` can print a `check` command reproducing a failed testcase.
+struct Run {
+    /// Task number [0..5]
+    #[arg(value_name = "task", value_parser = task_parser())]
+    task: Task,
+    /// Solution path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    wpk_path: String,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+    /// Testcase to run [0..99]
+    #[arg(long, value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(i8).range(0..100))]
+    tc: i8,
+    /// Seed to generate the testcase from; Defaults to WPKPP_SEED or the config's seed
+    #[arg(long)]
+    seed: Option<String>,
+    /// Explain what each of the first N steps does; Defaults to 50 steps
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "50")]
+    explain: Option<u64>,
+    /// Largest script file accepted, in bytes or with a k / M suffix; Defaults to 10M for
+    /// .wpk and 5M for .wpkm / .wpkb
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    max_file_size: Option<u64>,
+    /// Read scripts which are not valid UTF-8 as Latin-1 instead of rejecting them
+    #[arg(long)]
+    latin1: bool,
+    /// How memory usage is measured, as for grade
+    #[arg(long, value_name = "span|touched", default_value = "span", value_parser = memory_metric_parser())]
+    memory_metric: MemoryMetric,
+    /// Fail the testcase if it leaves any cell past the output set, as grade does
+    #[arg(long, conflicts_with = "dirty_scratch")]
+    strict_clean: bool,
+    /// Start with the random bits grade --dirty-scratch puts in every cell but the input
+    #[arg(long)]
+    dirty_scratch: bool,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Find at which step, and by which instruction, an output bit was last flipped in one testcase
+/// Bits are counted from the start of the output, e.g. --bit 0 is the cell right after the input
+struct When {
+    /// Solution path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    wpk_path: String,
+    /// Task number [0..5]; Defaults to the task in the script's ";!" metadata
+    #[arg(long, value_name = "task", value_parser = task_parser())]
+    task: Option<Task>,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+    /// Testcase to run [0..99]
+    #[arg(long, visible_alias = "tc", value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(i8).range(0..100))]
+    case: i8,
+    /// Seed to generate the testcase from; Defaults to WPKPP_SEED or the config's seed
+    #[arg(long)]
+    seed: Option<String>,
+    /// Output bit to ask about
+    #[arg(long, value_name = "N")]
+    bit: usize,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Estimate what grading a script will come to from a few sampled testcases
+/// Instructions are exact, since every testcase takes the same number of steps; Memory usage is
+/// the most any sampled testcase used, with an upper bound from the script itself
+struct Predict {
+    /// Script path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    path: String,
+    /// Task number [0..5]; Defaults to the task in the script's ";!" metadata
+    #[arg(long, value_name = "task", value_parser = task_parser())]
+    task: Option<Task>,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+    /// Testcases to run, spread evenly over all 100
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SAMPLES, value_parser = clap::value_parser!(u64).range(1..=100).map(|samples| samples as usize))]
+    samples: usize,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Print what a script is made of without running it: Instruction and step counts, INC / CDEC
+/// run lengths and how far the pointer can reach
+struct Stats {
+    /// Script path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm|wpkb)", value_parser = parse_script_name)]
+    path: String,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm|wpkb", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Run the test vectors embedded in a script's comments, independent of the official tasks
+///   ;layout in=16,16 out=16
+///   ;test in=0x3,0x5 out=0x8
+/// Inputs start at cell 0 and outputs follow them; Widths come from ";layout" or a ":width"
+/// after each value
+struct Test {
+    /// Script path, or "-" to read from stdin
+    #[arg(value_name = "script.(wpk|wpkm)", value_parser = parse_script_name)]
+    path: String,
+    /// Script format; Required when reading from stdin
+    #[arg(long, value_name = "wpk|wpkm", value_parser = parse_format_name)]
+    format: Option<ScriptFormat>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Start a solution: Writes <name>.wpk with the task's memory layout as comments and its first
+/// testcases as ";test" vectors, and lists it under [solutions] in ./wpkpp.toml
+struct Init {
+    /// Task number [0..5]
+    #[arg(value_name = "task", value_parser = task_parser())]
+    task: Task,
+    /// Solution name; The script is written to <name>.wpk. Defaults to "task<task>"
+    #[arg(long)]
+    name: Option<String>,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Generate a straightforward, unoptimized solution to one of the tasks 0, 1, 2, 2a and 3, as a
+/// correctness oracle or a baseline score
+struct Reference {
+    /// Task number [0, 1, 2, 2a, 3]
+    #[arg(value_name = "task", value_parser = task_parser())]
+    task: Task,
+    /// Output path; The format follows its extension
+    #[arg(long, value_name = "ref.(wpk|wpkm|wpkb)")]
+    out: String,
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Report pairs of scripts which share suspiciously much code, for plagiarism screening
+/// Scripts are compared in canonical form, so formats, comments and labels make no difference
+struct Similarity {
+    /// Directory searched recursively, or a glob pattern, e.g. "submissions/*/mul.*"
+    #[arg(value_name = "dir|pattern")]
+    pattern: String,
+    /// Report pairs sharing at least this share of their fingerprints, from 0 to 1
+    #[arg(long, value_name = "SHARE", default_value_t = DEFAULT_THRESHOLD)]
+    threshold: f64,
+    /// Instructions hashed together; Lower finds smaller shared pieces
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_K, value_parser = clap::value_parser!(u64).range(1..).map(|k| k as usize))]
+    k: usize,
+    /// Consecutive hashes winnowed down to one; Higher keeps smaller fingerprints
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_WINDOW, value_parser = clap::value_parser!(u64).range(1..).map(|window| window as usize))]
+    window: usize,
+}
+
+/// Organizer tools for running a contest
+#[derive(Subcommand)]
+enum ContestCommand {
+    Grade(ContestGrade),
+}
+
+#[derive(Args)]
+#[command(verbatim_doc_comment)]
+/// Grade every submission in a directory with one subdirectory per user, writing a JSON report
+/// per script and a ranked leaderboard.csv to the output directory
+/// The task map names the scripts to grade, e.g. mul = "3" grades <user>/mul.(wpk|wpkm|wpkb)
+struct ContestGrade {
+    /// Directory of submissions, one subdirectory per user
+    #[arg(value_name = "submissions/")]
+    submissions_dir: String,
+    /// TOML file mapping script names to task numbers
+    #[arg(long, value_name = "tasks.toml")]
+    task_map: String,
+    /// Directory to write reports and the leaderboard to
+    #[arg(long, value_name = "results/")]
+    out: String,
+}
+
+fn chat_service_parser() -> impl TypedValueParser<Value = ChatService> {
+    PossibleValuesParser::new(ChatService::NAMES).map(|name| ChatService::from_name(&name).unwrap())
+}
+
+fn color_parser() -> impl TypedValueParser<Value = ColorChoice> {
+    PossibleValuesParser::new(ColorChoice::NAMES).map(|name| ColorChoice::from_name(&name).unwrap())
+}
+
+fn memory_metric_parser() -> impl TypedValueParser<Value = MemoryMetric> {
+    PossibleValuesParser::new(MemoryMetric::NAMES)
+        .map(|name| MemoryMetric::from_name(&name).unwrap())
+}
+
+/// Accepts the known task numbers, which also makes them show up in shell completions
+fn task_parser() -> impl TypedValueParser<Value = Task> {
+    PossibleValuesParser::new(Task::ALL.map(Task::name)).map(|name| Task::from_str(&name).unwrap())
+}
+
+fn parse_file_size(size: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match size.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1_000),
+        None => match size.strip_suffix('M') {
+            Some(digits) => (digits, 1_000_000),
+            None => (size, 1),
+        },
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("Invalid file size \"{}\"", size))
 }
 
-fn parse_task_name(task_name: &str) -> Result<Task, String> {
-    Task::from_str(task_name).map_err(|_| format!("Unknown task \"{}\"", {task_name}))
+fn parse_format_name(format_name: &str) -> Result<ScriptFormat, String> {
+    ScriptFormat::from_name(format_name)
+        .ok_or_else(|| format!("Unknown script format \"{}\"", format_name))
+}
+
+fn parse_container_name(encoding_name: &str) -> Result<ContainerEncoding, String> {
+    ContainerEncoding::from_name(encoding_name)
+        .ok_or_else(|| format!("Unknown container encoding \"{}\"", encoding_name))
 }
 
 fn parse_script_name(path: &str) -> Result<String, String> {
-    match check_valid_extension(path) {
+    match path == STDIN_PATH || check_valid_extension(path) {
         true => Ok(path.to_string()),
-        false => Err(format!("Invalid input woodpecker script name {}, should end in \".wpk\" or \".wpkm\"", path))
+        false => Err(format!("Invalid input woodpecker script name {}, should end in \".wpk\", \".wpkm\", \".wpkb\" or \".wpkc\"", path))
+    }
+}
+
+fn parse_input_name(path: &str) -> Result<String, String> {
+    match std::path::Path::new(path).is_dir() {
+        true => Ok(path.to_string()),
+        false => parse_script_name(path),
+    }
+}
+
+fn run_compress(compress: Compress, config: &Config) -> Result<()> {
+    let mut options = CompressOptions {
+        keep_comments: compress.keep_comments,
+        input_format: compress.format,
+        output_format: compress.output_format,
+        check: compress.check,
+        wrap: compress.wrap,
+        dry_run: compress.dry_run,
+        json: compress.json,
+        porcelain: compress.porcelain,
+        verify: compress.verify.map(|task| match task {
+            Some(task) => VerifyTarget::Task(task),
+            None => VerifyTarget::Random,
+        }),
+        optimize: compress.optimize,
+        optimize_task: None,
+        wrap_repeats: compress.wrap_repeats,
+        split_repeats: compress.split_repeats,
+        latin1: compress.latin1,
+        jobs: compress.jobs.or(config.jobs),
+        container: compress.container,
+        cache: !compress.no_cache,
+    };
+    let input_path = compress.input_path;
+    if let Some(out_dir) = compress.out_dir {
+        options.output_format = options.output_format.or(config.output_format);
+        return Ok(do_compress_batch(&input_path, &out_dir, &options)?);
     }
+    if std::path::Path::new(&input_path).is_dir() {
+        return Err(anyhow!("Compressing a directory requires --out-dir"));
+    }
+
+    let output_path = match compress.output_path {
+        Some(output_path) => output_path,
+        None if compress.check => STDOUT_PATH.to_string(),
+        None if compress.stdout => STDOUT_PATH.to_string(),
+        None if compress.dry_run && input_path == STDIN_PATH => STDOUT_PATH.to_string(),
+        None if input_path == STDIN_PATH => {
+            return Err(anyhow!(
+                "An output path is required when reading from stdin"
+            ))
+        }
+        None => default_output_path(&input_path, "-compress"),
+    };
+    if ScriptFormat::from_path(&output_path).is_none() {
+        options.output_format = options.output_format.or(config.output_format);
+    }
+    Ok(do_compress(
+        input_path.as_str(),
+        output_path.as_str(),
+        &options,
+    )?)
+}
+
+/// [infile][suffix].(wpk|wpkm|wpkb), dropping any .gz / .zst extension
+fn default_output_path(input_path: &str, suffix: &str) -> String {
+    let (uncompressed_path, _) = ScriptCompression::split_path(input_path);
+    let extension_idx = uncompressed_path.rfind(".wpk").unwrap();
+    let basename = &uncompressed_path[..extension_idx];
+    let extension = &uncompressed_path[extension_idx..];
+    basename.to_string() + suffix + extension
+}
+
+fn run_optimize(optimize: Optimize, config: &Config) -> Result<()> {
+    if optimize.input_path == STDIN_PATH {
+        return Err(anyhow!("Optimizing requires an input file"));
+    }
+    let options = CompressOptions {
+        keep_comments: optimize.keep_comments,
+        input_format: None,
+        output_format: None,
+        check: false,
+        wrap: None,
+        dry_run: optimize.dry_run,
+        json: false,
+        porcelain: false,
+        verify: optimize.verify.then_some(match optimize.task {
+            Some(task) => VerifyTarget::Task(task),
+            None => VerifyTarget::Random,
+        }),
+        optimize: optimize.level,
+        optimize_task: optimize.task,
+        wrap_repeats: false,
+        split_repeats: false,
+        latin1: false,
+        jobs: config.jobs,
+        container: None,
+        cache: false,
+    };
+    let output_path = optimize
+        .output_path
+        .unwrap_or_else(|| default_output_path(&optimize.input_path, "-optimize"));
+    Ok(do_compress(&optimize.input_path, &output_path, &options)?)
+}
+
+/// Runs a command; Library errors become anyhow errors here at the binary boundary
+fn run(command: Commands, config: &Config, color: bool) -> Result<()> {
+    match command {
+        Commands::Grade(grade_args) => {
+            let options = GradeOptions {
+                progress: !(grade_args.noprogress || config.noprogress == Some(true)),
+                color: color && !grade_args.nocolor,
+                json: grade_args.json,
+                coverage: grade_args.coverage,
+                hash_every: grade_args.hash_every,
+                format: grade_args.format,
+                max_file_size: grade_args.max_file_size,
+                latin1: grade_args.latin1,
+                jobs: grade_args.jobs.or(config.jobs),
+                log: grade_args.log_file.map(|path| RunLog {
+                    path,
+                    arguments: std::env::args().collect(),
+                }),
+                verdicts: config.verdicts.strings(),
+                assert: grade_args.assert,
+                html: grade_args.html,
+                badge: grade_args.badge,
+                notify: grade_args
+                    .notify
+                    .zip(grade_args.webhook)
+                    .map(|(service, webhook)| Notifier { service, webhook }),
+                max_instructions: grade_args.max_instructions,
+                complexity: grade_args.complexity,
+                sparkline: grade_args.sparkline,
+                series: grade_args.series,
+                exhaustive: grade_args.exhaustive,
+                memory_metric: grade_args.memory_metric,
+                show_seeds: grade_args.show_seeds,
+                strict_clean: grade_args.strict_clean,
+                dirty_scratch: grade_args.dirty_scratch,
+                first_touch: grade_args.first_touch,
+                cache: !grade_args.no_cache,
+                seed: Task::seed(),
+                contest: grade_args
+                    .contest
+                    .as_deref()
+                    .map(ContestWindow::load)
+                    .transpose()?,
+                seen_hashes: grade_args.seen_hashes.map(|path| SeenHashes {
+                    path,
+                    reject: grade_args.reject_duplicates,
+                }),
+            };
+            do_grade(grade_args.task, &grade_args.wpk_path, &options)?
+        }
+        Commands::Compress(compress) => run_compress(compress, config)?,
+        Commands::Convert(convert) => {
+            let output_format = match ScriptFormat::from_path(&convert.output_path) {
+                Some(_) => convert.output_format,
+                None => convert.output_format.or(config.output_format),
+            };
+            let options = ConvertOptions {
+                compress: !convert.no_compress,
+                input_format: convert.format,
+                output_format,
+                wrap_repeats: convert.wrap_repeats,
+                split_repeats: convert.split_repeats,
+                latin1: convert.latin1,
+                jobs: convert.jobs.or(config.jobs),
+            };
+            do_convert(&convert.input_path, &convert.output_path, &options)?
+        }
+        Commands::Fmt(fmt) => do_fmt(&fmt.path, &FmtOptions { width: fmt.width })?,
+        Commands::Optimize(optimize) => run_optimize(optimize, config)?,
+        Commands::Canon(canon) => {
+            let options = CanonOptions {
+                format: canon.format,
+                hash: canon.hash,
+            };
+            do_canon(&canon.input_path, &canon.output_path, &options)?
+        }
+        Commands::Seal(seal) => do_seal(&seal.path)?,
+        Commands::Similarity(similarity) => {
+            let options = SimilarityOptions {
+                k: similarity.k,
+                window: similarity.window,
+                threshold: similarity.threshold,
+            };
+            do_similarity(&similarity.pattern, &options)?
+        }
+        Commands::Lint(lint) => do_lint(
+            &lint.path,
+            &LintOptions {
+                format: lint.format,
+            },
+        )?,
+        Commands::Lsp(_) => do_lsp()?,
+        Commands::Tokens(tokens) => {
+            let options = TokensOptions {
+                format: tokens.format,
+                json: tokens.json,
+            };
+            do_tokens(&tokens.path, &options)?
+        }
+        Commands::Render(render) => {
+            let options = RenderOptions {
+                format: render.format,
+                html: render.html,
+            };
+            do_render(&render.path, &options)?
+        }
+        Commands::Bench(bench) => {
+            let program = match bench.program.as_str() {
+                "synthetic" => BenchProgram::Synthetic,
+                path if check_valid_extension(path) => BenchProgram::File(path.to_string()),
+                path => {
+                    return Err(anyhow!(
+                        "Invalid benchmark program {}, should be \"synthetic\" or a script path",
+                        path
+                    ))
+                }
+            };
+            do_bench(&BenchOptions {
+                mem_bits: bench.mem_bits,
+                program,
+            })?
+        }
+        Commands::Calibrate(calibrate) => {
+            let options = CalibrateOptions {
+                runs: calibrate.runs,
+                output_path: calibrate.out,
+            };
+            do_calibrate(&options)?
+        }
+        Commands::Record(record) => {
+            let options = RecordOptions {
+                format: record.format,
+                seed: record.seed,
+                hash_every: record.hash_every,
+            };
+            do_record(record.task, &record.wpk_path, &record.output_path, &options)?
+        }
+        Commands::Replay(replay) => do_replay(&replay.path)?,
+        Commands::Compare(compare) => do_compare(&compare.old_path, &compare.new_path)?,
+        Commands::Repl(repl) => do_repl(&ReplOptions {
+            format: repl.format,
+        })?,
+        Commands::Init(init) => {
+            let name = init
+                .name
+                .unwrap_or_else(|| format!("task{}", init.task.name()));
+            do_init(init.task, &InitOptions { name })?
+        }
+        Commands::Contest(ContestCommand::Grade(grade)) => {
+            let options = ContestOptions {
+                task_map_path: grade.task_map,
+                out_dir: grade.out,
+            };
+            do_contest_grade(&grade.submissions_dir, &options)?
+        }
+        Commands::Reference(reference) => do_reference(reference.task, &reference.out)?,
+        Commands::Test(test) => do_test(
+            &test.path,
+            &TestOptions {
+                format: test.format,
+            },
+        )?,
+        Commands::Run(run) => {
+            let options = RunOptions {
+                format: run.format,
+                tc_id: run.tc,
+                seed: run.seed,
+                explain: run.explain,
+                max_file_size: run.max_file_size,
+                latin1: run.latin1,
+                memory_metric: run.memory_metric,
+                strict_clean: run.strict_clean,
+                dirty_scratch: run.dirty_scratch,
+            };
+            do_run(run.task, &run.wpk_path, &options)?
+        }
+        Commands::When(when) => {
+            let options = WhenOptions {
+                format: when.format,
+                task: when.task,
+                tc_id: when.case,
+                seed: when.seed,
+                bit: when.bit,
+            };
+            do_when(&when.wpk_path, &options)?
+        }
+        Commands::Predict(predict) => {
+            let options = PredictOptions {
+                format: predict.format,
+                task: predict.task,
+                samples: predict.samples,
+            };
+            do_predict(&predict.path, &options)?
+        }
+        Commands::Stats(stats) => do_stats(
+            &stats.path,
+            &StatsOptions {
+                format: stats.format,
+            },
+        )?,
+        Commands::Vectors(vectors) => {
+            let options = VectorsOptions {
+                seed: vectors.seed,
+                output_path: vectors.out,
+            };
+            do_vectors(&options)?
+        }
+        Commands::Conformance(conformance) => {
+            let options = ConformanceOptions {
+                runner: conformance.runner,
+                script: conformance.task.zip(conformance.wpk_path),
+                format: conformance.format,
+            };
+            do_conformance(&options)?
+        }
+        Commands::ExplainTask(explain) => {
+            let options = ExplainTaskOptions {
+                tc_id: explain.tc,
+                seed: explain.seed,
+            };
+            do_explain_task(explain.task, &options)?
+        }
+        Commands::Tc(tc) => {
+            let options = TcOptions { seed: tc.seed };
+            do_tc(tc.task, tc.id, &options)?
+        }
+        Commands::Selftest(selftest) => {
+            let options = SelftestOptions {
+                digests_path: selftest.digests,
+                save_digests_path: selftest.save_digests,
+            };
+            do_selftest(&options)?
+        }
+        Commands::Completions(completions) => clap_complete::generate(
+            completions.shell,
+            &mut Cli::command(),
+            "wpkpp",
+            &mut std::io::stdout(),
+        ),
+    }
+    Ok(())
+}
+
+/// Sends library logs to stderr, so they never mix with JSON on stdout
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
 }
 
 fn main() {
     let args = Cli::parse();
-    let res = match args.command {
-        Commands::Grade(grade_args) => {
-            do_grade(grade_args.task, &grade_args.wpk_path, !grade_args.noprogress, !grade_args.nocolor, grade_args.json)
-        },
-        Commands::Compress(compress) => {
-            let input_path = compress.input_path;
-            let output_path = compress.output_path.unwrap_or_else(|| {
-                let extension_idx = input_path.rfind(".wpk").unwrap();
-                let basename = &input_path[..extension_idx];
-                let extension = &input_path[extension_idx..];
-                basename.to_string() + "-compress" + extension
-            });
-            do_compress(input_path.as_str(), output_path.as_str())
+    init_logging(args.verbose);
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
     };
-    if let Some(e) = res.err() {
-        println!("Error: {}", e);
+    // Testcases read their seed from the environment, which the config only fills in
+    if let (Some(seed), None) = (&config.seed, std::env::var_os("WPKPP_SEED")) {
+        std::env::set_var("WPKPP_SEED", seed);
+    }
+    let color = args
+        .color
+        .or(config.color)
+        .unwrap_or(ColorChoice::Auto)
+        .enabled();
+    // colored makes its own guess otherwise, which would undo --color always
+    colored::control::set_override(color);
+    if let Err(e) = run(args.command, &config, color) {
+        eprintln!("Error: {}", e);
         process::exit(1);
     }
     process::exit(0);
-}
\ No newline at end of file
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::parse::ParseErrorKind;
+
+/// Starts a metadata comment, which must come before anything else in a script
+pub const META_STR: &str = ";!";
+const META_FIELD_SEPARATOR: char = ',';
+const META_VALUE_SEPARATOR: char = '=';
+
+/// Fields of a leading `;! author=ada, task=5, version=3` comment, in the order written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptMeta {
+    pub fields: Vec<(String, String)>,
+}
+
+impl ScriptMeta {
+    /// Parses the text after `;!`; Keys are alphanumeric, and values may not hold commas
+    pub fn parse(text: &str) -> Result<Self, ParseErrorKind> {
+        let mut fields: Vec<(String, String)> = vec![];
+        for field in text.split(META_FIELD_SEPARATOR).map(str::trim) {
+            if field.is_empty() {
+                continue;
+            }
+            let (key, value) = field
+                .split_once(META_VALUE_SEPARATOR)
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| ParseErrorKind::InvalidMetadata(field.to_string()))?;
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                || fields.iter().any(|(other, _)| other == key)
+            {
+                return Err(ParseErrorKind::InvalidMetadata(field.to_string()));
+            }
+            fields.push((key.to_string(), value.to_string()));
+        }
+        Ok(Self { fields })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(other, _)| other == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.get("author")
+    }
+
+    pub fn task(&self) -> Option<&str> {
+        self.get("task")
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.get("version")
+    }
+
+    /// Fields keyed by name, for JSON output
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.fields.iter().cloned().collect()
+    }
+}
+
+impl fmt::Display for ScriptMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}{}{}", key, META_VALUE_SEPARATOR, value))
+            .collect::<Vec<_>>();
+        write!(f, "{}", fields.join(", "))
+    }
+}
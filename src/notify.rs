@@ -0,0 +1,176 @@
+//! `grade --notify`: A short, human readable summary of a grade posted to a Slack or Discord
+//! channel through an incoming webhook, e.g. for a team following a contest.
+
+use miniserde::{json, Serialize};
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::VerdictStrings,
+    report::{abbreviate, GradeReport},
+    task::Task,
+};
+
+/// Chat service a webhook belongs to, deciding how messages are shaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatService {
+    Slack,
+    Discord,
+}
+
+impl ChatService {
+    pub const NAMES: [&'static str; 2] = ["slack", "discord"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "slack" => Some(Self::Slack),
+            "discord" => Some(Self::Discord),
+            _ => None,
+        }
+    }
+
+    fn bold(self, text: &str) -> String {
+        match self {
+            Self::Slack => format!("*{}*", text),
+            Self::Discord => format!("**{}**", text),
+        }
+    }
+}
+
+/// Where `grade --notify` posts its summary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notifier {
+    pub service: ChatService,
+    pub webhook: String,
+}
+
+#[derive(Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct DiscordMessage {
+    content: String,
+}
+
+/// One line summing up a grade, e.g. "*Task 5* solution.wpk: OK 🎉 100/100, 1.2M steps, 214
+/// cells"
+pub fn summary(
+    service: ChatService,
+    task: Task,
+    path: &str,
+    report: &GradeReport,
+    verdicts: &VerdictStrings,
+) -> String {
+    format!(
+        "{} {}: {} {}/{}, {} steps, {} cells",
+        service.bold(&format!("Task {}", task.name())),
+        path,
+        verdicts.render_report(report),
+        report.score,
+        report.total,
+        abbreviate(report.runtime),
+        report.memory
+    )
+}
+
+impl Notifier {
+    /// The webhook request body carrying `text`
+    pub fn payload(&self, text: String) -> String {
+        match self.service {
+            ChatService::Slack => json::to_string(&SlackMessage { text }),
+            ChatService::Discord => json::to_string(&DiscordMessage { content: text }),
+        }
+    }
+
+    /// Posts a summary of the grade to the webhook
+    pub fn notify(
+        &self,
+        task: Task,
+        path: &str,
+        report: &GradeReport,
+        verdicts: &VerdictStrings,
+    ) -> Result<()> {
+        let text = summary(self.service, task, path, report, verdicts);
+        post(&self.webhook, &self.payload(text))
+    }
+}
+
+#[cfg(feature = "notify")]
+fn post(url: &str, body: &str) -> Result<()> {
+    ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(body)
+        .map_err(|e| WpkppError::NotifyFailed(format!("Posting to the webhook failed: {}", e)))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+fn post(_url: &str, _body: &str) -> Result<()> {
+    Err(WpkppError::NotifyFailed(
+        "wpkpp was built without the notify feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        report::{OpCounts, Timings},
+        vm::MemoryMetric,
+    };
+
+    fn report(passed: bool, late: bool) -> GradeReport {
+        GradeReport {
+            passed,
+            score: if passed { 100 } else { 99 },
+            total: 100,
+            runtime: 1_200_000,
+            runtime_aggregates: None,
+            runtime_percentiles: None,
+            memory: 214,
+            instructions: OpCounts::default(),
+            timings: Timings::default(),
+            coverage: None,
+            transcript: None,
+            meta: None,
+            hash: None,
+            max_instructions: None,
+            late,
+            complexity: None,
+            exhaustive: false,
+            memory_metric: MemoryMetric::Span,
+            strict_clean: false,
+            dirty_scratch: false,
+        }
+    }
+
+    #[test]
+    fn late_grades_are_summed_up_as_late() {
+        let task = Task::from_str("5").unwrap();
+        let verdicts = VerdictStrings::default();
+        let text = |passed, late| {
+            summary(
+                ChatService::Slack,
+                task,
+                "a.wpk",
+                &report(passed, late),
+                &verdicts,
+            )
+        };
+        assert_eq!(
+            text(true, false),
+            "*Task 5* a.wpk: OK 🎉 100/100, 1.2M steps, 214 cells"
+        );
+        assert_eq!(
+            text(false, false),
+            "*Task 5* a.wpk: WA ❌ 99/100, 1.2M steps, 214 cells"
+        );
+
+        // Late is late whatever the score, as in the text report
+        let late = "*Task 5* a.wpk: LATE (submitted outside the contest window) 100/100, 1.2M \
+                    steps, 214 cells";
+        assert_eq!(text(true, true), late);
+        assert!(text(false, true).starts_with("*Task 5* a.wpk: LATE "));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::{
+    parse::{Script, SourceMap},
+    vm::{Instruction, Instructions, MEMORY_BITS},
+};
+
+/// Highest supported `--optimize` level
+pub const MAX_OPTIMIZE_LEVEL: u8 = 3;
+
+pub struct OptimizeReport {
+    pub instructions_removed: usize,
+    /// Steps saved each time the removed instructions would have run
+    pub steps_saved: u64,
+}
+
+/// Removes provably redundant instructions from a script, keeping labels, comments and the
+/// source map pointing at the right instructions. Level 0 leaves the script untouched;
+/// Level 1 drops `INV INV` pairs and zero length INC / CDEC; Level 2 also drops a LOAD right
+/// after another LOAD, which reads the same cell again; Level 3 also drops CDECs which run
+/// while the register is known to be false, i.e. before the first LOAD.
+pub fn optimize_script(script: &mut Script, level: u8) -> OptimizeReport {
+    let mut report = OptimizeReport {
+        instructions_removed: 0,
+        steps_saved: 0,
+    };
+    if level == 0 {
+        return report;
+    }
+
+    let old_instructions = std::mem::take(&mut script.instructions);
+    let old_source_map = script.source_map.take();
+    let mut instructions = Instructions::with_capacity(old_instructions.len());
+    let mut source_map: Option<SourceMap> = old_source_map.as_ref().map(|_| vec![]);
+    // New index of the position before each old instruction, plus one for the end
+    let mut remap: Vec<usize> = Vec::with_capacity(old_instructions.len() + 1);
+    let mut labels = script.labels.iter().map(|(idx, _)| *idx).peekable();
+    let mut floor = 0;
+    // Scripts have no jumps, so the register stays false until the first LOAD
+    let mut register_false = true;
+
+    for (idx, instruction) in old_instructions.iter().enumerate() {
+        remap.push(instructions.len());
+        // Nothing may be merged or cancelled across a label
+        while labels.next_if(|label_idx| *label_idx <= idx).is_some() {
+            floor = instructions.len();
+        }
+        let tail = match instructions.len() > floor {
+            true => instructions.last().copied(),
+            false => None,
+        };
+
+        match (instruction, tail) {
+            (Instruction::Inc(0) | Instruction::Cdec(0), _) => {}
+            (Instruction::Inv, Some(Instruction::Inv)) => {
+                instructions.pop();
+                if let Some(source_map) = source_map.as_mut() {
+                    source_map.pop();
+                }
+                report.steps_saved += 2;
+            }
+            (Instruction::Load, Some(Instruction::Load)) if level >= 2 => {
+                report.steps_saved += 1;
+            }
+            (Instruction::Cdec(x), _) if level >= 3 && register_false => {
+                report.steps_saved += *x as u64;
+            }
+            _ => {
+                if *instruction == Instruction::Load {
+                    register_false = false;
+                }
+                let new_idx = instructions.push_compressed(*instruction, floor);
+                if let (Some(new_idx), Some(source_map), Some(old_source_map)) =
+                    (new_idx, source_map.as_mut(), old_source_map.as_ref())
+                {
+                    let span = old_source_map[idx];
+                    match source_map.get_mut(new_idx) {
+                        Some(merged) => merged.cover(span),
+                        None => source_map.push(span),
+                    }
+                }
+            }
+        }
+    }
+    remap.push(instructions.len());
+    report.instructions_removed = old_instructions.len() - instructions.len();
+
+    for (idx, _) in script.labels.iter_mut() {
+        *idx = remap[*idx];
+    }
+    for (idx, _) in script.comments.iter_mut() {
+        *idx = remap[*idx];
+    }
+    script.instructions = instructions;
+    script.source_map = source_map;
+
+    report
+}
+
+/// Whether a pointer anywhere in `lo..=hi`, as an unwrapped offset from address 0, may land
+/// inside the addresses `start..end`. Addresses alias modulo `MEMORY_BITS`, like the cells
+/// `Vm::cell_index` maps them to.
+fn may_reach(lo: i64, hi: i64, (start, end): (u64, u64)) -> bool {
+    let mem_size = MEMORY_BITS as i64;
+    let span = hi - lo;
+    if span + 1 >= mem_size {
+        return true;
+    }
+    let lo = lo.rem_euclid(mem_size);
+    let hi = lo + span;
+    let start_wrapped = (start as i64).rem_euclid(mem_size);
+    let (start, end) = (start_wrapped, start_wrapped + (end - start) as i64);
+    // The wrapped range may run past the top of memory into the region's copy above it
+    (lo < end && start <= hi) || (lo < end + mem_size && start + mem_size <= hi)
+}
+
+/// Drops every instruction after the last INV which might write inside the `output`
+/// addresses. Scripts have no jumps, so the pointer after each instruction is bounded by
+/// adding up INCs, and CDECs which may or may not be taken; Past the last INV which could
+/// land in the output, nothing can change the answer.
+pub fn eliminate_dead_tail(script: &mut Script, output: (u64, u64)) -> OptimizeReport {
+    let (mut lo, mut hi): (i64, i64) = (0, 0);
+    let mut keep = 0;
+    for (idx, instruction) in script.instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Inc(x) => {
+                lo += *x as i64;
+                hi += *x as i64;
+            }
+            Instruction::Cdec(x) => lo -= *x as i64,
+            Instruction::Inv if may_reach(lo, hi, output) => keep = idx + 1,
+            _ => {}
+        }
+    }
+
+    let removed = &script.instructions[keep..];
+    let report = OptimizeReport {
+        instructions_removed: removed.len(),
+        steps_saved: removed
+            .iter()
+            .map(|instruction| match instruction {
+                Instruction::Inc(x) | Instruction::Cdec(x) => *x as u64,
+                _ => 1,
+            })
+            .sum(),
+    };
+
+    script.instructions.truncate(keep);
+    if let Some(source_map) = script.source_map.as_mut() {
+        source_map.truncate(keep);
+    }
+    for (idx, _) in script.labels.iter_mut() {
+        *idx = (*idx).min(keep);
+    }
+    for (idx, _) in script.comments.iter_mut() {
+        *idx = (*idx).min(keep);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse::{parse_script_bytes, ScriptFormat},
+        test_util::{random_window, run_window},
+        vm::Vm,
+    };
+
+    /// Scripts exercising every pass, with labels to keep passes from reaching across
+    const SCRIPTS: &[&str] = &[
+        "INV\nINV\nLOAD\nCDEC 3\nINC 0\nINV\n",
+        "INC 2\nINV\nINV\nINV\nLOAD\nCDEC 1\nINV\nINV\nINC 5\nLOAD\nINV\n",
+        "INV\n@again:\nINV\nINC 0\nCDEC 0\nLOAD\nCDEC 2\nINV\n",
+        "REPEAT 6 {\nINV\nLOAD\nINC 1\n}\nREPEAT 4 {\nINV\n}\nCDEC 3\nLOAD\n",
+        "DEFINE STEP 3\nREPEAT 5 {\nINC STEP\nINV\nINV\nLOAD\nCDEC STEP\nINV\n}\n",
+        "LOAD\nLOAD\nCDEC 1\nLOAD\nINV\nINV\nLOAD\nLOAD\n@read:\nLOAD\nCDEC 4\nINV\n",
+        "CDEC 5\nINC 2\nCDEC 1\nINV\n@start:\nCDEC 3\nLOAD\nCDEC 2\nINV\n",
+    ];
+    const TRIALS: u64 = 32;
+    /// Cells either side of address 0 given random values; Wide enough for every script above
+    const WINDOW: i64 = 64;
+
+    fn optimized(source: &str, level: u8) -> (Script, Script, OptimizeReport) {
+        let original = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+        let mut script = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+        let report = optimize_script(&mut script, level);
+        (original, script, report)
+    }
+
+    #[test]
+    fn level_zero_changes_nothing() {
+        for source in SCRIPTS {
+            let (original, script, report) = optimized(source, 0);
+            assert_eq!(script.instructions, original.instructions);
+            assert_eq!(report.instructions_removed, 0);
+            assert_eq!(report.steps_saved, 0);
+        }
+    }
+
+    #[test]
+    fn inv_pairs_and_empty_moves_are_dropped() {
+        let (_, script, report) = optimized("INV\nINV\nLOAD\nINC 0\nINV\nINV\nINV\nCDEC 0\n", 1);
+        assert_eq!(
+            script.instructions[..],
+            [Instruction::Load, Instruction::Inv]
+        );
+        assert_eq!(report.instructions_removed, 6);
+        assert_eq!(report.steps_saved, 4);
+    }
+
+    #[test]
+    fn repeated_loads_are_dropped_from_level_two() {
+        let source = "LOAD\nLOAD\nINV\nINV\nLOAD\nCDEC 2\nLOAD\n";
+        let (original, script, _) = optimized(source, 1);
+        assert_eq!(script.instructions.len(), original.instructions.len() - 2);
+
+        let (_, script, report) = optimized(source, 2);
+        assert_eq!(
+            script.instructions[..],
+            [Instruction::Load, Instruction::Cdec(2), Instruction::Load]
+        );
+        assert_eq!(report.steps_saved, 4);
+    }
+
+    #[test]
+    fn cdecs_before_the_first_load_are_dropped_from_level_three() {
+        let source = "CDEC 5\nINC 2\nCDEC 1\nINV\nLOAD\nCDEC 2\nINV\n";
+        let (original, script, _) = optimized(source, 2);
+        assert_eq!(script.instructions, original.instructions);
+
+        let (_, script, report) = optimized(source, 3);
+        assert_eq!(
+            script.instructions[..],
+            [
+                Instruction::Inc(2),
+                Instruction::Inv,
+                Instruction::Load,
+                Instruction::Cdec(2),
+                Instruction::Inv
+            ]
+        );
+        assert_eq!(report.steps_saved, 6);
+    }
+
+    #[test]
+    fn labels_fence_off_optimization() {
+        let (original, script, _) = optimized("INV\n@again:\nINV\nLOAD\n", 1);
+        assert_eq!(script.instructions, original.instructions);
+        assert_eq!(script.labels, original.labels);
+
+        let (_, script, _) = optimized("INV\nINV\n@end:\nLOAD\n", 1);
+        assert_eq!(script.instructions[..], [Instruction::Load]);
+        assert_eq!(script.labels[0].0, 0);
+    }
+
+    #[test]
+    fn optimized_scripts_run_like_the_original() {
+        let mut vm = Vm::new(Instructions::new());
+        for level in 1..=MAX_OPTIMIZE_LEVEL {
+            for source in SCRIPTS {
+                let (original, script, report) = optimized(source, level);
+                for trial in 0..TRIALS {
+                    let input = random_window(&format!("WPKPP/optimize/{}", trial), WINDOW);
+
+                    let (cells, ptr, register, runtime) =
+                        run_window(&mut vm, &original.instructions, &input);
+                    let optimized = run_window(&mut vm, &script.instructions, &input);
+                    let context = format!("level {} trial {} on {:?}", level, trial, source);
+                    assert_eq!(
+                        (&optimized.0, optimized.1, optimized.2),
+                        (&cells, ptr, register),
+                        "{}",
+                        context
+                    );
+                    assert_eq!(
+                        report.steps_saved as i64,
+                        runtime - optimized.3,
+                        "{}",
+                        context
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pointer_ranges_reach_wrapped_regions() {
+        let top = MEMORY_BITS as u64;
+        assert!(may_reach(0, 0, (0, 1)));
+        assert!(!may_reach(1, 7, (8, 16)));
+        assert!(may_reach(1, 8, (8, 16)));
+        assert!(!may_reach(16, 40, (8, 16)));
+        assert!(may_reach(-3, -1, (top - 4, top)));
+        assert!(!may_reach(-3, -1, (0, 8)));
+        assert!(may_reach(top as i64 - 2, top as i64 + 1, (0, 1)));
+        assert!(may_reach(-(top as i64), 0, (top / 2, top / 2 + 1)));
+        assert!(may_reach(1, 1, (top + 1, top + 2)));
+    }
+
+    #[test]
+    fn tails_past_the_last_output_write_are_dropped() {
+        let source = "INC 8\nINV\nINC 1\nINV\nINC 20\nINV\nLOAD\n";
+        let mut script = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+        let report = eliminate_dead_tail(&mut script, (8, 16));
+        assert_eq!(
+            script.instructions[..],
+            [
+                Instruction::Inc(8),
+                Instruction::Inv,
+                Instruction::Inc(1),
+                Instruction::Inv
+            ]
+        );
+        assert_eq!(report.instructions_removed, 3);
+        assert_eq!(report.steps_saved, 22);
+
+        // A CDEC which may or may not be taken leaves the INV after it able to reach the output
+        let source = "INC 30\nLOAD\nCDEC 20\nINV\nINC 40\n";
+        let mut script = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+        eliminate_dead_tail(&mut script, (8, 16));
+        assert_eq!(script.instructions.len(), 4);
+    }
+
+    #[test]
+    fn writes_aliasing_the_output_are_kept() {
+        // The second INV lands one memory size past cell 8, which is cell 8 again
+        let source = format!("INC 30\nINV\nINC {}\nINV\nINC 20\nINV\n", MEMORY_BITS - 22);
+        let mut script = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+        let report = eliminate_dead_tail(&mut script, (8, 16));
+        assert_eq!(script.instructions.len(), 4);
+        assert_eq!(report.instructions_removed, 2);
+    }
+
+    #[test]
+    fn dead_tails_do_not_change_the_output() {
+        const OUTPUT: (u64, u64) = (8, 16);
+        let sources = [
+            "INC 8\nINV\nINC 1\nINV\nINC 20\nINV\nLOAD\n",
+            "INC 12\nLOAD\nCDEC 6\nINV\nINC 3\nLOAD\nCDEC 1\nINV\nINC 30\nINV\n",
+            "REPEAT 10 {\nINC 2\nLOAD\nINV\nCDEC 1\n}\nINC 20\nINV\nCDEC 5\n",
+            "CDEC 4\nINV\nINC 2\nINV\n",
+        ];
+        let output_cells =
+            (WINDOW as usize + OUTPUT.0 as usize)..(WINDOW as usize + OUTPUT.1 as usize);
+        let mut vm = Vm::new(Instructions::new());
+        for source in sources {
+            let original = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+            let mut script = parse_script_bytes(source.as_bytes(), ScriptFormat::Wpk).unwrap();
+            eliminate_dead_tail(&mut script, OUTPUT);
+            for trial in 0..TRIALS {
+                let input = random_window(&format!("WPKPP/dead-tail/{}", trial), WINDOW);
+
+                let (cells, ..) = run_window(&mut vm, &original.instructions, &input);
+                let (optimized, ..) = run_window(&mut vm, &script.instructions, &input);
+                assert_eq!(
+                    optimized[output_cells.clone()],
+                    cells[output_cells.clone()],
+                    "trial {} on {:?}",
+                    trial,
+                    source
+                );
+            }
+        }
+    }
+}
@@ -1,311 +1,2965 @@
-use anyhow::{anyhow, Result};
-use std::fs::File;
+use flate2::read::GzDecoder;
+use memmap2::Mmap;
+use miniserde::{json, Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::max;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{prelude::*, BufReader, BufWriter};
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use thiserror::Error;
+use tracing::{debug, instrument};
 use utf8_chars::BufReadCharsExt;
 
-use crate::vm::{Instruction, Instructions, VmUsize, WpkOpcount, MEM_SIZE};
+use crate::{
+    assertion::Assertion,
+    cache::parse_script_cached,
+    container::{decode_container, encode_container, ContainerEncoding},
+    error::{Result, WpkppError},
+    meta::{ScriptMeta, META_STR},
+    optimize::{eliminate_dead_tail, optimize_script},
+    seal::{ChecksumState, CHECKSUM_STR},
+    task::Task,
+    util::{fnv1a, InstructionCount, FNV_OFFSET},
+    verify::{verify_equivalence, VerifyTarget},
+    vm::{Instruction, Instructions, VmUsize, MEM_SIZE},
+};
 
-const INC_STR: &str = "INC";
-const CDEC_STR: &str = "CDEC";
-const LOAD_STR: &str = "LOAD";
-const INV_STR: &str = "INV";
+pub(crate) const INC_STR: &str = "INC";
+pub(crate) const CDEC_STR: &str = "CDEC";
+pub(crate) const LOAD_STR: &str = "LOAD";
+pub(crate) const INV_STR: &str = "INV";
+pub(crate) const REPEAT_STR: &str = "REPEAT";
+pub(crate) const DEFINE_STR: &str = "DEFINE";
+pub(crate) const BLOCK_OPEN_STR: &str = "{";
+pub(crate) const BLOCK_CLOSE_STR: &str = "}";
 
-const INC_M_STR: char = '>';
-const CDEC_M_STR: char = '<';
-const LOAD_M_STR: char = '?';
-const LOAD_M_STR_ALT: char = 'v';
-const INV_M_STR: char = '!';
-const INV_M_STR_ALT: char = '^';
+pub(crate) const INC_M_STR: char = '>';
+pub(crate) const CDEC_M_STR: char = '<';
+pub(crate) const LOAD_M_STR: char = '?';
+pub(crate) const LOAD_M_STR_ALT: char = 'v';
+pub(crate) const INV_M_STR: char = '!';
+pub(crate) const INV_M_STR_ALT: char = '^';
+pub(crate) const BLOCK_OPEN_M_STR: char = '[';
+pub(crate) const BLOCK_CLOSE_M_STR: char = ']';
 
-const MEGABYTE: u64 = 1_000_000;
+pub(crate) const COMMENT_STR: char = '#';
+pub(crate) const COMMENT_STR_ALT: char = ';';
+
+pub(crate) const LABEL_START: char = '@';
+pub(crate) const LABEL_END: char = ':';
+/// Byte order mark, skipped at the start of text scripts
+pub(crate) const BOM: char = '\u{feff}';
+
+pub(crate) const MEGABYTE: u64 = 1_000_000;
 const MAX_FILE_SIZE: u64 = 10_000_000;
 const MAX_M_FILE_SIZE: u64 = 5_000_000;
+const MAX_B_FILE_SIZE: u64 = 5_000_000;
+#[cfg(not(test))]
+const MAX_REPEAT_EXPANSION: u64 = 100_000_000;
+// Small enough for tests to go over it without expanding a hundred million instructions
+#[cfg(test)]
+const MAX_REPEAT_EXPANSION: u64 = 1_000_000;
+const MAX_DECOMPRESSED_SIZE: u64 = 1_000_000_000;
+const MMAP_THRESHOLD: u64 = 1_000_000;
+const PARALLEL_THRESHOLD: u64 = 1_000_000;
 
-pub fn check_valid_extension(path: &str) -> bool {
-    path.ends_with(".wpk") || path.ends_with(".wpkm")
+/// Bytes which carry parser state across instructions and so rule out chunked parsing
+const WPKM_STATEFUL_BYTES: &[u8] = b"#;@[]";
+const WPKM_BOUNDARY_BYTES: &[u8] = b"?v!^";
+
+const WPKB_MAGIC: &[u8] = b"WPKB";
+const WPKB_VERSION: u8 = 1;
+const WPKB_OP_INC: u64 = 0;
+const WPKB_OP_CDEC: u64 = 1;
+const WPKB_OP_LOAD: u64 = 2;
+const WPKB_OP_INV: u64 = 3;
+const WPKB_CHECKSUM_SIZE: usize = 8;
+
+/// Extension of text containers wrapping a script in any format
+pub const CONTAINER_EXTENSION: &str = ".wpkc";
+
+/// Path standing in for stdin
+pub const STDIN_PATH: &str = "-";
+/// Path standing in for stdout
+pub const STDOUT_PATH: &str = "-";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScriptFormat {
+    Wpk,
+    Wpkm,
+    Wpkb,
 }
 
-fn push_and_compress_instruction(instructions: &mut Instructions, new_instruction: Instruction) {
-    let n_instructions = instructions.len();
-    let tail = instructions
-        .get(n_instructions.wrapping_sub(1))
-        .map(|x| x.clone());
-    let tail_two = instructions
-        .get(n_instructions.wrapping_sub(2))
-        .map(|x| x.clone());
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScriptCompression {
+    None,
+    Gzip,
+    Zstd,
+}
 
-    match (new_instruction, tail, tail_two) {
-        (Instruction::Null, _, _) => {}
-        (Instruction::Inc(x), Some(Instruction::Inc(y)), _) => {
-            instructions[n_instructions - 1] = Instruction::Inc(y.wrapping_add(x));
+impl ScriptCompression {
+    /// Splits a trailing ".gz" / ".zst" off a path
+    pub fn split_path(path: &str) -> (&str, Self) {
+        if let Some(inner) = path.strip_suffix(".gz") {
+            (inner, Self::Gzip)
+        } else if let Some(inner) = path.strip_suffix(".zst") {
+            (inner, Self::Zstd)
+        } else {
+            (path, Self::None)
         }
-        (Instruction::Cdec(x), Some(Instruction::Cdec(y)), _) => {
-            instructions[n_instructions - 1] = Instruction::Cdec(y.wrapping_add(x));
+    }
+}
+
+impl ScriptFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wpk" => Some(Self::Wpk),
+            "wpkm" => Some(Self::Wpkm),
+            "wpkb" => Some(Self::Wpkb),
+            _ => None,
         }
-        (Instruction::Inc(x), Some(Instruction::Cdec(_)), Some(Instruction::Inc(y))) => {
-            instructions[n_instructions - 2] = Instruction::Inc(y.wrapping_add(x));
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wpk => "wpk",
+            Self::Wpkm => "wpkm",
+            Self::Wpkb => "wpkb",
         }
-        (Instruction::Cdec(x), Some(Instruction::Inc(_)), Some(Instruction::Cdec(y))) => {
-            instructions[n_instructions - 2] = Instruction::Cdec(y.wrapping_add(x));
+    }
+
+    /// Largest file accepted by the grader for this format by default
+    pub fn default_max_file_size(&self) -> u64 {
+        match self {
+            Self::Wpk => MAX_FILE_SIZE,
+            Self::Wpkm => MAX_M_FILE_SIZE,
+            Self::Wpkb => MAX_B_FILE_SIZE,
         }
-        _ => {
-            instructions.push(new_instruction);
+    }
+
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (path, _) = ScriptCompression::split_path(path);
+        if path.ends_with(".wpk") {
+            Some(Self::Wpk)
+        } else if path.ends_with(".wpkm") {
+            Some(Self::Wpkm)
+        } else if path.ends_with(".wpkb") {
+            Some(Self::Wpkb)
+        } else {
+            None
         }
     }
 }
 
-fn parse_wpk_line(raw_instruction: &[&str], line_trace: usize) -> Result<Instruction> {
-    let instruction = match raw_instruction {
-        [] => Instruction::Null,
-        [INC_STR] => Instruction::Inc(1),
-        [INC_STR, nstr] => {
-            let x: u64 = nstr.parse().map_err(|e| {
-                anyhow!(
-                    "{}: {} @ line {}",
-                    e,
-                    raw_instruction.join(" "),
-                    line_trace + 1
-                )
-            })?;
-            if (x as usize) >= MEM_SIZE {
-                Err(anyhow!(
-                    "INC repetition of {} too large @ line {}",
-                    x,
-                    line_trace
-                ))?;
-            }
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseErrorKind {
+    #[error("Unknown instruction")]
+    UnknownInstruction,
+    #[error("{0}")]
+    InvalidNumber(ParseIntError),
+    #[error("{0} repetition of {1} too large; the largest is {}, or try --split-repeats", MEM_SIZE - 1)]
+    RepeatTooLarge(&'static str, String),
+    #[error("Cannot repeat {0} instruction {1} times")]
+    RepeatNotAllowed(&'static str, String),
+    #[error("Repeat of {0} times too large")]
+    CountTooLarge(String),
+    #[error("Dangling repeat {0}")]
+    DanglingRepeat(String),
+    #[error("REPEAT block missing repeat count")]
+    MissingBlockRepeat,
+    #[error("Unmatched end of REPEAT block")]
+    UnmatchedBlockEnd,
+    #[error("{0} unclosed REPEAT block(s)")]
+    UnclosedBlocks(usize),
+    #[error(
+        "REPEAT blocks and split repeats expand to over {} instructions",
+        MAX_REPEAT_EXPANSION
+    )]
+    ExpansionTooLarge,
+    #[error("Invalid label name \"{0}\"")]
+    InvalidLabel(String),
+    #[error("Duplicate label \"{0}\"")]
+    DuplicateLabel(String),
+    #[error("Label \"{0}\" cannot be inside a REPEAT block")]
+    LabelInBlock(String),
+    #[error("Unterminated label \"{0}\"")]
+    UnterminatedLabel(String),
+    #[error("Invalid constant name \"{0}\"")]
+    InvalidConstant(String),
+    #[error("Constant \"{0}\" defined twice")]
+    DuplicateConstant(String),
+    #[error("Invalid metadata field \"{0}\"; expected a unique key=value")]
+    InvalidMetadata(String),
+    #[error("Checksum mismatch, script hashes to {0}; the file is truncated or corrupted")]
+    ChecksumMismatch(String),
+    #[error("Checksum footer must be the last line of the script")]
+    MisplacedChecksum,
+    #[error("Invalid UTF-8 at byte {0}; pass --latin1 for Latin-1 scripts")]
+    InvalidUtf8(usize),
+    #[error("Invalid .wpkb data: {0}")]
+    InvalidBinary(String),
+    #[error("Invalid assertion \"{0}\"; expected e.g. \"#assert ptr == 32\" or \"#assert mem[16..20] != 0b1010\"")]
+    InvalidAssertion(String),
+    #[error("Assertions cannot be inside a REPEAT block")]
+    AssertionInBlock,
+}
+
+/// A syntax error located in the source script. Positions are 1-based; .wpkb errors are
+/// reported on line 1 with the byte offset as the column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub line: usize,
+    pub column: usize,
+    /// Offending source text, or empty if there is none to show
+    pub snippet: String,
+}
 
-            Instruction::Inc(x as VmUsize)
+impl ParseError {
+    fn new(kind: ParseErrorKind, line: usize, column: usize, snippet: &str) -> Self {
+        Self {
+            kind,
+            line,
+            column,
+            snippet: snippet.to_string(),
         }
-        [CDEC_STR] => Instruction::Cdec(1),
-        [CDEC_STR, nstr] => {
-            let x: u64 = nstr.parse().map_err(|e| {
-                anyhow!(
-                    "{}: {} @ line {}",
-                    e,
-                    raw_instruction.join(" "),
-                    line_trace + 1
-                )
-            })?;
-            if (x as usize) >= MEM_SIZE {
-                Err(anyhow!(
-                    "CDEC repetition of {} too large @ line {}",
-                    x,
-                    line_trace
-                ))?;
-            }
-
-            Instruction::Cdec(x as VmUsize)
-        }
-        [LOAD_STR] => Instruction::Load,
-        [INV_STR] => Instruction::Inv,
-        _ => {
-            return Err(anyhow!(
-                "Unknown instruction '{:?}' @ line {}",
-                raw_instruction,
-                line_trace
-            ))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} @ line {}, column {}",
+            self.kind, self.line, self.column
+        )?;
+        if !self.snippet.is_empty() {
+            write!(f, ": {}", self.snippet)?;
         }
-    };
+        Ok(())
+    }
+}
 
-    Ok(instruction)
+impl std::error::Error for ParseError {}
+
+/// Decodes script text; With `latin1`, text which is not valid UTF-8 is read as Latin-1
+/// instead. Errors hold the length of the valid UTF-8 prefix.
+fn decode_text(bytes: &[u8], latin1: bool) -> Result<Cow<'_, str>, usize> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(Cow::Borrowed(text)),
+        Err(_) if latin1 => Ok(Cow::Owned(bytes.iter().map(|b| *b as char).collect())),
+        Err(e) => Err(e.valid_up_to()),
+    }
 }
 
-fn parse_wpk(path: &str, check_size: bool) -> Result<Instructions> {
-    let file = File::options().read(true).open(path)?;
+/// Blames invalid UTF-8 on the first bad byte, `valid_up_to` bytes into `bytes`
+fn utf8_error(bytes: &[u8], valid_up_to: usize) -> ParseError {
+    let (line, column) = locate_end(std::str::from_utf8(&bytes[..valid_up_to]).unwrap());
+    ParseError::new(ParseErrorKind::InvalidUtf8(valid_up_to), line, column, "")
+}
 
-    if check_size {
-        let filesize = file.metadata()?.len();
-        if filesize >= MAX_FILE_SIZE {
-            return Err(anyhow!(
-                "File size {:.2}/{:.2} MB is too large; try compressing your instructions",
-                (filesize as f64) / (MEGABYTE as f64),
-                (MAX_FILE_SIZE as f64) / (MEGABYTE as f64)
-            ));
+/// Finds the 1-based line and column just past the end of `prefix`
+fn locate_end(prefix: &str) -> (usize, usize) {
+    let line = prefix.matches('\n').count() + 1;
+    let column = prefix.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+/// 1-based position in a script; .wpkb positions are on line 1 with the byte offset as the column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePos {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Inclusive range of source text an instruction was parsed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub start: SourcePos,
+    pub end: SourcePos,
+}
+
+impl SourceSpan {
+    fn new(line: usize, start_column: usize, end_column: usize) -> Self {
+        Self {
+            start: SourcePos {
+                line,
+                column: start_column,
+            },
+            end: SourcePos {
+                line,
+                column: end_column,
+            },
         }
     }
 
-    let reader = BufReader::new(file);
+    /// Widens the span to also cover `other`, for merged instructions
+    pub(crate) fn cover(&mut self, other: SourceSpan) {
+        self.start = self.start.min(other.start);
+        self.end = self.end.max(other.end);
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
 
-    let mut instructions: Instructions = vec![];
+/// Source spans paired index for index with a script's instructions
+pub type SourceMap = Vec<SourceSpan>;
 
-    for (line_idx, line) in reader.lines().enumerate() {
-        let raw_line = line?;
-        let raw_instruction = raw_line.split_whitespace().collect::<Vec<_>>();
-        let new_instruction: Instruction = parse_wpk_line(raw_instruction.as_slice(), line_idx)?;
+/// Settings for how a script is turned into instructions
+#[derive(Copy, Clone, Debug)]
+pub struct ParseOptions {
+    /// Record where each instruction came from in `Script::source_map`
+    pub source_map: bool,
+    /// Merge neighbouring INC / CDEC instructions as they are parsed
+    pub compress: bool,
+    /// Reduce .wpk / .wpkm INC / CDEC counts of MEM_SIZE or more modulo MEM_SIZE instead of
+    /// erroring; The pointer wraps around memory, so only the step count changes
+    pub wrap_repeats: bool,
+    /// Split .wpk / .wpkm INC / CDEC counts of MEM_SIZE or more into several instructions
+    /// instead of erroring, keeping the step count exact
+    pub split_repeats: bool,
+    /// Largest file in bytes accepted when sizes are checked, replacing the format's default
+    pub max_file_size: Option<u64>,
+    /// Read text which is not valid UTF-8 as Latin-1 instead of erroring
+    pub latin1: bool,
+    /// Threads for parsing large .wpkm scripts; None uses every core
+    pub jobs: Option<usize>,
+    /// Reuse the instructions of an earlier parse of the same file, see `cache`
+    pub cache: bool,
+}
 
-        push_and_compress_instruction(&mut instructions, new_instruction);
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            source_map: false,
+            compress: true,
+            wrap_repeats: false,
+            split_repeats: false,
+            max_file_size: None,
+            latin1: false,
+            jobs: None,
+            cache: false,
+        }
     }
+}
 
-    Ok(instructions)
+impl ParseOptions {
+    pub(crate) fn file_size_limit(&self, format: ScriptFormat) -> u64 {
+        self.max_file_size
+            .unwrap_or_else(|| format.default_max_file_size())
+    }
 }
 
-fn parse_wpkm(path: &str, check_size: bool) -> Result<Instructions> {
-    let file = File::options().read(true).open(path)?;
+/// Constants declared with DEFINE in .wpk scripts
+type Defines = HashMap<String, u64>;
 
-    if check_size {
-        let filesize = file.metadata()?.len();
-        if filesize >= MAX_M_FILE_SIZE {
-            return Err(anyhow!(
-                "File size {:.2}/{:.2} MB is too large; try compressing your instructions",
-                (filesize as f64) / (MEGABYTE as f64),
-                (MAX_M_FILE_SIZE as f64) / (MEGABYTE as f64)
-            ));
+/// Names paired with the index of the instruction they are written before
+pub type Labels = Vec<(usize, String)>;
+
+pub struct Script {
+    pub instructions: Instructions,
+    /// Comments paired with the index of the instruction they are written before
+    pub comments: Vec<(usize, String)>,
+    pub labels: Labels,
+    /// Where each instruction came from, when requested
+    pub source_map: Option<SourceMap>,
+    /// Fields of a leading `;!` comment
+    pub meta: Option<ScriptMeta>,
+    /// `#assert` comments paired with the number of instructions run before they are checked
+    pub assertions: Vec<(usize, Assertion)>,
+    /// Instructions before this index may not be merged into, so labels stay accurate
+    compress_floor: usize,
+    /// Whether instructions are merged at all
+    compress: bool,
+    /// Whether over-large repeat counts wrap around instead of erroring
+    wrap_repeats: bool,
+    /// Whether over-large repeat counts are split into several instructions
+    split_repeats: bool,
+    /// Open REPEAT blocks with their repeat count, body and body spans
+    blocks: Vec<(u64, Instructions, SourceMap)>,
+    /// Instructions emitted so far by expanding REPEAT blocks
+    expanded: u64,
+    /// Instructions already handed out by an InstructionStream
+    base: usize,
+}
+
+impl Script {
+    /// A script of nothing but instructions, as read back from the parse cache
+    pub(crate) fn from_instructions(instructions: Instructions) -> Self {
+        Self {
+            instructions,
+            ..Self::new(ParseOptions::default())
+        }
+    }
+
+    fn new(options: ParseOptions) -> Self {
+        Self {
+            instructions: Instructions::new(),
+            comments: vec![],
+            labels: vec![],
+            source_map: options.source_map.then(Vec::new),
+            meta: None,
+            assertions: vec![],
+            compress_floor: 0,
+            compress: options.compress,
+            wrap_repeats: options.wrap_repeats,
+            split_repeats: options.split_repeats,
+            blocks: vec![],
+            expanded: 0,
+            base: 0,
         }
     }
 
-    let mut reader = BufReader::new(file);
-    let mut instructions: Instructions = vec![];
-    let mut ctr: Option<u64> = None;
+    /// Index the next instruction will be pushed at
+    fn position(&self) -> usize {
+        self.base + self.instructions.len()
+    }
 
-    for (c_trace, c) in reader.chars().enumerate() {
-        let c = c.unwrap();
-        let new_instruction: Instruction = match c {
-            INC_M_STR => {
-                let x = ctr.unwrap_or(1);
-                if (x as usize) >= MEM_SIZE {
-                    Err(anyhow!(
-                        "INC repetition of {} too large @ char {}",
-                        x,
-                        c_trace
-                    ))?;
-                }
-                let i = Instruction::Inc(x as VmUsize);
-                ctr = None;
-                i
+    /// Takes out instructions which later pushes can no longer merge into
+    fn drain_settled(&mut self) -> std::vec::Drain<'_, Instruction> {
+        let settled = self.instructions.len().saturating_sub(2);
+        self.base += settled;
+        self.compress_floor = self.compress_floor.saturating_sub(settled);
+        if let Some(source_map) = self.source_map.as_mut() {
+            source_map.drain(..settled);
+        }
+        self.instructions.drain(..settled)
+    }
+
+    fn push(&mut self, instruction: Instruction, span: SourceSpan) {
+        let (idx, source_map) = match self.blocks.last_mut() {
+            Some((_, block, spans)) => {
+                let floor = match self.compress {
+                    true => 0,
+                    false => block.len(),
+                };
+                (block.push_compressed(instruction, floor), Some(spans))
             }
-            CDEC_M_STR => {
-                let x = ctr.unwrap_or(1);
-                if (x as usize) >= MEM_SIZE {
-                    Err(anyhow!(
-                        "CDEC repetition of {} too large @ char {}",
-                        x,
-                        c_trace
-                    ))?;
-                }
-                let i = Instruction::Cdec(x as VmUsize);
-                ctr = None;
-                i
-            }
-            LOAD_M_STR | LOAD_M_STR_ALT => {
-                if let Some(x) = ctr {
-                    Err(anyhow!(
-                        "Cannot repeat LOAD instruction {} times @ char {}",
-                        x,
-                        c_trace
-                    ))?;
-                }
-                Instruction::Load
-            }
-            INV_M_STR | INV_M_STR_ALT => {
-                if let Some(x) = ctr {
-                    Err(anyhow!(
-                        "Cannot repeat INV instruction {} times @ char {}",
-                        x,
-                        c_trace
-                    ))?;
-                }
-                Instruction::Inv
-            }
-            '0'..='9' => {
-                ctr = match ctr {
-                    None => Some(c.to_digit(10).unwrap() as u64),
-                    Some(ctr_i) => {
-                        let ctr_new = ctr_i * 10 + c.to_digit(10).unwrap() as u64;
-                        if ctr_new > MEM_SIZE as u64 {
-                            Err(anyhow!(
-                                "Repeat of {} times too large @ char {}",
-                                ctr_new,
-                                c_trace
-                            ))?;
-                        }
-                        Some(ctr_new)
-                    }
+            None => {
+                let floor = match self.compress {
+                    true => self.compress_floor,
+                    false => self.instructions.len(),
                 };
-                Instruction::Null
+                (
+                    self.instructions.push_compressed(instruction, floor),
+                    self.source_map.as_mut(),
+                )
+            }
+        };
+
+        if let (Some(idx), Some(source_map)) = (idx, source_map) {
+            match source_map.get_mut(idx) {
+                Some(merged) => merged.cover(span),
+                None => source_map.push(span),
             }
-            ' ' | '\n' | '\t' => Instruction::Null,
-            _ => return Err(anyhow!("Invalid instruction {} @ char {}", &c, c_trace)),
+        }
+    }
+
+    /// Pushes `full` INC / CDEC instructions of the largest count ahead of `instruction`, for
+    /// repeats split by `check_repeat`
+    fn push_split(
+        &mut self,
+        instruction: Instruction,
+        full: u64,
+        span: SourceSpan,
+    ) -> Result<(), ParseErrorKind> {
+        let piece = match instruction {
+            Instruction::Inc(_) => Instruction::Inc(VmUsize::MAX),
+            Instruction::Cdec(_) => Instruction::Cdec(VmUsize::MAX),
+            _ => Instruction::Null,
         };
+        if full > 0 {
+            self.expanded = self.expanded.saturating_add(full);
+            if self.expanded > MAX_REPEAT_EXPANSION {
+                return Err(ParseErrorKind::ExpansionTooLarge);
+            }
+        }
+        for _ in 0..full {
+            self.push(piece, span);
+        }
+        self.push(instruction, span);
+        Ok(())
+    }
+
+    fn open_block(&mut self, repeat: u64) {
+        self.blocks.push((repeat, Instructions::new(), vec![]));
+    }
+
+    fn close_block(&mut self) -> Result<(), ParseErrorKind> {
+        let (repeat, block, spans) = self.blocks.pop().ok_or(ParseErrorKind::UnmatchedBlockEnd)?;
+        // Empty bodies expand to nothing however often they repeat
+        if block.is_empty() {
+            return Ok(());
+        }
+
+        self.expanded = self
+            .expanded
+            .saturating_add(repeat.saturating_mul(block.len() as u64));
+        if self.expanded > MAX_REPEAT_EXPANSION {
+            return Err(ParseErrorKind::ExpansionTooLarge);
+        }
+
+        for _ in 0..repeat {
+            for (instruction, span) in block.iter().zip(spans.iter()) {
+                self.push(*instruction, *span);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_finished(&self) -> Result<(), ParseErrorKind> {
+        match self.blocks.len() {
+            0 => Ok(()),
+            n => Err(ParseErrorKind::UnclosedBlocks(n)),
+        }
+    }
 
-        push_and_compress_instruction(&mut instructions, new_instruction);
+    /// Checks the script is complete, blaming errors on the given end of script position
+    fn finish(self, line: usize, column: usize) -> Result<Self, ParseError> {
+        self.check_finished()
+            .map_err(|kind| ParseError::new(kind, line, column, ""))?;
+        Ok(self)
     }
 
-    if let Some(c) = ctr {
-        return Err(anyhow!("Dangling repeat {} at end of script", &c));
+    /// Records a comment; A `;!` comment before anything else holds the script's metadata
+    fn push_comment(&mut self, idx: usize, comment: &str) -> Result<(), ParseErrorKind> {
+        let leading = self.position() == 0
+            && self.blocks.is_empty()
+            && self.comments.is_empty()
+            && self.labels.is_empty()
+            && self.meta.is_none();
+        match comment.strip_prefix(META_STR) {
+            Some(fields) if leading => self.meta = Some(ScriptMeta::parse(fields)?),
+            // Footers only hold for the exact text they were sealed with
+            _ if comment.starts_with(CHECKSUM_STR) => {}
+            _ => {
+                if let Some(assertion) = Assertion::parse(comment) {
+                    if !self.blocks.is_empty() {
+                        return Err(ParseErrorKind::AssertionInBlock);
+                    }
+                    // Merging an instruction across the assertion would change what it sees
+                    self.compress_floor = self.instructions.len();
+                    self.assertions.push((self.position(), assertion?));
+                }
+                self.comments.push((idx, comment.to_string()));
+            }
+        }
+        Ok(())
     }
 
-    Ok(instructions)
+    fn push_label(&mut self, name: &str) -> Result<(), ParseErrorKind> {
+        if !self.blocks.is_empty() {
+            return Err(ParseErrorKind::LabelInBlock(name.to_string()));
+        }
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(ParseErrorKind::InvalidLabel(name.to_string()));
+        }
+        if self.labels.iter().any(|(_, label)| label == name) {
+            return Err(ParseErrorKind::DuplicateLabel(name.to_string()));
+        }
+
+        self.compress_floor = self.instructions.len();
+        self.labels.push((self.position(), name.to_string()));
+        Ok(())
+    }
 }
 
-pub fn parse_file(path: &str, check_size: bool) -> Result<Instructions> {
-    if !check_valid_extension(path) {
-        Err(anyhow!(
-            "Invalid input woodpecker script name {}, should end in \".wpk\" or \".wpkm\"",
-            path
-        ))?;
+/// Describes an instruction index relative to the closest label at or before it
+pub fn format_location(labels: &Labels, idx: usize) -> String {
+    match labels.iter().rev().find(|(label_idx, _)| *label_idx <= idx) {
+        Some((label_idx, name)) if *label_idx == idx => {
+            format!("{} ({}{})", idx, LABEL_START, name)
+        }
+        Some((label_idx, name)) => format!("{} ({}{}+{})", idx, LABEL_START, name, idx - label_idx),
+        None => idx.to_string(),
     }
+}
 
-    if path.ends_with(".wpk") {
-        parse_wpk(path, check_size)
-    } else if path.ends_with(".wpkm") {
-        parse_wpkm(path, check_size)
-    } else {
-        Err(anyhow!("Unknown file type {}", path))
+/// Describes where in the source file an instruction came from, e.g. "solution.wpk:10233:1"
+pub fn format_source(path: &str, source_map: &SourceMap, idx: usize) -> Option<String> {
+    source_map.get(idx).map(|span| format!("{}:{}", path, span))
+}
+
+pub fn check_valid_extension(path: &str) -> bool {
+    ScriptFormat::from_path(path).is_some() || is_container_path(path)
+}
+
+/// Whether a path names a .wpkc container, which says its script format inside
+pub fn is_container_path(path: &str) -> bool {
+    ScriptCompression::split_path(path)
+        .0
+        .ends_with(CONTAINER_EXTENSION)
+}
+
+/// Fails reads once more than `limit` bytes have come out of a decompressor or stdin
+struct SizeCappedReader<R: Read> {
+    inner: R,
+    read: u64,
+    limit: u64,
+}
+
+impl<R: Read> Read for SizeCappedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.read >= self.limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                WpkppError::FileTooLarge {
+                    size: None,
+                    limit: self.limit,
+                },
+            ));
+        }
+        Ok(n)
     }
 }
 
-pub fn do_compress(input_path: &str, output_path: &str) -> Result<()> {
-    if !check_valid_extension(input_path) {
-        Err(anyhow!(
-            "Invalid input woodpecker script name {}, should end in \".wpk\" or \".wpkm\"",
-            input_path
-        ))?;
+fn check_file_size(filesize: u64, max_size: u64) -> Result<()> {
+    if filesize >= max_size {
+        return Err(WpkppError::FileTooLarge {
+            size: Some(filesize),
+            limit: max_size,
+        });
     }
-    if !check_valid_extension(output_path) {
-        Err(anyhow!(
-            "Invalid output woodpecker script name {}, should end in \".wpk\" or \".wpkm\"",
-            output_path
-        ))?;
+    Ok(())
+}
+
+/// Memory maps large uncompressed script files; Returns None for anything else
+fn mmap_script(path: &str, check_size: bool, max_size: u64) -> Result<Option<Mmap>> {
+    if path == STDIN_PATH || ScriptCompression::split_path(path).1 != ScriptCompression::None {
+        return Ok(None);
+    }
+
+    let file = File::options().read(true).open(path)?;
+    let filesize = file.metadata()?.len();
+    if check_size {
+        check_file_size(filesize, max_size)?;
     }
-    if input_path == output_path {
-        Err(anyhow!("Input and output paths the same; aborting"))?;
+    if filesize < MMAP_THRESHOLD {
+        return Ok(None);
     }
 
-    println!("Compressing {} => {}", input_path, output_path);
-    println!("Parsing...");
-    let instructions = parse_file(input_path, false)?;
-    let opcounts = instructions.opcount();
+    // Safety: the map is only read while parsing; Scripts truncated or modified by another
+    // process mid-parse are not supported
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(Some(mmap))
+}
 
-    println!(
-        "Instruction Counts: INC {} / CDEC {} / LOAD {} / INV {}",
-        opcounts.0, opcounts.1, opcounts.2, opcounts.3
-    );
-    println!(
-        "Total {} instruction(s) compressed into {} steps",
-        opcounts.0 + opcounts.1 + opcounts.2 + opcounts.3,
-        instructions.len()
-    );
+fn open_script(path: &str, check_size: bool, max_size: u64) -> Result<Box<dyn Read>> {
+    let limit = match check_size {
+        true => max_size,
+        false => MAX_DECOMPRESSED_SIZE,
+    };
+    if path == STDIN_PATH {
+        return Ok(Box::new(SizeCappedReader {
+            inner: std::io::stdin(),
+            read: 0,
+            limit,
+        }));
+    }
 
-    println!("Writing...");
-    let output_file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output_path)?;
-    let mut writer = BufWriter::new(output_file);
-    if output_path.ends_with(".wpk") {
-        for instruction in instructions.iter() {
-            writer.write(instruction.to_wpk_string().as_bytes())?;
-        }
-    } else if output_path.ends_with(".wpkm") {
-        for instruction in instructions.iter() {
-            writer.write(instruction.to_wpkm_string().as_bytes())?;
+    let file = File::options().read(true).open(path)?;
+
+    let reader: Box<dyn Read> = match ScriptCompression::split_path(path).1 {
+        ScriptCompression::None => {
+            if check_size {
+                check_file_size(file.metadata()?.len(), max_size)?;
+            }
+            Box::new(file)
         }
-    } else {
-        unreachable!();
+        ScriptCompression::Gzip => Box::new(SizeCappedReader {
+            inner: GzDecoder::new(file),
+            read: 0,
+            limit,
+        }),
+        #[cfg(not(target_arch = "wasm32"))]
+        ScriptCompression::Zstd => Box::new(SizeCappedReader {
+            inner: zstd::stream::read::Decoder::new(file)?,
+            read: 0,
+            limit,
+        }),
+        #[cfg(target_arch = "wasm32")]
+        ScriptCompression::Zstd => {
+            return Err(WpkppError::InvalidArgument(format!(
+                "Cannot read {}; .zst scripts are not supported on wasm32",
+                path
+            )))
+        }
+    };
+
+    Ok(reader)
+}
+
+fn parse_count(nstr: &str, defines: &Defines) -> Result<u64, ParseIntError> {
+    match defines.get(nstr) {
+        Some(x) => Ok(*x),
+        None => nstr.parse(),
+    }
+}
+
+fn parse_define(name: &str, value: &str, defines: &mut Defines) -> Result<(), ParseErrorKind> {
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let keyword = [INC_STR, CDEC_STR, LOAD_STR, INV_STR, REPEAT_STR, DEFINE_STR].contains(&name);
+    if !valid_name || keyword {
+        return Err(ParseErrorKind::InvalidConstant(name.to_string()));
+    }
+    if defines.contains_key(name) {
+        return Err(ParseErrorKind::DuplicateConstant(name.to_string()));
+    }
+
+    let x = parse_count(value, defines).map_err(ParseErrorKind::InvalidNumber)?;
+    defines.insert(name.to_string(), x);
+    Ok(())
+}
+
+/// An INC / CDEC repeat count as written, accumulated without overflowing so that errors can
+/// quote it in full
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RepeatCount {
+    literal: String,
+    /// None once the count is past u128::MAX
+    value: Option<u128>,
+    /// The count modulo MEM_SIZE, however long the literal gets
+    wrapped: u64,
+}
+
+impl RepeatCount {
+    fn new(value: u64) -> Self {
+        Self {
+            literal: value.to_string(),
+            value: Some(value as u128),
+            wrapped: value % MEM_SIZE,
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            literal: String::new(),
+            value: Some(0),
+            wrapped: 0,
+        }
+    }
+
+    /// Reads a count written only with digits, however large
+    fn parse(literal: &str) -> Option<Self> {
+        if literal.is_empty() || !literal.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut count = Self::empty();
+        literal.chars().for_each(|c| count.push_digit(c));
+        Some(count)
+    }
+
+    fn push_digit(&mut self, c: char) {
+        let digit = c.to_digit(10).unwrap();
+        self.literal.push(c);
+        self.value = self
+            .value
+            .and_then(|value| value.checked_mul(10)?.checked_add(digit as u128));
+        self.wrapped = (self.wrapped * 10 + digit as u64) % MEM_SIZE;
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        self.value.and_then(|value| u64::try_from(value).ok())
+    }
+}
+
+/// Checks an INC / CDEC repeat count fits in memory, wraps it around with `wrap_repeats`, or
+/// splits it with `split_repeats` into a number of full instructions of the largest count and
+/// the count left over
+fn check_repeat(
+    name: &'static str,
+    count: &RepeatCount,
+    wrap_repeats: bool,
+    split_repeats: bool,
+) -> Result<(VmUsize, u64), ParseErrorKind> {
+    let largest = VmUsize::MAX as u128;
+    match count.value {
+        Some(x) if x <= largest => Ok((x as VmUsize, 0)),
+        _ if wrap_repeats => Ok((count.wrapped as VmUsize, 0)),
+        // Full instructions past u64::MAX are over the expansion limit anyway
+        Some(x) if split_repeats => Ok((
+            (x % largest) as VmUsize,
+            u64::try_from(x / largest).unwrap_or(u64::MAX),
+        )),
+        _ => Err(ParseErrorKind::RepeatTooLarge(name, count.literal.clone())),
+    }
+}
+
+/// Parses the repeat count of an INC / CDEC
+fn parse_repeat(
+    name: &'static str,
+    nstr: &str,
+    defines: &Defines,
+    wrap_repeats: bool,
+    split_repeats: bool,
+) -> Result<(VmUsize, u64), ParseErrorKind> {
+    let count = match parse_count(nstr, defines) {
+        Ok(x) => RepeatCount::new(x),
+        Err(e) => RepeatCount::parse(nstr).ok_or(ParseErrorKind::InvalidNumber(e))?,
+    };
+    check_repeat(name, &count, wrap_repeats, split_repeats)
+}
+
+/// Parses one line's worth of tokens, along with the full instructions a split repeat comes
+/// with; Errors come with the index of the token to blame
+fn parse_wpk_line(
+    raw_instruction: &[&str],
+    defines: &Defines,
+    wrap_repeats: bool,
+    split_repeats: bool,
+) -> Result<(Instruction, u64), (ParseErrorKind, usize)> {
+    let repeat = |name, nstr| {
+        parse_repeat(name, nstr, defines, wrap_repeats, split_repeats).map_err(|e| (e, 1))
+    };
+    let instruction = match raw_instruction {
+        [] => (Instruction::Null, 0),
+        [INC_STR] => (Instruction::Inc(1), 0),
+        [INC_STR, nstr] => {
+            let (x, full) = repeat(INC_STR, nstr)?;
+            (Instruction::Inc(x), full)
+        }
+        [CDEC_STR] => (Instruction::Cdec(1), 0),
+        [CDEC_STR, nstr] => {
+            let (x, full) = repeat(CDEC_STR, nstr)?;
+            (Instruction::Cdec(x), full)
+        }
+        [LOAD_STR] => (Instruction::Load, 0),
+        [INV_STR] => (Instruction::Inv, 0),
+        _ => return Err((ParseErrorKind::UnknownInstruction, 0)),
+    };
+
+    Ok(instruction)
+}
+
+/// Parses a single instruction written either as a .wpk line ("INC 3") or as .wpkm ("3>"),
+/// so that `instruction.to_string().parse()` and `instruction.wpkm().to_string().parse()`
+/// give the instruction back. Named constants are not available.
+impl FromStr for Instruction {
+    type Err = ParseErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let digits = s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (nstr, op) = s.split_at(digits);
+        let count = RepeatCount::parse(nstr).unwrap_or_else(|| RepeatCount::new(1));
+        let repeat = |name| check_repeat(name, &count, false, false).map(|(x, _)| x);
+        let single = |name: &'static str, instruction: Instruction| match nstr {
+            "" => Ok(instruction),
+            _ => Err(ParseErrorKind::RepeatNotAllowed(name, nstr.to_string())),
+        };
+
+        let mut op_chars = op.chars();
+        match (op_chars.next(), op_chars.next()) {
+            (Some(INC_M_STR), None) => Ok(Instruction::Inc(repeat(INC_STR)?)),
+            (Some(CDEC_M_STR), None) => Ok(Instruction::Cdec(repeat(CDEC_STR)?)),
+            (Some(LOAD_M_STR | LOAD_M_STR_ALT), None) => single(LOAD_STR, Instruction::Load),
+            (Some(INV_M_STR | INV_M_STR_ALT), None) => single(INV_STR, Instruction::Inv),
+            _ => {
+                let tokens = s.split_whitespace().collect::<Vec<_>>();
+                match parse_wpk_line(&tokens, &Defines::new(), false, false) {
+                    Ok((Instruction::Null, _)) => Err(ParseErrorKind::UnknownInstruction),
+                    Ok((instruction, _)) => Ok(instruction),
+                    Err((kind, _)) => Err(kind),
+                }
+            }
+        }
+    }
+}
+
+fn parse_wpk(path: &str, check_size: bool, options: ParseOptions) -> Result<Script> {
+    let file = open_script(path, check_size, options.file_size_limit(ScriptFormat::Wpk))?;
+    parse_wpk_reader(BufReader::new(file), options)
+}
+
+fn parse_wpk_reader<R: BufRead>(reader: R, options: ParseOptions) -> Result<Script> {
+    let mut script = Script::new(options);
+    let mut parser = WpkParser::new();
+
+    let mut reader = reader;
+    let mut buf: Vec<u8> = vec![];
+    let mut offset: usize = 0;
+    loop {
+        buf.clear();
+        let n = reader.read_until(b'\n', &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let raw_line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        let raw_line = raw_line.strip_suffix(b"\r").unwrap_or(raw_line);
+        let line = decode_text(raw_line, options.latin1).map_err(|valid_up_to| {
+            let mut e = utf8_error(raw_line, valid_up_to);
+            e.kind = ParseErrorKind::InvalidUtf8(offset + valid_up_to);
+            e.line = parser.line_idx + 1;
+            e
+        })?;
+        parser.feed_line(&line, &mut script)?;
+        offset += n;
+    }
+
+    Ok(script.finish(parser.line_idx + 1, 1)?)
+}
+
+/// Line at a time .wpk parsing state
+struct WpkParser {
+    defines: Defines,
+    line_idx: usize,
+    checksum: ChecksumState,
+}
+
+impl WpkParser {
+    fn new() -> Self {
+        Self {
+            defines: Defines::new(),
+            line_idx: 0,
+            checksum: ChecksumState::new(),
+        }
+    }
+
+    fn feed_line(&mut self, raw_line: &str, script: &mut Script) -> Result<(), ParseError> {
+        let line_idx = self.line_idx;
+        self.line_idx += 1;
+        let raw_line = match line_idx {
+            0 => raw_line.strip_prefix(BOM).unwrap_or(raw_line),
+            _ => raw_line,
+        };
+
+        let column_of = |token: &str| {
+            raw_line[..(token.as_ptr() as usize - raw_line.as_ptr() as usize)]
+                .chars()
+                .count()
+                + 1
+        };
+        let error_at = |kind: ParseErrorKind, token: &str| {
+            ParseError::new(kind, line_idx + 1, column_of(token), raw_line.trim())
+        };
+
+        let is_footer = self
+            .checksum
+            .feed_line(raw_line)
+            .map_err(|kind| error_at(kind, raw_line.trim()))?;
+        if is_footer {
+            return Ok(());
+        }
+
+        let (code, comment) = match raw_line.find([COMMENT_STR, COMMENT_STR_ALT]) {
+            Some(idx) => (&raw_line[..idx], Some(raw_line[idx..].trim_end())),
+            None => (raw_line, None),
+        };
+        let mut raw_instruction = code.split_whitespace().collect::<Vec<_>>();
+        while let Some(token) = raw_instruction.first().copied() {
+            let label = match token.strip_prefix(LABEL_START) {
+                Some(label) => label,
+                None => break,
+            };
+            let name = label.strip_suffix(LABEL_END).ok_or_else(|| {
+                error_at(ParseErrorKind::UnterminatedLabel(label.to_string()), token)
+            })?;
+            script
+                .push_label(name)
+                .map_err(|kind| error_at(kind, token))?;
+            raw_instruction.remove(0);
+        }
+        let (new_instruction, full) = match raw_instruction.as_slice() {
+            [DEFINE_STR, name, value] => {
+                parse_define(name, value, &mut self.defines)
+                    .map_err(|kind| error_at(kind, name))?;
+                (Instruction::Null, 0)
+            }
+            [REPEAT_STR, nstr, BLOCK_OPEN_STR] => {
+                let x: u64 = parse_count(nstr, &self.defines)
+                    .map_err(|e| error_at(ParseErrorKind::InvalidNumber(e), nstr))?;
+                script.open_block(x);
+                (Instruction::Null, 0)
+            }
+            [BLOCK_CLOSE_STR] => {
+                script
+                    .close_block()
+                    .map_err(|kind| error_at(kind, raw_instruction[0]))?;
+                (Instruction::Null, 0)
+            }
+            _ => parse_wpk_line(
+                raw_instruction.as_slice(),
+                &self.defines,
+                script.wrap_repeats,
+                script.split_repeats,
+            )
+            .map_err(|(kind, token_idx)| error_at(kind, raw_instruction[token_idx]))?,
+        };
+
+        let span = match (raw_instruction.first(), raw_instruction.last()) {
+            (Some(first), Some(last)) => SourceSpan::new(
+                line_idx + 1,
+                column_of(first),
+                column_of(last) + last.chars().count() - 1,
+            ),
+            _ => SourceSpan::new(line_idx + 1, 1, 1),
+        };
+        script
+            .push_split(new_instruction, full, span)
+            .map_err(|kind| error_at(kind, raw_line.trim()))?;
+
+        if let Some(comment) = comment {
+            // Trailing comments stick to the instruction they annotate
+            let comment_idx = match new_instruction {
+                Instruction::Null => script.position(),
+                _ => script.position() - 1,
+            };
+            script
+                .push_comment(comment_idx, comment)
+                .map_err(|kind| error_at(kind, comment))?;
+        }
+
+        Ok(())
     }
-    println!("Done!");
+}
+
+fn parse_wpkm(path: &str, check_size: bool, options: ParseOptions) -> Result<Script> {
+    let max_size = options.file_size_limit(ScriptFormat::Wpkm);
+    if let Some(mmap) = mmap_script(path, check_size, max_size)? {
+        return parse_wpkm_slice(&mmap, options);
+    }
+
+    let mut file = open_script(path, check_size, max_size)?;
+    let mut bytes: Vec<u8> = vec![];
+    file.read_to_end(&mut bytes)?;
+    parse_wpkm_slice(&bytes, options)
+}
+
+fn parse_wpkm_slice(bytes: &[u8], options: ParseOptions) -> Result<Script> {
+    // wasm32 cannot spawn threads
+    if !options.source_map
+        && !cfg!(target_arch = "wasm32")
+        && options.compress
+        && bytes.len() as u64 >= PARALLEL_THRESHOLD
+        && bytes.is_ascii()
+        && !bytes.iter().any(|b| WPKM_STATEFUL_BYTES.contains(b))
+    {
+        return parse_wpkm_parallel(bytes, options);
+    }
+
+    // Validating the whole buffer up front is far cheaper than decoding char by char
+    let source =
+        decode_text(bytes, options.latin1).map_err(|valid_up_to| utf8_error(bytes, valid_up_to))?;
+    let source = source.as_ref();
 
+    // Checksum footers are ";" comments, so only scripts with comments need checking
+    if source.contains(CHECKSUM_STR) {
+        verify_checksum(source)?;
+    }
+
+    let mut script = Script::new(options);
+    let mut parser = WpkmParser::new();
+
+    for c in source.chars() {
+        parser.feed_char(c, &mut script)?;
+    }
+    parser.finish(&mut script)?;
+
+    Ok(script.finish(parser.line, parser.column)?)
+}
+
+/// Checks a whole script against its checksum footer, if it has one
+fn verify_checksum(source: &str) -> Result<(), ParseError> {
+    let mut checksum = ChecksumState::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        checksum
+            .feed_line(line)
+            .map_err(|kind| ParseError::new(kind, line_idx + 1, 1, line.trim()))?;
+    }
     Ok(())
 }
+
+/// Splits a plain .wpkm script into roughly even chunks, each ending right after a LOAD or INV.
+/// Nothing pushed after a LOAD / INV can merge into or past it, so chunks compress independently.
+fn wpkm_chunk_boundaries(bytes: &[u8], n_chunks: usize) -> Vec<usize> {
+    let mut bounds = vec![0];
+    for chunk_idx in 1..n_chunks {
+        let target = max(bytes.len() * chunk_idx / n_chunks, *bounds.last().unwrap());
+        match bytes[target..]
+            .iter()
+            .position(|b| WPKM_BOUNDARY_BYTES.contains(b))
+        {
+            Some(offset) => bounds.push(target + offset + 1),
+            None => break,
+        }
+    }
+    bounds.push(bytes.len());
+    bounds.dedup();
+    bounds
+}
+
+/// Parses one chunk; Error positions are relative to the start of the chunk
+fn parse_wpkm_chunk(chunk: &[u8], options: ParseOptions) -> Result<Script, ParseError> {
+    let mut script = Script::new(options);
+    let mut parser = WpkmParser::new();
+
+    for b in chunk.iter() {
+        parser.feed_char(*b as char, &mut script)?;
+    }
+    parser.finish(&mut script)?;
+
+    Ok(script)
+}
+
+/// Parses an ASCII .wpkm script free of comments, labels and REPEAT blocks on all cores, or
+/// on `options.jobs` threads
+fn parse_wpkm_parallel(bytes: &[u8], options: ParseOptions) -> Result<Script> {
+    let n_chunks = options.jobs.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let bounds = wpkm_chunk_boundaries(bytes, n_chunks);
+    debug!(
+        bytes = bytes.len(),
+        chunks = bounds.len() - 1,
+        "Parsing .wpkm in parallel"
+    );
+
+    let chunks: Vec<Result<Script, ParseError>> = thread::scope(|scope| {
+        let handles = bounds
+            .windows(2)
+            .map(|bound| {
+                let (start, end) = (bound[0], bound[1]);
+                scope.spawn(move || parse_wpkm_chunk(&bytes[start..end], options))
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut script = Script::new(options);
+    for (chunk, bound) in chunks.into_iter().zip(bounds.windows(2)) {
+        let mut chunk = chunk.map_err(|mut e| {
+            // Shift chunk relative positions; The input is ASCII so any prefix is valid UTF-8
+            let (line, column) = locate_end(std::str::from_utf8(&bytes[..bound[0]]).unwrap());
+            if e.line == 1 {
+                e.column += column - 1;
+            }
+            e.line += line - 1;
+            e
+        })?;
+        // Each chunk only saw its own split repeats, so the limit holds for their sum
+        script.expanded = script.expanded.saturating_add(chunk.expanded);
+        if script.expanded > MAX_REPEAT_EXPANSION {
+            let (line, column) = locate_end(std::str::from_utf8(&bytes[..bound[1]]).unwrap());
+            let kind = ParseErrorKind::ExpansionTooLarge;
+            return Err(ParseError::new(kind, line, column, "").into());
+        }
+        script.instructions.append(&mut chunk.instructions);
+    }
+
+    let (line, column) = locate_end(std::str::from_utf8(bytes).unwrap());
+    Ok(script.finish(line, column)?)
+}
+
+/// Character at a time .wpkm parsing state
+struct WpkmParser {
+    ctr: Option<RepeatCount>,
+    /// Where the pending repeat count started
+    ctr_start: SourcePos,
+    comment: Option<String>,
+    label: Option<String>,
+    /// 1-based position of the next character
+    line: usize,
+    column: usize,
+}
+
+impl WpkmParser {
+    fn new() -> Self {
+        Self {
+            ctr: None,
+            ctr_start: SourcePos { line: 1, column: 1 },
+            comment: None,
+            label: None,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn feed_char(&mut self, c: char, script: &mut Script) -> Result<(), ParseError> {
+        let (line, column) = (self.line, self.column);
+        if c == BOM && (line, column) == (1, 1) {
+            return Ok(());
+        }
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        if let Some(label_name) = self.label.as_mut() {
+            match c {
+                LABEL_END => {
+                    script.push_label(label_name).map_err(|kind| {
+                        let snippet = format!("{}{}{}", LABEL_START, label_name, LABEL_END);
+                        let column = (column + 1).saturating_sub(snippet.chars().count()).max(1);
+                        ParseError::new(kind, line, column, &snippet)
+                    })?;
+                    self.label = None;
+                }
+                _ => label_name.push(c),
+            }
+            return Ok(());
+        }
+
+        if let Some(comment_text) = self.comment.as_mut() {
+            match c {
+                '\n' => {
+                    let comment_text = comment_text.trim_end();
+                    script
+                        .push_comment(script.position(), comment_text)
+                        .map_err(|kind| ParseError::new(kind, line, 1, comment_text))?;
+                    self.comment = None;
+                }
+                _ => comment_text.push(c),
+            }
+            return Ok(());
+        }
+
+        // Digits are only collected here; The full count is checked once its instruction is
+        // reached, so errors can quote every digit of it
+        if c.is_ascii_digit() {
+            if self.ctr.is_none() {
+                self.ctr_start = SourcePos { line, column };
+            }
+            self.ctr
+                .get_or_insert_with(RepeatCount::empty)
+                .push_digit(c);
+            return Ok(());
+        }
+        // Whitespace may sit between a repeat count and its instruction
+        if matches!(c, ' ' | '\n' | '\t' | '\r') {
+            return Ok(());
+        }
+
+        // Blames the current character, showing any repeat count before it
+        let ctr = self.ctr.take();
+        let error_at = |kind: ParseErrorKind| {
+            let snippet = match ctr.as_ref() {
+                Some(count) => format!("{}{}", count.literal, c),
+                None => c.to_string(),
+            };
+            ParseError::new(kind, line, column, &snippet)
+        };
+        let check_dangling = || match ctr.as_ref() {
+            Some(count) => Err(error_at(ParseErrorKind::DanglingRepeat(
+                count.literal.clone(),
+            ))),
+            None => Ok(()),
+        };
+        let (wrap_repeats, split_repeats) = (script.wrap_repeats, script.split_repeats);
+        let repeat = |name| {
+            let count = ctr.clone().unwrap_or_else(|| RepeatCount::new(1));
+            check_repeat(name, &count, wrap_repeats, split_repeats).map_err(error_at)
+        };
+        let single = |name, instruction| match ctr.as_ref() {
+            Some(count) => Err(error_at(ParseErrorKind::RepeatNotAllowed(
+                name,
+                count.literal.clone(),
+            ))),
+            None => Ok((instruction, 0)),
+        };
+
+        let (new_instruction, full) = match c {
+            INC_M_STR => {
+                let (x, full) = repeat(INC_STR)?;
+                (Instruction::Inc(x), full)
+            }
+            CDEC_M_STR => {
+                let (x, full) = repeat(CDEC_STR)?;
+                (Instruction::Cdec(x), full)
+            }
+            LOAD_M_STR | LOAD_M_STR_ALT => single(LOAD_STR, Instruction::Load)?,
+            INV_M_STR | INV_M_STR_ALT => single(INV_STR, Instruction::Inv)?,
+            COMMENT_STR | COMMENT_STR_ALT => {
+                check_dangling()?;
+                self.comment = Some(c.to_string());
+                (Instruction::Null, 0)
+            }
+            LABEL_START => {
+                check_dangling()?;
+                self.label = Some(String::new());
+                (Instruction::Null, 0)
+            }
+            BLOCK_OPEN_M_STR => {
+                let count = ctr
+                    .as_ref()
+                    .ok_or_else(|| error_at(ParseErrorKind::MissingBlockRepeat))?;
+                let x = count.to_u64().ok_or_else(|| {
+                    error_at(ParseErrorKind::CountTooLarge(count.literal.clone()))
+                })?;
+                script.open_block(x);
+                (Instruction::Null, 0)
+            }
+            BLOCK_CLOSE_M_STR => {
+                check_dangling()?;
+                script.close_block().map_err(error_at)?;
+                (Instruction::Null, 0)
+            }
+            _ => return Err(error_at(ParseErrorKind::UnknownInstruction)),
+        };
+
+        let here = SourcePos { line, column };
+        let span = SourceSpan {
+            start: match ctr {
+                Some(_) => self.ctr_start,
+                None => here,
+            },
+            end: here,
+        };
+        script
+            .push_split(new_instruction, full, span)
+            .map_err(error_at)?;
+        Ok(())
+    }
+
+    /// Drops any half-parsed repeat count or label after an error
+    fn recover(&mut self) {
+        self.ctr = None;
+        self.label = None;
+    }
+
+    fn finish(&mut self, script: &mut Script) -> Result<(), ParseError> {
+        let (line, column) = (self.line, self.column);
+        if let Some(name) = self.label.take() {
+            return Err(ParseError::new(
+                ParseErrorKind::UnterminatedLabel(name),
+                line,
+                column,
+                "",
+            ));
+        }
+        if let Some(count) = self.ctr.take() {
+            let kind = ParseErrorKind::DanglingRepeat(count.literal);
+            return Err(ParseError::new(kind, line, column, ""));
+        }
+        if let Some(comment_text) = self.comment.take() {
+            let comment_text = comment_text.trim_end();
+            script
+                .push_comment(script.position(), comment_text)
+                .map_err(|kind| ParseError::new(kind, line, 1, comment_text))?;
+        }
+        Ok(())
+    }
+}
+
+/// Locates an error in binary data at the given byte offset
+fn binary_error(kind: ParseErrorKind, pos: usize) -> ParseError {
+    ParseError::new(kind, 1, pos + 1, "")
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, ParseError> {
+    let mut x: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or_else(|| {
+            binary_error(
+                ParseErrorKind::InvalidBinary("truncated varint".to_string()),
+                *pos,
+            )
+        })?;
+        *pos += 1;
+        x |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(binary_error(
+        ParseErrorKind::InvalidBinary("varint too long".to_string()),
+        *pos,
+    ))
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut x: u64) -> Result<()> {
+    while x >= 0x80 {
+        writer.write_all(&[(x as u8) | 0x80])?;
+        x >>= 7;
+    }
+    writer.write_all(&[x as u8])?;
+    Ok(())
+}
+
+fn parse_wpkb(path: &str, check_size: bool, options: ParseOptions) -> Result<Script> {
+    let mut file = open_script(
+        path,
+        check_size,
+        options.file_size_limit(ScriptFormat::Wpkb),
+    )?;
+
+    let mut bytes: Vec<u8> = vec![];
+    file.read_to_end(&mut bytes)?;
+    Ok(parse_wpkb_slice(&bytes, options)?)
+}
+
+fn parse_wpkb_slice(bytes: &[u8], options: ParseOptions) -> Result<Script, ParseError> {
+    let invalid =
+        |reason: String, pos: usize| binary_error(ParseErrorKind::InvalidBinary(reason), pos);
+
+    let header_size = WPKB_MAGIC.len() + 1;
+    if bytes.len() < header_size + WPKB_CHECKSUM_SIZE || !bytes.starts_with(WPKB_MAGIC) {
+        return Err(invalid("missing magic header".to_string(), 0));
+    }
+    if bytes[WPKB_MAGIC.len()] != WPKB_VERSION {
+        return Err(invalid(
+            format!("unsupported version {}", bytes[WPKB_MAGIC.len()]),
+            WPKB_MAGIC.len(),
+        ));
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - WPKB_CHECKSUM_SIZE);
+    if fnv1a(FNV_OFFSET, body).to_le_bytes() != checksum {
+        return Err(invalid(
+            "checksum mismatch; file is corrupted".to_string(),
+            body.len(),
+        ));
+    }
+
+    let mut script = Script::new(options);
+    let mut pos = header_size;
+    let n_instructions = read_varint(body, &mut pos)?;
+
+    for _ in 0..n_instructions {
+        let byte_trace = pos;
+        let code = read_varint(body, &mut pos)?;
+        let x = code >> 2;
+        if x >= MEM_SIZE {
+            return Err(binary_error(
+                ParseErrorKind::CountTooLarge(x.to_string()),
+                byte_trace,
+            ));
+        }
+        let new_instruction = match (code & 0x03, x) {
+            (WPKB_OP_INC, _) => Instruction::Inc(x as VmUsize),
+            (WPKB_OP_CDEC, _) => Instruction::Cdec(x as VmUsize),
+            (WPKB_OP_LOAD, 0) => Instruction::Load,
+            (WPKB_OP_INV, 0) => Instruction::Inv,
+            _ => {
+                return Err(invalid(
+                    format!("invalid instruction code {}", code),
+                    byte_trace,
+                ))
+            }
+        };
+
+        script.push(new_instruction, SourceSpan::new(1, byte_trace + 1, pos));
+    }
+
+    if pos != body.len() {
+        return Err(invalid(
+            format!("trailing data after {} instructions", n_instructions),
+            pos,
+        ));
+    }
+
+    script.finish(1, pos + 1)
+}
+
+pub(crate) fn write_wpkb<W: Write>(writer: &mut W, instructions: &Instructions) -> Result<()> {
+    let mut body: Vec<u8> = vec![];
+    body.extend_from_slice(WPKB_MAGIC);
+    body.push(WPKB_VERSION);
+
+    let n_instructions = instructions
+        .iter()
+        .filter(|x| !matches!(x, Instruction::Inc(0) | Instruction::Cdec(0)))
+        .count();
+    write_varint(&mut body, n_instructions as u64)?;
+
+    for instruction in instructions.iter() {
+        let code = match instruction {
+            Instruction::Null => unreachable!(),
+            Instruction::Inc(0) | Instruction::Cdec(0) => continue,
+            Instruction::Inc(x) => ((*x as u64) << 2) | WPKB_OP_INC,
+            Instruction::Cdec(x) => ((*x as u64) << 2) | WPKB_OP_CDEC,
+            Instruction::Load => WPKB_OP_LOAD,
+            Instruction::Inv => WPKB_OP_INV,
+        };
+        write_varint(&mut body, code)?;
+    }
+
+    writer.write_all(&body)?;
+    writer.write_all(&fnv1a(FNV_OFFSET, &body).to_le_bytes())?;
+    Ok(())
+}
+
+enum StreamParser {
+    Wpk(WpkParser),
+    Wpkm(WpkmParser),
+}
+
+/// Parses a script incrementally, yielding compressed instructions as soon as later input can no
+/// longer merge into them. Only a couple of instructions are buffered at a time, except for the
+/// expansion of REPEAT blocks, which is held in full until it is yielded.
+pub struct InstructionStream<R: BufRead> {
+    reader: R,
+    parser: StreamParser,
+    script: Script,
+    ready: VecDeque<Instruction>,
+    done: bool,
+    /// Bytes read so far, for pointing at invalid UTF-8
+    offset: usize,
+}
+
+impl<R: BufRead> InstructionStream<R> {
+    pub fn new(reader: R, format: ScriptFormat) -> Result<Self> {
+        let parser = match format {
+            ScriptFormat::Wpk => StreamParser::Wpk(WpkParser::new()),
+            ScriptFormat::Wpkm => StreamParser::Wpkm(WpkmParser::new()),
+            ScriptFormat::Wpkb => Err(WpkppError::InvalidArgument(
+                ".wpkb scripts cannot be streamed".to_string(),
+            ))?,
+        };
+
+        Ok(Self {
+            reader,
+            parser,
+            script: Script::new(ParseOptions::default()),
+            ready: VecDeque::new(),
+            done: false,
+            offset: 0,
+        })
+    }
+
+    /// Consumes the stream, counting INC / CDEC / LOAD / INV steps
+    pub fn opcount(self) -> Result<(u64, u64, u64, u64)> {
+        let mut opcounts = (0, 0, 0, 0);
+        for instruction in self {
+            match instruction? {
+                Instruction::Inc(x) => opcounts.0 += x as u64,
+                Instruction::Cdec(x) => opcounts.1 += x as u64,
+                Instruction::Load => opcounts.2 += 1,
+                Instruction::Inv => opcounts.3 += 1,
+                Instruction::Null => unreachable!(),
+            }
+        }
+        Ok(opcounts)
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        let more = match &mut self.parser {
+            StreamParser::Wpk(parser) => {
+                let mut buf = vec![];
+                match self.reader.read_until(b'\n', &mut buf)? {
+                    0 => false,
+                    n => {
+                        let raw_line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+                        let raw_line = raw_line.strip_suffix(b"\r").unwrap_or(raw_line);
+                        let line = decode_text(raw_line, false).map_err(|valid_up_to| {
+                            let mut e = utf8_error(raw_line, valid_up_to);
+                            e.kind = ParseErrorKind::InvalidUtf8(self.offset + valid_up_to);
+                            e.line = parser.line_idx + 1;
+                            e
+                        })?;
+                        parser.feed_line(&line, &mut self.script)?;
+                        self.offset += n;
+                        true
+                    }
+                }
+            }
+            StreamParser::Wpkm(parser) => match self.reader.read_char() {
+                Ok(Some(c)) => {
+                    parser.feed_char(c, &mut self.script)?;
+                    self.offset += c.len_utf8();
+                    true
+                }
+                Ok(None) => {
+                    parser.finish(&mut self.script)?;
+                    false
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => Err(ParseError::new(
+                    ParseErrorKind::InvalidUtf8(self.offset),
+                    parser.line,
+                    parser.column,
+                    "",
+                ))?,
+                Err(e) => Err(e)?,
+            },
+        };
+
+        if more {
+            let settled = self.script.drain_settled();
+            self.ready.extend(settled);
+        } else {
+            let (line, column) = match &self.parser {
+                StreamParser::Wpk(parser) => (parser.line_idx + 1, 1),
+                StreamParser::Wpkm(parser) => (parser.line, parser.column),
+            };
+            self.script
+                .check_finished()
+                .map_err(|kind| ParseError::new(kind, line, column, ""))?;
+            self.ready.extend(self.script.instructions.drain(..));
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for InstructionStream<R> {
+    type Item = Result<Instruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(instruction) = self.ready.pop_front() {
+                return Some(Ok(instruction));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.advance() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Opens a .wpk / .wpkm script (or stdin) as an InstructionStream, skipping the grading file size limits
+pub fn stream_script(
+    path: &str,
+    format: Option<ScriptFormat>,
+) -> Result<InstructionStream<BufReader<Box<dyn Read>>>> {
+    let format = format
+        .or_else(|| ScriptFormat::from_path(path))
+        .ok_or_else(|| WpkppError::InvalidArgument(format!("Unknown file type {}", path)))?;
+    let file = open_script(path, false, MAX_DECOMPRESSED_SIZE)?;
+    InstructionStream::new(BufReader::new(file), format)
+}
+
+/// Parses a script without stopping at the first syntax error, returning every error found.
+/// .wpk scripts resume at the next line and .wpkm scripts at the next character.
+pub fn check_script(path: &str, format: Option<ScriptFormat>) -> Result<Vec<ParseError>> {
+    let format = format
+        .or_else(|| ScriptFormat::from_path(path))
+        .ok_or_else(|| WpkppError::InvalidArgument(format!("Unknown file type {}", path)))?;
+    let mut file = open_script(path, false, MAX_DECOMPRESSED_SIZE)?;
+    let mut bytes: Vec<u8> = vec![];
+    file.read_to_end(&mut bytes)?;
+    Ok(check_script_bytes(&bytes, format))
+}
+
+/// Like `check_script`, for a script already in memory
+pub fn check_script_bytes(bytes: &[u8], format: ScriptFormat) -> Vec<ParseError> {
+    let mut script = Script::new(ParseOptions::default());
+    let mut errors = vec![];
+
+    let (line, column) = match format {
+        ScriptFormat::Wpk => {
+            let mut parser = WpkParser::new();
+            let mut offset: usize = 0;
+            for raw_line in bytes.split_inclusive(|b| *b == b'\n') {
+                let line = raw_line.strip_suffix(b"\n").unwrap_or(raw_line);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                match decode_text(line, false) {
+                    Ok(line) => errors.extend(parser.feed_line(&line, &mut script).err()),
+                    Err(valid_up_to) => {
+                        parser.line_idx += 1;
+                        let mut e = utf8_error(line, valid_up_to);
+                        e.kind = ParseErrorKind::InvalidUtf8(offset + valid_up_to);
+                        e.line = parser.line_idx;
+                        errors.push(e);
+                    }
+                }
+                offset += raw_line.len();
+            }
+            (parser.line_idx + 1, 1)
+        }
+        ScriptFormat::Wpkm => {
+            let source = match std::str::from_utf8(bytes) {
+                Ok(source) => source,
+                Err(e) => return vec![utf8_error(bytes, e.valid_up_to())],
+            };
+            let mut parser = WpkmParser::new();
+            for c in source.chars() {
+                if let Err(e) = parser.feed_char(c, &mut script) {
+                    errors.push(e);
+                    parser.recover();
+                }
+            }
+            errors.extend(parser.finish(&mut script).err());
+            (parser.line, parser.column)
+        }
+        ScriptFormat::Wpkb => {
+            errors.extend(parse_wpkb_slice(bytes, ParseOptions::default()).err());
+            (1, 1)
+        }
+    };
+    errors.extend(
+        script
+            .check_finished()
+            .err()
+            .map(|kind| ParseError::new(kind, line, column, "")),
+    );
+
+    errors
+}
+
+/// Parses an in-memory .wpk script
+pub fn parse_wpk_str(source: &str) -> Result<Instructions> {
+    Ok(parse_wpk_reader(source.as_bytes(), ParseOptions::default())?.instructions)
+}
+
+/// Parses an in-memory .wpkm script
+pub fn parse_wpkm_bytes(source: &[u8]) -> Result<Instructions> {
+    Ok(parse_wpkm_slice(source, ParseOptions::default())?.instructions)
+}
+
+/// Parses an in-memory .wpkb script
+pub fn parse_wpkb_bytes(source: &[u8]) -> Result<Instructions> {
+    Ok(parse_wpkb_slice(source, ParseOptions::default())?.instructions)
+}
+
+/// Like `parse_wpkb_bytes`, keeping the instructions exactly as stored instead of merging them
+pub(crate) fn parse_wpkb_exact(source: &[u8]) -> Result<Instructions> {
+    let options = ParseOptions {
+        compress: false,
+        ..Default::default()
+    };
+    Ok(parse_wpkb_slice(source, options)?.instructions)
+}
+
+/// Parses an in-memory script of any format, keeping comments and labels
+pub fn parse_script_bytes(source: &[u8], format: ScriptFormat) -> Result<Script> {
+    parse_script_bytes_with(source, format, ParseOptions::default())
+}
+
+pub(crate) fn parse_script_bytes_with(
+    source: &[u8],
+    format: ScriptFormat,
+    options: ParseOptions,
+) -> Result<Script> {
+    match format {
+        ScriptFormat::Wpk => parse_wpk_reader(source, options),
+        ScriptFormat::Wpkm => parse_wpkm_slice(source, options),
+        ScriptFormat::Wpkb => Ok(parse_wpkb_slice(source, options)?),
+    }
+}
+
+/// Parses a script wrapped by `encode_container`, as embedded in a JSON payload
+pub fn parse_container_str(text: &str) -> Result<Instructions> {
+    let (format, bytes) = decode_container(text)?;
+    Ok(parse_script_bytes(&bytes, format)?.instructions)
+}
+
+/// Parses a .wpkc file; The size limits of the wrapped format apply to the decoded script
+fn parse_container_file(path: &str, check_size: bool, options: ParseOptions) -> Result<Script> {
+    // Hex payloads are twice the size of the script they carry
+    let max_size = options.max_file_size.unwrap_or(MAX_FILE_SIZE);
+    let mut file = open_script(path, check_size, max_size.saturating_mul(2))?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+
+    let (format, bytes) = decode_container(&text)?;
+    if check_size {
+        check_file_size(bytes.len() as u64, options.file_size_limit(format))?;
+    }
+    parse_script_bytes_with(&bytes, format, options)
+}
+
+/// Reads a script's bytes without parsing them, unwrapping containers and compression, so the
+/// script can be stored exactly as written
+pub fn read_script_source(
+    path: &str,
+    format: Option<ScriptFormat>,
+) -> Result<(ScriptFormat, Vec<u8>)> {
+    if path != STDIN_PATH && is_container_path(path) {
+        let mut text = String::new();
+        open_script(path, true, MAX_FILE_SIZE.saturating_mul(2))?.read_to_string(&mut text)?;
+        return decode_container(&text);
+    }
+    let format = format
+        .or_else(|| ScriptFormat::from_path(path))
+        .ok_or_else(|| {
+            WpkppError::InvalidArgument(format!(
+                "Cannot tell the format of {}; pass wpk, wpkm or wpkb",
+                path
+            ))
+        })?;
+    let mut bytes = vec![];
+    open_script(path, true, format.default_max_file_size())?.read_to_end(&mut bytes)?;
+    Ok((format, bytes))
+}
+
+pub fn parse_file(path: &str, check_size: bool) -> Result<Instructions> {
+    Ok(parse_script(path, check_size)?.instructions)
+}
+
+pub fn parse_script(path: &str, check_size: bool) -> Result<Script> {
+    parse_script_as(path, None, check_size)
+}
+
+/// Parses a script, using `format` instead of the path's extension when given
+pub fn parse_script_as(
+    path: &str,
+    format: Option<ScriptFormat>,
+    check_size: bool,
+) -> Result<Script> {
+    parse_script_with(path, format, check_size, ParseOptions::default())
+}
+
+/// Like `parse_script_as`, with control over merging, source maps and repeat wrapping.
+/// Large .wpkm scripts are parsed on a single thread unless using the default options.
+#[instrument(level = "info", skip(options))]
+pub fn parse_script_with(
+    path: &str,
+    format: Option<ScriptFormat>,
+    check_size: bool,
+    options: ParseOptions,
+) -> Result<Script> {
+    if options.cache {
+        return parse_script_cached(path, format, check_size, options);
+    }
+    let script = match format.or_else(|| ScriptFormat::from_path(path)) {
+        _ if path != STDIN_PATH && is_container_path(path) => {
+            parse_container_file(path, check_size, options)
+        }
+        Some(ScriptFormat::Wpk) => parse_wpk(path, check_size, options),
+        Some(ScriptFormat::Wpkm) => parse_wpkm(path, check_size, options),
+        Some(ScriptFormat::Wpkb) => parse_wpkb(path, check_size, options),
+        None if path == STDIN_PATH => Err(WpkppError::InvalidArgument(
+            "Reading a script from stdin requires a format (wpk, wpkm or wpkb)".to_string(),
+        )),
+        None => Err(WpkppError::InvalidArgument(format!(
+            "Invalid input woodpecker script name {}, should end in \".wpk\", \".wpkm\", \".wpkb\" or \".wpkc\"",
+            path
+        ))),
+    }?;
+    debug!(
+        instructions = script.instructions.len(),
+        labels = script.labels.len(),
+        "Parsed script"
+    );
+    Ok(script)
+}
+
+/// Writes a script out; `wrap` breaks .wpkm output into lines of at most that many characters
+fn write_script<W: Write>(
+    writer: &mut W,
+    script: &Script,
+    format: ScriptFormat,
+    keep_comments: bool,
+    wrap: Option<usize>,
+) -> Result<()> {
+    if format == ScriptFormat::Wpkb {
+        // The binary container has no room for comments or labels
+        return write_wpkb(writer, &script.instructions);
+    }
+    let wpkm = format == ScriptFormat::Wpkm;
+    let mut comments = script.comments.iter().filter(|_| keep_comments).peekable();
+    let mut labels = script.labels.iter().peekable();
+    if let Some(meta) = script.meta.as_ref() {
+        // Metadata is kept even when comments are stripped
+        writeln!(writer, "{} {}", META_STR, meta)?;
+    }
+    let mut line_start = true;
+    let mut column: usize = 0;
+    // Starts a new line if `token` would overflow the wrap width
+    let wrap_before = |writer: &mut W, token: &str, column: &mut usize| -> Result<()> {
+        match wrap {
+            Some(width) if wpkm && *column > 0 && *column + token.len() > width => {
+                writer.write_all(b"\n")?;
+                *column = token.len();
+            }
+            _ => *column += token.len(),
+        }
+        Ok(())
+    };
+
+    // One extra pass for annotations trailing the last instruction
+    for idx in 0..=script.instructions.len() {
+        while let Some((_, comment)) = comments.next_if(|(comment_idx, _)| *comment_idx <= idx) {
+            if !line_start {
+                writer.write_all(b"\n")?;
+            }
+            writer.write_all(comment.as_bytes())?;
+            writer.write_all(b"\n")?;
+            line_start = true;
+            column = 0;
+        }
+        while let Some((_, name)) = labels.next_if(|(label_idx, _)| *label_idx <= idx) {
+            let label = format!("{}{}{}", LABEL_START, name, LABEL_END);
+            wrap_before(writer, &label, &mut column)?;
+            writer.write_all(label.as_bytes())?;
+            line_start = false;
+            if !wpkm {
+                writer.write_all(b"\n")?;
+                line_start = true;
+            }
+        }
+
+        if let Some(instruction) = script.instructions.get(idx) {
+            let instruction_str = match (instruction, wpkm) {
+                (Instruction::Inc(0) | Instruction::Cdec(0), _) => String::new(),
+                (_, true) => instruction.wpkm().to_string(),
+                (_, false) => format!("{}\n", instruction),
+            };
+            wrap_before(writer, &instruction_str, &mut column)?;
+            writer.write_all(instruction_str.as_bytes())?;
+            line_start =
+                (line_start && instruction_str.is_empty()) || instruction_str.ends_with('\n');
+        }
+    }
+
+    Ok(())
+}
+
+pub struct CompressOptions {
+    /// Carry comments from the input script over to the output
+    pub keep_comments: bool,
+    /// Input format, overriding the input path's extension
+    pub input_format: Option<ScriptFormat>,
+    /// Output format, overriding the output path's extension; Defaults to the input format
+    /// when writing to stdout
+    pub output_format: Option<ScriptFormat>,
+    /// Only report every syntax error in the input, without writing any output
+    pub check: bool,
+    /// Break .wpkm output into lines of at most this many characters
+    pub wrap: Option<usize>,
+    /// Report compression statistics without writing any output
+    pub dry_run: bool,
+    /// Print a JSON summary instead of progress messages
+    pub json: bool,
+    /// Print tab-separated result lines instead of progress messages
+    pub porcelain: bool,
+    /// Check the compressed script behaves exactly like the original before writing it
+    pub verify: Option<VerifyTarget>,
+    /// Optimization level to apply after compressing; 0 keeps the instructions as written
+    pub optimize: u8,
+    /// Task whose output the script must keep; Instructions which cannot change it are removed
+    pub optimize_task: Option<Task>,
+    /// Wrap INC / CDEC counts of MEM_SIZE or more around instead of erroring
+    pub wrap_repeats: bool,
+    /// Split INC / CDEC counts of MEM_SIZE or more into several instructions instead of erroring
+    pub split_repeats: bool,
+    /// Read input which is not valid UTF-8 as Latin-1
+    pub latin1: bool,
+    /// Threads for parsing large .wpkm scripts; None uses every core
+    pub jobs: Option<usize>,
+    /// Write the output as a text container; .wpkc output paths default to base64
+    pub container: Option<ContainerEncoding>,
+    /// Reuse the instructions of an earlier parse of the same input from the parse cache
+    pub cache: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CompressResult {
+    input: String,
+    output: String,
+    opcounts: InstructionCount,
+    instructions_before: String,
+    instructions_after: String,
+    bytes_written: String,
+}
+
+/// Escapes tabs, newlines and backslashes so a path stays within one porcelain field
+fn porcelain_field(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+impl CompressResult {
+    /// `compressed`, input, output, INC / CDEC / LOAD / INV counts, instructions before and
+    /// after and bytes written, separated by tabs; Fields are only ever added at the end
+    fn porcelain(&self) -> String {
+        [
+            "compressed".to_string(),
+            porcelain_field(&self.input),
+            porcelain_field(&self.output),
+            self.opcounts.inc.clone(),
+            self.opcounts.cdec.clone(),
+            self.opcounts.load.clone(),
+            self.opcounts.inv.clone(),
+            self.instructions_before.clone(),
+            self.instructions_after.clone(),
+            self.bytes_written.clone(),
+        ]
+        .join("\t")
+    }
+}
+
+pub fn do_compress(input_path: &str, output_path: &str, options: &CompressOptions) -> Result<()> {
+    if options.check {
+        eprintln!("Checking {}", input_path);
+        let errors = check_script(input_path, options.input_format)?;
+        for e in errors.iter() {
+            eprintln!("{}", e);
+        }
+        return match errors.len() {
+            0 => {
+                eprintln!("No syntax errors");
+                Ok(())
+            }
+            n => Err(WpkppError::CheckFailed(format!(
+                "{} syntax error(s) in {}",
+                n, input_path
+            ))),
+        };
+    }
+
+    let result = compress_script(
+        input_path,
+        output_path,
+        options,
+        options.json || options.porcelain,
+    )?;
+
+    let summary = match (options.json, options.porcelain) {
+        (true, _) => json::to_string(&result),
+        (false, true) => result.porcelain(),
+        (false, false) => return Ok(()),
+    };
+    // The compressed script owns stdout when written there
+    match output_path == STDOUT_PATH && !options.dry_run {
+        true => eprintln!("{}", summary),
+        false => println!("{}", summary),
+    }
+    Ok(())
+}
+
+/// Compresses one script, printing progress unless `quiet`
+fn compress_script(
+    input_path: &str,
+    output_path: &str,
+    options: &CompressOptions,
+    quiet: bool,
+) -> Result<CompressResult> {
+    // Progress goes to stderr, leaving stdout to the compressed program or JSON
+    macro_rules! chatter {
+        ($($arg:tt)*) => {
+            if !quiet {
+                eprintln!($($arg)*);
+            }
+        };
+    }
+
+    if options.input_format.is_none()
+        && input_path != STDIN_PATH
+        && !check_valid_extension(input_path)
+    {
+        Err(WpkppError::InvalidArgument(format!(
+            "Invalid input woodpecker script name {}, should end in \".wpk\", \".wpkm\", \".wpkb\" or \".wpkc\"",
+            input_path
+        )))?;
+    }
+    if output_path != STDOUT_PATH && !check_valid_extension(output_path) {
+        Err(WpkppError::InvalidArgument(format!(
+            "Invalid output woodpecker script name {}, should end in \".wpk\", \".wpkm\", \".wpkb\" or \".wpkc\"",
+            output_path
+        )))?;
+    }
+    if output_path != STDOUT_PATH && input_path == output_path {
+        Err(WpkppError::InvalidArgument(
+            "Input and output paths the same; aborting".to_string(),
+        ))?;
+    }
+    if ScriptCompression::split_path(output_path).1 != ScriptCompression::None {
+        Err(WpkppError::InvalidArgument(format!(
+            "Cannot write compressed output {}; only inputs may be .gz / .zst",
+            output_path
+        )))?;
+    }
+    let output_format = options
+        .output_format
+        .or_else(|| ScriptFormat::from_path(output_path))
+        .or(options.input_format)
+        .or_else(|| ScriptFormat::from_path(input_path))
+        .ok_or_else(|| {
+            WpkppError::InvalidArgument(format!(
+                "Unknown output format for {}; try --output-format",
+                output_path
+            ))
+        })?;
+    let container = options
+        .container
+        .or_else(|| is_container_path(output_path).then_some(ContainerEncoding::Base64));
+
+    chatter!("Compressing {} => {}", input_path, output_path);
+    chatter!("Parsing...");
+    let parse_options = ParseOptions {
+        wrap_repeats: options.wrap_repeats,
+        split_repeats: options.split_repeats,
+        latin1: options.latin1,
+        jobs: options.jobs,
+        cache: options.cache,
+        ..Default::default()
+    };
+    let mut script = parse_script_with(input_path, options.input_format, false, parse_options)?;
+    let opcounts = script.instructions.opcount();
+    if options.optimize > 0 {
+        let report = optimize_script(&mut script, options.optimize);
+        chatter!(
+            "Optimized away {} instruction(s), saving {} step(s)",
+            report.instructions_removed,
+            report.steps_saved
+        );
+    }
+    let output_region = match options.optimize_task {
+        Some(task) => {
+            let output_region = task.output_region()?;
+            let report = eliminate_dead_tail(&mut script, output_region);
+            chatter!(
+                "Removed {} trailing instruction(s) which cannot affect the output, saving {} step(s)",
+                report.instructions_removed,
+                report.steps_saved
+            );
+            Some(output_region)
+        }
+        None => None,
+    };
+    let instructions = &script.instructions;
+
+    chatter!(
+        "Instruction Counts: INC {} / CDEC {} / LOAD {} / INV {}",
+        opcounts.0,
+        opcounts.1,
+        opcounts.2,
+        opcounts.3
+    );
+    chatter!(
+        "Total {} instruction(s) compressed into {} steps",
+        opcounts.0 + opcounts.1 + opcounts.2 + opcounts.3,
+        instructions.len()
+    );
+
+    let mut output: Vec<u8> = vec![];
+    write_script(
+        &mut output,
+        &script,
+        output_format,
+        options.keep_comments,
+        options.wrap,
+    )?;
+
+    if let Some(target) = options.verify {
+        if input_path == STDIN_PATH {
+            return Err(WpkppError::InvalidArgument(
+                "Cannot verify a script read from stdin".to_string(),
+            ));
+        }
+        chatter!("Verifying...");
+        // Compare the literal input against what a later parse of the output will see
+        let parse_options = ParseOptions {
+            compress: false,
+            wrap_repeats: options.wrap_repeats,
+            split_repeats: options.split_repeats,
+            latin1: options.latin1,
+            jobs: options.jobs,
+            ..Default::default()
+        };
+        let original = parse_script_with(input_path, options.input_format, false, parse_options)?;
+        let compressed = parse_script_bytes(&output, output_format)?;
+        let report = verify_equivalence(
+            &original.instructions,
+            &compressed.instructions,
+            target,
+            options.optimize > 0 || output_region.is_some(),
+            output_region,
+        )?;
+        chatter!(
+            "Verified {} trial(s); Memory usage changed in {}",
+            report.trials,
+            report.memory_differs
+        );
+    }
+    if let Some(encoding) = container {
+        output = encode_container(&output, output_format, encoding).into_bytes();
+    }
+
+    let bytes_written = match options.dry_run {
+        true => {
+            let input_size = match input_path == STDIN_PATH {
+                true => None,
+                false => Some(std::fs::metadata(input_path)?.len()),
+            };
+            match input_size {
+                Some(input_size) => chatter!(
+                    "Size: {} => {} bytes ({:.2}% of input)",
+                    input_size,
+                    output.len(),
+                    100.0 * (output.len() as f64) / (max(input_size, 1) as f64)
+                ),
+                None => chatter!("Size: {} bytes", output.len()),
+            }
+            chatter!("Dry run; nothing written to {}", output_path);
+            0
+        }
+        false => {
+            chatter!("Writing...");
+            let mut writer = create_output(output_path)?;
+            writer.write_all(&output)?;
+            writer.flush()?;
+            chatter!("Done!");
+            output.len()
+        }
+    };
+
+    Ok(CompressResult {
+        input: input_path.to_string(),
+        output: output_path.to_string(),
+        opcounts: InstructionCount::new(opcounts),
+        instructions_before: (opcounts.0 + opcounts.1 + opcounts.2 + opcounts.3).to_string(),
+        instructions_after: instructions.len().to_string(),
+        bytes_written: bytes_written.to_string(),
+    })
+}
+
+/// Finds the scripts matched by a glob pattern or inside a directory, each paired with its
+/// path relative to the pattern's fixed leading directories
+pub(crate) fn expand_script_paths(pattern: &str) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut paths = vec![];
+
+    if Path::new(pattern).is_dir() {
+        let mut found = vec![];
+        let mut pending = vec![PathBuf::from(pattern)];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if check_valid_extension(&path.to_string_lossy()) {
+                    found.push(path);
+                }
+            }
+        }
+        found.sort();
+        return Ok(found
+            .into_iter()
+            .map(|path| {
+                let relative = path.strip_prefix(pattern).unwrap().to_path_buf();
+                (path, relative)
+            })
+            .collect());
+    }
+
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect();
+    let matches = glob::glob(pattern)
+        .map_err(|e| WpkppError::InvalidArgument(format!("Invalid pattern {}: {}", pattern, e)))?;
+    for path in matches {
+        let path = path.map_err(std::io::Error::from)?;
+        if path.is_file() && check_valid_extension(&path.to_string_lossy()) {
+            let relative = path.strip_prefix(&base).unwrap_or(&path).to_path_buf();
+            paths.push((path, relative));
+        }
+    }
+    Ok(paths)
+}
+
+/// Compresses every script matched by a glob pattern or found in a directory into `out_dir`,
+/// keeping their relative names, then prints a summary table
+pub fn do_compress_batch(pattern: &str, out_dir: &str, options: &CompressOptions) -> Result<()> {
+    let paths = expand_script_paths(pattern)?;
+    if paths.is_empty() {
+        return Err(WpkppError::InvalidArgument(format!(
+            "No woodpecker scripts match {}",
+            pattern
+        )));
+    }
+
+    let mut results = vec![];
+    let mut failures = vec![];
+    for (input, relative) in paths.iter() {
+        // Compressed inputs are written out uncompressed, in the requested format if any
+        let relative = relative.to_string_lossy();
+        let (relative, _) = ScriptCompression::split_path(&relative);
+        let mut output = Path::new(out_dir).join(relative);
+        if let Some(format) = options.output_format {
+            output.set_extension(format.name());
+        }
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let input = input.to_string_lossy();
+        let output = output.to_string_lossy();
+        match compress_script(&input, &output, options, true) {
+            Ok(result) => results.push(result),
+            Err(e) => failures.push((input.to_string(), e.to_string())),
+        }
+    }
+
+    if options.json {
+        println!("{}", json::to_string(&results));
+    } else if options.porcelain {
+        for result in results.iter() {
+            println!("{}", result.porcelain());
+        }
+        for (input, error) in failures.iter() {
+            println!(
+                "failed\t{}\t{}",
+                porcelain_field(input),
+                porcelain_field(error)
+            );
+        }
+    } else {
+        println!(
+            "{:<40} {:>14} {:>14} {:>12}",
+            "Script", "Instructions", "Compressed", "Bytes"
+        );
+        for result in results.iter() {
+            println!(
+                "{:<40} {:>14} {:>14} {:>12}",
+                result.input,
+                result.instructions_before,
+                result.instructions_after,
+                result.bytes_written
+            );
+        }
+        for (input, error) in failures.iter() {
+            eprintln!("Failed {}: {}", input, error);
+        }
+        println!(
+            "Compressed {}/{} script(s) into {}",
+            results.len(),
+            paths.len(),
+            out_dir
+        );
+    }
+
+    match failures.len() {
+        0 => Ok(()),
+        n => Err(WpkppError::CheckFailed(format!(
+            "{} script(s) failed to compress",
+            n
+        ))),
+    }
+}
+
+/// Opens an output file for writing, or stdout for STDOUT_PATH
+pub(crate) fn create_output(output_path: &str) -> Result<BufWriter<Box<dyn Write>>> {
+    let output: Box<dyn Write> = match output_path == STDOUT_PATH {
+        true => Box::new(std::io::stdout().lock()),
+        false => Box::new(
+            File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output_path)?,
+        ),
+    };
+    Ok(BufWriter::new(output))
+}
+
+pub struct ConvertOptions {
+    /// Merge neighbouring INC / CDEC instructions; Otherwise instructions are kept one for one,
+    /// except for REPEAT blocks, which are always expanded
+    pub compress: bool,
+    /// Input format, overriding the input path's extension
+    pub input_format: Option<ScriptFormat>,
+    /// Output format, overriding the output path's extension
+    pub output_format: Option<ScriptFormat>,
+    /// Wrap INC / CDEC counts of MEM_SIZE or more around instead of erroring
+    pub wrap_repeats: bool,
+    /// Split INC / CDEC counts of MEM_SIZE or more into several instructions instead of erroring
+    pub split_repeats: bool,
+    /// Read input which is not valid UTF-8 as Latin-1
+    pub latin1: bool,
+    /// Threads for parsing large .wpkm scripts; None uses every core
+    pub jobs: Option<usize>,
+}
+
+/// Converts a script between formats, keeping comments and labels where the output format
+/// has room for them
+pub fn do_convert(input_path: &str, output_path: &str, options: &ConvertOptions) -> Result<()> {
+    let input_format = options
+        .input_format
+        .or_else(|| ScriptFormat::from_path(input_path))
+        .ok_or_else(|| {
+            WpkppError::InvalidArgument(format!(
+                "Unknown input format for {}; try --format",
+                input_path
+            ))
+        })?;
+    let output_format = options
+        .output_format
+        .or_else(|| ScriptFormat::from_path(output_path))
+        .ok_or_else(|| {
+            WpkppError::InvalidArgument(format!(
+                "Unknown output format for {}; try --output-format",
+                output_path
+            ))
+        })?;
+    if output_path != STDOUT_PATH && input_path == output_path {
+        Err(WpkppError::InvalidArgument(
+            "Input and output paths the same; aborting".to_string(),
+        ))?;
+    }
+    if ScriptCompression::split_path(output_path).1 != ScriptCompression::None {
+        Err(WpkppError::InvalidArgument(format!(
+            "Cannot write compressed output {}; only inputs may be .gz / .zst",
+            output_path
+        )))?;
+    }
+
+    let parse_options = ParseOptions {
+        compress: options.compress,
+        wrap_repeats: options.wrap_repeats,
+        split_repeats: options.split_repeats,
+        latin1: options.latin1,
+        jobs: options.jobs,
+        ..Default::default()
+    };
+    let script = parse_script_with(input_path, Some(input_format), false, parse_options)?;
+
+    let mut writer = create_output(output_path)?;
+    write_script(&mut writer, &script, output_format, true, None)?;
+    writer.flush()?;
+
+    let summary = format!(
+        "Converted {} ({}) => {} ({}), {} instruction(s)",
+        input_path,
+        input_format.name(),
+        output_path,
+        output_format.name(),
+        script.instructions.len()
+    );
+    eprintln!("{}", summary);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    /// Parses a script from a temporary file, named so the extension picks the format
+    fn parse_source(name: &str, source: impl AsRef<[u8]>) -> Result<Instructions> {
+        let path = env::temp_dir().join(format!("wpkpp-test-{}-{}", process::id(), name));
+        fs::write(&path, source).unwrap();
+        let parsed = parse_file(path.to_str().unwrap(), false);
+        fs::remove_file(&path).unwrap();
+        parsed
+    }
+
+    #[test]
+    fn repeat_blocks_nest() {
+        let expected = [
+            Instruction::Inv,
+            Instruction::Inc(1),
+            Instruction::Load,
+            Instruction::Inc(1),
+            Instruction::Load,
+        ]
+        .repeat(2);
+        let wpk = parse_source(
+            "nest.wpk",
+            "REPEAT 2 {\nINV\nREPEAT 2 {\nINC 1\nLOAD\n}\n}\n",
+        )
+        .unwrap();
+        assert_eq!(wpk[..], expected[..]);
+        let wpkm = parse_source("nest.wpkm", "2[!2[>?]]").unwrap();
+        assert_eq!(wpkm[..], expected[..]);
+    }
+
+    #[test]
+    fn repeat_blocks_compress() {
+        let wpk = parse_source("compress.wpk", "INC 1\nREPEAT 4 {\nINC 2\n}\n").unwrap();
+        assert_eq!(wpk[..], [Instruction::Inc(9)]);
+    }
+
+    #[test]
+    fn empty_repeat_blocks_expand_to_nothing() {
+        let wpk = parse_source(
+            "empty.wpk",
+            "REPEAT 18446744073709551615 {\nREPEAT 18446744073709551615 {\n}\n}\nINV\n",
+        )
+        .unwrap();
+        assert_eq!(wpk[..], [Instruction::Inv]);
+        let wpkm = parse_source("empty.wpkm", "4294967296[4294967296[]]!").unwrap();
+        assert_eq!(wpkm[..], [Instruction::Inv]);
+    }
+
+    #[test]
+    fn repeat_expansion_is_capped() {
+        let error = parse_source("cap.wpk", "REPEAT 100000001 {\nINV\n}\n").unwrap_err();
+        assert!(error.to_string().contains("expand to over"), "{}", error);
+        let error = parse_source("cap.wpkm", "10000[10001[!]]").unwrap_err();
+        assert!(error.to_string().contains("expand to over"), "{}", error);
+        assert!(parse_source("under-cap.wpkm", "100[100[!]]").is_ok());
+    }
+
+    #[test]
+    fn unbalanced_repeat_blocks_fail() {
+        assert!(parse_source("unclosed.wpk", "REPEAT 2 {\nINV\n").is_err());
+        assert!(parse_source("unmatched.wpkm", "!]").is_err());
+    }
+
+    /// A .wpkb file around `body`, which starts after the magic header and version
+    fn wpkb_with_body(body: &[u8]) -> Vec<u8> {
+        let mut bytes = [WPKB_MAGIC, &[WPKB_VERSION], body].concat();
+        let checksum = fnv1a(FNV_OFFSET, &bytes).to_le_bytes();
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for x in [0, 1, 0x7f, 0x80, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, x).unwrap();
+            let mut pos = 0;
+            assert_eq!(read_varint(&bytes, &mut pos).unwrap(), x);
+            assert_eq!(pos, bytes.len());
+        }
+
+        let mut pos = 0;
+        assert!(read_varint(&[0x80, 0x80], &mut pos).is_err());
+        let mut pos = 0;
+        assert!(read_varint(&[0xff; 11], &mut pos).is_err());
+    }
+
+    #[test]
+    fn wpkb_round_trips() {
+        let instructions: Instructions = vec![
+            Instruction::Inc(3),
+            Instruction::Load,
+            Instruction::Cdec(200),
+            Instruction::Inv,
+            Instruction::Inc(VmUsize::MAX),
+        ]
+        .into();
+        let mut bytes = vec![];
+        write_wpkb(&mut bytes, &instructions).unwrap();
+        assert!(bytes.starts_with(WPKB_MAGIC));
+        assert_eq!(
+            parse_source("round-trip.wpkb", &bytes).unwrap(),
+            instructions
+        );
+
+        // Zero length moves are dropped rather than written
+        let mut bytes = vec![];
+        write_wpkb(
+            &mut bytes,
+            &vec![Instruction::Inc(0), Instruction::Inv].into(),
+        )
+        .unwrap();
+        assert_eq!(
+            parse_source("zero.wpkb", &bytes).unwrap()[..],
+            [Instruction::Inv]
+        );
+    }
+
+    #[test]
+    fn corrupt_wpkb_fails() {
+        let mut valid = vec![];
+        write_wpkb(
+            &mut valid,
+            &vec![Instruction::Inc(5), Instruction::Inv].into(),
+        )
+        .unwrap();
+        let fails_with = |name: &str, bytes: &[u8], message: &str| {
+            let error = parse_source(name, bytes).unwrap_err().to_string();
+            assert!(error.contains(message), "{}: {}", name, error);
+        };
+
+        let mut flipped = valid.clone();
+        flipped[6] ^= 0x04;
+        fails_with("flipped.wpkb", &flipped, "checksum mismatch");
+        fails_with(
+            "truncated.wpkb",
+            &valid[..valid.len() - 1],
+            "checksum mismatch",
+        );
+        fails_with("short.wpkb", b"WPKB", "missing magic header");
+        fails_with(
+            "magic.wpkb",
+            &[b"WPKM", &valid[4..]].concat(),
+            "missing magic header",
+        );
+
+        let mut version = valid.clone();
+        version[WPKB_MAGIC.len()] = WPKB_VERSION + 1;
+        fails_with("version.wpkb", &version, "unsupported version");
+
+        // Well formed checksums around bad bodies
+        fails_with(
+            "count.wpkb",
+            &wpkb_with_body(&[2, 0x0c]),
+            "truncated varint",
+        );
+        fails_with(
+            "trailing.wpkb",
+            &wpkb_with_body(&[1, 0x0c, 0x03]),
+            "trailing data",
+        );
+        fails_with(
+            "load.wpkb",
+            &wpkb_with_body(&[1, 0x06]),
+            "invalid instruction code",
+        );
+    }
+
+    /// Instructions of a script read through an InstructionStream
+    fn stream(source: &str, format: ScriptFormat) -> Result<Vec<Instruction>> {
+        InstructionStream::new(source.as_bytes(), format)?.collect()
+    }
+
+    /// Streaming must yield exactly what parsing the whole script at once does
+    fn assert_streams_like_parse(wpk: &str, wpkm: &str) {
+        assert_eq!(
+            stream(wpk, ScriptFormat::Wpk).unwrap()[..],
+            parse_wpk_str(wpk).unwrap()[..],
+            "{}",
+            wpk
+        );
+        assert_eq!(
+            stream(wpkm, ScriptFormat::Wpkm).unwrap()[..],
+            parse_wpkm_bytes(wpkm.as_bytes()).unwrap()[..],
+            "{}",
+            wpkm
+        );
+    }
+
+    #[test]
+    fn stream_matches_parse() {
+        assert_streams_like_parse("", "");
+        assert_streams_like_parse("INC 3\nLOAD\nCDEC 2\nINV\n", "3>?2<!");
+        // Merges reach back past the instruction before, so the last two stay buffered
+        assert_streams_like_parse("INC 1\nCDEC 2\nINC 3\nCDEC 4\nINC 5\n", "><>2<3>4<5>");
+        assert_streams_like_parse(&"INC 1\nCDEC 1\n".repeat(1000), &"><".repeat(1000));
+        assert_streams_like_parse(&"INC 7\nLOAD\n".repeat(1000), &"7>?".repeat(1000));
+        assert_streams_like_parse("INC 1\n@a:\nINC 2\n", ">@a:2>");
+        assert_streams_like_parse("INV # note\nINV\n", "!; note\n!");
+    }
+
+    #[test]
+    fn stream_matches_parse_with_repeat_and_define() {
+        assert_streams_like_parse("INC 1\nREPEAT 3 {\nINC 2\nCDEC 1\n}\nLOAD\n", ">3[2><]?");
+        assert_streams_like_parse(
+            "REPEAT 2 {\nINV\nREPEAT 3 {\nINC\nLOAD\n}\n}\n",
+            "2[!3[>?]]",
+        );
+        assert_streams_like_parse("REPEAT 5 {\n}\nINV\n", "5[]!");
+        let wpk = "DEFINE WIDTH 16\nDEFINE TWICE WIDTH\nREPEAT WIDTH {\nINC\nLOAD\n}\nCDEC TWICE\n";
+        assert_eq!(
+            stream(wpk, ScriptFormat::Wpk).unwrap()[..],
+            parse_wpk_str(wpk).unwrap()[..]
+        );
+        assert_eq!(
+            parse_wpk_str(wpk).unwrap()[..],
+            parse_wpkm_bytes(b"16[>?]16<").unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn stream_fails_like_parse() {
+        for wpk in ["INC\nREPEAT 2 {\nINV\n", "}\n", "INC x\n", "DEFINE INC 3\n"] {
+            assert!(stream(wpk, ScriptFormat::Wpk).is_err(), "{}", wpk);
+            assert!(parse_wpk_str(wpk).is_err(), "{}", wpk);
+        }
+        for wpkm in ["2[!", "]", "3?"] {
+            assert!(stream(wpkm, ScriptFormat::Wpkm).is_err(), "{}", wpkm);
+            assert!(parse_wpkm_bytes(wpkm.as_bytes()).is_err(), "{}", wpkm);
+        }
+    }
+
+    /// Parses .wpkm one char at a time, never taking the parallel path
+    fn parse_wpkm_sequential(bytes: &[u8]) -> Vec<Instruction> {
+        stream(std::str::from_utf8(bytes).unwrap(), ScriptFormat::Wpkm).unwrap()
+    }
+
+    /// A plain .wpkm script of mergeable runs cut up by LOAD / INV
+    fn plain_wpkm(len: usize) -> Vec<u8> {
+        let pieces: [&[u8]; 8] = [b"3>", b"<", b"12<", b"?", b">>", b"7> 2<", b"!\n", b"?!"];
+        let mut bytes = vec![];
+        let mut idx = 0usize;
+        while bytes.len() < len {
+            bytes.extend_from_slice(pieces[idx % pieces.len()]);
+            idx = idx.wrapping_mul(31).wrapping_add(17) % 1009;
+        }
+        bytes
+    }
+
+    #[test]
+    fn wpkm_chunks_parse_like_whole_script() {
+        let samples: [&[u8]; 5] = [b"", b"3>4<?", b"?????", b"3>2<>>>5<", b"2>?3<!4>?\n5<?"];
+        for bytes in samples.into_iter().chain([&plain_wpkm(5000)[..]]) {
+            let whole = parse_wpkm_sequential(bytes);
+            for n_chunks in 1..=12 {
+                let bounds = wpkm_chunk_boundaries(bytes, n_chunks);
+                assert_eq!(bounds.first(), Some(&0));
+                assert_eq!(bounds.last(), Some(&bytes.len()));
+                if let [_, inner @ .., _] = &bounds[..] {
+                    for bound in inner {
+                        assert!(WPKM_BOUNDARY_BYTES.contains(&bytes[bound - 1]));
+                    }
+                }
+
+                let mut chunked = vec![];
+                for bound in bounds.windows(2) {
+                    let chunk =
+                        parse_wpkm_chunk(&bytes[bound[0]..bound[1]], ParseOptions::default())
+                            .unwrap();
+                    chunked.extend(chunk.instructions.iter().copied());
+                }
+                assert_eq!(chunked, whole, "{} chunks", n_chunks);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_parse_matches_sequential() {
+        let bytes = plain_wpkm(PARALLEL_THRESHOLD as usize + 4096);
+        assert_eq!(
+            parse_wpkm_bytes(&bytes).unwrap()[..],
+            parse_wpkm_sequential(&bytes)[..]
+        );
+
+        // A bad instruction still fails when it lands in a later chunk
+        let mut bad = bytes.clone();
+        bad.extend_from_slice(b"3?");
+        assert!(parse_wpkm_bytes(&bad).is_err());
+    }
+
+    #[test]
+    fn parallel_parse_caps_expansion_across_chunks() {
+        // Split repeats spread over the script, each under the cap but together over it
+        let count = VmUsize::MAX as u128 * (MAX_REPEAT_EXPANSION as u128 / 3);
+        let quarter = plain_wpkm(PARALLEL_THRESHOLD as usize / 4);
+        let mut bytes = vec![];
+        for _ in 0..4 {
+            bytes.extend_from_slice(format!("{}>!", count).as_bytes());
+            bytes.extend_from_slice(&quarter);
+        }
+
+        let options = |source_map, jobs| ParseOptions {
+            source_map,
+            split_repeats: true,
+            jobs,
+            ..Default::default()
+        };
+        for (source_map, jobs) in [(true, None), (false, Some(1)), (false, Some(4))] {
+            match parse_wpkm_slice(&bytes, options(source_map, jobs)) {
+                Err(WpkppError::ParseError(error)) => {
+                    assert_eq!(error.kind, ParseErrorKind::ExpansionTooLarge, "{:?}", jobs)
+                }
+                other => panic!("{:?} jobs parsed to {:?}", jobs, other.map(|_| ())),
+            }
+        }
+    }
+
+    /// The ParseError a script fails with
+    fn parse_error(parsed: Result<Instructions>) -> ParseError {
+        match parsed.unwrap_err() {
+            WpkppError::ParseError(error) => error,
+            other => panic!("not a parse error: {}", other),
+        }
+    }
+
+    #[test]
+    fn locate_end_counts_lines_and_chars() {
+        assert_eq!(locate_end(""), (1, 1));
+        assert_eq!(locate_end("INC"), (1, 4));
+        assert_eq!(locate_end("INC\n"), (2, 1));
+        assert_eq!(locate_end("INC\nLOAD\nIN"), (3, 3));
+        assert_eq!(locate_end("\u{e9}\u{e9}"), (1, 3));
+    }
+
+    #[test]
+    fn wpk_errors_point_at_the_token() {
+        let error = parse_error(parse_wpk_str("INC 1\nLOAD\n  FOO 2\n"));
+        assert_eq!(error.kind, ParseErrorKind::UnknownInstruction);
+        assert_eq!((error.line, error.column), (3, 3));
+        assert_eq!(error.snippet, "FOO 2");
+        assert_eq!(
+            error.to_string(),
+            "Unknown instruction @ line 3, column 3: FOO 2"
+        );
+
+        let error = parse_error(parse_wpk_str("INV\nINC x\n"));
+        assert!(matches!(error.kind, ParseErrorKind::InvalidNumber(_)));
+        assert_eq!(error.line, 2);
+
+        let error = parse_error(parse_wpk_str("INV\n}\n"));
+        assert_eq!(error.kind, ParseErrorKind::UnmatchedBlockEnd);
+        assert_eq!((error.line, error.column), (2, 1));
+
+        let error = parse_error(parse_wpk_str("REPEAT 2 {\nINV\n"));
+        assert_eq!(error.kind, ParseErrorKind::UnclosedBlocks(1));
+        assert_eq!(error.line, 3);
+    }
+
+    #[test]
+    fn wpkm_errors_point_at_the_character() {
+        let error = parse_error(parse_wpkm_bytes(b"!!\n>3?"));
+        assert_eq!((error.line, error.column), (2, 3));
+        assert_eq!(
+            error.kind,
+            ParseErrorKind::RepeatNotAllowed("LOAD", "3".to_string())
+        );
+
+        let error = parse_error(parse_wpkm_bytes(b"!>]"));
+        assert_eq!(error.kind, ParseErrorKind::UnmatchedBlockEnd);
+        assert_eq!((error.line, error.column), (1, 3));
+
+        let error = parse_error(parse_wpkm_bytes(b"!\n12"));
+        assert_eq!(error.kind, ParseErrorKind::DanglingRepeat("12".to_string()));
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn wpkb_errors_point_at_the_byte() {
+        let error = parse_wpkb_slice(&wpkb_with_body(&[1, 0x06]), ParseOptions::default())
+            .err()
+            .unwrap();
+        assert!(matches!(error.kind, ParseErrorKind::InvalidBinary(_)));
+        assert_eq!((error.line, error.column), (1, WPKB_MAGIC.len() + 3));
+    }
+
+    #[test]
+    fn decode_text_falls_back_to_latin1() {
+        assert!(matches!(
+            decode_text(b"INV", false),
+            Ok(Cow::Borrowed("INV"))
+        ));
+        assert_eq!(decode_text(b"; caf\xe9\nINV", false), Err(5));
+        assert_eq!(
+            decode_text(b"; caf\xe9\nINV", true).unwrap(),
+            "; caf\u{e9}\nINV"
+        );
+    }
+
+    #[test]
+    fn boms_and_crlf_parse_like_plain_text() {
+        let wpk = parse_wpk_str("INC 2\nLOAD\nINV\n").unwrap();
+        assert_eq!(
+            parse_wpk_str("\u{feff}INC 2\r\nLOAD\r\nINV\r\n").unwrap(),
+            wpk
+        );
+        let wpkm = parse_wpkm_bytes(b"2>\n?!").unwrap();
+        assert_eq!(
+            parse_wpkm_bytes("\u{feff}2>\r\n?!\r\n".as_bytes()).unwrap(),
+            wpkm
+        );
+
+        // Only a leading BOM is skipped
+        assert!(parse_wpkm_bytes("!\u{feff}".as_bytes()).is_err());
+        assert!(parse_wpk_str("INV\n\u{feff}INV\n").is_err());
+    }
+
+    #[test]
+    fn invalid_utf8_is_blamed_on_its_byte() {
+        let source = b"INC 1\r\n; caf\xe9\nINV\n";
+        let error = parse_error(
+            parse_wpk_reader(&source[..], ParseOptions::default()).map(|s| s.instructions),
+        );
+        assert_eq!(error.kind, ParseErrorKind::InvalidUtf8(12));
+        assert_eq!((error.line, error.column), (2, 6));
+
+        let error = parse_error(parse_wpkm_bytes(b"!>\n?\xff!"));
+        assert_eq!(error.kind, ParseErrorKind::InvalidUtf8(4));
+        assert_eq!((error.line, error.column), (2, 2));
+
+        let streamed: Result<Vec<_>> =
+            InstructionStream::new(&b"!>\n?\xff!"[..], ScriptFormat::Wpkm)
+                .unwrap()
+                .collect();
+        let error = parse_error(streamed.map(Instructions::from));
+        assert_eq!(error.kind, ParseErrorKind::InvalidUtf8(4));
+
+        let latin1 = ParseOptions {
+            latin1: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse_wpk_reader(&source[..], latin1).unwrap().instructions,
+            parse_wpk_str("INC 1\nINV\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn repeat_counts_accumulate_past_u64() {
+        let huge = "123456789012345678901234567890";
+        let count = RepeatCount::parse(huge).unwrap();
+        assert_eq!(count.value, Some(123456789012345678901234567890));
+        assert_eq!(
+            count.wrapped,
+            (123456789012345678901234567890u128 % MEM_SIZE as u128) as u64
+        );
+        assert_eq!(count.to_u64(), None);
+
+        let overflowing = "9".repeat(40);
+        let count = RepeatCount::parse(&overflowing).unwrap();
+        assert_eq!(count.value, None);
+        assert_eq!(count.literal, overflowing);
+        assert!(RepeatCount::parse("").is_none());
+        assert!(RepeatCount::parse("1x").is_none());
+    }
+
+    #[test]
+    fn oversized_repeats_split_wrap_or_fail() {
+        let options = |wrap_repeats, split_repeats| ParseOptions {
+            wrap_repeats,
+            split_repeats,
+            ..ParseOptions::default()
+        };
+        let wpk = |source: &str, options| {
+            parse_wpk_reader(source.as_bytes(), options).map(|s| s.instructions)
+        };
+        let wpkm = |source: &str, options| {
+            parse_wpkm_slice(source.as_bytes(), options).map(|s| s.instructions)
+        };
+        let max = VmUsize::MAX;
+
+        let split = [Instruction::Inc(max), Instruction::Inc(1)];
+        assert_eq!(
+            wpk("INC 4294967296", options(false, true)).unwrap()[..],
+            split
+        );
+        assert_eq!(
+            wpkm("4294967296>", options(false, true)).unwrap()[..],
+            split
+        );
+        assert_eq!(
+            wpkm("8589934592<", options(false, true)).unwrap()[..],
+            [
+                Instruction::Cdec(max),
+                Instruction::Cdec(max),
+                Instruction::Cdec(2)
+            ]
+        );
+
+        assert_eq!(
+            wpk("INC 4294967297", options(true, false)).unwrap()[..],
+            [Instruction::Inc(1)]
+        );
+        assert_eq!(
+            wpkm("4294967297>", options(true, false)).unwrap()[..],
+            [Instruction::Inc(1)]
+        );
+
+        let too_large = ParseErrorKind::RepeatTooLarge(INC_STR, "4294967296".to_string());
+        assert_eq!(
+            parse_error(wpk("INC 4294967296", options(false, false))).kind,
+            too_large
+        );
+        assert_eq!(
+            parse_error(wpkm("4294967296>", options(false, false))).kind,
+            too_large
+        );
+
+        // Counts beyond u128 can still be wrapped, but not split
+        let overflowing = format!("{}>", "9".repeat(40));
+        assert!(wpkm(&overflowing, options(true, false)).is_ok());
+        assert!(wpkm(&overflowing, options(false, true)).is_err());
+    }
+}
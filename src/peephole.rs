@@ -0,0 +1,349 @@
+//! Peephole rules: How `Instructions::push_compressed` merges each new instruction into the ones
+//! before it, as a list of rules library users can extend or trim when generating scripts.
+//!
+//! Rules are tried in order and the first one to apply wins; When none does, the instruction is
+//! pushed as is. A rule only ever sees the instructions from the floor on, so it cannot merge
+//! across a label or block boundary the caller wants kept.
+
+use crate::vm::{Instruction, Instructions};
+
+/// A rewrite merging a new instruction into the end of a program
+pub trait PeepholeRule {
+    /// Name to disable the rule by, e.g. "merge-runs"
+    fn name(&self) -> &str;
+
+    /// Merges `new` into `window`, the instructions from the floor on, returning the index in
+    /// `window` it was merged into, or None to leave it to the next rule. Merges should keep
+    /// the step count and what the program does exactly as they were.
+    fn apply(&self, window: &mut [Instruction], new: Instruction) -> Option<usize>;
+}
+
+/// `INC x; INC y` to `INC x+y`, and the same for CDEC
+pub struct MergeRuns;
+
+impl PeepholeRule for MergeRuns {
+    fn name(&self) -> &str {
+        "merge-runs"
+    }
+
+    fn apply(&self, window: &mut [Instruction], new: Instruction) -> Option<usize> {
+        let idx = window.len().checked_sub(1)?;
+        window[idx] = match (window[idx], new) {
+            (Instruction::Inc(y), Instruction::Inc(x)) => Instruction::Inc(y.checked_add(x)?),
+            (Instruction::Cdec(y), Instruction::Cdec(x)) => Instruction::Cdec(y.checked_add(x)?),
+            _ => return None,
+        };
+        Some(idx)
+    }
+}
+
+/// `INC y; CDEC z; INC x` to `INC y+x; CDEC z`, and the same with INC and CDEC swapped, since
+/// nothing in between changes the register
+pub struct MergeAcross;
+
+impl PeepholeRule for MergeAcross {
+    fn name(&self) -> &str {
+        "merge-across"
+    }
+
+    fn apply(&self, window: &mut [Instruction], new: Instruction) -> Option<usize> {
+        let idx = window.len().checked_sub(2)?;
+        window[idx] = match (window[idx], window[idx + 1], new) {
+            (Instruction::Inc(y), Instruction::Cdec(_), Instruction::Inc(x)) => {
+                Instruction::Inc(y.checked_add(x)?)
+            }
+            (Instruction::Cdec(y), Instruction::Inc(_), Instruction::Cdec(x)) => {
+                Instruction::Cdec(y.checked_add(x)?)
+            }
+            _ => return None,
+        };
+        Some(idx)
+    }
+}
+
+/// Pushes `new`, or merges it into the instructions from `floor` on with `merge`, which returns
+/// the index in the window it was merged into
+fn push_with(
+    instructions: &mut Instructions,
+    new: Instruction,
+    floor: usize,
+    merge: impl FnOnce(&mut [Instruction]) -> Option<usize>,
+) -> Option<usize> {
+    if new == Instruction::Null {
+        return None;
+    }
+    if let Some(idx) = merge(&mut instructions[floor..]) {
+        return Some(floor + idx);
+    }
+    instructions.push(new);
+    Some(instructions.len() - 1)
+}
+
+/// Pushes an instruction with the built-in rules, `MergeRuns` then `MergeAcross`. Every parsed
+/// instruction goes through here, so the rules are called directly rather than as
+/// `dyn PeepholeRule`. Instructions before `floor` are never merged into. Returns the index the
+/// instruction ended up at, or None for Null.
+pub fn push_builtin(
+    instructions: &mut Instructions,
+    new: Instruction,
+    floor: usize,
+) -> Option<usize> {
+    push_with(instructions, new, floor, |window| {
+        MergeRuns
+            .apply(window, new)
+            .or_else(|| MergeAcross.apply(window, new))
+    })
+}
+
+/// Pushes an instruction, merging it with the first of `rules` to apply. Instructions before
+/// `floor` are never merged into. Returns the index the instruction ended up at, or None for
+/// Null.
+pub fn push_with_rules<'a>(
+    instructions: &mut Instructions,
+    rules: impl IntoIterator<Item = &'a dyn PeepholeRule>,
+    new: Instruction,
+    floor: usize,
+) -> Option<usize> {
+    push_with(instructions, new, floor, |window| {
+        rules.into_iter().find_map(|rule| rule.apply(window, new))
+    })
+}
+
+/// An ordered list of peephole rules, starting from the built-in ones
+pub struct Peephole {
+    /// Whether the built-in rules are on; They always come first, and are kept out of `rules`
+    /// so that only rules added with `with_rule` cost a dynamic call
+    merge_runs: bool,
+    merge_across: bool,
+    rules: Vec<Box<dyn PeepholeRule>>,
+}
+
+impl Default for Peephole {
+    fn default() -> Self {
+        Self {
+            merge_runs: true,
+            merge_across: true,
+            rules: vec![],
+        }
+    }
+}
+
+impl Peephole {
+    /// The built-in rules, as used by `push_compressed`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No rules at all, pushing every instruction as is
+    pub fn empty() -> Self {
+        Self {
+            merge_runs: false,
+            merge_across: false,
+            rules: vec![],
+        }
+    }
+
+    /// Adds a rule after the ones already there
+    pub fn with_rule(mut self, rule: impl PeepholeRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Drops every rule with this name, e.g. "merge-across"
+    pub fn without(mut self, name: &str) -> Self {
+        self.merge_runs &= MergeRuns.name() != name;
+        self.merge_across &= MergeAcross.name() != name;
+        self.rules.retain(|rule| rule.name() != name);
+        self
+    }
+
+    pub fn rule_names(&self) -> Vec<&str> {
+        let builtin = [
+            (self.merge_runs, MergeRuns.name()),
+            (self.merge_across, MergeAcross.name()),
+        ];
+        builtin
+            .into_iter()
+            .filter_map(|(on, name)| on.then_some(name))
+            .chain(self.rules.iter().map(|rule| rule.name()))
+            .collect()
+    }
+
+    /// Like `Instructions::push_compressed`, with these rules
+    pub fn push(
+        &self,
+        instructions: &mut Instructions,
+        new: Instruction,
+        floor: usize,
+    ) -> Option<usize> {
+        push_with(instructions, new, floor, |window| {
+            self.merge_runs
+                .then(|| MergeRuns.apply(window, new))
+                .flatten()
+                .or_else(|| {
+                    self.merge_across
+                        .then(|| MergeAcross.apply(window, new))
+                        .flatten()
+                })
+                .or_else(|| self.rules.iter().find_map(|rule| rule.apply(window, new)))
+        })
+    }
+
+    /// A whole program pushed through these rules
+    pub fn compress(&self, program: &[Instruction]) -> Instructions {
+        let mut instructions = Instructions::with_capacity(program.len());
+        for instruction in program {
+            self.push(&mut instructions, *instruction, 0);
+        }
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng};
+    use rand_seeder::Seeder;
+
+    use super::*;
+    use crate::{
+        test_util::{random_window, run_window},
+        vm::{Vm, VmUsize},
+    };
+
+    use Instruction::{Cdec, Inc, Inv, Load};
+
+    const TRIALS: u64 = 64;
+    /// Cells either side of address 0 given random values; Programs below stay within them
+    const WINDOW: i64 = 128;
+
+    /// Merges a LOAD into a LOAD right before it
+    struct MergeLoads;
+
+    impl PeepholeRule for MergeLoads {
+        fn name(&self) -> &str {
+            "merge-loads"
+        }
+
+        fn apply(&self, window: &mut [Instruction], new: Instruction) -> Option<usize> {
+            let idx = window.len().checked_sub(1)?;
+            match (window[idx], new) {
+                (Load, Load) => Some(idx),
+                _ => None,
+            }
+        }
+    }
+
+    fn random_program(rng: &mut StdRng) -> Vec<Instruction> {
+        (0..rng.gen_range(0..40))
+            .map(|_| match rng.gen_range(0..4) {
+                0 => Inc(rng.gen_range(0..3)),
+                1 => Cdec(rng.gen_range(0..3)),
+                2 => Load,
+                _ => Inv,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builtin_rules_merge_runs_and_across_cdecs() {
+        let program = [
+            Inc(1),
+            Inc(2),
+            Cdec(3),
+            Inc(4),
+            Cdec(1),
+            Load,
+            Cdec(2),
+            Cdec(2),
+            Inv,
+        ];
+        assert_eq!(
+            Peephole::new().compress(&program)[..],
+            [Inc(7), Cdec(4), Load, Cdec(4), Inv]
+        );
+        assert_eq!(Peephole::new().rule_names(), ["merge-runs", "merge-across"]);
+    }
+
+    #[test]
+    fn peephole_matches_push_compressed() {
+        let mut rng: StdRng = Seeder::from("WPKPP/peephole/programs").make_rng();
+        for _ in 0..200 {
+            let program = random_program(&mut rng);
+            let mut expected = Instructions::new();
+            for instruction in &program {
+                expected.push_compressed(*instruction, 0);
+            }
+            assert_eq!(
+                Peephole::new().compress(&program),
+                expected,
+                "{:?}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn rules_can_be_dropped_and_added() {
+        let program = [Inc(1), Cdec(2), Inc(3), Load, Load, Inv];
+        assert_eq!(
+            Peephole::new().without("merge-across").compress(&program)[..],
+            [Inc(1), Cdec(2), Inc(3), Load, Load, Inv]
+        );
+        assert_eq!(
+            Peephole::empty().compress(&[Inc(1), Inc(1)])[..],
+            [Inc(1), Inc(1)]
+        );
+
+        let peephole = Peephole::new().with_rule(MergeLoads);
+        assert_eq!(
+            peephole.rule_names(),
+            ["merge-runs", "merge-across", "merge-loads"]
+        );
+        assert_eq!(
+            peephole.compress(&program)[..],
+            [Inc(4), Cdec(2), Load, Inv]
+        );
+    }
+
+    #[test]
+    fn merges_stop_at_the_floor_and_on_overflow() {
+        let mut instructions = Instructions::new();
+        for instruction in [Inc(1), Cdec(2)] {
+            instructions.push_compressed(instruction, 0);
+        }
+        assert_eq!(Peephole::new().push(&mut instructions, Inc(3), 2), Some(2));
+        assert_eq!(Peephole::new().push(&mut instructions, Cdec(1), 3), Some(3));
+        assert_eq!(Peephole::new().push(&mut instructions, Cdec(1), 3), Some(3));
+        assert_eq!(instructions[..], [Inc(1), Cdec(2), Inc(3), Cdec(2)]);
+
+        assert_eq!(
+            Peephole::new().compress(&[Inc(VmUsize::MAX), Inc(1)])[..],
+            [Inc(VmUsize::MAX), Inc(1)]
+        );
+        assert_eq!(
+            Peephole::new().push(&mut instructions, Instruction::Null, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn compressed_programs_run_like_the_original() {
+        let mut rng: StdRng = Seeder::from("WPKPP/peephole/runs").make_rng();
+        let mut vm = Vm::new(Instructions::new());
+        for _ in 0..50 {
+            let program = random_program(&mut rng);
+            let original = Instructions::from(program.clone());
+            let compressed = Peephole::new().compress(&program);
+            for trial in 0..TRIALS {
+                let input = random_window(&format!("WPKPP/peephole/{}", trial), WINDOW);
+                assert_eq!(
+                    run_window(&mut vm, &compressed, &input),
+                    run_window(&mut vm, &original, &input),
+                    "trial {} on {:?}",
+                    trial,
+                    program
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! `wpkpp predict`: A quick estimate of what a full grading will come to, from the script
+//! itself and a few sampled testcases.
+//!
+//! Woodpecker has no jumps and CDEC costs its steps whether or not it moves the pointer, so
+//! every testcase runs for exactly the same number of steps, which can be read off the script.
+//! Memory does depend on the input, so it is bounded statically and sampled.
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::{load_testcase, run_testcase, TESTCASES},
+    parse::{parse_script_as, ScriptFormat},
+    task::Task,
+    util::ResetableTimer,
+    vm::{Instructions, Vm, MEM_SIZE},
+};
+
+/// Testcases run by default
+pub const DEFAULT_SAMPLES: usize = 5;
+
+pub struct PredictOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Task to predict for, instead of the one in the script's metadata
+    pub task: Option<Task>,
+    /// Testcases to run
+    pub samples: usize,
+}
+
+/// What can be told about a script without running it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticBounds {
+    /// Steps taken by every testcase
+    pub runtime: i64,
+    /// Most memory any testcase could span: Every INC moving forward and every CDEC back
+    pub max_memory: i64,
+}
+
+impl StaticBounds {
+    pub fn new(instructions: &Instructions) -> Self {
+        let (inc, cdec, _, _) = instructions.opcount();
+        Self {
+            runtime: instructions.total_steps() as i64,
+            max_memory: (inc + cdec + 1).min(MEM_SIZE) as i64,
+        }
+    }
+}
+
+/// A full grading, estimated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prediction {
+    pub bounds: StaticBounds,
+    /// Testcases run for the estimate
+    pub sampled: Vec<i8>,
+    /// Whether every sampled testcase was correct
+    pub correct: bool,
+    /// Most memory spanned by a sampled testcase
+    pub memory: i64,
+    /// Seconds a full grading should take, scaled up from the sampled testcases
+    pub seconds: f64,
+}
+
+/// `samples` testcases spread evenly over all of them, starting with testcase 0
+pub fn sample_testcases(samples: usize) -> Vec<i8> {
+    let samples = samples.clamp(1, TESTCASES);
+    (0..samples)
+        .map(|i| (i * TESTCASES / samples) as i8)
+        .collect()
+}
+
+/// Estimates a full grading of `instructions` by running `samples` testcases
+pub fn predict(task: Task, instructions: Instructions, samples: usize) -> Result<Prediction> {
+    let bounds = StaticBounds::new(&instructions);
+    let sampled = sample_testcases(samples);
+    let mut vm = Vm::new(instructions);
+
+    let mut timer = ResetableTimer::new();
+    let mut correct = true;
+    let mut memory = 0;
+    for tc_id in sampled.iter().copied() {
+        let testcase = load_testcase(task, &mut vm, tc_id)?;
+        let (result, _) = run_testcase(&mut vm, &testcase, None);
+        correct &= result.correct;
+        memory = memory.max(result.memory);
+    }
+    let seconds = timer.seconds_since() * TESTCASES as f64 / sampled.len() as f64;
+
+    Ok(Prediction {
+        bounds,
+        sampled,
+        correct,
+        memory,
+        seconds,
+    })
+}
+
+/// Prints an estimate of a full grading, taking the task from `--task` or the script's
+/// metadata
+pub fn do_predict(path: &str, options: &PredictOptions) -> Result<()> {
+    let script = parse_script_as(path, options.format, true)?;
+    let task = match options.task {
+        Some(task) => task,
+        None => match script.meta.as_ref().and_then(|meta| meta.task()) {
+            Some(task) => Task::from_str(task)?,
+            None => {
+                return Err(WpkppError::InvalidArgument(format!(
+                    "{} has no \"task\" metadata; try --task",
+                    path
+                )))
+            }
+        },
+    };
+
+    let prediction = predict(task, script.instructions, options.samples)?;
+    let bounds = prediction.bounds;
+    println!(
+        "Task {} ({}), sampled {} of {} testcases",
+        task.name(),
+        task.description(),
+        prediction.sampled.len(),
+        TESTCASES
+    );
+    if !prediction.correct {
+        println!("Some sampled testcases were wrong, so the script will not pass");
+    }
+    println!("Instructions: {} (exact)", bounds.runtime);
+    println!(
+        "Memory Usage: {} sampled, at most {}",
+        prediction.memory, bounds.max_memory
+    );
+    println!("Grading time: about {:.3}s", prediction.seconds);
+    Ok(())
+}
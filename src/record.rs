@@ -0,0 +1,254 @@
+//! `wpkpp record` / `wpkpp replay`: Everything a grade depends on (the script's exact bytes,
+//! task, seed and settings) saved to one file along with what each testcase came to, so that
+//! anyone can rerun the grade later, on any machine, and see whether it comes out the same.
+
+use miniserde::{json, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+
+use crate::{
+    container::{decode_container, encode_container, ContainerEncoding},
+    error::{Result, WpkppError},
+    grader::{load_testcase_seeded, run_testcase, TESTCASES},
+    parse::{parse_script_bytes, read_script_source, ScriptFormat},
+    task::Task,
+    vm::{Instructions, Vm},
+};
+
+/// Bumped whenever recordings change shape
+pub const RECORDING_VERSION: &str = "1";
+
+/// What a testcase came to; Numbers are strings, as in `grade --json`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordedCase {
+    pub tc_id: String,
+    pub correct: bool,
+    pub runtime: String,
+    pub memory: String,
+    /// End of the hash chain over touched memory, as in `grade --hash-every`
+    pub hash: String,
+}
+
+/// Contents of a file written by `wpkpp record`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Recording {
+    pub version: String,
+    /// Version of wpkpp which made the recording
+    pub wpkpp: String,
+    pub task: String,
+    pub seed: String,
+    /// SHA-256 of the script's bytes, in hex
+    pub script_sha256: String,
+    /// The script itself, wrapped by `encode_container`
+    pub script: String,
+    /// Steps between memory hashes; Without it, memory is only hashed when a testcase halts
+    pub hash_every: Option<String>,
+    pub cases: Vec<RecordedCase>,
+}
+
+pub struct RecordOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Seed to generate testcases from, instead of WPKPP_SEED
+    pub seed: Option<String>,
+    /// Hash touched memory every N steps of each testcase
+    pub hash_every: Option<i64>,
+}
+
+/// A testcase which came out differently on replay
+#[derive(Debug, Clone)]
+pub struct CaseDiff {
+    pub recorded: RecordedCase,
+    pub replayed: RecordedCase,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Runs every testcase of `task` generated from `seed`
+pub fn run_cases(
+    task: Task,
+    seed: &str,
+    instructions: Instructions,
+    hash_every: Option<i64>,
+) -> Result<Vec<RecordedCase>> {
+    let mut vm = Vm::new(instructions);
+    let mut cases = Vec::with_capacity(TESTCASES);
+    for tc_id in 0..TESTCASES as i8 {
+        let testcase = load_testcase_seeded(task, seed, &mut vm, tc_id)?;
+        // A checkpoint that is never reached still hashes memory once at halt
+        let (result, hashes) =
+            run_testcase(&mut vm, &testcase, Some(hash_every.unwrap_or(i64::MAX)));
+        cases.push(RecordedCase {
+            tc_id: tc_id.to_string(),
+            correct: result.correct,
+            runtime: result.runtime.to_string(),
+            memory: result.memory.to_string(),
+            hash: format!("{:016x}", hashes.unwrap().last().unwrap()),
+        });
+    }
+    Ok(cases)
+}
+
+impl Recording {
+    /// Grades a script given as `format` bytes and records the results
+    pub fn new(
+        task: Task,
+        seed: &str,
+        format: ScriptFormat,
+        bytes: &[u8],
+        hash_every: Option<i64>,
+    ) -> Result<Self> {
+        let instructions = parse_script_bytes(bytes, format)?.instructions;
+        Ok(Self {
+            version: RECORDING_VERSION.to_string(),
+            wpkpp: env!("CARGO_PKG_VERSION").to_string(),
+            task: task.name().to_string(),
+            seed: seed.to_string(),
+            script_sha256: sha256_hex(bytes),
+            script: encode_container(bytes, format, ContainerEncoding::Base64),
+            hash_every: hash_every.map(|every| every.to_string()),
+            cases: run_cases(task, seed, instructions, hash_every)?,
+        })
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let recording: Self = json::from_str(text).map_err(|_| invalid("not a wpkpp recording"))?;
+        if recording.version != RECORDING_VERSION {
+            return Err(invalid(format!(
+                "version {} is not supported; expected {}",
+                recording.version, RECORDING_VERSION
+            )));
+        }
+        Ok(recording)
+    }
+
+    pub fn to_json(&self) -> String {
+        json::to_string(self)
+    }
+
+    /// Reruns the recorded script with the recorded task, seed and settings, returning every
+    /// testcase which came out differently
+    pub fn replay(&self) -> Result<Vec<CaseDiff>> {
+        let task = Task::from_str(&self.task)?;
+        let (format, bytes) = decode_container(&self.script)?;
+        if sha256_hex(&bytes) != self.script_sha256 {
+            return Err(invalid("the script does not match its recorded SHA-256"));
+        }
+        let hash_every = match self.hash_every.as_deref() {
+            Some(every) => Some(
+                every
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|every| *every > 0)
+                    .ok_or_else(|| {
+                        invalid(format!(
+                            "\"hash_every\" should be a positive number, got \"{}\"",
+                            every
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+
+        let instructions = parse_script_bytes(&bytes, format)?.instructions;
+        let replayed = run_cases(task, &self.seed, instructions, hash_every)?;
+        if replayed.len() != self.cases.len() {
+            return Err(invalid(format!(
+                "{} testcases were recorded, but task {} has {}",
+                self.cases.len(),
+                self.task,
+                replayed.len()
+            )));
+        }
+        Ok(self
+            .cases
+            .iter()
+            .zip(replayed)
+            .filter(|(recorded, replayed)| *recorded != replayed)
+            .map(|(recorded, replayed)| CaseDiff {
+                recorded: recorded.clone(),
+                replayed,
+            })
+            .collect())
+    }
+}
+
+fn invalid(message: impl Into<String>) -> WpkppError {
+    WpkppError::InvalidRecording(message.into())
+}
+
+/// Grades a script and writes a recording of the run to `output_path`
+pub fn do_record(
+    task: Task,
+    wpk_path: &str,
+    output_path: &str,
+    options: &RecordOptions,
+) -> Result<()> {
+    let (format, bytes) = read_script_source(wpk_path, options.format)?;
+    let seed = options.seed.clone().unwrap_or_else(Task::seed);
+    let recording = Recording::new(task, &seed, format, &bytes, options.hash_every)?;
+    fs::write(output_path, recording.to_json())?;
+
+    let score = recording.cases.iter().filter(|case| case.correct).count();
+    println!(
+        "Recorded task {} with seed {}: Score {}/{}",
+        recording.task,
+        recording.seed,
+        score,
+        recording.cases.len()
+    );
+    println!("Script SHA-256: {}", recording.script_sha256);
+    Ok(())
+}
+
+fn describe_case(case: &RecordedCase) -> String {
+    format!(
+        "{} / runtime {} / memory {} / hash {}",
+        match case.correct {
+            true => "correct",
+            false => "wrong",
+        },
+        case.runtime,
+        case.memory,
+        case.hash
+    )
+}
+
+/// Replays a recording, printing every testcase which came out differently; Fails if any did
+pub fn do_replay(recording_path: &str) -> Result<()> {
+    let recording = Recording::from_json(&fs::read_to_string(recording_path)?)?;
+    if recording.wpkpp != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "Warning: Recorded with wpkpp {}, replaying with {}",
+            recording.wpkpp,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    println!(
+        "Replaying task {} with seed {}, script SHA-256 {}",
+        recording.task, recording.seed, recording.script_sha256
+    );
+
+    let diffs = recording.replay()?;
+    for diff in diffs.iter() {
+        println!("Testcase {}:", diff.recorded.tc_id);
+        println!("  Recorded: {}", describe_case(&diff.recorded));
+        println!("  Replayed: {}", describe_case(&diff.replayed));
+    }
+    match diffs.len() {
+        0 => {
+            println!(
+                "All {} testcases match the recording",
+                recording.cases.len()
+            );
+            Ok(())
+        }
+        differing => Err(WpkppError::CheckFailed(format!(
+            "{}/{} testcases differ from the recording",
+            differing,
+            recording.cases.len()
+        ))),
+    }
+}
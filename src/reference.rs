@@ -0,0 +1,323 @@
+//! `wpkpp reference`: Generated, deliberately unoptimized solutions to the basic tasks, as a
+//! correctness oracle, compressor input and scoring baseline.
+//!
+//! Each task is first written as a circuit of gates updating single cells, then every gate is
+//! turned into instructions. Woodpecker has no jumps, so a gate branches on a bit by letting
+//! CDEC move the pointer back by some distance `d` or not: The two outcomes then carry on as
+//! "worlds" `d` cells apart, running the same instructions. The world that should not act
+//! flips a scratch cell `d` above the target instead, and both are brought back together at a
+//! marker cell which is 1 while the cells `d` and `2d` below it are 0. Every gate gets its own
+//! odd multiple of the working memory size as `d`, so scratch cells never collide with cells
+//! read later.
+
+use std::io::Write;
+
+use crate::{
+    error::{Result, WpkppError},
+    meta::META_STR,
+    parse::{create_output, write_wpkb, ScriptFormat},
+    task::Task,
+    vm::{Instruction, Instructions, VmUsize},
+};
+
+/// Address of a cell in the circuit's working memory
+type Cell = u64;
+
+/// One step of a circuit, updating a single cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gate {
+    /// `target ^= 1`
+    Not(Cell),
+    /// `target ^= x`
+    Xor { x: Cell, target: Cell },
+    /// `target ^= x & y`
+    And { x: Cell, y: Cell, target: Cell },
+}
+
+/// Gates over working memory: The task's input and output, then scratch cells, all starting
+/// at 0 apart from the input
+struct Circuit {
+    gates: Vec<Gate>,
+    cells: u64,
+}
+
+impl Circuit {
+    fn new(cells: u64) -> Self {
+        Self {
+            gates: vec![],
+            cells,
+        }
+    }
+
+    /// A fresh cell holding 0
+    fn alloc(&mut self) -> Cell {
+        self.cells += 1;
+        self.cells - 1
+    }
+
+    fn not(&mut self, target: Cell) {
+        self.gates.push(Gate::Not(target));
+    }
+
+    fn xor(&mut self, x: Cell, target: Cell) {
+        self.gates.push(Gate::Xor { x, target });
+    }
+
+    fn and(&mut self, x: Cell, y: Cell, target: Cell) {
+        self.gates.push(Gate::And { x, y, target });
+    }
+
+    /// `target += x + carry` for one bit, leaving the carry out in `carry_out`, which must hold 0
+    fn add_bit(&mut self, target: Cell, x: Cell, carry: Option<Cell>, carry_out: Option<Cell>) {
+        if let Some(carry_out) = carry_out {
+            // Majority of the three bits, from the target before it is overwritten
+            self.and(target, x, carry_out);
+            if let Some(carry) = carry {
+                self.and(target, carry, carry_out);
+                self.and(x, carry, carry_out);
+            }
+        }
+        self.xor(x, target);
+        if let Some(carry) = carry {
+            self.xor(carry, target);
+        }
+    }
+
+    /// `target += x` over numbers of the same width, `carry` being added at the lowest bit and
+    /// the final carry going to `carry_out` when given
+    fn add(
+        &mut self,
+        target: &[Cell],
+        x: &[Cell],
+        mut carry: Option<Cell>,
+        carry_out: Option<Cell>,
+    ) {
+        for (i, (&target_bit, &x_bit)) in target.iter().zip(x).enumerate() {
+            let next = match i + 1 == x.len() {
+                true => carry_out,
+                false => Some(self.alloc()),
+            };
+            self.add_bit(target_bit, x_bit, carry, next);
+            carry = next;
+        }
+    }
+}
+
+/// Cells `start..start + width`
+fn cells(start: Cell, width: u64) -> Vec<Cell> {
+    (start..start + width).collect()
+}
+
+/// The circuit solving a task, or None if there is no reference solution for it
+fn task_circuit(task: Task) -> Option<Circuit> {
+    let circuit = match task {
+        Task::ZeroXor => {
+            let mut circuit = Circuit::new(3);
+            circuit.xor(0, 2);
+            circuit.xor(1, 2);
+            circuit
+        }
+        Task::OneAdd1 => {
+            let mut circuit = Circuit::new(4);
+            circuit.xor(0, 2);
+            circuit.xor(1, 2);
+            circuit.and(0, 1, 3);
+            circuit
+        }
+        Task::TwoAdd16 => {
+            let (a, b, out) = (cells(0, 16), cells(16, 16), cells(32, 16));
+            let mut circuit = Circuit::new(49);
+            for (&a, &out) in a.iter().zip(&out) {
+                circuit.xor(a, out);
+            }
+            circuit.add(&out, &b, None, Some(48));
+            circuit
+        }
+        Task::TwoSub16 => {
+            // a - b = a + !b + 1, dropping the final carry
+            let (a, b, out) = (cells(0, 16), cells(16, 16), cells(32, 16));
+            let mut circuit = Circuit::new(48);
+            for (&a, &out) in a.iter().zip(&out) {
+                circuit.xor(a, out);
+            }
+            let not_b: Vec<Cell> = b
+                .iter()
+                .map(|&b| {
+                    let not_b = circuit.alloc();
+                    circuit.xor(b, not_b);
+                    circuit.not(not_b);
+                    not_b
+                })
+                .collect();
+            let one = circuit.alloc();
+            circuit.not(one);
+            circuit.add(&out, &not_b, Some(one), None);
+            circuit
+        }
+        Task::ThreeMul16 => {
+            // Schoolbook: Adds a * b_i into the product at bit i, for each bit of b
+            let (a, b, out) = (cells(0, 16), cells(16, 16), cells(32, 32));
+            let mut circuit = Circuit::new(64);
+            for (i, &b) in b.iter().enumerate() {
+                let partial: Vec<Cell> = a
+                    .iter()
+                    .map(|&a| {
+                        let partial = circuit.alloc();
+                        circuit.and(a, b, partial);
+                        partial
+                    })
+                    .collect();
+                // The product so far is below 2^(16 + i), so its bit 16 + i is still free
+                circuit.add(&out[i..i + 16], &partial, None, Some(out[i + 16]));
+            }
+            circuit
+        }
+        _ => return None,
+    };
+    Some(circuit)
+}
+
+/// Turns gates into instructions, tracking where the pointer is
+struct Emitter {
+    program: Instructions,
+    pos: u64,
+    /// Marker cell, set to 1 before the first gate
+    marker: u64,
+}
+
+impl Emitter {
+    fn push(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Inc(0) | Instruction::Cdec(0) => {}
+            instruction => self.program.push(instruction),
+        }
+    }
+
+    fn inc(&mut self, x: u64) {
+        self.push(Instruction::Inc(x as VmUsize));
+    }
+
+    fn cdec(&mut self, x: u64) {
+        self.push(Instruction::Cdec(x as VmUsize));
+    }
+
+    /// Moves the pointer to `cell`; Going back loads the marker to make CDEC move
+    fn goto(&mut self, cell: u64) {
+        if cell >= self.pos {
+            self.inc(cell - self.pos);
+        } else {
+            self.inc(self.marker - self.pos);
+            self.push(Instruction::Load);
+            self.cdec(self.marker - cell);
+        }
+        self.pos = cell;
+    }
+
+    /// Brings worlds `d` apart back together below the marker, from the pointer at `from` in
+    /// the world furthest ahead
+    fn rejoin(&mut self, from: u64, d: u64) {
+        self.inc(self.marker - from);
+        self.push(Instruction::Load);
+        self.cdec(d);
+        self.pos = self.marker - d;
+    }
+
+    /// Instructions for one gate, given its distance between worlds
+    fn gate(&mut self, gate: Gate, d: u64) {
+        match gate {
+            Gate::Not(target) => {
+                self.goto(target);
+                self.push(Instruction::Inv);
+            }
+            Gate::Xor { x, target } => {
+                // If x, flip target; Else flip target + d
+                self.goto(x);
+                self.push(Instruction::Load);
+                self.inc(target + d - x);
+                self.cdec(d);
+                self.push(Instruction::Inv);
+                self.rejoin(target + d, d);
+            }
+            Gate::And { x, y, target } => {
+                // If not x, read y + d, which is still 0, so there are three worlds: 0, d and 2d
+                // back for none, x, and both of x and y. Only the last one lands on the target.
+                self.goto(x);
+                self.push(Instruction::Load);
+                self.inc(y + d - x);
+                self.cdec(d);
+                self.push(Instruction::Load);
+                self.cdec(d);
+                self.inc(target + d - y);
+                self.push(Instruction::Inv);
+                self.rejoin(target + 2 * d, d);
+                // The world 2d back is still d behind the others
+                self.rejoin(self.marker - d, d);
+            }
+        }
+    }
+}
+
+/// A straightforward solution to a task, or None if it has none; See the module docs for how
+/// it works
+pub fn reference_solution(task: Task) -> Option<Instructions> {
+    let circuit = task_circuit(task)?;
+    // Odd multiples of the working memory size: Scratch cells of different gates, at
+    // target + d or target + 2d, then never land on cells read at y + d
+    let spacing = circuit.cells;
+    let distance = |i: usize| (2 * i as u64 + 1) * spacing;
+    let max_distance = distance(circuit.gates.len());
+    // Far enough that the cells d and 2d below the marker are never scratch cells
+    let marker = 4 * max_distance + 2 * spacing;
+
+    let mut emitter = Emitter {
+        program: Instructions::new(),
+        pos: 0,
+        marker,
+    };
+    emitter.goto(marker);
+    emitter.push(Instruction::Inv);
+    for (i, gate) in circuit.gates.iter().enumerate() {
+        emitter.gate(*gate, distance(i));
+    }
+    Some(emitter.program)
+}
+
+/// Writes the reference solution of a task to `output_path`, in the format of its extension
+pub fn do_reference(task: Task, output_path: &str) -> Result<()> {
+    let instructions = reference_solution(task).ok_or_else(|| {
+        WpkppError::InvalidArgument(format!(
+            "No reference solution for task {}; Only tasks 0, 1, 2, 2a and 3 have one",
+            task.name()
+        ))
+    })?;
+    let format = ScriptFormat::from_path(output_path).ok_or_else(|| {
+        WpkppError::InvalidArgument(format!(
+            "Unknown output format for {}, should end in \".wpk\", \".wpkm\" or \".wpkb\"",
+            output_path
+        ))
+    })?;
+
+    let mut writer = create_output(output_path)?;
+    let meta = format!("{} task={}, generator=reference\n", META_STR, task.name());
+    match format {
+        ScriptFormat::Wpk => {
+            writer.write_all(meta.as_bytes())?;
+            writer.write_all(instructions.to_wpk().as_bytes())?;
+        }
+        ScriptFormat::Wpkm => {
+            writer.write_all(meta.as_bytes())?;
+            writer.write_all(instructions.to_wpkm().as_bytes())?;
+        }
+        ScriptFormat::Wpkb => write_wpkb(&mut writer, &instructions)?,
+    }
+    writer.flush()?;
+
+    println!(
+        "Wrote a reference solution for task {} ({}), {} instructions, to {}",
+        task.name(),
+        task.description(),
+        instructions.len(),
+        output_path
+    );
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use std::fmt::Write as _;
+use std::fs;
+
+use crate::error::Result;
+use crate::parse::{
+    is_container_path, parse_script_with, ParseOptions, Script, ScriptCompression, ScriptFormat,
+    SourcePos, BOM, LABEL_END, LABEL_START, STDIN_PATH,
+};
+use crate::vm::Instruction;
+
+pub struct RenderOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Write an HTML table instead of plain text
+    pub html: bool,
+}
+
+/// One line of the listing
+struct Row {
+    idx: usize,
+    /// Where the instruction starts in the script file, when the file can be read as written
+    byte: Option<usize>,
+    /// Pointer offset after the instruction in the best case, when no CDEC is taken
+    ptr: i64,
+    instruction: Instruction,
+}
+
+/// Maps source positions back to byte offsets in the script text
+struct ByteIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    /// Columns count characters, which are bytes in the usual ASCII script
+    ascii: bool,
+}
+
+impl<'a> ByteIndex<'a> {
+    fn new(source: &'a str) -> Self {
+        // Columns on the first line count from after a leading BOM, which the parser skips
+        let first = match source.starts_with(BOM) {
+            true => BOM.len_utf8(),
+            false => 0,
+        };
+        let line_starts = std::iter::once(first)
+            .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+            ascii: source[first..].is_ascii(),
+        }
+    }
+
+    fn offset(&self, pos: SourcePos) -> Option<usize> {
+        let start = *self.line_starts.get(pos.line - 1)?;
+        match self.ascii {
+            true => Some(start + pos.column - 1),
+            false => self.source[start..]
+                .char_indices()
+                .nth(pos.column - 1)
+                .map(|(idx, _)| start + idx),
+        }
+    }
+}
+
+fn rows(script: &Script, bytes: Option<ByteIndex>, format: Option<ScriptFormat>) -> Vec<Row> {
+    let mut ptr: i64 = 0;
+    script
+        .instructions
+        .iter()
+        .enumerate()
+        .map(|(idx, instruction)| {
+            if let Instruction::Inc(x) = instruction {
+                ptr += *x as i64;
+            }
+            let start = script.source_map.as_ref().map(|map| map[idx].start);
+            let byte = match (format, start) {
+                // .wpkb positions already hold the byte offset as the column
+                (Some(ScriptFormat::Wpkb), Some(start)) => Some(start.column - 1),
+                (_, Some(start)) => bytes.as_ref().and_then(|bytes| bytes.offset(start)),
+                (_, None) => None,
+            };
+            Row {
+                idx,
+                byte,
+                ptr,
+                instruction: *instruction,
+            }
+        })
+        .collect()
+}
+
+fn render_text(script: &Script, rows: &[Row]) -> String {
+    let mut out = String::new();
+    let mut labels = script.labels.iter().peekable();
+    writeln!(
+        out,
+        "{:>10} {:>12} {:>12}  instruction",
+        "idx", "byte", "ptr"
+    )
+    .unwrap();
+    for row in rows.iter() {
+        while let Some((_, name)) = labels.next_if(|(label_idx, _)| *label_idx <= row.idx) {
+            writeln!(out, "{}{}{}", LABEL_START, name, LABEL_END).unwrap();
+        }
+        let byte = row.byte.map_or("-".to_string(), |byte| byte.to_string());
+        writeln!(
+            out,
+            "{:>10} {:>12} {:>12}  {}",
+            row.idx, byte, row.ptr, row.instruction
+        )
+        .unwrap();
+    }
+    out
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_html(path: &str, script: &Script, rows: &[Row]) -> String {
+    let mut out = String::new();
+    let title = escape_html(path);
+    writeln!(out, "<!DOCTYPE html>").unwrap();
+    writeln!(
+        out,
+        "<html><head><meta charset=\"utf-8\"><title>{}</title>",
+        title
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<style>td {{ font-family: monospace; padding: 0 1em; text-align: right; }} \
+         td.op {{ text-align: left; }} tr.label td {{ text-align: left; font-weight: bold; }}</style>"
+    )
+    .unwrap();
+    writeln!(out, "</head><body><h1>{}</h1><table>", title).unwrap();
+    writeln!(
+        out,
+        "<tr><th>idx</th><th>byte</th><th>ptr</th><th>instruction</th></tr>"
+    )
+    .unwrap();
+
+    let mut labels = script.labels.iter().peekable();
+    for row in rows.iter() {
+        while let Some((_, name)) = labels.next_if(|(label_idx, _)| *label_idx <= row.idx) {
+            writeln!(
+                out,
+                "<tr class=\"label\" id=\"{0}\"><td colspan=\"4\">{1}{0}{2}</td></tr>",
+                escape_html(name),
+                LABEL_START,
+                LABEL_END
+            )
+            .unwrap();
+        }
+        let byte = row.byte.map_or("-".to_string(), |byte| byte.to_string());
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"op\">{}</td></tr>",
+            row.idx, byte, row.ptr, row.instruction
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table></body></html>").unwrap();
+    out
+}
+
+/// Prints a listing of a script with each instruction's index, best case pointer offset and
+/// byte offset in the file
+pub fn do_render(path: &str, options: &RenderOptions) -> Result<()> {
+    let parse_options = ParseOptions {
+        source_map: true,
+        ..Default::default()
+    };
+    let script = parse_script_with(path, options.format, false, parse_options)?;
+    let format = options.format.or_else(|| ScriptFormat::from_path(path));
+
+    // Byte offsets are only known for script text read as written
+    let source = match path != STDIN_PATH
+        && format != Some(ScriptFormat::Wpkb)
+        && !is_container_path(path)
+        && ScriptCompression::split_path(path).1 == ScriptCompression::None
+    {
+        true => fs::read_to_string(path).ok(),
+        false => None,
+    };
+    let rows = rows(&script, source.as_deref().map(ByteIndex::new), format);
+
+    match options.html {
+        true => print!("{}", render_html(path, &script, &rows)),
+        false => print!("{}", render_text(&script, &rows)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Byte offsets of the first and fifth character of line 1 and the first of line 2
+    fn offsets(source: &str) -> [Option<usize>; 3] {
+        let index = ByteIndex::new(source);
+        [(1, 1), (1, 5), (2, 1)].map(|(line, column)| index.offset(SourcePos { line, column }))
+    }
+
+    #[test]
+    fn offsets_count_bytes_from_the_start_of_the_file() {
+        assert_eq!(offsets("INC 2\nLOAD\n"), [Some(0), Some(4), Some(6)]);
+        assert_eq!(offsets("INC 2\r\nLOAD\r\n"), [Some(0), Some(4), Some(7)]);
+        assert_eq!(offsets("2>é?!\n?"), [Some(0), Some(5), Some(7)]);
+    }
+
+    #[test]
+    fn offsets_include_a_leading_bom() {
+        assert_eq!(
+            offsets("\u{feff}INC 2\nLOAD\n"),
+            [Some(3), Some(7), Some(9)]
+        );
+        assert_eq!(offsets("\u{feff}2>é?!\n?"), [Some(3), Some(8), Some(10)]);
+    }
+}
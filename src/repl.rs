@@ -0,0 +1,235 @@
+//! `wpkpp repl`: Instructions run against a live VM as soon as they are typed.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    error::{Result, WpkppError},
+    parse::{parse_wpk_str, parse_wpkm_bytes, ScriptFormat},
+    vm::{cell_index, Instructions, Vm, VmUsize},
+};
+
+/// Cells shown either side of the pointer by `:mem`
+pub const DEFAULT_RADIUS: u32 = 8;
+
+const HELP: &str = "\
+Type instructions to run them, e.g. \"INC 3\" / \"LOAD\" (.wpk) or \"3>?!<\" (.wpkm)
+:mem [N]     Show N cells either side of the pointer (default 8)
+:undo        Take back the last line of instructions
+:reset       Clear memory and forget every instruction
+:dump [PATH] Print the session as a .wpkm script, or write it to a .wpk / .wpkm file
+:help        Show this help
+:quit        Leave; So does end of input";
+
+pub struct ReplOptions {
+    /// Syntax of typed instructions; None tells .wpk from .wpkm by the first character
+    pub format: Option<ScriptFormat>,
+}
+
+/// A live VM which instructions are appended to and run as soon as they are typed
+pub struct Repl {
+    vm: Vm,
+    /// Number of instructions after each line typed so far, for undo
+    lines: Vec<usize>,
+    format: Option<ScriptFormat>,
+}
+
+impl Repl {
+    pub fn new(format: Option<ScriptFormat>) -> Self {
+        Self {
+            vm: Vm::new(Instructions::new()),
+            lines: vec![],
+            format,
+        }
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Instructions typed so far
+    pub fn program(&self) -> &Instructions {
+        &self.vm.program
+    }
+
+    fn parse_line(&self, line: &str) -> Result<Instructions> {
+        let wpk = match self.format {
+            Some(ScriptFormat::Wpk) => true,
+            Some(ScriptFormat::Wpkm) => false,
+            Some(ScriptFormat::Wpkb) => {
+                return Err(WpkppError::InvalidArgument(
+                    "Instructions cannot be typed in .wpkb format".to_string(),
+                ))
+            }
+            // "v" is .wpkm for LOAD, every other letter starts a .wpk keyword
+            None => line.starts_with(|c: char| c.is_ascii_alphabetic() && c != 'v'),
+        };
+        match wpk {
+            true => parse_wpk_str(line),
+            false => parse_wpkm_bytes(line.as_bytes()),
+        }
+    }
+
+    /// Parses a line of instructions and runs them; Nothing is kept if the line fails to parse
+    pub fn execute(&mut self, line: &str) -> Result<()> {
+        let mut instructions = self.parse_line(line)?;
+        self.vm.program.append(&mut instructions);
+        self.lines.push(self.vm.program.len());
+        self.run();
+        Ok(())
+    }
+
+    fn run(&mut self) {
+        self.vm.halted = self.vm.intsruction_pointer >= self.vm.program.len();
+        while !self.vm.halted {
+            self.vm.step();
+        }
+    }
+
+    /// Takes back the last line, returning whether there was one. Memory cannot be stepped
+    /// backwards, so every earlier line is run again from the start.
+    pub fn undo(&mut self) -> bool {
+        if self.lines.pop().is_none() {
+            return false;
+        }
+        let len = self.lines.last().copied().unwrap_or(0);
+        self.vm.program.truncate(len);
+        self.vm.reset();
+        self.run();
+        true
+    }
+
+    /// Clears memory and forgets every instruction
+    pub fn reset(&mut self) {
+        self.vm.program.truncate(0);
+        self.lines.clear();
+        self.vm.reset();
+    }
+
+    /// Cells `radius` either side of the pointer, bracketing the one under it, e.g.
+    /// `0110[1]0000`
+    pub fn memory_around(&self, radius: u32) -> String {
+        let ptr = self.vm.memory_pointer.ptr;
+        let mut cells = String::new();
+        for offset in -(radius as i64)..=(radius as i64) {
+            let address = ptr.wrapping_add(offset as VmUsize);
+            let bit = match self.vm.memory[cell_index(address)] {
+                true => '1',
+                false => '0',
+            };
+            match offset {
+                0 => cells.push_str(&format!("[{}]", bit)),
+                _ => cells.push(bit),
+            }
+        }
+        cells
+    }
+
+    /// Pointer, register, runtime and memory span
+    pub fn status(&self) -> String {
+        let run_stats = self.vm.run_result();
+        format!(
+            "Pointer: {} / Register: {} / Instructions: {} / Memory Usage: {}",
+            self.vm.memory_pointer.ptr_i,
+            self.vm.register as u8,
+            run_stats.runtime,
+            run_stats.memory
+        )
+    }
+
+    /// Writes the session as a script, in the format given by the path's extension
+    pub fn dump(&self, path: &str) -> Result<()> {
+        let script = match ScriptFormat::from_path(path) {
+            Some(ScriptFormat::Wpk) => self.vm.program.to_wpk(),
+            Some(ScriptFormat::Wpkm) => self.vm.program.to_wpkm(),
+            _ => {
+                return Err(WpkppError::InvalidArgument(format!(
+                    "Cannot dump the session to {}, should end in \".wpk\" or \".wpkm\"",
+                    path
+                )))
+            }
+        };
+        fs::write(path, script)?;
+        Ok(())
+    }
+
+    /// Handles a `:` command, returning false to leave
+    fn command(&mut self, command: &str) -> Result<bool> {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("mem"), radius) => {
+                let radius = match radius {
+                    Some(radius) => radius.parse().map_err(|_| {
+                        WpkppError::InvalidArgument(format!("Invalid cell count {}", radius))
+                    })?,
+                    None => DEFAULT_RADIUS,
+                };
+                println!("{}", self.memory_around(radius));
+            }
+            (Some("undo"), None) => match self.undo() {
+                true => println!("{}", self.status()),
+                false => println!("Nothing to undo"),
+            },
+            (Some("reset"), None) => {
+                self.reset();
+                println!("{}", self.status());
+            }
+            (Some("dump"), None) => print!("{}", self.vm.program.to_wpkm()),
+            (Some("dump"), Some(path)) => {
+                self.dump(path)?;
+                println!("Wrote {} instructions to {}", self.vm.program.len(), path);
+            }
+            (Some("help"), None) => println!("{}", HELP),
+            (Some("quit" | "q" | "exit"), None) => return Ok(false),
+            _ => {
+                return Err(WpkppError::InvalidArgument(format!(
+                    "Unknown command :{}; Try :help",
+                    command
+                )))
+            }
+        }
+        Ok(true)
+    }
+
+    /// Handles one line of input, returning false to leave
+    pub fn handle_line(&mut self, line: &str) -> Result<bool> {
+        let line = line.trim();
+        if let Some(command) = line.strip_prefix(':') {
+            return self.command(command);
+        }
+        if !line.is_empty() {
+            self.execute(line)?;
+            println!("{}", self.status());
+        }
+        Ok(true)
+    }
+}
+
+/// Reads instructions and commands from stdin until `:quit` or end of input
+pub fn do_repl(options: &ReplOptions) -> Result<()> {
+    let mut repl = Repl::new(options.format);
+    println!(
+        "wpkpp {} REPL; Type :help for commands",
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                println!();
+                break;
+            }
+        };
+        match repl.handle_line(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    Ok(())
+}
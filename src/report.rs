@@ -0,0 +1,452 @@
+//! Typed grade results, for tools linking against wpkpp instead of parsing `grade --json`.
+//!
+//! These types follow semver: Fields are only added in minor releases, which `#[non_exhaustive]`
+//! keeps from breaking code outside this crate, and are only removed or changed in major ones.
+//! `GradeReport::into_json` writes the same schema as `grade --json`, whose numbers are strings.
+
+use miniserde::json;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{
+    coverage::CoverageReport,
+    error::{Result, WpkppError},
+    grader::{GradeResult, TimeTaken, TranscriptCase},
+    stats::{Complexity, ComplexityReport},
+    task::Task,
+    util::InstructionCount,
+    vm::MemoryMetric,
+};
+
+/// What a grade comes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Verdict {
+    Ok,
+    WrongAnswer,
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+}
+
+impl Verdict {
+    pub const ALL: [Verdict; 4] = [
+        Verdict::Ok,
+        Verdict::WrongAnswer,
+        Verdict::TimeLimitExceeded,
+        Verdict::MemoryLimitExceeded,
+    ];
+
+    /// Short code, as in `grade --json`
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Ok => "OK",
+            Self::WrongAnswer => "WA",
+            Self::TimeLimitExceeded => "TLE",
+            Self::MemoryLimitExceeded => "MLE",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|verdict| verdict.code() == code)
+    }
+}
+
+/// Outcome of a single testcase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaseResult {
+    pub tc_id: i8,
+    pub correct: bool,
+    pub runtime: i64,
+    pub memory: i64,
+}
+
+/// Instructions of each kind in a script
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpCounts {
+    pub inc: u64,
+    pub cdec: u64,
+    pub load: u64,
+    pub inv: u64,
+}
+
+/// Seconds spent in each stage of grading
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timings {
+    pub parse: f64,
+    pub vm: f64,
+    pub grade: f64,
+}
+
+/// Runtime over every testcase rather than the worst case, for scoring by total work
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuntimeAggregates {
+    pub mean: f64,
+    pub median: f64,
+    pub total: i64,
+}
+
+impl RuntimeAggregates {
+    /// Aggregates of each testcase's runtime; None without any testcases
+    pub fn new(runtimes: &[i64]) -> Option<Self> {
+        if runtimes.is_empty() {
+            return None;
+        }
+        let mut sorted = runtimes.to_vec();
+        sorted.sort_unstable();
+        let middle = sorted.len() / 2;
+        let median = match sorted.len() % 2 {
+            0 => (sorted[middle - 1] as f64 + sorted[middle] as f64) / 2.0,
+            _ => sorted[middle] as f64,
+        };
+        let total = runtimes
+            .iter()
+            .fold(0_i64, |total, runtime| total.saturating_add(*runtime));
+        Some(Self {
+            mean: runtimes.iter().map(|runtime| *runtime as f64).sum::<f64>()
+                / runtimes.len() as f64,
+            median,
+            total,
+        })
+    }
+}
+
+impl fmt::Display for RuntimeAggregates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean {:.2} / median {} / total {}",
+            self.mean, self.median, self.total
+        )
+    }
+}
+
+/// Runtime percentiles over the testcases by nearest rank, to tell uniformly slow code from a
+/// few pathological testcases
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuntimePercentiles {
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+}
+
+impl RuntimePercentiles {
+    /// Percentiles of each testcase's runtime; None without any testcases
+    pub fn new(runtimes: &[i64]) -> Option<Self> {
+        if runtimes.is_empty() {
+            return None;
+        }
+        let mut sorted = runtimes.to_vec();
+        sorted.sort_unstable();
+        // Smallest runtime which at least `percent`% of testcases are within
+        let percentile = |percent: usize| {
+            let rank = (percent * sorted.len()).div_ceil(100);
+            sorted[rank.max(1) - 1]
+        };
+        Some(Self {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+impl fmt::Display for RuntimePercentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "p50 {} / p90 {} / p99 {}", self.p50, self.p90, self.p99)
+    }
+}
+
+/// Result of grading a script against a task, as printed by `grade --json`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct GradeReport {
+    /// Every testcase was correct, printed as verdict "OK" rather than "WA"
+    pub passed: bool,
+    pub score: u64,
+    pub total: u64,
+    /// Most steps taken by any testcase
+    pub runtime: i64,
+    /// Mean, median and total steps over the testcases; None in reports from older versions
+    pub runtime_aggregates: Option<RuntimeAggregates>,
+    /// p50 / p90 / p99 steps over the testcases; None in reports from older versions
+    pub runtime_percentiles: Option<RuntimePercentiles>,
+    /// Most memory cells spanned by any testcase
+    pub memory: i64,
+    pub instructions: OpCounts,
+    pub timings: Timings,
+    pub coverage: Option<CoverageReport>,
+    pub transcript: Option<Vec<TranscriptCase>>,
+    /// Fields of the script's `;!` metadata comment
+    pub meta: Option<BTreeMap<String, String>>,
+    /// Canonical hash of the script's instructions; None in reports from older versions
+    pub hash: Option<u64>,
+    /// Instruction and step ceiling the script was held to with `--max-instructions`
+    pub max_instructions: Option<u64>,
+    /// Submitted outside the `--contest` window, printed as verdict "LATE" whatever the score
+    pub late: bool,
+    /// Static complexity metrics of the script, with `--complexity`
+    pub complexity: Option<Complexity>,
+    /// Graded on every possible input rather than seeded testcases, with `--exhaustive`
+    pub exhaustive: bool,
+    /// How `memory` was measured
+    pub memory_metric: MemoryMetric,
+    /// Testcases failed unless memory past the output was clear, with `--strict-clean`
+    pub strict_clean: bool,
+    /// Memory other than the input started with random bits, with `--dirty-scratch`
+    pub dirty_scratch: bool,
+}
+
+impl From<(u64, u64, u64, u64)> for OpCounts {
+    fn from((inc, cdec, load, inv): (u64, u64, u64, u64)) -> Self {
+        Self {
+            inc,
+            cdec,
+            load,
+            inv,
+        }
+    }
+}
+
+impl From<OpCounts> for InstructionCount {
+    fn from(counts: OpCounts) -> Self {
+        InstructionCount::new((counts.inc, counts.cdec, counts.load, counts.inv))
+    }
+}
+
+impl TryFrom<InstructionCount> for OpCounts {
+    type Error = WpkppError;
+
+    fn try_from(counts: InstructionCount) -> Result<Self> {
+        Ok(Self {
+            inc: parse_field("instructions.inc", &counts.inc)?,
+            cdec: parse_field("instructions.cdec", &counts.cdec)?,
+            load: parse_field("instructions.load", &counts.load)?,
+            inv: parse_field("instructions.inv", &counts.inv)?,
+        })
+    }
+}
+
+impl TryFrom<ComplexityReport> for Complexity {
+    type Error = WpkppError;
+
+    fn try_from(report: ComplexityReport) -> Result<Self> {
+        Ok(Self {
+            distinct_runs: parse_field("complexity.distinct_runs", &report.distinct_runs)?,
+            run_entropy: parse_field("complexity.run_entropy", &report.run_entropy)?,
+            load_density: parse_field("complexity.load_density", &report.load_density)?,
+            inv_clustering: parse_field("complexity.inv_clustering", &report.inv_clustering)?,
+        })
+    }
+}
+
+impl From<Timings> for TimeTaken {
+    fn from(timings: Timings) -> Self {
+        TimeTaken {
+            parse: timings.parse,
+            vm: timings.vm,
+            grade: timings.grade,
+        }
+    }
+}
+
+impl From<TimeTaken> for Timings {
+    fn from(time_taken: TimeTaken) -> Self {
+        Self {
+            parse: time_taken.parse,
+            vm: time_taken.vm,
+            grade: time_taken.grade,
+        }
+    }
+}
+
+impl From<GradeReport> for GradeResult {
+    fn from(report: GradeReport) -> Self {
+        GradeResult {
+            verdict: report.verdict().to_string(),
+            score: report.score.to_string(),
+            total: report.total.to_string(),
+            runtime: report.runtime.to_string(),
+            runtime_mean: report
+                .runtime_aggregates
+                .map(|aggregates| format!("{:.2}", aggregates.mean)),
+            runtime_median: report
+                .runtime_aggregates
+                .map(|aggregates| aggregates.median.to_string()),
+            runtime_total: report
+                .runtime_aggregates
+                .map(|aggregates| aggregates.total.to_string()),
+            runtime_p50: report
+                .runtime_percentiles
+                .map(|percentiles| percentiles.p50.to_string()),
+            runtime_p90: report
+                .runtime_percentiles
+                .map(|percentiles| percentiles.p90.to_string()),
+            runtime_p99: report
+                .runtime_percentiles
+                .map(|percentiles| percentiles.p99.to_string()),
+            memory: report.memory.to_string(),
+            instructions: report.instructions.into(),
+            time_taken: report.timings.into(),
+            coverage: report.coverage,
+            transcript: report.transcript,
+            meta: report.meta,
+            hash: report.hash.map(|hash| format!("{:016x}", hash)),
+            max_instructions: report.max_instructions.map(|limit| limit.to_string()),
+            complexity: report.complexity.map(ComplexityReport::from),
+            exhaustive: report.exhaustive.then_some(true),
+            memory_metric: Some(report.memory_metric.name().to_string()),
+            strict_clean: report.strict_clean.then_some(true),
+            dirty_scratch: report.dirty_scratch.then_some(true),
+        }
+    }
+}
+
+impl TryFrom<GradeResult> for GradeReport {
+    type Error = WpkppError;
+
+    fn try_from(result: GradeResult) -> Result<Self> {
+        let score = parse_field("score", &result.score)?;
+        let total = parse_field("total", &result.total)?;
+        let (passed, late) = match result.verdict.as_str() {
+            "OK" => (true, false),
+            "WA" => (false, false),
+            "LATE" => (score == total, true),
+            verdict => return Err(invalid(format!("unknown verdict \"{}\"", verdict))),
+        };
+        Ok(Self {
+            passed,
+            score,
+            total,
+            runtime: parse_field("runtime", &result.runtime)?,
+            runtime_aggregates: match (
+                result.runtime_mean,
+                result.runtime_median,
+                result.runtime_total,
+            ) {
+                (Some(mean), Some(median), Some(total)) => Some(RuntimeAggregates {
+                    mean: parse_field("runtime_mean", &mean)?,
+                    median: parse_field("runtime_median", &median)?,
+                    total: parse_field("runtime_total", &total)?,
+                }),
+                _ => None,
+            },
+            runtime_percentiles: match (result.runtime_p50, result.runtime_p90, result.runtime_p99)
+            {
+                (Some(p50), Some(p90), Some(p99)) => Some(RuntimePercentiles {
+                    p50: parse_field("runtime_p50", &p50)?,
+                    p90: parse_field("runtime_p90", &p90)?,
+                    p99: parse_field("runtime_p99", &p99)?,
+                }),
+                _ => None,
+            },
+            memory: parse_field("memory", &result.memory)?,
+            instructions: result.instructions.try_into()?,
+            timings: result.time_taken.into(),
+            coverage: result.coverage,
+            transcript: result.transcript,
+            meta: result.meta,
+            hash: result
+                .hash
+                .map(|hash| {
+                    u64::from_str_radix(&hash, 16)
+                        .map_err(|_| invalid(format!("hash \"{}\" is not hexadecimal", hash)))
+                })
+                .transpose()?,
+            max_instructions: result
+                .max_instructions
+                .map(|limit| parse_field("max_instructions", &limit))
+                .transpose()?,
+            late,
+            complexity: result.complexity.map(TryInto::try_into).transpose()?,
+            exhaustive: result.exhaustive.unwrap_or(false),
+            memory_metric: match result.memory_metric.as_deref() {
+                None => MemoryMetric::Span,
+                Some(name) => MemoryMetric::from_name(name)
+                    .ok_or_else(|| invalid(format!("unknown memory metric \"{}\"", name)))?,
+            },
+            strict_clean: result.strict_clean.unwrap_or(false),
+            dirty_scratch: result.dirty_scratch.unwrap_or(false),
+        })
+    }
+}
+
+impl GradeReport {
+    /// "OK" or "WA", or "LATE" outside the contest window
+    pub fn verdict(&self) -> &'static str {
+        match (self.late, self.passed) {
+            (true, _) => "LATE",
+            (false, true) => "OK",
+            (false, false) => "WA",
+        }
+    }
+
+    /// The report as `grade --json` prints it
+    pub fn into_json(self) -> String {
+        json::to_string(&GradeResult::from(self))
+    }
+
+    /// Shields.io badge as markdown, e.g. "task 5: OK, 1.2M steps", for solution repositories
+    pub fn badge(&self, task: Task) -> String {
+        let label = format!("task {}", task.name());
+        let message = format!("{}, {} steps", self.verdict(), abbreviate(self.runtime));
+        let color = match self.passed {
+            true => "brightgreen",
+            false => "red",
+        };
+        format!(
+            "![{}: {}](https://img.shields.io/badge/{}-{}-{})",
+            label,
+            message,
+            shields_escape(&label),
+            shields_escape(&message),
+            color
+        )
+    }
+
+    /// Reads a report printed by `grade --json`
+    pub fn from_json(text: &str) -> Result<Self> {
+        let result: GradeResult =
+            json::from_str(text).map_err(|_| invalid("not a grade report"))?;
+        result.try_into()
+    }
+}
+
+/// A count with a k / M / G suffix, e.g. 1234567 as "1.2M"
+pub(crate) fn abbreviate(count: i64) -> String {
+    let suffixes = [(1_000_000_000, "G"), (1_000_000, "M"), (1_000, "k")];
+    match suffixes.iter().find(|(scale, _)| count.abs() >= *scale) {
+        Some((scale, suffix)) => format!("{:.1}{}", count as f64 / *scale as f64, suffix),
+        None => count.to_string(),
+    }
+}
+
+/// Escapes text for a shields.io badge path, where "-" and "_" separate fields
+fn shields_escape(text: &str) -> String {
+    text.replace('-', "--")
+        .replace('_', "__")
+        .replace('%', "%25")
+        .replace(' ', "%20")
+        .replace(',', "%2C")
+        .replace('/', "%2F")
+}
+
+fn invalid(message: impl Into<String>) -> WpkppError {
+    WpkppError::InvalidReport(message.into())
+}
+
+fn parse_field<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| {
+        invalid(format!(
+            "\"{}\" should be a number, got \"{}\"",
+            name, value
+        ))
+    })
+}
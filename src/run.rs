@@ -0,0 +1,167 @@
+//! `wpkpp run`: One testcase of a task, optionally narrating each step for newcomers learning
+//! what the instructions do.
+
+use crate::{
+    assertion::{AssertChecker, AssertFailure},
+    error::{Result, WpkppError},
+    grader::{
+        assertion_failed, check_testcase, fill_scratch, first_dirty_cell, load_testcase_seeded,
+        scratch_seed,
+    },
+    parse::{format_location, parse_script_with, Labels, ParseOptions, ScriptFormat},
+    task::Task,
+    vm::{cell_index, Instruction, MemoryMetric, Vm},
+};
+
+pub struct RunOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Testcase to run
+    pub tc_id: i8,
+    /// Seed to generate the testcase from, instead of WPKPP_SEED
+    pub seed: Option<String>,
+    /// Narrate this many steps before running the rest silently
+    pub explain: Option<u64>,
+    /// Largest script file in bytes, replacing the 10 MB / 5 MB defaults
+    pub max_file_size: Option<u64>,
+    /// Read scripts which are not valid UTF-8 as Latin-1
+    pub latin1: bool,
+    /// How memory usage is measured
+    pub memory_metric: MemoryMetric,
+    /// Fail the testcase if it leaves any cell past the output set
+    pub strict_clean: bool,
+    /// Start with the random scratch bits `grade --dirty-scratch` uses for the testcase
+    pub dirty_scratch: bool,
+}
+
+fn bit(value: bool) -> u8 {
+    value as u8
+}
+
+/// What the instruction under the instruction pointer is about to do, in plain English, e.g.
+/// "register is 1, so CDEC 3 moves the pointer back from 15 to 12"
+pub fn explain_step(vm: &Vm) -> String {
+    let ptr = vm.memory_pointer.ptr_i;
+    let cell = vm.memory[cell_index(vm.memory_pointer.ptr)];
+    match vm.program[vm.intsruction_pointer] {
+        Instruction::Inc(x) => format!(
+            "INC {} moves the pointer forward from {} to {}",
+            x,
+            ptr,
+            ptr + x as i64
+        ),
+        Instruction::Cdec(x) if vm.register => format!(
+            "register is 1, so CDEC {} moves the pointer back from {} to {}",
+            x,
+            ptr,
+            ptr - x as i64
+        ),
+        Instruction::Cdec(x) => {
+            format!("register is 0, so CDEC {} leaves the pointer at {}", x, ptr)
+        }
+        Instruction::Load => format!(
+            "LOAD copies cell {} into the register, which becomes {}",
+            ptr,
+            bit(cell)
+        ),
+        Instruction::Inv => format!(
+            "INV flips cell {} from {} to {}",
+            ptr,
+            bit(cell),
+            bit(!cell)
+        ),
+        Instruction::Null => unreachable!(),
+    }
+}
+
+/// Steps through at most `steps` instructions, printing what each one does and checking
+/// assertions on the way
+pub fn explain_steps(
+    vm: &mut Vm,
+    labels: &Labels,
+    checker: &mut AssertChecker,
+    steps: u64,
+) -> Result<(), AssertFailure> {
+    let mut step = 0;
+    while !vm.halted && step < steps {
+        checker.check(vm)?;
+        step += 1;
+        println!(
+            "Step {} (instruction {}): {}",
+            step,
+            format_location(labels, vm.intsruction_pointer),
+            explain_step(vm)
+        );
+        vm.step();
+    }
+    if !vm.halted {
+        println!(
+            "... {} more instructions run without explanation",
+            vm.program.len() - vm.intsruction_pointer
+        );
+    }
+    Ok(())
+}
+
+/// Runs a script on one testcase of a task and prints how it went, failing on the first
+/// `#assert` comment which does not hold
+pub fn do_run(task: Task, wpk_path: &str, options: &RunOptions) -> Result<()> {
+    if options.strict_clean && options.dirty_scratch {
+        return Err(WpkppError::InvalidArgument(
+            "Scratch memory cannot be checked clear when it starts dirty".to_string(),
+        ));
+    }
+    let parse_options = ParseOptions {
+        max_file_size: options.max_file_size,
+        latin1: options.latin1,
+        ..Default::default()
+    };
+    let script = parse_script_with(wpk_path, options.format, true, parse_options)?;
+    let mut vm = Vm::new(script.instructions);
+    vm.set_memory_metric(options.memory_metric);
+    let seed = options.seed.clone().unwrap_or_else(Task::seed);
+    let testcase = load_testcase_seeded(task, &seed, &mut vm, options.tc_id)?;
+    if options.dirty_scratch {
+        fill_scratch(
+            &mut vm,
+            &testcase,
+            &scratch_seed(task, &seed, options.tc_id),
+        );
+    }
+    let mut checker = AssertChecker::new(&script.assertions);
+    let failed = |failure| assertion_failed(options.tc_id, &script.labels, failure);
+
+    if let Some(steps) = options.explain {
+        println!(
+            "Testcase {}: Memory starts with the input at cells 0 and up, the pointer at 0 and \
+             the register at 0",
+            options.tc_id
+        );
+        explain_steps(&mut vm, &script.labels, &mut checker, steps).map_err(failed)?;
+    }
+    // Carries on from wherever the explanation stopped
+    checker.run(&mut vm).map_err(failed)?;
+    let mut result = check_testcase(&vm, &testcase);
+    let dirty = match options.strict_clean {
+        true => first_dirty_cell(&vm, &testcase),
+        false => None,
+    };
+    if dirty.is_some() {
+        result.correct = false;
+    }
+
+    println!(
+        "Testcase {}: {}",
+        result.tc_id,
+        match result.correct {
+            true => "Correct",
+            false => "Wrong",
+        }
+    );
+    if let Some(cell) = dirty {
+        println!("Scratch: left set, first at cell {}", cell);
+    }
+    println!("Instructions: {}", result.runtime);
+    println!("Memory Usage: {}", result.memory);
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use miniserde::{json, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{error::Result, grader::TimeTaken, report::CaseResult, task::Task};
+
+/// Where `grade --log-file` appends its records, and the command line to note in them
+pub struct RunLog {
+    pub path: String,
+    pub arguments: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct RunLogCase {
+    pub tc_id: String,
+    pub correct: bool,
+    pub runtime: String,
+    pub memory: String,
+}
+
+/// One line of a grading log; Numbers are strings, as in `grade --json`
+#[derive(Serialize)]
+pub struct RunLogRecord {
+    /// Seconds since the Unix epoch
+    pub timestamp: String,
+    pub arguments: Vec<String>,
+    pub task: String,
+    pub script: String,
+    pub seed: String,
+    pub verdict: String,
+    pub score: String,
+    pub total: String,
+    pub time_taken: TimeTaken,
+    pub cases: Vec<RunLogCase>,
+}
+
+impl RunLogRecord {
+    pub fn new(
+        arguments: &[String],
+        task: Task,
+        script: &str,
+        seed: &str,
+        cases: &[CaseResult],
+        time_taken: TimeTaken,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        let score = cases.iter().filter(|case| case.correct).count();
+        Self {
+            timestamp: format!("{:.3}", timestamp),
+            arguments: arguments.to_vec(),
+            task: task.name().to_string(),
+            script: script.to_string(),
+            seed: seed.to_string(),
+            verdict: match score == cases.len() {
+                true => "OK",
+                false => "WA",
+            }
+            .to_string(),
+            score: score.to_string(),
+            total: cases.len().to_string(),
+            time_taken,
+            cases: cases
+                .iter()
+                .map(|case| RunLogCase {
+                    tc_id: case.tc_id.to_string(),
+                    correct: case.correct,
+                    runtime: case.runtime.to_string(),
+                    memory: case.memory.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Appends a record as one line of JSON, creating the log if needed
+pub fn append_record(path: &str, record: &RunLogRecord) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // One write per line, so concurrent runs appending to the same log don't interleave
+    file.write_all(format!("{}\n", json::to_string(record)).as_bytes())?;
+    Ok(())
+}
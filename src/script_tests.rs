@@ -0,0 +1,239 @@
+//! Test vectors embedded in a script's comments, run by `wpkpp test`: Unit tests for
+//! woodpecker routines, independent of the official tasks.
+//!
+//! ```text
+//! ;layout in=16,16 out=16
+//! ;test in=0x3,0x5 out=0x8
+//! ;test in=0xffff:16,1:16 out=0:16
+//! ```
+//!
+//! Inputs are written to memory from cell 0, one after another, and outputs are read right
+//! after the inputs, as in the official tasks. Each value is least significant bit first and
+//! takes the width given after a `:`, or else the width at its position in the last `;layout`.
+
+use crate::{
+    assertion::AssertChecker,
+    error::{Result, WpkppError},
+    parse::{format_location, parse_script_as, Script, ScriptFormat},
+    task::{MemoryLayout, Task},
+    vm::Vm,
+};
+
+/// Word following the comment character of a test vector
+pub const TEST_STR: &str = "test";
+/// Word following the comment character of a layout giving default widths
+pub const LAYOUT_STR: &str = "layout";
+const INPUT_KEY: &str = "in=";
+const OUTPUT_KEY: &str = "out=";
+
+pub struct TestOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+}
+
+/// One `;test` comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    pub input: MemoryLayout,
+    pub output: MemoryLayout,
+    /// The comment after `test`, for reports
+    pub text: String,
+}
+
+/// What a test vector came to
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    pub passed: bool,
+    /// Values read from the output cells
+    pub actual: Vec<u64>,
+    /// An `#assert` comment which did not hold, stopping the run
+    pub failed_assertion: Option<String>,
+    pub runtime: i64,
+    pub memory: i64,
+}
+
+/// The text after `word` in a `#` / `;` comment, if the comment starts with it
+fn directive<'a>(comment: &'a str, word: &str) -> Option<&'a str> {
+    let mut chars = comment.chars();
+    chars.next()?;
+    let body = chars.as_str().trim_start().strip_prefix(word)?;
+    match body.is_empty() || body.starts_with(char::is_whitespace) {
+        true => Some(body.trim()),
+        false => None,
+    }
+}
+
+fn invalid(comment: &str, reason: &str) -> WpkppError {
+    WpkppError::InvalidTestVector(format!("\"{}\": {}", comment, reason))
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// The comma separated list after `key` among a comment's fields
+fn field<'a>(fields: &'a str, key: &str) -> Option<&'a str> {
+    fields
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix(key))
+}
+
+/// Reads `widths` from a `;layout` list, e.g. "16,16"
+fn parse_widths(list: &str) -> Option<Vec<u64>> {
+    list.split(',')
+        .map(|width| parse_number(width).filter(|width| (1..=64).contains(width)))
+        .collect()
+}
+
+/// Reads values from a `;test` list, e.g. "0x3:16,5", taking missing widths from `defaults`
+fn parse_values(list: &str, defaults: &[u64]) -> Result<MemoryLayout, String> {
+    let mut layout = MemoryLayout::new();
+    for (i, item) in list.split(',').enumerate() {
+        let (value, width) = match item.split_once(':') {
+            Some((value, width)) => (value, parse_widths(width).and_then(|w| w.first().copied())),
+            None => (item, defaults.get(i).copied()),
+        };
+        let width = width.ok_or_else(|| format!("no width for \"{}\"", item))?;
+        let value = parse_number(value).ok_or_else(|| format!("invalid number \"{}\"", value))?;
+        if width < 64 && value >> width != 0 {
+            return Err(format!("{} does not fit in {} bits", value, width));
+        }
+        layout.push((value, width));
+    }
+    Ok(layout)
+}
+
+/// Collects a script's `;test` comments in order, applying `;layout` comments on the way
+pub fn parse_test_vectors(script: &Script) -> Result<Vec<TestVector>> {
+    let mut input_widths: Vec<u64> = vec![];
+    let mut output_widths: Vec<u64> = vec![];
+    let mut vectors = vec![];
+    for (_, comment) in script.comments.iter() {
+        if let Some(fields) = directive(comment, LAYOUT_STR) {
+            let widths = |key| {
+                field(fields, key)
+                    .map(|list| {
+                        parse_widths(list).ok_or_else(|| invalid(comment, "invalid widths"))
+                    })
+                    .transpose()
+            };
+            input_widths = widths(INPUT_KEY)?.unwrap_or_default();
+            output_widths = widths(OUTPUT_KEY)?.unwrap_or_default();
+        } else if let Some(fields) = directive(comment, TEST_STR) {
+            let values = |key, widths: &[u64]| {
+                let list = field(fields, key)
+                    .ok_or_else(|| invalid(comment, &format!("missing \"{}\"", key)))?;
+                parse_values(list, widths).map_err(|reason| invalid(comment, &reason))
+            };
+            vectors.push(TestVector {
+                input: values(INPUT_KEY, &input_widths)?,
+                output: values(OUTPUT_KEY, &output_widths)?,
+                text: fields.to_string(),
+            });
+        }
+    }
+    Ok(vectors)
+}
+
+/// Runs a test vector on `vm` from a reset, checking the script's assertions along the way
+pub fn run_test_vector(vm: &mut Vm, script: &Script, vector: &TestVector) -> TestOutcome {
+    let input = Task::pack(vector.input.clone());
+    let answer = Task::pack(vector.output.clone());
+    vm.reset();
+    vm.memory[0..input.len()].copy_from_bitslice(&input);
+
+    let failed_assertion = AssertChecker::new(&script.assertions)
+        .run(vm)
+        .err()
+        .map(|failure| {
+            format!(
+                "instruction {}: {}",
+                format_location(&script.labels, failure.idx),
+                failure
+            )
+        });
+    let run_stats = vm.run_result();
+
+    let output = &vm.memory[input.len()..(input.len() + answer.len())];
+    let mut start = 0;
+    let actual = vector
+        .output
+        .iter()
+        .map(|(_, width)| {
+            let bits = &output[start..start + *width as usize];
+            start += *width as usize;
+            bits.iter()
+                .enumerate()
+                .fold(0u64, |value, (pos, bit)| value | ((*bit as u64) << pos))
+        })
+        .collect();
+
+    TestOutcome {
+        passed: failed_assertion.is_none() && output == answer,
+        actual,
+        failed_assertion,
+        runtime: run_stats.runtime,
+        memory: run_stats.memory,
+    }
+}
+
+fn format_values(values: impl Iterator<Item = u64>) -> String {
+    values
+        .map(|value| format!("{:#x}", value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Runs every test vector embedded in a script, printing one line per vector; Fails if any
+/// vector fails
+pub fn do_test(path: &str, options: &TestOptions) -> Result<()> {
+    let script = parse_script_as(path, options.format, true)?;
+    let vectors = parse_test_vectors(&script)?;
+    if vectors.is_empty() {
+        return Err(WpkppError::InvalidTestVector(format!(
+            "{} has no \";{} in=... out=...\" comments",
+            path, TEST_STR
+        )));
+    }
+
+    let mut vm = Vm::new(script.instructions.clone());
+    let mut failed = 0;
+    for (i, vector) in vectors.iter().enumerate() {
+        let outcome = run_test_vector(&mut vm, &script, vector);
+        let status = match (&outcome.failed_assertion, outcome.passed) {
+            (Some(assertion), _) => format!("FAILED, {}", assertion),
+            (None, true) => "ok".to_string(),
+            (None, false) => format!(
+                "FAILED, got out={}",
+                format_values(outcome.actual.iter().copied())
+            ),
+        };
+        println!(
+            "Test {} ({}): {} / Instructions: {} / Memory Usage: {}",
+            i + 1,
+            vector.text,
+            status,
+            outcome.runtime,
+            outcome.memory
+        );
+        if !outcome.passed {
+            failed += 1;
+        }
+    }
+
+    println!("{} passed, {} failed", vectors.len() - failed, failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(WpkppError::CheckFailed(format!(
+            "{}/{} test vectors failed",
+            failed,
+            vectors.len()
+        ))),
+    }
+}
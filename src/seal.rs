@@ -0,0 +1,215 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+
+use crate::error::{Result, WpkppError};
+use crate::parse::{ParseErrorKind, ScriptCompression, ScriptFormat};
+
+/// Starts the checksum footer closing a sealed .wpk / .wpkm script
+pub const CHECKSUM_STR: &str = ";#sha256=";
+
+/// Checks lines against a checksum footer as they are read. The checksum covers every line
+/// before the footer, each ended by a single "\n", so CRLF line endings do not matter.
+pub struct ChecksumState {
+    hasher: Sha256,
+    sealed: bool,
+}
+
+impl ChecksumState {
+    pub fn new() -> Self {
+        Self {
+            hasher: Sha256::new(),
+            sealed: false,
+        }
+    }
+
+    /// Feeds one line without its line ending; Returns whether it was the footer
+    pub fn feed_line(&mut self, line: &str) -> Result<bool, ParseErrorKind> {
+        if let Some(expected) = line.trim().strip_prefix(CHECKSUM_STR) {
+            if self.sealed {
+                return Err(ParseErrorKind::MisplacedChecksum);
+            }
+            let actual = format!("{:x}", self.hasher.clone().finalize());
+            if !expected.eq_ignore_ascii_case(&actual) {
+                return Err(ParseErrorKind::ChecksumMismatch(actual));
+            }
+            self.sealed = true;
+            return Ok(true);
+        }
+        // Only blank lines may follow the footer
+        if self.sealed && !line.trim().is_empty() {
+            return Err(ParseErrorKind::MisplacedChecksum);
+        }
+        self.hasher.update(line.as_bytes());
+        self.hasher.update(b"\n");
+        Ok(false)
+    }
+}
+
+impl Default for ChecksumState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_footer(line: &str) -> bool {
+    line.trim().starts_with(CHECKSUM_STR)
+}
+
+/// Whether a script ends with a checksum footer
+pub fn is_sealed(source: &str) -> bool {
+    source.lines().any(is_footer)
+}
+
+/// Source without its checksum footer
+pub fn unseal_source(source: &str) -> String {
+    source
+        .lines()
+        .filter(|line| !is_footer(line))
+        .map(|line| line.to_string() + "\n")
+        .collect()
+}
+
+/// Source with any existing footer replaced by a fresh one; Trailing blank lines are dropped
+pub fn seal_source(source: &str) -> String {
+    let mut lines = source
+        .lines()
+        .filter(|line| !is_footer(line))
+        .collect::<Vec<_>>();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let mut sealed = String::with_capacity(source.len() + CHECKSUM_STR.len() + 65);
+    let mut hasher = Sha256::new();
+    for line in lines {
+        sealed.push_str(line);
+        sealed.push('\n');
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    sealed.push_str(&format!("{}{:x}\n", CHECKSUM_STR, hasher.finalize()));
+    sealed
+}
+
+/// Appends a checksum footer to a .wpk / .wpkm script in place, replacing any old one
+pub fn do_seal(path: &str) -> Result<()> {
+    if ScriptCompression::split_path(path).1 != ScriptCompression::None {
+        return Err(WpkppError::InvalidArgument(format!(
+            "Compressed scripts cannot be sealed in place: {}",
+            path
+        )));
+    }
+    match ScriptFormat::from_path(path) {
+        Some(ScriptFormat::Wpk | ScriptFormat::Wpkm) => {}
+        Some(ScriptFormat::Wpkb) => {
+            return Err(WpkppError::InvalidArgument(format!(
+                ".wpkb scripts already carry a checksum: {}",
+                path
+            )))
+        }
+        None => {
+            return Err(WpkppError::InvalidArgument(format!(
+                "Unknown file type {}",
+                path
+            )))
+        }
+    }
+
+    let source = fs::read_to_string(path)?;
+    let sealed = seal_source(&source);
+    fs::write(path, &sealed)?;
+    eprintln!(
+        "Sealed {} ({})",
+        path,
+        sealed.lines().last().unwrap_or_default()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_wpk_str, parse_wpkm_bytes};
+
+    const SOURCE: &str = "; add one\nINC 1\nLOAD\nCDEC 2\nINV\n";
+
+    /// Feeds every line of a source, returning the first error or how many footers it saw
+    fn check(source: &str) -> Result<usize, ParseErrorKind> {
+        let mut state = ChecksumState::new();
+        let mut footers = 0;
+        for line in source.lines() {
+            footers += state.feed_line(line)? as usize;
+        }
+        Ok(footers)
+    }
+
+    #[test]
+    fn sealed_sources_verify() {
+        let sealed = seal_source(SOURCE);
+        assert!(sealed.starts_with(SOURCE));
+        assert!(is_sealed(&sealed) && !is_sealed(SOURCE));
+        assert_eq!(check(&sealed), Ok(1));
+        assert_eq!(check(SOURCE), Ok(0));
+        assert_eq!(unseal_source(&sealed), SOURCE);
+
+        // CRLF line endings, blank lines after the footer and an uppercase digest still pass
+        assert_eq!(check(&sealed.replace('\n', "\r\n")), Ok(1));
+        assert_eq!(check(&(sealed.clone() + "\n  \n")), Ok(1));
+        let footer = sealed.lines().last().unwrap();
+        let upper = sealed.replace(
+            footer,
+            &footer.to_ascii_uppercase().replace("SHA256", "sha256"),
+        );
+        assert_eq!(check(&upper), Ok(1));
+    }
+
+    #[test]
+    fn resealing_replaces_the_footer() {
+        let sealed = seal_source(SOURCE);
+        assert_eq!(seal_source(&sealed), sealed);
+        assert_eq!(seal_source(&(SOURCE.to_string() + "\n\n")), sealed);
+        assert_eq!(
+            seal_source(&sealed.replace("INV", "LOAD")).lines().count(),
+            6
+        );
+        assert_ne!(seal_source(&sealed.replace("INV", "LOAD")), sealed);
+    }
+
+    #[test]
+    fn tampered_sources_fail() {
+        let sealed = seal_source(SOURCE);
+        assert!(matches!(
+            check(&sealed.replace("CDEC 2", "CDEC 3")),
+            Err(ParseErrorKind::ChecksumMismatch(_))
+        ));
+        let mut truncated: Vec<&str> = sealed.lines().collect();
+        truncated.remove(2);
+        assert!(matches!(
+            check(&truncated.join("\n")),
+            Err(ParseErrorKind::ChecksumMismatch(_))
+        ));
+        assert_eq!(
+            check(&(sealed.clone() + "INV\n")),
+            Err(ParseErrorKind::MisplacedChecksum)
+        );
+        let footer = sealed.lines().last().unwrap();
+        assert_eq!(
+            check(&format!("{}{}\n", sealed, footer)),
+            Err(ParseErrorKind::MisplacedChecksum)
+        );
+    }
+
+    #[test]
+    fn parsing_checks_footers() {
+        let sealed = seal_source(SOURCE);
+        assert_eq!(
+            parse_wpk_str(&sealed).unwrap(),
+            parse_wpk_str(SOURCE).unwrap()
+        );
+        assert!(parse_wpk_str(&sealed.replace("INC 1", "INC 2")).is_err());
+
+        let sealed = seal_source(";two\n>?2<!\n");
+        assert!(parse_wpkm_bytes(sealed.as_bytes()).is_ok());
+        assert!(parse_wpkm_bytes(sealed.replace('?', "!").as_bytes()).is_err());
+    }
+}
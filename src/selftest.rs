@@ -0,0 +1,285 @@
+//! `wpkpp selftest`: Checks a build behaves as it should on this machine before it is trusted
+//! with a contest. Reference solutions go through every script format, compression and the VM
+//! and must score full marks, small programs must give their known results, and testcase
+//! generation must be deterministic.
+//!
+//! Testcases come from a seeded RNG, so to check that two machines generate the same
+//! testcases, save their digests on one with `--save-digests` and check them on the other
+//! with `--digests`.
+
+use std::fs;
+
+use crate::{
+    canon::canonical_hash,
+    error::{Result, WpkppError},
+    grader::{load_testcase_seeded, run_testcase, TESTCASES},
+    parse::{parse_script_bytes, write_wpkb, ScriptFormat},
+    reference::reference_solution,
+    task::Task,
+    util::{fnv1a, FNV_OFFSET},
+    vectors::vm_vectors,
+    vm::{Instruction, Instructions, Vm},
+};
+
+/// Seed every selftest run generates testcases from, as well as the configured seed
+pub const SELFTEST_SEED: &str = "wpkpp-selftest";
+
+/// Name of a `wpkpp vectors` VM program with its runtime, final pointer, set cells, register
+/// and memory usage
+type VmResult = (&'static str, i64, u64, &'static [u64], bool, i64);
+
+/// Known results of the `wpkpp vectors` VM programs
+const VM_RESULTS: [VmResult; 9] = [
+    ("inv", 1, 0, &[0], false, 1),
+    ("inc-then-inv", 4, 3, &[3], false, 4),
+    (
+        "load-set-cdec-moves",
+        4,
+        4294967295,
+        &[0, 4294967295],
+        true,
+        2,
+    ),
+    ("load-clear-cdec-stays", 3, 0, &[0], false, 1),
+    ("cdec-steps-when-not-taken", 6, 0, &[], false, 1),
+    ("load-reads-input", 4, 0, &[0, 1], true, 2),
+    ("load-reads-input-clear", 4, 1, &[1], false, 2),
+    (
+        "inc-wraps-around",
+        4294967298,
+        0,
+        &[0, 4294967295],
+        false,
+        4294967296,
+    ),
+    ("inv-twice", 2, 0, &[0], false, 1),
+];
+
+pub struct SelftestOptions {
+    /// Digests of testcases from another machine to compare against
+    pub digests_path: Option<String>,
+    /// Write the digests of this machine's testcases here
+    pub save_digests_path: Option<String>,
+}
+
+/// Tallies checks as they are printed
+#[derive(Default)]
+struct Checks {
+    passed: usize,
+    failed: usize,
+}
+
+impl Checks {
+    fn check(&mut self, name: &str, problem: Option<String>) {
+        match problem {
+            None => {
+                self.passed += 1;
+                println!("ok   {}", name);
+            }
+            Some(problem) => {
+                self.failed += 1;
+                println!("FAIL {}: {}", name, problem);
+            }
+        }
+    }
+}
+
+/// The same program with every INC / CDEC longer than 1 split in two, for compression to merge
+/// back together
+fn split_repeats(instructions: &Instructions) -> Instructions {
+    let mut split = Instructions::with_capacity(instructions.len() * 2);
+    for instruction in instructions.iter() {
+        match *instruction {
+            Instruction::Inc(x) if x > 1 => {
+                split.push(Instruction::Inc(x / 2));
+                split.push(Instruction::Inc(x - x / 2));
+            }
+            Instruction::Cdec(x) if x > 1 => {
+                split.push(Instruction::Cdec(x / 2));
+                split.push(Instruction::Cdec(x - x / 2));
+            }
+            other => split.push(other),
+        }
+    }
+    split
+}
+
+/// Writes the reference solution of `task` in every format, parses it back and grades it
+fn check_reference(task: Task, reference: &Instructions, vm: &mut Vm) -> Result<Option<String>> {
+    let split = split_repeats(reference);
+    let mut wpkb = vec![];
+    write_wpkb(&mut wpkb, &split)?;
+    let written = [
+        (ScriptFormat::Wpk, split.to_wpk().into_bytes()),
+        (ScriptFormat::Wpkm, split.to_wpkm().into_bytes()),
+        (ScriptFormat::Wpkb, wpkb),
+    ];
+    let hash = canonical_hash(reference);
+    let mut programs = vec![];
+    for (format, bytes) in written {
+        let parsed = parse_script_bytes(&bytes, format)?.instructions;
+        if canonical_hash(&parsed) != hash {
+            return Ok(Some(format!(
+                "does not compress back to the same instructions as .{}",
+                format.name()
+            )));
+        }
+        programs.push(parsed);
+    }
+
+    // Every format came back the same, so any of them will do
+    vm.program = programs.pop().unwrap();
+    let mut score = 0;
+    for tc_id in 0..TESTCASES as i8 {
+        let testcase = load_testcase_seeded(task, SELFTEST_SEED, vm, tc_id)?;
+        let (result, _) = run_testcase(vm, &testcase, None);
+        score += result.correct as usize;
+    }
+    Ok(match score == TESTCASES {
+        true => None,
+        false => Some(format!("scored {}/{}", score, TESTCASES)),
+    })
+}
+
+/// Hash of every testcase of `task` generated from `seed`
+fn testcase_digest(task: Task, seed: &str) -> Result<u64> {
+    let mut digest = FNV_OFFSET;
+    for (input, answer) in task.testcases(seed)? {
+        for bits in [input, answer] {
+            digest = fnv1a(digest, &(bits.len() as u64).to_le_bytes());
+            digest = fnv1a(digest, bits.as_raw_slice());
+        }
+    }
+    Ok(digest)
+}
+
+/// Digest recorded for a task and seed in a digests file's text
+fn find_digest(text: &str, task: Task, seed: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let (digest, rest) = line.split_once(' ')?;
+        let (line_task, line_seed) = rest.split_once(' ')?;
+        match line_task == task.name() && line_seed == seed {
+            true => u64::from_str_radix(digest, 16).ok(),
+            false => None,
+        }
+    })
+}
+
+/// Runs every check, printing each, and fails if any did
+pub fn do_selftest(options: &SelftestOptions) -> Result<()> {
+    let mut checks = Checks::default();
+
+    for vector in vm_vectors()? {
+        let known = VM_RESULTS.iter().find(|known| known.0 == vector.name);
+        let (_, runtime, ptr, ones, register, memory) = match known {
+            Some(known) => known,
+            None => {
+                checks.check(
+                    &format!("vm {}", vector.name),
+                    Some("no known result".to_string()),
+                );
+                continue;
+            }
+        };
+        let ones: Vec<String> = ones.iter().map(|cell| cell.to_string()).collect();
+        let expected = (
+            runtime.to_string(),
+            ptr.to_string(),
+            ones,
+            *register,
+            memory.to_string(),
+        );
+        let actual = (
+            vector.runtime,
+            vector.ptr,
+            vector.ones,
+            vector.register,
+            vector.memory,
+        );
+        let problem = (actual != expected).then(|| {
+            format!(
+                "runtime {}, pointer {}, cells {:?}, register {}, memory {}; expected runtime \
+                 {}, pointer {}, cells {:?}, register {}, memory {}",
+                actual.0,
+                actual.1,
+                actual.2,
+                actual.3,
+                actual.4,
+                expected.0,
+                expected.1,
+                expected.2,
+                expected.3,
+                expected.4
+            )
+        });
+        checks.check(&format!("vm {}", vector.name), problem);
+    }
+
+    let mut vm = Vm::new(Instructions::new());
+    for task in Task::ALL {
+        if let Some(reference) = reference_solution(task) {
+            let problem = check_reference(task, &reference, &mut vm)?;
+            checks.check(&format!("reference task {}", task.name()), problem);
+        }
+    }
+    drop(vm);
+
+    let recorded = match options.digests_path.as_ref() {
+        Some(path) => Some(fs::read_to_string(path)?),
+        None => None,
+    };
+    let mut seeds = vec![SELFTEST_SEED.to_string()];
+    if Task::seed() != SELFTEST_SEED {
+        seeds.push(Task::seed());
+    }
+    let mut digests = String::new();
+    for task in Task::ALL {
+        if task.testcases(SELFTEST_SEED).is_err() {
+            continue;
+        }
+        for seed in seeds.iter() {
+            let digest = testcase_digest(task, seed)?;
+            let mut problem = match testcase_digest(task, seed)? == digest {
+                true => None,
+                false => Some("testcases differ between two generations".to_string()),
+            };
+            if let Some(text) = recorded.as_ref() {
+                problem = problem.or_else(|| match find_digest(text, task, seed) {
+                    Some(recorded) if recorded != digest => Some(format!(
+                        "digest {:016x}, expected {:016x}",
+                        digest, recorded
+                    )),
+                    Some(_) => None,
+                    None => Some("no recorded digest".to_string()),
+                });
+            }
+            checks.check(
+                &format!(
+                    "testcases task {} seed {} ({:016x})",
+                    task.name(),
+                    seed,
+                    digest
+                ),
+                problem,
+            );
+            digests.push_str(&format!("{:016x} {} {}\n", digest, task.name(), seed));
+        }
+    }
+    if let Some(path) = options.save_digests_path.as_ref() {
+        fs::write(path, digests)?;
+        println!("Saved testcase digests to {}", path);
+    }
+
+    println!(
+        "Selftest: {}/{} check(s) passed",
+        checks.passed,
+        checks.passed + checks.failed
+    );
+    match checks.failed {
+        0 => Ok(()),
+        n => Err(WpkppError::CheckFailed(format!(
+            "{} selftest check(s) failed",
+            n
+        ))),
+    }
+}
@@ -0,0 +1,182 @@
+//! Sessions: Grading the same script against several tasks, e.g. 2 and 2a or 4, 4a and 5, while
+//! parsing and compressing it only once.
+//!
+//! A session reads each file the first time it is asked for and keeps the parsed script until
+//! it is dropped or the file is forgotten, so changes to the file in between are not seen.
+
+use std::collections::HashMap;
+
+use crate::{
+    cache::parse_script_cached,
+    error::Result,
+    grader::grade_script,
+    parse::{parse_script_with, ParseOptions, Script, ScriptFormat},
+    report::GradeReport,
+    task::Task,
+    util::ResetableTimer,
+};
+
+/// A script parsed by a session, with how long parsing it took
+pub struct ParsedScript {
+    pub script: Script,
+    pub parse_time: f64,
+}
+
+/// Parsed scripts shared between gradings in one process
+pub struct Session {
+    options: ParseOptions,
+    /// Whether to enforce the file size limit, as `grade` does
+    check_size: bool,
+    scripts: HashMap<(String, Option<ScriptFormat>), ParsedScript>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(ParseOptions::default())
+    }
+}
+
+impl Session {
+    /// A session parsing every script with `options`, checking file sizes like `grade`; With
+    /// `options.cache`, scripts parsed by an earlier process are read from the parse cache
+    pub fn new(options: ParseOptions) -> Self {
+        Self {
+            options,
+            check_size: true,
+            scripts: HashMap::new(),
+        }
+    }
+
+    /// Whether to enforce the file size limit; On by default
+    pub fn check_size(mut self, check_size: bool) -> Self {
+        self.check_size = check_size;
+        self
+    }
+
+    /// The script at `path`, parsed on the first call for this path and format
+    pub fn script(&mut self, path: &str, format: Option<ScriptFormat>) -> Result<&ParsedScript> {
+        let key = (path.to_string(), format);
+        if !self.scripts.contains_key(&key) {
+            let mut timer = ResetableTimer::new();
+            let script = match self.options.cache {
+                true => parse_script_cached(path, format, self.check_size, self.options)?,
+                false => parse_script_with(path, format, self.check_size, self.options)?,
+            };
+            let parse_time = timer.seconds_since();
+            self.scripts
+                .insert(key.clone(), ParsedScript { script, parse_time });
+        }
+        Ok(&self.scripts[&key])
+    }
+
+    /// Grades the script at `path` on the testcases of a task generated from `seed`, parsing it
+    /// only if no earlier call has. The report's parse time is the time actually spent parsing
+    /// for this call, so 0 when reused.
+    pub fn grade(
+        &mut self,
+        task: Task,
+        seed: &str,
+        path: &str,
+        format: Option<ScriptFormat>,
+    ) -> Result<GradeReport> {
+        let parsed_before = self.is_parsed(path, format);
+        let parsed = self.script(path, format)?;
+        let parse_time = match parsed_before {
+            true => 0.0,
+            false => parsed.parse_time,
+        };
+        grade_script(task, seed, &parsed.script, parse_time)
+    }
+
+    pub fn is_parsed(&self, path: &str, format: Option<ScriptFormat>) -> bool {
+        self.scripts.contains_key(&(path.to_string(), format))
+    }
+
+    /// Drops every parse of `path`, so the next call reads the file again
+    pub fn forget(&mut self, path: &str) {
+        self.scripts
+            .retain(|(script_path, _), _| script_path != path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    const SEED: &str = "session";
+
+    /// A fresh file under the temp directory holding `text`
+    fn script_file(name: &str, text: &str) -> String {
+        let dir = env::temp_dir().join(format!("wpkpp-session-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn later_tasks_reuse_the_parse() {
+        let path = script_file("reuse.wpkm", "1>!?2>!\n");
+        let options = ParseOptions {
+            cache: true,
+            ..Default::default()
+        };
+        let mut session = Session::new(options);
+        session.grade(Task::ZeroXor, SEED, &path, None).unwrap();
+        let second = session.grade(Task::OneAdd1, SEED, &path, None).unwrap();
+        assert!(session.is_parsed(&path, None));
+        assert_eq!(second.timings.parse, 0.0);
+
+        let fresh = parse_script_with(&path, None, true, ParseOptions::default()).unwrap();
+        let expected = grade_script(Task::OneAdd1, SEED, &fresh, 0.0).unwrap();
+        assert_eq!(
+            (second.passed, second.score, second.runtime, second.memory),
+            (
+                expected.passed,
+                expected.score,
+                expected.runtime,
+                expected.memory
+            )
+        );
+        assert_eq!(second.hash, expected.hash);
+    }
+
+    #[test]
+    fn forgotten_files_are_read_again() {
+        let path = script_file("forget.wpkm", "!");
+        let mut session = Session::default();
+        assert_eq!(
+            session
+                .script(&path, None)
+                .unwrap()
+                .script
+                .instructions
+                .len(),
+            1
+        );
+
+        fs::write(&path, "!>!").unwrap();
+        assert_eq!(
+            session
+                .script(&path, None)
+                .unwrap()
+                .script
+                .instructions
+                .len(),
+            1
+        );
+        session.forget(&path);
+        assert!(!session.is_parsed(&path, None));
+        assert_eq!(
+            session
+                .script(&path, None)
+                .unwrap()
+                .script
+                .instructions
+                .len(),
+            3
+        );
+    }
+}
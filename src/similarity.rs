@@ -0,0 +1,163 @@
+//! `wpkpp similarity`: Pairs of submissions which share suspiciously much of their code, for
+//! plagiarism screening.
+//!
+//! Scripts are first put in canonical form, so formats, comments, labels and how INC / CDEC
+//! runs are split no longer matter. Every run of `k` instructions is hashed, and winnowing keeps
+//! the smallest hash of every `window` consecutive ones as the script's fingerprint, which
+//! still catches shared code after lines are inserted or moved around. Pairs are scored by how
+//! much of their fingerprints they share.
+
+use std::collections::HashSet;
+
+use crate::{
+    canon::canonical_instructions,
+    error::{Result, WpkppError},
+    parse::{expand_script_paths, parse_script},
+    util::{fnv1a, FNV_OFFSET},
+    vm::{Instruction, Instructions},
+};
+
+/// Instructions hashed together
+pub const DEFAULT_K: usize = 8;
+/// Consecutive hashes winnowed down to one
+pub const DEFAULT_WINDOW: usize = 4;
+/// Share of fingerprints above which a pair is reported
+pub const DEFAULT_THRESHOLD: f64 = 0.8;
+
+pub struct SimilarityOptions {
+    pub k: usize,
+    pub window: usize,
+    pub threshold: f64,
+}
+
+/// Hashes of the instruction runs selected by winnowing
+pub type Fingerprint = HashSet<u64>;
+
+fn instruction_bytes(instruction: &Instruction) -> [u8; 5] {
+    let (op, count) = match instruction {
+        Instruction::Inc(x) => (0, *x),
+        Instruction::Cdec(x) => (1, *x),
+        Instruction::Load => (2, 0),
+        Instruction::Inv => (3, 0),
+        Instruction::Null => unreachable!(),
+    };
+    let count = count.to_le_bytes();
+    [op, count[0], count[1], count[2], count[3]]
+}
+
+/// Winnowed hashes of every `k` consecutive canonical instructions; Scripts shorter than `k`
+/// are hashed whole
+pub fn fingerprint(instructions: &Instructions, k: usize, window: usize) -> Fingerprint {
+    let canonical = canonical_instructions(instructions);
+    let k = k.clamp(1, canonical.len().max(1));
+    let hashes: Vec<u64> = canonical
+        .windows(k)
+        .map(|gram| {
+            gram.iter().fold(FNV_OFFSET, |hash, instruction| {
+                fnv1a(hash, &instruction_bytes(instruction))
+            })
+        })
+        .collect();
+    if hashes.len() <= window {
+        return hashes.into_iter().min().into_iter().collect();
+    }
+    hashes
+        .windows(window.max(1))
+        .filter_map(|hashes| hashes.iter().min().copied())
+        .collect()
+}
+
+/// Shared fingerprints over all fingerprints of either script, from 0 to 1
+pub fn similarity(a: &Fingerprint, b: &Fingerprint) -> f64 {
+    let union = a.union(b).count();
+    match union {
+        0 => 1.0,
+        union => a.intersection(b).count() as f64 / union as f64,
+    }
+}
+
+/// Fingerprints every script in a directory or matched by a glob pattern, printing each pair
+/// at least `threshold` similar, most similar first
+pub fn do_similarity(pattern: &str, options: &SimilarityOptions) -> Result<()> {
+    if !(0.0..=1.0).contains(&options.threshold) {
+        return Err(WpkppError::InvalidArgument(format!(
+            "Similarity threshold {} is out of range; expected 0 to 1",
+            options.threshold
+        )));
+    }
+    let mut scripts = vec![];
+    for (path, _) in expand_script_paths(pattern)? {
+        let path = path.to_string_lossy().to_string();
+        match parse_script(&path, true) {
+            Ok(script) => {
+                let fingerprint = fingerprint(&script.instructions, options.k, options.window);
+                scripts.push((path, fingerprint));
+            }
+            Err(e) => eprintln!("{}: {}; skipping", path, e),
+        }
+    }
+
+    let mut pairs = vec![];
+    for (i, (path_a, a)) in scripts.iter().enumerate() {
+        for (path_b, b) in scripts[i + 1..].iter() {
+            let score = similarity(a, b);
+            if score >= options.threshold {
+                pairs.push((score, path_a, path_b));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    for (score, path_a, path_b) in pairs.iter() {
+        println!("{:.2} {} {}", score, path_a, path_b);
+    }
+    println!(
+        "{} of {} pairs of {} scripts are at least {:.2} similar",
+        pairs.len(),
+        scripts.len() * scripts.len().saturating_sub(1) / 2,
+        scripts.len(),
+        options.threshold
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_wpk_str, parse_wpkm_bytes};
+
+    fn wpkm_fingerprint(source: &str) -> Fingerprint {
+        fingerprint(&parse_wpkm_bytes(source.as_bytes()).unwrap(), 3, 2)
+    }
+
+    #[test]
+    fn formatting_does_not_change_fingerprints() {
+        let wpkm = wpkm_fingerprint("!>?!2>?!<?3<!?>!");
+        let wpk = parse_wpk_str(
+            "; same program\nINV\nINC 1\nLOAD\nINV\nINC 1\nINC 1\nLOAD\nINV\n\
+             CDEC 1\nLOAD\nCDEC 3\nINV\nLOAD\nINC 1\nINV\n",
+        )
+        .unwrap();
+        assert_eq!(fingerprint(&wpk, 3, 2), wpkm);
+        assert_eq!(similarity(&wpkm, &fingerprint(&wpk, 3, 2)), 1.0);
+    }
+
+    #[test]
+    fn shared_code_scores_by_shared_fingerprints() {
+        let original = wpkm_fingerprint("!>?!2>?!<?3<!?>!");
+        let edited = wpkm_fingerprint("!>?!2>?!<?3<!?>!7>?");
+        let unrelated = wpkm_fingerprint("?5>?5>!9<?!?!");
+        let edited_score = similarity(&original, &edited);
+        assert!(edited_score > 0.5 && edited_score < 1.0, "{}", edited_score);
+        assert!(similarity(&original, &unrelated) < edited_score);
+        assert_eq!(similarity(&Fingerprint::new(), &Fingerprint::new()), 1.0);
+    }
+
+    #[test]
+    fn short_scripts_are_hashed_whole() {
+        let short = wpkm_fingerprint("!>?");
+        assert_eq!(short.len(), 1);
+        assert_ne!(short, wpkm_fingerprint("!<?"));
+        assert!(wpkm_fingerprint("").is_empty());
+    }
+}
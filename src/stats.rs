@@ -0,0 +1,423 @@
+//! `wpkpp stats`: What a script is made of, read off the script alone without running it.
+
+use miniserde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::{
+    canon::canonical_instructions,
+    error::Result,
+    parse::{parse_script_with, ParseOptions, Script, ScriptFormat, SourceMap},
+    predict::StaticBounds,
+    vm::{Instruction, Instructions, MEM_SIZE},
+};
+
+/// Widest bar drawn in a histogram
+const BAR_WIDTH: u64 = 40;
+
+pub struct StatsOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+}
+
+/// Runs of consecutive INC or CDEC steps, bucketed by powers of two: Bucket `i` counts runs of
+/// `2^i` to `2^(i+1) - 1` steps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunHistogram {
+    pub buckets: Vec<u64>,
+}
+
+impl RunHistogram {
+    fn add(&mut self, run: u64) {
+        let bucket = (u64::BITS - 1 - run.max(1).leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str) {
+        let most = self.buckets.iter().copied().max().unwrap_or(0);
+        if most == 0 {
+            writeln!(out, "{} runs: none", name).unwrap();
+            return;
+        }
+        let rows: Vec<(String, u64)> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| {
+                let low: u64 = 1 << bucket;
+                let range = match bucket {
+                    0 => "1".to_string(),
+                    _ => format!("{}-{}", low, low.saturating_mul(2) - 1),
+                };
+                (range, *count)
+            })
+            .collect();
+        let width = rows.iter().map(|(range, _)| range.len()).max().unwrap_or(0);
+        writeln!(out, "{} runs:", name).unwrap();
+        for (range, count) in rows {
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(most) as usize);
+            writeln!(
+                out,
+                "  {:>width$} {:<bar_width$} {}",
+                range,
+                bar,
+                count,
+                width = width,
+                bar_width = BAR_WIDTH as usize
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// The longest run of consecutive INC or CDEC steps and the instruction it starts at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LongestRun {
+    pub steps: u64,
+    pub index: usize,
+}
+
+/// Pointer spans read off the script, in cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerExtent {
+    /// Every INC moving forward and no CDEC moving back
+    pub no_cdec: i64,
+    /// Every CDEC moving back as well
+    pub every_cdec: i64,
+    /// Most any testcase could span
+    pub most: i64,
+}
+
+impl PointerExtent {
+    pub fn new(instructions: &Instructions) -> Self {
+        let (mut ptr, mut lowest, mut highest) = (0_i128, 0_i128, 0_i128);
+        for instruction in instructions.iter() {
+            match instruction {
+                Instruction::Inc(x) => ptr += *x as i128,
+                Instruction::Cdec(x) => ptr -= *x as i128,
+                _ => continue,
+            }
+            lowest = lowest.min(ptr);
+            highest = highest.max(ptr);
+        }
+        let (inc, _, _, _) = instructions.opcount();
+        Self {
+            no_cdec: (inc + 1).min(MEM_SIZE) as i64,
+            every_cdec: (highest - lowest + 1).min(MEM_SIZE as i128) as i64,
+            most: StaticBounds::new(instructions).max_memory,
+        }
+    }
+
+    /// The smaller of the two spans, as an estimate of the best case
+    pub fn best_case(&self) -> i64 {
+        self.no_cdec.min(self.every_cdec)
+    }
+}
+
+/// Everything `wpkpp stats` prints
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptStats {
+    /// Instructions as written, with zero length INC / CDEC
+    pub raw_instructions: usize,
+    /// Instructions once compressed
+    pub instructions: usize,
+    /// INC / CDEC / LOAD / INV instructions once compressed
+    pub kinds: (usize, usize, usize, usize),
+    /// Steps of the script as written
+    pub raw_steps: u64,
+    /// Steps of the compressed script
+    pub steps: u64,
+    pub inc_runs: RunHistogram,
+    pub cdec_runs: RunHistogram,
+    pub longest_inc: Option<LongestRun>,
+    pub longest_cdec: Option<LongestRun>,
+    pub extent: PointerExtent,
+    pub complexity: Complexity,
+}
+
+impl ScriptStats {
+    /// Stats of a script parsed without compression, so that runs are seen as written
+    pub fn new(raw: &Instructions) -> Self {
+        let compressed = canonical_instructions(raw);
+        let mut kinds = (0, 0, 0, 0);
+        for instruction in compressed.iter() {
+            match instruction {
+                Instruction::Inc(_) => kinds.0 += 1,
+                Instruction::Cdec(_) => kinds.1 += 1,
+                Instruction::Load => kinds.2 += 1,
+                Instruction::Inv => kinds.3 += 1,
+                Instruction::Null => {}
+            }
+        }
+
+        let mut stats = Self {
+            raw_instructions: raw.len(),
+            instructions: compressed.len(),
+            kinds,
+            raw_steps: raw.total_steps(),
+            steps: compressed.total_steps(),
+            inc_runs: RunHistogram::default(),
+            cdec_runs: RunHistogram::default(),
+            longest_inc: None,
+            longest_cdec: None,
+            extent: PointerExtent::new(raw),
+            complexity: Complexity::new(raw),
+        };
+        for run in runs(raw) {
+            stats.add_run(run);
+        }
+        stats
+    }
+
+    fn add_run(&mut self, run: Run) {
+        let (histogram, longest) = match run.is_inc {
+            true => (&mut self.inc_runs, &mut self.longest_inc),
+            false => (&mut self.cdec_runs, &mut self.longest_cdec),
+        };
+        histogram.add(run.steps);
+        if longest.is_none_or(|longest| run.steps > longest.steps) {
+            *longest = Some(LongestRun {
+                steps: run.steps,
+                index: run.index,
+            });
+        }
+    }
+}
+
+/// Consecutive INC or CDEC steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Run {
+    is_inc: bool,
+    steps: u64,
+    /// Instruction the run starts at
+    index: usize,
+}
+
+/// Every run of consecutive INC or CDEC steps, skipping zero length instructions
+fn runs(instructions: &Instructions) -> Vec<Run> {
+    let mut runs: Vec<Run> = vec![];
+    let mut open = false;
+    for (index, instruction) in instructions.iter().enumerate() {
+        let (is_inc, steps) = match instruction {
+            Instruction::Inc(0) | Instruction::Cdec(0) => continue,
+            Instruction::Inc(x) => (true, *x as u64),
+            Instruction::Cdec(x) => (false, *x as u64),
+            _ => {
+                open = false;
+                continue;
+            }
+        };
+        match runs.last_mut() {
+            Some(run) if open && run.is_inc == is_inc => run.steps += steps,
+            _ => runs.push(Run {
+                is_inc,
+                steps,
+                index,
+            }),
+        }
+        open = true;
+    }
+    runs
+}
+
+/// How regular a script is, e.g. to tell generated code from handwritten
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complexity {
+    /// Different lengths among INC / CDEC runs
+    pub distinct_runs: usize,
+    /// Shannon entropy of INC / CDEC run lengths, in bits; Low for code repeating a few
+    /// patterns
+    pub run_entropy: f64,
+    /// LOAD instructions per compressed instruction
+    pub load_density: f64,
+    /// Burstiness of the gaps between INV instructions, from -1 for evenly spread through 0
+    /// for random to 1 for clustered; 0 with fewer than three INVs
+    pub inv_clustering: f64,
+}
+
+impl Complexity {
+    pub fn new(instructions: &Instructions) -> Self {
+        let runs = runs(instructions);
+        let mut lengths: HashMap<u64, usize> = HashMap::new();
+        for run in runs.iter() {
+            *lengths.entry(run.steps).or_default() += 1;
+        }
+        let run_entropy = lengths
+            .values()
+            .map(|count| {
+                let p = *count as f64 / runs.len() as f64;
+                -p * p.log2()
+            })
+            .sum::<f64>()
+            .max(0.0);
+
+        let compressed = canonical_instructions(instructions);
+        let loads = compressed
+            .iter()
+            .filter(|instruction| matches!(instruction, Instruction::Load))
+            .count();
+        let load_density = match compressed.len() {
+            0 => 0.0,
+            len => loads as f64 / len as f64,
+        };
+
+        let invs: Vec<usize> = compressed
+            .iter()
+            .enumerate()
+            .filter(|(_, instruction)| matches!(instruction, Instruction::Inv))
+            .map(|(index, _)| index)
+            .collect();
+        let gaps: Vec<f64> = invs.windows(2).map(|w| (w[1] - w[0]) as f64).collect();
+        let inv_clustering = match gaps.len() {
+            0 | 1 => 0.0,
+            n => {
+                let mean = gaps.iter().sum::<f64>() / n as f64;
+                let deviation =
+                    (gaps.iter().map(|gap| (gap - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+                (deviation - mean) / (deviation + mean)
+            }
+        };
+
+        Self {
+            distinct_runs: lengths.len(),
+            run_entropy,
+            load_density,
+            inv_clustering,
+        }
+    }
+}
+
+/// `grade --complexity` metrics; Numbers are strings, as in the rest of `grade --json`
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComplexityReport {
+    pub distinct_runs: String,
+    pub run_entropy: String,
+    pub load_density: String,
+    pub inv_clustering: String,
+}
+
+impl From<Complexity> for ComplexityReport {
+    fn from(complexity: Complexity) -> Self {
+        Self {
+            distinct_runs: complexity.distinct_runs.to_string(),
+            run_entropy: format!("{:.4}", complexity.run_entropy),
+            load_density: format!("{:.4}", complexity.load_density),
+            inv_clustering: format!("{:.4}", complexity.inv_clustering),
+        }
+    }
+}
+
+impl fmt::Display for Complexity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} distinct run lengths / run entropy {:.2} bits / LOAD density {:.3} / INV \
+             clustering {:+.2}",
+            self.distinct_runs, self.run_entropy, self.load_density, self.inv_clustering
+        )
+    }
+}
+
+/// Where an instruction came from: its line and column when known, or its index
+fn locate(path: &str, source_map: Option<&SourceMap>, index: usize) -> String {
+    match source_map.and_then(|map| map.get(index)) {
+        Some(span) => format!("{}:{}", path, span),
+        None => format!("instruction {}", index),
+    }
+}
+
+/// What `wpkpp stats` prints for a script parsed without compression, starting with its
+/// metadata as `grade` prints it
+fn render_stats(path: &str, script: &Script) -> String {
+    let stats = ScriptStats::new(&script.instructions);
+    let (inc, cdec, load, inv) = stats.kinds;
+    let mut out = String::new();
+
+    if let Some(meta) = script.meta.as_ref() {
+        writeln!(out, "Script: {}", meta).unwrap();
+    }
+    writeln!(
+        out,
+        "Instructions: {} compressed, {} as written",
+        stats.instructions, stats.raw_instructions
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "  INC {} / CDEC {} / LOAD {} / INV {}",
+        inc, cdec, load, inv
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "Steps: {} compressed, {} as written",
+        stats.steps, stats.raw_steps
+    )
+    .unwrap();
+    stats.inc_runs.write(&mut out, "INC");
+    stats.cdec_runs.write(&mut out, "CDEC");
+    for (name, longest) in [("INC", stats.longest_inc), ("CDEC", stats.longest_cdec)] {
+        if let Some(longest) = longest {
+            writeln!(
+                out,
+                "Longest {} run: {} steps at {}",
+                name,
+                longest.steps,
+                locate(path, script.source_map.as_ref(), longest.index)
+            )
+            .unwrap();
+        }
+    }
+    let extent = stats.extent;
+    writeln!(
+        out,
+        "Pointer extent: {} cells at best; {} if no CDEC moves back, {} if every CDEC does, at \
+         most {}",
+        extent.best_case(),
+        extent.no_cdec,
+        extent.every_cdec,
+        extent.most
+    )
+    .unwrap();
+    writeln!(out, "Complexity: {}", stats.complexity).unwrap();
+    out
+}
+
+/// Prints counts, run histograms and pointer extents of a script without running it
+pub fn do_stats(path: &str, options: &StatsOptions) -> Result<()> {
+    let parse_options = ParseOptions {
+        compress: false,
+        source_map: true,
+        ..Default::default()
+    };
+    let script = parse_script_with(path, options.format, true, parse_options)?;
+    print!("{}", render_stats(path, &script));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_script_bytes;
+
+    #[test]
+    fn stats_start_with_the_script_metadata() {
+        let script =
+            parse_script_bytes(b";! author=ada, version=3\nINC\nLOAD\n", ScriptFormat::Wpk)
+                .unwrap();
+        let text = render_stats("a.wpk", &script);
+        assert!(
+            text.starts_with("Script: author=ada, version=3\nInstructions: "),
+            "{}",
+            text
+        );
+
+        let script = parse_script_bytes(b"INC\nLOAD\n", ScriptFormat::Wpk).unwrap();
+        assert!(render_stats("a.wpk", &script).starts_with("Instructions: "));
+    }
+}
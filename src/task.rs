@@ -1,17 +1,21 @@
-use std::env;
 use bitvec::prelude::*;
 use rand::{rngs::StdRng, Rng};
 use rand_seeder::Seeder;
-use anyhow::{anyhow, Result};
+use std::env;
 
+use crate::error::{Result, WpkppError};
+use crate::grader::TESTCASES;
 use crate::util::mod_inv;
 
-type MemoryLayout = Vec<(u64, u64)>;
-type MemoryLayoutIO = (MemoryLayout, MemoryLayout);
+/// Values paired with their width in bits, laid out one after another from cell 0
+pub type MemoryLayout = Vec<(u64, u64)>;
+/// Input and output layouts of a testcase
+pub type MemoryLayoutIO = (MemoryLayout, MemoryLayout);
 
 const ECC_MOD: u64 = (1u64 << 16) - 17;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Task {
     ZeroXor,
     OneAdd1,
@@ -28,24 +32,76 @@ pub enum Task {
 }
 
 impl Task {
-    pub fn from_str(task_name: &str) -> Result<Self> {
-        match task_name {
-            "0" => Ok(Self::ZeroXor),
-            "1" => Ok(Self::OneAdd1),
-            "2" => Ok(Self::TwoAdd16),
-            "2a" => Ok(Self::TwoSub16),
-            "3" => Ok(Self::ThreeMul16),
-            "4" => Ok(Self::FourAdd16Mod),
-            "4a" => Ok(Self::FourASub16Mod),
-            "5" => Ok(Self::FiveMul16Mod),
-            "5a" => Ok(Self::FiveAInv16Mod),
-            "6" => Ok(Self::SixPointAdd),
-            "7" => Ok(Self::SevenPointMul),
-            "8" => Ok(Self::EightSha256),
-            _ => Err(anyhow!("Unknown task number {}", task_name))
+    pub const ALL: [Task; 12] = [
+        Task::ZeroXor,
+        Task::OneAdd1,
+        Task::TwoAdd16,
+        Task::TwoSub16,
+        Task::ThreeMul16,
+        Task::FourAdd16Mod,
+        Task::FourASub16Mod,
+        Task::FiveMul16Mod,
+        Task::FiveAInv16Mod,
+        Task::SixPointAdd,
+        Task::SevenPointMul,
+        Task::EightSha256,
+    ];
+
+    /// Task number as written on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ZeroXor => "0",
+            Self::OneAdd1 => "1",
+            Self::TwoAdd16 => "2",
+            Self::TwoSub16 => "2a",
+            Self::ThreeMul16 => "3",
+            Self::FourAdd16Mod => "4",
+            Self::FourASub16Mod => "4a",
+            Self::FiveMul16Mod => "5",
+            Self::FiveAInv16Mod => "5a",
+            Self::SixPointAdd => "6",
+            Self::SevenPointMul => "7",
+            Self::EightSha256 => "8",
+        }
+    }
+
+    /// What the task computes, as listed by `wpkpp grade --help`
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::ZeroXor => "1 bit XOR",
+            Self::OneAdd1 => "1 bit half adder",
+            Self::TwoAdd16 => "16 bit addition",
+            Self::TwoSub16 => "16 bit subtraction",
+            Self::ThreeMul16 => "16 bit multiplication",
+            Self::FourAdd16Mod => "16 bit addition modulo 2**16 - 17",
+            Self::FourASub16Mod => "16 bit subtraction modulo 2**16 - 17",
+            Self::FiveMul16Mod => "16 bit multiplication modulo 2**16 - 17",
+            Self::FiveAInv16Mod => "16 bit multiplicative inverse modulo 2**16 - 17",
+            Self::SixPointAdd => "Elliptic curve point addition",
+            Self::SevenPointMul => "Elliptic curve point multiplication",
+            Self::EightSha256 => "SHA-256",
+        }
+    }
+
+    /// Modulus the output is reduced by, for tasks with modular arithmetic
+    pub fn modulus(self) -> Option<u64> {
+        match self {
+            Self::TwoSub16 => Some(1 << 16),
+            Self::FourAdd16Mod | Self::FourASub16Mod | Self::FiveMul16Mod | Self::FiveAInv16Mod => {
+                Some(ECC_MOD)
+            }
+            _ => None,
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(task_name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|task| task.name() == task_name)
+            .ok_or_else(|| WpkppError::UnknownTask(task_name.to_string()))
+    }
+
     fn get_tc(self, tc_id: i8, rng: &mut StdRng) -> Result<MemoryLayoutIO> {
         let tc = match self {
             Task::ZeroXor => {
@@ -140,12 +196,12 @@ impl Task {
                     2 => (0, 1),
                     3 => (1, 1),
                     4 => (0x0100, 0x0080),
-                    5 => (ECC_MOD-1, 0),
-                    6 => (ECC_MOD-1, 1),
+                    5 => (ECC_MOD - 1, 0),
+                    6 => (ECC_MOD - 1, 1),
                     7 => (0x0080, 0x0100),
-                    8 => (0, ECC_MOD-1),
-                    9 => (1, ECC_MOD-1),
-                    10 => (ECC_MOD-1, ECC_MOD-1),
+                    8 => (0, ECC_MOD - 1),
+                    9 => (1, ECC_MOD - 1),
+                    10 => (ECC_MOD - 1, ECC_MOD - 1),
                     _ => (rng.gen::<u64>() % ECC_MOD, rng.gen::<u64>() % ECC_MOD),
                 };
                 let out = (in_a + in_b) % ECC_MOD;
@@ -159,12 +215,12 @@ impl Task {
                     2 => (0, 1),
                     3 => (1, 1),
                     4 => (0x0100, 0x0080),
-                    5 => (ECC_MOD-1, 0),
-                    6 => (ECC_MOD-1, 1),
+                    5 => (ECC_MOD - 1, 0),
+                    6 => (ECC_MOD - 1, 1),
                     7 => (0x0080, 0x0100),
-                    8 => (0, ECC_MOD-1),
-                    9 => (1, ECC_MOD-1),
-                    10 => (ECC_MOD-1, ECC_MOD-1),
+                    8 => (0, ECC_MOD - 1),
+                    9 => (1, ECC_MOD - 1),
+                    10 => (ECC_MOD - 1, ECC_MOD - 1),
                     _ => (rng.gen::<u64>() % ECC_MOD, rng.gen::<u64>() % ECC_MOD),
                 };
                 let out = (in_a + ECC_MOD - in_b) % ECC_MOD;
@@ -178,12 +234,12 @@ impl Task {
                     2 => (0, 1),
                     3 => (1, 1),
                     4 => (0x0aa0, 0x0003),
-                    5 => (ECC_MOD-1, 0),
-                    6 => (ECC_MOD-1, 1),
+                    5 => (ECC_MOD - 1, 0),
+                    6 => (ECC_MOD - 1, 1),
                     7 => (0x0003, 0x0aa0),
-                    8 => (0, ECC_MOD-1),
-                    9 => (1, ECC_MOD-1),
-                    10 => (ECC_MOD-1, ECC_MOD-1),
+                    8 => (0, ECC_MOD - 1),
+                    9 => (1, ECC_MOD - 1),
+                    10 => (ECC_MOD - 1, ECC_MOD - 1),
                     _ => (rng.gen::<u64>() % ECC_MOD, rng.gen::<u64>() % ECC_MOD),
                 };
                 let out = (in_a * in_b) % ECC_MOD;
@@ -199,16 +255,16 @@ impl Task {
                     4 => mod_inv(2, ECC_MOD),
                     5 => mod_inv(3, ECC_MOD),
                     6 => mod_inv(4, ECC_MOD),
-                    7 => ECC_MOD-2,
-                    8 => ECC_MOD-1,
-                    _ => 1 + (rng.gen::<u64>() % (ECC_MOD-1)),
+                    7 => ECC_MOD - 2,
+                    8 => ECC_MOD - 1,
+                    _ => 1 + (rng.gen::<u64>() % (ECC_MOD - 1)),
                 };
                 let out = mod_inv(in_a, ECC_MOD);
 
                 (vec![(in_a, 16)], vec![(out, 16)])
             }
             _ => {
-                Err(anyhow!("Task {:?} not implemented", self))?;
+                Err(WpkppError::TaskNotImplemented(format!("{:?}", self)))?;
                 unreachable!();
             }
         };
@@ -216,8 +272,9 @@ impl Task {
         Ok(tc)
     }
 
-    fn pack(spans: MemoryLayout) -> BitVec<u8> {
-        let mut bv = bitvec![u8, Lsb0; 0; spans.iter().map(|x| (*x).1).sum::<u64>() as usize];
+    /// Bits of a layout, each value least significant bit first
+    pub fn pack(spans: MemoryLayout) -> BitVec<u8> {
+        let mut bv = bitvec![u8, Lsb0; 0; spans.iter().map(|x| x.1).sum::<u64>() as usize];
 
         let mut cur: usize = 0;
         for (value, width) in spans.iter() {
@@ -230,16 +287,140 @@ impl Task {
         bv
     }
 
+    /// Seed every testcase is generated from, taken from WPKPP_SEED and namespaced by
+    /// WPKPP_SEED_NAMESPACE when set
+    pub fn seed() -> String {
+        let seed = env::var("WPKPP_SEED").unwrap_or("NOSEED".to_string());
+        match env::var("WPKPP_SEED_NAMESPACE") {
+            Ok(namespace) => Self::namespaced_seed(&seed, &namespace),
+            Err(_) => seed,
+        }
+    }
+
+    /// Seed for one participant of a contest, e.g. namespace "spring/ada", so that everyone
+    /// gets different testcases while each participant's runs stay reproducible; The seed's
+    /// length is included so that no two seed and namespace pairs collide
+    pub fn namespaced_seed(seed: &str, namespace: &str) -> String {
+        format!("{}:{}/{}", seed.len(), seed, namespace)
+    }
+
     pub fn load_tc(self, tc_id: i8) -> Result<(BitVec<u8>, BitVec<u8>)> {
-        let mut rng: StdRng = Seeder::from(format!(
-            "WPKPP/{}/{:?}/{}",
-            env::var("WPKPP_SEED").unwrap_or("NOSEED".to_string()),
-            self,
-            tc_id
-        ))
-        .make_rng();
-
-        let (input_layout, output_layout) = self.get_tc(tc_id, &mut rng)?;
+        self.load_tc_seeded(&Self::seed(), tc_id)
+    }
+
+    /// Like `load_tc`, generating the testcase from `seed` instead of WPKPP_SEED
+    pub fn load_tc_seeded(self, seed: &str, tc_id: i8) -> Result<(BitVec<u8>, BitVec<u8>)> {
+        let (input_layout, output_layout) = self.testcase_layout_seeded(seed, tc_id)?;
         Ok((Self::pack(input_layout), Self::pack(output_layout)))
     }
+
+    /// Input and answer memory for every possible input of tasks small enough to try them all,
+    /// in the same order as their first seeded testcases; None for tasks only graded on seeded
+    /// testcases
+    pub fn exhaustive_testcases(self) -> Option<Vec<(BitVec<u8>, BitVec<u8>)>> {
+        // Layout of the input whose bits, least significant first, are `bits`
+        let (input_bits, layout): (u32, fn(u64) -> MemoryLayoutIO) = match self {
+            Task::ZeroXor => (2, |bits| {
+                let (in_a, in_b) = (bits & 1, bits >> 1);
+                (vec![(in_a, 1), (in_b, 1)], vec![(in_a ^ in_b, 1)])
+            }),
+            Task::OneAdd1 => (2, |bits| {
+                let (in_a, in_b) = (bits & 1, bits >> 1);
+                (vec![(in_a, 1), (in_b, 1)], vec![(in_a + in_b, 2)])
+            }),
+            _ => return None,
+        };
+        let testcases = (0..1u64 << input_bits)
+            .map(|bits| {
+                let (input_layout, output_layout) = layout(bits);
+                (Self::pack(input_layout), Self::pack(output_layout))
+            })
+            .collect();
+        Some(testcases)
+    }
+
+    /// Input and answer memory of all of the task's testcases in grading order, exactly as
+    /// `grade` generates them from `seed`, e.g. `Task::seed()`; Fails up front for tasks which
+    /// are not implemented
+    pub fn testcases(self, seed: &str) -> Result<impl Iterator<Item = (BitVec<u8>, BitVec<u8>)>> {
+        self.load_tc_seeded(seed, 0)?;
+        let seed = seed.to_string();
+        Ok((0..TESTCASES as i8).map(move |tc_id| {
+            // Only unimplemented tasks fail, which was checked above
+            self.load_tc_seeded(&seed, tc_id)
+                .expect("testcase of an implemented task")
+        }))
+    }
+
+    /// Input and output values of a testcase with their widths, before packing into memory
+    pub fn testcase_layout(self, tc_id: i8) -> Result<MemoryLayoutIO> {
+        self.testcase_layout_seeded(&Self::seed(), tc_id)
+    }
+
+    /// String the RNG of one testcase is seeded with, e.g. "WPKPP/NOSEED/FiveMul16Mod/17"
+    pub fn testcase_seed(self, seed: &str, tc_id: i8) -> String {
+        format!("WPKPP/{}/{:?}/{}", seed, self, tc_id)
+    }
+
+    /// Like `testcase_layout`, generating the testcase from `seed` instead of WPKPP_SEED
+    pub fn testcase_layout_seeded(self, seed: &str, tc_id: i8) -> Result<MemoryLayoutIO> {
+        let mut rng: StdRng = Seeder::from(self.testcase_seed(seed, tc_id)).make_rng();
+        self.get_tc(tc_id, &mut rng)
+    }
+
+    /// Addresses `start..end` which the grader reads the answer from; The output is placed
+    /// right after the input, which has the same width in every testcase
+    pub fn output_region(self) -> Result<(u64, u64)> {
+        let (input, output) = self.load_tc(0)?;
+        let start = input.len() as u64;
+        Ok((start, start + output.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testcases(seed: &str) -> Vec<(BitVec<u8>, BitVec<u8>)> {
+        (0..100)
+            .map(|tc_id| Task::OneAdd1.load_tc_seeded(seed, tc_id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn namespaces_get_different_testcases() {
+        let ada = testcases(&Task::namespaced_seed("spring", "ada"));
+        let bob = testcases(&Task::namespaced_seed("spring", "bob"));
+        assert_ne!(ada, bob);
+        assert_ne!(ada, testcases("spring"));
+    }
+
+    #[test]
+    fn a_namespace_gets_the_same_testcases_every_time() {
+        let seed = Task::namespaced_seed("spring", "ada");
+        assert_eq!(testcases(&seed), testcases(&seed));
+        assert_eq!(seed, Task::namespaced_seed("spring", "ada"));
+    }
+
+    #[test]
+    fn moving_text_between_seed_and_namespace_changes_the_seed() {
+        let pairs = [
+            ("a/1", "x"),
+            ("a", "1:x"),
+            ("a", "1/x"),
+            ("a/1:x", ""),
+            ("", "a/1:x"),
+        ];
+        for (i, first) in pairs.iter().enumerate() {
+            for second in &pairs[i + 1..] {
+                assert_ne!(
+                    Task::namespaced_seed(first.0, first.1),
+                    Task::namespaced_seed(second.0, second.1),
+                    "{:?} and {:?}",
+                    first,
+                    second
+                );
+            }
+        }
+    }
 }
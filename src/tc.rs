@@ -0,0 +1,55 @@
+//! `wpkpp tc`: The exact memory of one testcase as the grader will load it, to trace a script
+//! by hand against.
+
+use bitvec::prelude::*;
+
+use crate::{error::Result, task::Task, vectors::bit_string};
+
+pub struct TcOptions {
+    /// Seed to generate the testcase from, instead of WPKPP_SEED
+    pub seed: Option<String>,
+}
+
+/// Memory as one hexadecimal number, with cell 0 as its least significant bit
+fn packed_hex(bits: &BitSlice<u8>) -> String {
+    let digits: String = bits
+        .chunks(4)
+        .rev()
+        .map(|nibble| {
+            let value = nibble
+                .iter()
+                .enumerate()
+                .fold(0, |value, (i, bit)| value | ((*bit as u32) << i));
+            char::from_digit(value, 16).unwrap()
+        })
+        .collect();
+    format!("0x{}", digits)
+}
+
+/// Prints one span of memory in binary and hex
+fn print_memory(name: &str, bits: &BitSlice<u8>, start: usize) {
+    println!(
+        "{}: {} bit(s), cells {}-{}",
+        name,
+        bits.len(),
+        start,
+        start + bits.len() - 1
+    );
+    println!("  binary {}  (cell {} first)", bit_string(bits), start);
+    println!(
+        "  hex    {}  (cell {} least significant)",
+        packed_hex(bits),
+        start
+    );
+}
+
+/// Prints the input and expected output of one testcase
+pub fn do_tc(task: Task, tc_id: i8, options: &TcOptions) -> Result<()> {
+    let seed = options.seed.clone().unwrap_or_else(Task::seed);
+    let (input, answer) = task.load_tc_seeded(&seed, tc_id)?;
+
+    println!("Task {} testcase {} with seed {}", task.name(), tc_id, seed);
+    print_memory("Input", &input, 0);
+    print_memory("Expected output", &answer, input.len());
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! Helpers shared by unit tests checking that rewritten programs still run like the original
+
+use rand::{rngs::StdRng, Rng};
+use rand_seeder::Seeder;
+
+use crate::vm::{Instructions, Vm, VmUsize};
+
+/// Random values for the `window` cells either side of address 0, generated from `seed`
+pub fn random_window(seed: &str, window: i64) -> Vec<bool> {
+    let mut rng: StdRng = Seeder::from(seed).make_rng();
+    (0..2 * window).map(|_| rng.gen()).collect()
+}
+
+/// Final window, pointer, register and runtime of running `program` with `input` in the cells
+/// either side of address 0, as made by `random_window`; Clears the window again after, as
+/// resetting all of memory per run is far too slow
+pub fn run_window(
+    vm: &mut Vm,
+    program: &Instructions,
+    input: &[bool],
+) -> (Vec<bool>, VmUsize, bool, i64) {
+    let window = input.len() as i64 / 2;
+    let addresses = || (-window..window).map(|pos| (pos as VmUsize) as usize);
+    vm.program = program.clone();
+    vm.intsruction_pointer = 0;
+    vm.halted = program.is_empty();
+    vm.runtime = 0;
+    vm.register = false;
+    vm.memory_pointer.reset();
+    for (address, bit) in addresses().zip(input) {
+        vm.memory.set(address, *bit);
+    }
+
+    let runtime = vm.run().runtime;
+    let cells = addresses().map(|address| vm.memory[address]).collect();
+    for address in addresses() {
+        vm.memory.set(address, false);
+    }
+    (cells, vm.memory_pointer.ptr, vm.register, runtime)
+}
@@ -0,0 +1,330 @@
+//! `wpkpp tokens`: A lexer for .wpk / .wpkm scripts, for editor plugins and web viewers to
+//! highlight scripts with without reimplementing the grammar.
+//!
+//! Tokens carry byte ranges into the source and cover everything but whitespace. The lexer
+//! never fails: Text the parser would reject comes out as `Unknown` tokens.
+
+use miniserde::{json, Serialize};
+
+use crate::{
+    error::{Result, WpkppError},
+    meta::META_STR,
+    parse::{
+        read_script_source, ScriptFormat, BLOCK_CLOSE_M_STR, BLOCK_CLOSE_STR, BLOCK_OPEN_M_STR,
+        BLOCK_OPEN_STR, BOM, CDEC_M_STR, CDEC_STR, COMMENT_STR, COMMENT_STR_ALT, DEFINE_STR,
+        INC_M_STR, INC_STR, INV_M_STR, INV_M_STR_ALT, INV_STR, LABEL_END, LABEL_START, LOAD_M_STR,
+        LOAD_M_STR_ALT, LOAD_STR, REPEAT_STR,
+    },
+    seal::CHECKSUM_STR,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Inc,
+    Cdec,
+    Load,
+    Inv,
+    /// A repeat count, or the value of a DEFINE
+    Number,
+    /// A DEFINE constant, where it is defined or used
+    Name,
+    Repeat,
+    Define,
+    BlockOpen,
+    BlockClose,
+    /// A label with its `@` and `:`
+    Label,
+    Comment,
+    /// A `;!` metadata comment
+    Meta,
+    /// A `;#sha256=` checksum footer
+    Checksum,
+    Unknown,
+}
+
+impl TokenKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Inc => "inc",
+            Self::Cdec => "cdec",
+            Self::Load => "load",
+            Self::Inv => "inv",
+            Self::Number => "number",
+            Self::Name => "name",
+            Self::Repeat => "repeat",
+            Self::Define => "define",
+            Self::BlockOpen => "block_open",
+            Self::BlockClose => "block_close",
+            Self::Label => "label",
+            Self::Comment => "comment",
+            Self::Meta => "meta",
+            Self::Checksum => "checksum",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// A token at bytes `start..end` of the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A token as printed by `wpkpp tokens --json`; Numbers are strings, as in `grade --json`
+#[derive(Serialize, Debug)]
+pub struct TokenRecord {
+    pub kind: String,
+    pub start: String,
+    pub end: String,
+    pub text: String,
+}
+
+pub struct TokensOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Print a JSON array instead of a line per token
+    pub json: bool,
+}
+
+/// A comment, metadata comment or checksum footer by how it starts
+fn comment_kind(comment: &str) -> TokenKind {
+    if comment.starts_with(CHECKSUM_STR) {
+        TokenKind::Checksum
+    } else if comment.starts_with(META_STR) {
+        TokenKind::Meta
+    } else {
+        TokenKind::Comment
+    }
+}
+
+/// Tokens of a .wpkm script
+pub fn tokenize_wpkm(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    let end_of = |start: usize, c: char| start + c.len_utf8();
+
+    while let Some((start, c)) = chars.next() {
+        let mut end = end_of(start, c);
+        let kind = match c {
+            BOM if start == 0 => continue,
+            c if c.is_whitespace() => continue,
+            COMMENT_STR | COMMENT_STR_ALT => {
+                while let Some((i, c)) = chars.next_if(|(_, c)| *c != '\n') {
+                    end = end_of(i, c);
+                }
+                comment_kind(source[start..end].trim_end())
+            }
+            LABEL_START => {
+                let mut closed = false;
+                while let Some((i, c)) = chars.next_if(|_| !closed) {
+                    end = end_of(i, c);
+                    closed = c == LABEL_END;
+                }
+                match closed {
+                    true => TokenKind::Label,
+                    false => TokenKind::Unknown,
+                }
+            }
+            '0'..='9' => {
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    end = end_of(i, c);
+                }
+                TokenKind::Number
+            }
+            INC_M_STR => TokenKind::Inc,
+            CDEC_M_STR => TokenKind::Cdec,
+            LOAD_M_STR | LOAD_M_STR_ALT => TokenKind::Load,
+            INV_M_STR | INV_M_STR_ALT => TokenKind::Inv,
+            BLOCK_OPEN_M_STR => TokenKind::BlockOpen,
+            BLOCK_CLOSE_M_STR => TokenKind::BlockClose,
+            _ => TokenKind::Unknown,
+        };
+        tokens.push(Token { kind, start, end });
+    }
+    tokens
+}
+
+/// A whitespace separated word of a .wpk line
+fn wpk_word_kind(word: &str) -> TokenKind {
+    match word {
+        INC_STR => TokenKind::Inc,
+        CDEC_STR => TokenKind::Cdec,
+        LOAD_STR => TokenKind::Load,
+        INV_STR => TokenKind::Inv,
+        REPEAT_STR => TokenKind::Repeat,
+        DEFINE_STR => TokenKind::Define,
+        BLOCK_OPEN_STR => TokenKind::BlockOpen,
+        BLOCK_CLOSE_STR => TokenKind::BlockClose,
+        _ if word.starts_with(LABEL_START) && word.ends_with(LABEL_END) && word.len() > 1 => {
+            TokenKind::Label
+        }
+        _ if word.chars().all(|c| c.is_ascii_digit()) => TokenKind::Number,
+        _ if word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            TokenKind::Name
+        }
+        _ => TokenKind::Unknown,
+    }
+}
+
+/// Tokens of a .wpk script
+pub fn tokenize_wpk(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut offset = match source.starts_with(BOM) {
+        true => BOM.len_utf8(),
+        false => 0,
+    };
+    for line in source[offset..].split_inclusive('\n') {
+        let (code, comment) = match line.find([COMMENT_STR, COMMENT_STR_ALT]) {
+            Some(idx) => (&line[..idx], Some((idx, line[idx..].trim_end()))),
+            None => (line, None),
+        };
+        for word in code.split_whitespace() {
+            let start = offset + (word.as_ptr() as usize - line.as_ptr() as usize);
+            tokens.push(Token {
+                kind: wpk_word_kind(word),
+                start,
+                end: start + word.len(),
+            });
+        }
+        if let Some((idx, comment)) = comment {
+            tokens.push(Token {
+                kind: comment_kind(comment),
+                start: offset + idx,
+                end: offset + idx + comment.len(),
+            });
+        }
+        offset += line.len();
+    }
+    tokens
+}
+
+/// Tokens of a script of either text format
+pub fn tokenize(source: &str, format: ScriptFormat) -> Result<Vec<Token>> {
+    match format {
+        ScriptFormat::Wpk => Ok(tokenize_wpk(source)),
+        ScriptFormat::Wpkm => Ok(tokenize_wpkm(source)),
+        ScriptFormat::Wpkb => Err(WpkppError::InvalidArgument(
+            ".wpkb scripts have no text to tokenize".to_string(),
+        )),
+    }
+}
+
+/// Prints every token of a script with its byte range
+pub fn do_tokens(path: &str, options: &TokensOptions) -> Result<()> {
+    let (format, bytes) = read_script_source(path, options.format)?;
+    let source = std::str::from_utf8(&bytes).map_err(|e| {
+        WpkppError::InvalidArgument(format!(
+            "{} is not valid UTF-8 after byte {}",
+            path,
+            e.valid_up_to()
+        ))
+    })?;
+    let tokens = tokenize(source, format)?;
+
+    if options.json {
+        let records: Vec<TokenRecord> = tokens
+            .iter()
+            .map(|token| TokenRecord {
+                kind: token.kind.name().to_string(),
+                start: token.start.to_string(),
+                end: token.end.to_string(),
+                text: source[token.start..token.end].to_string(),
+            })
+            .collect();
+        println!("{}", json::to_string(&records));
+        return Ok(());
+    }
+    for token in tokens {
+        println!(
+            "{}..{}\t{}\t{}",
+            token.start,
+            token.end,
+            token.kind.name(),
+            source[token.start..token.end].escape_debug()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each token's kind and text
+    fn lex(source: &str, format: ScriptFormat) -> Vec<(TokenKind, &str)> {
+        tokenize(source, format)
+            .unwrap()
+            .iter()
+            .map(|token| (token.kind, &source[token.start..token.end]))
+            .collect()
+    }
+
+    #[test]
+    fn wpk_tokens() {
+        use TokenKind::*;
+        let source = "\u{feff}DEFINE N 3\n@top: REPEAT N {\n  INC 2 ; up\r\n}\nLOAD\tINV # x\n;!name=a\nFOO 1x\n";
+        assert_eq!(
+            lex(source, ScriptFormat::Wpk),
+            [
+                (Define, "DEFINE"),
+                (Name, "N"),
+                (Number, "3"),
+                (Label, "@top:"),
+                (Repeat, "REPEAT"),
+                (Name, "N"),
+                (BlockOpen, "{"),
+                (Inc, "INC"),
+                (Number, "2"),
+                (Comment, "; up"),
+                (BlockClose, "}"),
+                (Load, "LOAD"),
+                (Inv, "INV"),
+                (Comment, "# x"),
+                (Meta, ";!name=a"),
+                (Name, "FOO"),
+                (Unknown, "1x"),
+            ]
+        );
+        let first = tokenize_wpk(source)[0];
+        assert_eq!((first.start, first.end), (3, 9));
+    }
+
+    #[test]
+    fn wpkm_tokens() {
+        use TokenKind::*;
+        let source = "@a:12>v<\n[^?] ;#sha256=00\n@open x";
+        assert_eq!(
+            lex(source, ScriptFormat::Wpkm),
+            [
+                (Label, "@a:"),
+                (Number, "12"),
+                (Inc, ">"),
+                (Load, "v"),
+                (Cdec, "<"),
+                (BlockOpen, "["),
+                (Inv, "^"),
+                (Load, "?"),
+                (BlockClose, "]"),
+                (Checksum, ";#sha256=00"),
+                (Unknown, "@open x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_cover_multibyte_text() {
+        let source = "; café\n!é";
+        let tokens = tokenize_wpkm(source);
+        assert_eq!(tokens.len(), 3);
+        assert_eq!((tokens[0].start, tokens[0].end), (0, 7));
+        assert_eq!(
+            (tokens[2].kind, tokens[2].start, tokens[2].end),
+            (TokenKind::Unknown, 9, 11)
+        );
+        assert!(tokenize("", ScriptFormat::Wpkb).is_err());
+    }
+}
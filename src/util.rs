@@ -1,19 +1,54 @@
+use miniserde::{Deserialize, Serialize};
 use std::time;
 
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstructionCount {
+    pub inc: String,
+    pub cdec: String,
+    pub load: String,
+    pub inv: String,
+}
+
+impl InstructionCount {
+    pub fn new(opcounts: (u64, u64, u64, u64)) -> Self {
+        Self {
+            inc: opcounts.0.to_string(),
+            cdec: opcounts.1.to_string(),
+            load: opcounts.2.to_string(),
+            inv: opcounts.3.to_string(),
+        }
+    }
+}
+
 pub struct ResetableTimer {
     time: Option<time::Instant>,
 }
 
+impl Default for ResetableTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ResetableTimer {
+    /// wasm32-unknown-unknown has no clock (Instant::now panics), so timings there are all zero
     pub fn new() -> Self {
         Self {
-            time: Some(time::Instant::now()),
+            time: match cfg!(target_arch = "wasm32") {
+                true => None,
+                false => Some(time::Instant::now()),
+            },
         }
     }
 
     pub fn seconds_since(&mut self) -> f64 {
+        let last = match self.time {
+            Some(last) => last,
+            None => return 0.0,
+        };
         let current = time::Instant::now();
-        let since = current.duration_since(self.time.unwrap());
+        let since = current.duration_since(last);
         self.time.replace(current);
 
         since.as_secs_f64()
@@ -38,3 +73,12 @@ pub fn mod_inv(a: u64, module: u64) -> u64 {
     }
     xy.0 as u64
 }
+
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ (*byte as u64)).wrapping_mul(FNV_PRIME)
+    })
+}
@@ -0,0 +1,173 @@
+//! `wpkpp vectors`: Test vectors for other woodpecker implementations to check themselves
+//! against. Every testcase of every implemented task, and a few small programs pinning down
+//! the VM's semantics, with exactly what wpkpp makes of them.
+//!
+//! Memory is written as strings of `0` / `1`, cell 0 first. Numbers are strings, as in
+//! `grade --json`.
+
+use bitvec::prelude::*;
+use miniserde::{json, Deserialize, Serialize};
+use std::fs;
+
+use crate::{
+    error::Result,
+    parse::parse_wpkm_bytes,
+    task::Task,
+    vm::{Instructions, Vm},
+};
+
+/// Bumped whenever the vectors change shape
+pub const VECTORS_VERSION: &str = "1";
+
+/// Small programs and the memory they start with, each pinning down one rule of the VM
+pub(crate) const VM_PROGRAMS: [(&str, &str, &str); 9] = [
+    ("inv", "!", ""),
+    ("inc-then-inv", "3>!", ""),
+    ("load-set-cdec-moves", "!?<!", ""),
+    ("load-clear-cdec-stays", "?<!", ""),
+    ("cdec-steps-when-not-taken", "?5<", ""),
+    ("load-reads-input", ">?<!", "01"),
+    ("load-reads-input-clear", ">?<!", "00"),
+    ("inc-wraps-around", "4294967295>!>!", ""),
+    ("inv-twice", "!!", "1"),
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskTestcase {
+    pub tc_id: String,
+    pub input: String,
+    /// Memory expected right after the input once the script halts
+    pub output: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskVectors {
+    pub task: String,
+    pub testcases: Vec<TaskTestcase>,
+}
+
+/// A program run from memory holding `input`, with everything else clear
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VmVector {
+    pub name: String,
+    /// The program in .wpkm syntax
+    pub program: String,
+    pub input: String,
+    /// Addresses of every set cell once the program halts
+    pub ones: Vec<String>,
+    /// Address the pointer ends at, wrapped into memory
+    pub ptr: String,
+    pub register: bool,
+    pub runtime: String,
+    /// Cells spanned by the pointer
+    pub memory: String,
+}
+
+/// Contents of a file written by `wpkpp vectors`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Vectors {
+    pub version: String,
+    /// Version of wpkpp which made the vectors
+    pub wpkpp: String,
+    pub seed: String,
+    pub tasks: Vec<TaskVectors>,
+    pub vm: Vec<VmVector>,
+}
+
+pub struct VectorsOptions {
+    /// Seed to generate testcases from, instead of WPKPP_SEED
+    pub seed: Option<String>,
+    /// Write the vectors here instead of stdout
+    pub output_path: Option<String>,
+}
+
+/// Memory as `0` / `1`, cell 0 first
+pub(crate) fn bit_string(bits: &BitSlice<u8>) -> String {
+    bits.iter()
+        .map(|bit| if *bit { '1' } else { '0' })
+        .collect()
+}
+
+/// Every testcase of every implemented task, generated from `seed`
+pub fn task_vectors(seed: &str) -> Vec<TaskVectors> {
+    Task::ALL
+        .iter()
+        .filter_map(|task| {
+            let testcases = task.testcases(seed).ok()?;
+            Some(TaskVectors {
+                task: task.name().to_string(),
+                testcases: testcases
+                    .enumerate()
+                    .map(|(tc_id, (input, output))| TaskTestcase {
+                        tc_id: tc_id.to_string(),
+                        input: bit_string(&input),
+                        output: bit_string(&output),
+                    })
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Runs each of the VM semantics programs
+pub fn vm_vectors() -> Result<Vec<VmVector>> {
+    let mut vm = Vm::new(Instructions::new());
+    let mut vectors = vec![];
+    for (name, program, input) in VM_PROGRAMS {
+        vm.program = parse_wpkm_bytes(program.as_bytes())?;
+        vm.reset();
+        for (cell, bit) in input.chars().enumerate() {
+            vm.memory.set(cell, bit == '1');
+        }
+        let result = vm.run();
+        vectors.push(VmVector {
+            name: name.to_string(),
+            program: program.to_string(),
+            input: input.to_string(),
+            ones: vm.memory.iter_ones().map(|cell| cell.to_string()).collect(),
+            ptr: vm.memory_pointer.ptr.to_string(),
+            register: vm.register,
+            runtime: result.runtime.to_string(),
+            memory: result.memory.to_string(),
+        });
+    }
+    Ok(vectors)
+}
+
+impl Vectors {
+    pub fn new(seed: &str) -> Result<Self> {
+        Ok(Self {
+            version: VECTORS_VERSION.to_string(),
+            wpkpp: env!("CARGO_PKG_VERSION").to_string(),
+            seed: seed.to_string(),
+            tasks: task_vectors(seed),
+            vm: vm_vectors()?,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        json::to_string(self)
+    }
+}
+
+/// Writes the test vectors as JSON
+pub fn do_vectors(options: &VectorsOptions) -> Result<()> {
+    let seed = options.seed.clone().unwrap_or_else(Task::seed);
+    let vectors = Vectors::new(&seed)?;
+    match options.output_path.as_ref() {
+        Some(output_path) => {
+            fs::write(output_path, vectors.to_json())?;
+            let testcases: usize = vectors.tasks.iter().map(|task| task.testcases.len()).sum();
+            println!(
+                "Wrote {} testcase(s) of {} task(s) and {} VM vector(s) with seed {} to {}",
+                testcases,
+                vectors.tasks.len(),
+                vectors.vm.len(),
+                seed,
+                output_path
+            );
+        }
+        None => println!("{}", vectors.to_json()),
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use bitvec::prelude::*;
+use rand::{rngs::StdRng, Rng};
+use rand_seeder::Seeder;
+
+use crate::{
+    error::{Result, WpkppError},
+    task::Task,
+    vm::{cell_index, Instructions, Vm, VmUsize},
+};
+
+const RANDOM_TRIALS: u64 = 16;
+/// Bits from address 0 filled with random values for each random trial
+const RANDOM_WINDOW: usize = 4096;
+
+/// Memory states to check equivalence on
+#[derive(Debug, Copy, Clone)]
+pub enum VerifyTarget {
+    Random,
+    Task(Task),
+}
+
+pub struct VerifyReport {
+    pub trials: usize,
+    /// Trials where the memory metric differed; Merging INC / CDEC may change how far the
+    /// pointer strays without changing the result
+    pub memory_differs: usize,
+}
+
+/// Final state of one run
+struct Outcome {
+    runtime: i64,
+    memory: i64,
+    ptr: VmUsize,
+    register: bool,
+    /// Lowest touched address, as an offset from 0
+    lb: i64,
+    /// Touched memory from `lb` onwards
+    cells: BitVec<u8>,
+}
+
+impl Outcome {
+    /// Value of a cell at the end of the run; Untouched cells still hold their input value
+    fn cell(&self, input: &BitSlice<u8>, pos: i64) -> bool {
+        match pos - self.lb {
+            offset if offset >= 0 && (offset as usize) < self.cells.len() => {
+                self.cells[offset as usize]
+            }
+            _ => input_cell(input, pos),
+        }
+    }
+}
+
+fn input_cell(input: &BitSlice<u8>, pos: i64) -> bool {
+    let address = (pos as VmUsize) as usize;
+    address < input.len() && input[address]
+}
+
+fn trial_inputs(target: VerifyTarget) -> Result<Vec<BitVec<u8>>> {
+    match target {
+        VerifyTarget::Task(task) => Ok(task
+            .testcases(&Task::seed())?
+            .map(|(input, _)| input)
+            .collect()),
+        VerifyTarget::Random => Ok((0..RANDOM_TRIALS)
+            .map(|trial| {
+                let mut rng: StdRng = Seeder::from(format!("WPKPP/verify/{}", trial)).make_rng();
+                (0..RANDOM_WINDOW).map(|_| rng.gen::<bool>()).collect()
+            })
+            .collect()),
+    }
+}
+
+fn run_trial(vm: &mut Vm, input: &BitSlice<u8>) -> Outcome {
+    vm.reset();
+    vm.memory[0..input.len()].copy_from_bitslice(input);
+    let run_stats = vm.run();
+
+    let lb = vm.memory_pointer.ptr_lb;
+    let cells = (0..run_stats.memory)
+        .map(|offset| vm.memory[cell_index((lb + offset) as VmUsize)])
+        .collect();
+    Outcome {
+        runtime: run_stats.runtime,
+        memory: run_stats.memory,
+        ptr: vm.memory_pointer.ptr,
+        register: vm.register,
+        lb,
+        cells,
+    }
+}
+
+/// Runs both programs from the same memory states, erroring on the first trial where their
+/// final memory, pointer, register or runtime differ. With `allow_faster`, as for optimized
+/// scripts, the compressed program may take fewer steps. With `output_only`, as for scripts
+/// trimmed to a task's output, only the cells in `start..end` are compared.
+pub fn verify_equivalence(
+    original: &Instructions,
+    compressed: &Instructions,
+    target: VerifyTarget,
+    allow_faster: bool,
+    output_only: Option<(u64, u64)>,
+) -> Result<VerifyReport> {
+    let inputs = trial_inputs(target)?;
+    let mut vm = Vm::new(original.clone());
+    let mut other_program = compressed.clone();
+    let mut memory_differs = 0;
+
+    for (trial, input) in inputs.iter().enumerate() {
+        let expected = run_trial(&mut vm, input);
+        std::mem::swap(&mut vm.program, &mut other_program);
+        let actual = run_trial(&mut vm, input);
+        std::mem::swap(&mut vm.program, &mut other_program);
+
+        let runtime_ok = match allow_faster {
+            true => actual.runtime <= expected.runtime,
+            false => actual.runtime == expected.runtime,
+        };
+        if !runtime_ok {
+            return Err(WpkppError::VerifyFailed(format!(
+                "Runtime differs on trial {}: {} before compression, {} after",
+                trial, expected.runtime, actual.runtime
+            )));
+        }
+        let (lb, ub) = match output_only {
+            Some((start, end)) => (start as i64, end as i64),
+            None => {
+                if expected.ptr != actual.ptr || expected.register != actual.register {
+                    return Err(WpkppError::VerifyFailed(format!(
+                        "Final pointer or register differs on trial {}",
+                        trial
+                    )));
+                }
+                (
+                    expected.lb.min(actual.lb),
+                    (expected.lb + expected.memory).max(actual.lb + actual.memory),
+                )
+            }
+        };
+        let differs_at = |pos: &i64| expected.cell(input, *pos) != actual.cell(input, *pos);
+        if let Some(pos) = (lb..ub).find(differs_at) {
+            return Err(WpkppError::VerifyFailed(format!(
+                "Memory differs on trial {} at address {}",
+                trial, pos as VmUsize
+            )));
+        }
+        if expected.memory != actual.memory {
+            memory_differs += 1;
+        }
+    }
+
+    Ok(VerifyReport {
+        trials: inputs.len(),
+        memory_differs,
+    })
+}
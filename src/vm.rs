@@ -1,13 +1,37 @@
 use bitvec::prelude::*;
 use std::cmp::{max, min};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::ops::{Deref, DerefMut, RangeBounds};
+use tracing::debug;
+
+use crate::{
+    peephole::push_builtin,
+    util::{fnv1a, FNV_OFFSET},
+};
 
 pub type VmUsize = u32;
-pub const MEM_SIZE: usize = 1 << 32;
+pub const MEM_SIZE: u64 = 1 << 32;
 
 // pub type VmUsize = u16;
-// pub const MEM_SIZE: usize = 1 << 16;
+// pub const MEM_SIZE: u64 = 1 << 16;
+
+/// Bits of memory actually allocated. 32-bit targets such as wasm32 cannot hold 2^32 bits in one
+/// BitVec, so there addresses wrap around a smaller memory instead, which only matters for
+/// programs touching cells 2^28 or more apart; The wasm wrappers reject such runs.
+#[cfg(target_pointer_width = "64")]
+pub const MEMORY_BITS: usize = 1 << 32;
+#[cfg(not(target_pointer_width = "64"))]
+pub const MEMORY_BITS: usize = 1 << 28;
+
+/// Index into `Vm::memory` of an address
+#[inline]
+pub fn cell_index(ptr: VmUsize) -> usize {
+    ptr as usize % MEMORY_BITS
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     Null,
     Inc(VmUsize),
@@ -16,12 +40,60 @@ pub enum Instruction {
     Inv,
 }
 
-pub type Instructions = Vec<Instruction>;
-pub trait WpkOpcount {
-    fn opcount(&self) -> (u64, u64, u64, u64);
-}
-impl WpkOpcount for Instructions {
-    fn opcount(&self) -> (u64, u64, u64, u64) {
+/// A woodpecker program. Derefs to a slice of instructions for reading; Writes go through
+/// `push` or `push_compressed`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Instructions(Vec<Instruction>);
+
+impl Instructions {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    pub fn into_vec(self) -> Vec<Instruction> {
+        self.0
+    }
+
+    /// Pushes an instruction as is, without merging
+    pub fn push(&mut self, instruction: Instruction) {
+        self.0.push(instruction);
+    }
+
+    pub fn pop(&mut self) -> Option<Instruction> {
+        self.0.pop()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.0.append(&mut other.0);
+    }
+
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> std::vec::Drain<'_, Instruction> {
+        self.0.drain(range)
+    }
+
+    /// Pushes an instruction, merging it into earlier ones with the built-in peephole rules.
+    /// Instructions before `floor` are never merged into, and counts are only merged while they
+    /// fit, so the step count stays exact. Returns the index the instruction ended up at, or None
+    /// for Null.
+    pub fn push_compressed(&mut self, new_instruction: Instruction, floor: usize) -> Option<usize> {
+        push_builtin(self, new_instruction, floor)
+    }
+
+    /// Steps spent on INC / CDEC / LOAD / INV when every repeat is written out
+    pub fn opcount(&self) -> (u64, u64, u64, u64) {
         let mut inc_count: u64 = 0;
         let mut cdec_count: u64 = 0;
         let mut load_count: u64 = 0;
@@ -47,34 +119,143 @@ impl WpkOpcount for Instructions {
             }
         }
 
-        return (inc_count, cdec_count, load_count, inv_count);
+        (inc_count, cdec_count, load_count, inv_count)
+    }
+
+    /// Instructions in the program when every repeat is written out
+    pub fn total_steps(&self) -> u64 {
+        let (inc, cdec, load, inv) = self.opcount();
+        inc + cdec + load + inv
+    }
+
+    /// The program as .wpk lines, leaving out zero length INC / CDEC
+    pub fn to_wpk(&self) -> String {
+        self.written()
+            .map(|instruction| format!("{}\n", instruction))
+            .collect()
+    }
+
+    /// The program as a single .wpkm line, leaving out zero length INC / CDEC
+    pub fn to_wpkm(&self) -> String {
+        let mut wpkm: String = self
+            .written()
+            .map(|instruction| instruction.wpkm().to_string())
+            .collect();
+        wpkm.push('\n');
+        wpkm
+    }
+
+    fn written(&self) -> impl Iterator<Item = &Instruction> {
+        self.iter().filter(|instruction| {
+            !matches!(instruction, Instruction::Inc(0) | Instruction::Cdec(0))
+        })
+    }
+}
+
+impl Deref for Instructions {
+    type Target = [Instruction];
+
+    fn deref(&self) -> &[Instruction] {
+        &self.0
+    }
+}
+
+impl DerefMut for Instructions {
+    fn deref_mut(&mut self) -> &mut [Instruction] {
+        &mut self.0
+    }
+}
+
+impl From<Vec<Instruction>> for Instructions {
+    fn from(instructions: Vec<Instruction>) -> Self {
+        Self(instructions)
+    }
+}
+
+impl FromIterator<Instruction> for Instructions {
+    fn from_iter<I: IntoIterator<Item = Instruction>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Instruction> for Instructions {
+    fn extend<I: IntoIterator<Item = Instruction>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl IntoIterator for Instructions {
+    type Item = Instruction;
+    type IntoIter = std::vec::IntoIter<Instruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Instructions {
+    type Item = &'a Instruction;
+    type IntoIter = std::slice::Iter<'a, Instruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
     }
 }
 
 impl Instruction {
+    /// Displays the instruction in .wpkm form, e.g. "3>", where `to_string` gives "INC 3"
+    pub fn wpkm(self) -> WpkmInstruction {
+        WpkmInstruction(self)
+    }
+
+    #[deprecated(note = "use `to_string`, which leaves out the line ending")]
     pub fn to_wpk_string(&self) -> String {
         match self {
             Self::Null => unreachable!(),
             Self::Inc(0) | Self::Cdec(0) => "".to_string(),
-            Self::Inc(1) => "INC\n".to_string(),
-            Self::Inc(x) => format!("INC {}\n", x),
-            Self::Cdec(1) => "CDEC\n".to_string(),
-            Self::Cdec(x) => format!("CDEC {}\n", x),
-            Self::Load => "LOAD\n".to_string(),
-            Self::Inv => "INV\n".to_string(),
+            _ => format!("{}\n", self),
         }
     }
 
+    #[deprecated(note = "use `wpkm().to_string()`")]
     pub fn to_wpkm_string(&self) -> String {
         match self {
             Self::Null => unreachable!(),
             Self::Inc(0) | Self::Cdec(0) => "".to_string(),
-            Self::Inc(1) => ">".to_string(),
-            Self::Inc(x) => format!("{}>", x),
-            Self::Cdec(1) => "<".to_string(),
-            Self::Cdec(x) => format!("{}<", x),
-            Self::Load => "?".to_string(),
-            Self::Inv => "!".to_string(),
+            _ => self.wpkm().to_string(),
+        }
+    }
+}
+
+/// Writes the instruction as a .wpk line without its line ending; Null writes nothing
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => Ok(()),
+            Self::Inc(1) => write!(f, "INC"),
+            Self::Inc(x) => write!(f, "INC {}", x),
+            Self::Cdec(1) => write!(f, "CDEC"),
+            Self::Cdec(x) => write!(f, "CDEC {}", x),
+            Self::Load => write!(f, "LOAD"),
+            Self::Inv => write!(f, "INV"),
+        }
+    }
+}
+
+/// Displays an instruction in .wpkm form; See `Instruction::wpkm`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WpkmInstruction(pub Instruction);
+
+impl fmt::Display for WpkmInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Instruction::Null => Ok(()),
+            Instruction::Inc(1) => write!(f, ">"),
+            Instruction::Inc(x) => write!(f, "{}>", x),
+            Instruction::Cdec(1) => write!(f, "<"),
+            Instruction::Cdec(x) => write!(f, "{}<", x),
+            Instruction::Load => write!(f, "?"),
+            Instruction::Inv => write!(f, "!"),
         }
     }
 }
@@ -86,6 +267,12 @@ pub struct MemoryPointer {
     pub ptr_ub: i64,
 }
 
+impl Default for MemoryPointer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MemoryPointer {
     pub fn new() -> Self {
         Self {
@@ -120,6 +307,48 @@ impl MemoryPointer {
     }
 }
 
+pub struct Coverage {
+    pub register_true: Vec<u64>,
+    pub register_false: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new(proglen: usize) -> Self {
+        Self {
+            register_true: vec![0; proglen],
+            register_false: vec![0; proglen],
+        }
+    }
+}
+
+/// How a run's memory usage is measured
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryMetric {
+    /// Cells from the lowest to the highest address the pointer reached
+    Span,
+    /// Distinct cells read by LOAD or written by INV, which does not punish sparse layouts
+    Touched,
+}
+
+impl MemoryMetric {
+    pub const NAMES: [&'static str; 2] = ["span", "touched"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "span" => Some(Self::Span),
+            "touched" => Some(Self::Touched),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Span => "span",
+            Self::Touched => "touched",
+        }
+    }
+}
+
 pub struct Vm {
     pub memory: BitVec<u8>,
     pub memory_pointer: MemoryPointer,
@@ -130,8 +359,13 @@ pub struct Vm {
     pub halted: bool,
 
     pub register: bool,
+
+    pub coverage: Option<Coverage>,
+    /// Cells read or written so far, when memory is measured in touched cells
+    pub touched: Option<HashSet<VmUsize>>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RunResult {
     pub runtime: i64,
     pub memory: i64,
@@ -140,8 +374,13 @@ pub struct RunResult {
 impl Vm {
     pub fn new(program: Instructions) -> Self {
         let proglen = program.len();
+        debug!(
+            instructions = proglen,
+            memory_bits = MEMORY_BITS,
+            "Setting up VM"
+        );
         Self {
-            memory: bitvec![u8, Lsb0; 0; MEM_SIZE],
+            memory: bitvec![u8, Lsb0; 0; MEMORY_BITS],
             memory_pointer: MemoryPointer::new(),
 
             program,
@@ -150,56 +389,205 @@ impl Vm {
             runtime: 0,
 
             register: false,
+
+            coverage: None,
+            touched: None,
+        }
+    }
+
+    pub fn enable_coverage(&mut self) {
+        debug!("Tracking CDEC coverage");
+        self.coverage = Some(Coverage::new(self.program.len()));
+    }
+
+    /// Measures memory usage with `metric` from now on
+    pub fn set_memory_metric(&mut self, metric: MemoryMetric) {
+        debug!(metric = metric.name(), "Measuring memory usage");
+        self.touched = match metric {
+            MemoryMetric::Span => None,
+            MemoryMetric::Touched => Some(HashSet::new()),
+        };
+    }
+
+    pub fn memory_metric(&self) -> MemoryMetric {
+        match self.touched {
+            Some(_) => MemoryMetric::Touched,
+            None => MemoryMetric::Span,
         }
     }
 
     pub fn reset(&mut self) {
-        self.memory.fill(false);
+        // A plain memset, which bitvec's own fill is far slower than in debug builds
+        self.memory.as_raw_mut_slice().fill(0);
+        if let Some(touched) = self.touched.as_mut() {
+            touched.clear();
+        }
         self.memory_pointer.reset();
         self.intsruction_pointer = 0;
-        self.halted = self.program.len() == 0;
+        self.halted = self.program.is_empty();
         self.runtime = 0;
         self.register = false;
     }
 
-    pub fn run(&mut self) -> RunResult {
-        while !self.halted {
-            let current_memory = self.memory[self.memory_pointer.ptr as usize];
+    pub fn step(&mut self) {
+        let current_memory = self.memory[cell_index(self.memory_pointer.ptr)];
 
-            match self.program[self.intsruction_pointer] {
-                Instruction::Inc(x) => {
-                    self.memory_pointer.inc(x);
-                    self.runtime += x as i64;
-                }
-                Instruction::Cdec(x) => {
-                    if self.register {
-                        self.memory_pointer.dec(x);
-                    }
-                    self.runtime += x as i64;
+        if let Some(coverage) = self.coverage.as_mut() {
+            match self.register {
+                true => coverage.register_true[self.intsruction_pointer] += 1,
+                false => coverage.register_false[self.intsruction_pointer] += 1,
+            }
+        }
+
+        match self.program[self.intsruction_pointer] {
+            Instruction::Inc(x) => {
+                self.memory_pointer.inc(x);
+                self.runtime += x as i64;
+            }
+            Instruction::Cdec(x) => {
+                if self.register {
+                    self.memory_pointer.dec(x);
                 }
-                Instruction::Load => {
-                    self.register = current_memory;
-                    self.runtime += 1;
+                self.runtime += x as i64;
+            }
+            Instruction::Load => {
+                self.register = current_memory;
+                self.runtime += 1;
+                if let Some(touched) = self.touched.as_mut() {
+                    touched.insert(self.memory_pointer.ptr);
                 }
-                Instruction::Inv => {
-                    self.memory
-                        .set(self.memory_pointer.ptr as usize, !current_memory);
-                    self.runtime += 1;
+            }
+            Instruction::Inv => {
+                if let Some(touched) = self.touched.as_mut() {
+                    touched.insert(self.memory_pointer.ptr);
                 }
-                Instruction::Null => {
-                    unreachable!();
+                self.memory
+                    .set(cell_index(self.memory_pointer.ptr), !current_memory);
+                self.runtime += 1;
+            }
+            Instruction::Null => {
+                unreachable!();
+            }
+        }
+
+        self.intsruction_pointer += 1;
+        if self.intsruction_pointer == self.program.len() {
+            self.halted = true;
+        }
+    }
+
+    pub fn run(&mut self) -> RunResult {
+        while !self.halted {
+            self.step();
+        }
+
+        self.run_result()
+    }
+
+    /// Runs to completion like `run`, asking `keep_going` every `every` steps whether to carry
+    /// on; Returns None, leaving the VM part way through, once it says no
+    pub fn run_while(
+        &mut self,
+        every: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> Option<RunResult> {
+        let mut until_check = every;
+        while !self.halted {
+            self.step();
+            until_check -= 1;
+            if until_check == 0 {
+                if !keep_going() {
+                    return None;
                 }
+                until_check = every;
             }
+        }
+
+        Some(self.run_result())
+    }
 
-            self.intsruction_pointer += 1;
-            if self.intsruction_pointer == self.program.len() {
-                self.halted = true;
+    /// Runs to completion, extending a hash chain every time the runtime crosses a multiple of
+    /// `every` steps, and once more at halt. Each link covers the cells written since the one
+    /// before, so checkpoints cost as much as the writes between them rather than the span.
+    pub fn run_hashed(&mut self, every: i64) -> (RunResult, Vec<u64>) {
+        let mut hashes = vec![];
+        let mut chain = FNV_OFFSET;
+        let mut next_checkpoint = every;
+        let mut written: BTreeSet<VmUsize> = BTreeSet::new();
+
+        while !self.halted {
+            if self.program[self.intsruction_pointer] == Instruction::Inv {
+                written.insert(self.memory_pointer.ptr);
+            }
+            self.step();
+            if self.runtime >= next_checkpoint {
+                chain = self.hash_written_memory(chain, &written);
+                written.clear();
+                hashes.push(chain);
+                next_checkpoint = (self.runtime / every + 1) * every;
+            }
+        }
+        hashes.push(self.hash_written_memory(chain, &written));
+
+        (self.run_result(), hashes)
+    }
+
+    /// Extends `chain` with the runtime, pointer, register and the address and value of every
+    /// cell in `written`
+    pub fn hash_written_memory(&self, chain: u64, written: &BTreeSet<VmUsize>) -> u64 {
+        let mut hash = fnv1a(chain, &self.runtime.to_le_bytes());
+        hash = fnv1a(hash, &self.memory_pointer.ptr.to_le_bytes());
+        hash = fnv1a(hash, &[self.register as u8]);
+
+        let mut chunk: Vec<u8> = Vec::with_capacity(4096);
+        for &ptr in written {
+            chunk.extend_from_slice(&ptr.to_le_bytes());
+            chunk.push(self.memory[cell_index(ptr)] as u8);
+            if chunk.len() + 5 > chunk.capacity() {
+                hash = fnv1a(hash, &chunk);
+                chunk.clear();
             }
         }
 
-        return RunResult {
+        fnv1a(hash, &chunk)
+    }
+
+    /// Runtime and memory used so far, by the VM's memory metric
+    pub fn run_result(&self) -> RunResult {
+        RunResult {
             runtime: self.runtime,
-            memory: self.memory_pointer.span(),
-        };
+            memory: match self.touched.as_ref() {
+                Some(touched) => touched.len() as i64,
+                None => self.memory_pointer.span(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Instruction::{Inv, Load};
+
+    fn run_hashed(program: &[Instruction], every: i64) -> (Vec<bool>, i64, bool, Vec<u64>) {
+        let mut vm = Vm::new(Instructions::from(program.to_vec()));
+        let (result, hashes) = vm.run_hashed(every);
+        let cells = (0..4).map(|address| vm.memory[address]).collect();
+        (cells, result.runtime, vm.register, hashes)
+    }
+
+    #[test]
+    fn hash_chains_see_writes_undone_before_halting() {
+        // Cell 0 is set for one step, then cleared again
+        let flipped = run_hashed(&[Inv, Inv, Load, Load], 1);
+        let untouched = run_hashed(&[Load, Load, Inv, Inv], 1);
+        assert_eq!(flipped.0, untouched.0);
+        assert_eq!((flipped.1, flipped.2), (untouched.1, untouched.2));
+        assert_ne!(flipped.3[0], untouched.3[0]);
+        assert_ne!(flipped.3.last(), untouched.3.last());
+
+        // The same run always hashes the same
+        assert_eq!(flipped, run_hashed(&[Inv, Inv, Load, Load], 1));
     }
 }
@@ -0,0 +1,288 @@
+//! wasm-bindgen wrappers for running and grading scripts in the browser. Everything works on
+//! in-memory scripts; Nothing here touches the filesystem.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    error::WpkppError,
+    grader::{check_testcase, grade_script, load_testcase_seeded, LoadedTestcase, TESTCASES},
+    parse::{parse_script_bytes, Script, ScriptFormat},
+    report,
+    task::Task,
+    util::ResetableTimer,
+    vm::{Vm, MEMORY_BITS},
+};
+
+/// Rejects runs whose pointer moved over more cells than the 2^28 bits of wasm32 memory; Cells
+/// that far apart would share a bit here but not in a native build, so results could differ
+fn check_memory_span(span: i64) -> Result<(), WpkppError> {
+    match span > MEMORY_BITS as i64 {
+        true => Err(WpkppError::VmLimitExceeded(format!(
+            "The pointer moved over {} cells, more than the {} bits of memory in the browser",
+            span, MEMORY_BITS
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// A parsed script
+#[wasm_bindgen]
+pub struct Program {
+    script: Script,
+    parse_time: f64,
+}
+
+/// Result of `Program.run`; Counts are numbers rather than BigInts for ease of use from JS
+#[wasm_bindgen]
+pub struct RunOutput {
+    runtime: f64,
+    memory: f64,
+    output: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl RunOutput {
+    #[wasm_bindgen(getter)]
+    pub fn runtime(&self) -> f64 {
+        self.runtime
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn memory(&self) -> f64 {
+        self.memory
+    }
+
+    /// The first bytes of memory after the run, bits least significant first
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> Vec<u8> {
+        self.output.clone()
+    }
+}
+
+#[wasm_bindgen]
+impl Program {
+    /// Parses a script in `format` ("wpk", "wpkm" or "wpkb"); Pass text through a TextEncoder
+    pub fn parse(source: &[u8], format: &str) -> Result<Program, JsError> {
+        let format = ScriptFormat::from_name(format)
+            .ok_or_else(|| JsError::new(&format!("Unknown script format \"{}\"", format)))?;
+        let mut timer = ResetableTimer::new();
+        let script = parse_script_bytes(source, format)?;
+        Ok(Program {
+            script,
+            parse_time: timer.seconds_since(),
+        })
+    }
+
+    /// Number of instructions after parsing
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.script.instructions.len()
+    }
+
+    /// Runs with `input` at the start of memory, returning the first `output_len` bytes of memory
+    pub fn run(&self, input: &[u8], output_len: usize) -> Result<RunOutput, JsError> {
+        let mut vm = Vm::new(self.script.instructions.clone());
+        let memory = vm.memory.as_raw_mut_slice();
+        if input.len() > memory.len() || output_len > memory.len() {
+            return Err(JsError::new("Input or output is larger than memory"));
+        }
+        memory[..input.len()].copy_from_slice(input);
+        let run_stats = vm.run();
+        check_memory_span(vm.memory_pointer.span())?;
+
+        Ok(RunOutput {
+            runtime: run_stats.runtime as f64,
+            memory: run_stats.memory as f64,
+            output: vm.memory.as_raw_slice()[..output_len].to_vec(),
+        })
+    }
+
+    /// Grades against `task` (e.g. "2a") on the testcases generated from `seed`, or the default
+    /// seed if not given, returning the same JSON report as `wpkpp grade --json`
+    pub fn grade(&self, task: &str, seed: Option<String>) -> Result<String, JsError> {
+        let task = Task::from_str(task)?;
+        let seed = seed.unwrap_or_else(Task::seed);
+        let report = grade_script(task, &seed, &self.script, self.parse_time)?;
+        check_memory_span(report.memory)?;
+        Ok(report.into_json())
+    }
+}
+
+/// Outcome of one testcase in a `PlaygroundSession`
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct CaseResult {
+    result: report::CaseResult,
+}
+
+#[wasm_bindgen]
+impl CaseResult {
+    #[wasm_bindgen(getter, js_name = tcId)]
+    pub fn tc_id(&self) -> i8 {
+        self.result.tc_id
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn correct(&self) -> bool {
+        self.result.correct
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn runtime(&self) -> f64 {
+        self.result.runtime as f64
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn memory(&self) -> f64 {
+        self.result.memory as f64
+    }
+}
+
+/// Grades a script a slice at a time, so a page can redraw between calls to `step` instead of
+/// freezing for a whole grade.
+///
+/// Load a script, pick a task and optionally a seed in any order; Each one restarts grading. `step` then runs up
+/// to a given number of VM instructions, calling the progress callback with a `CaseResult` as
+/// each testcase finishes.
+#[wasm_bindgen]
+pub struct PlaygroundSession {
+    vm: Option<Vm>,
+    task: Option<Task>,
+    seed: String,
+    testcase: Option<LoadedTestcase>,
+    next_tc: i8,
+    results: Vec<report::CaseResult>,
+    on_progress: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen]
+impl PlaygroundSession {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> PlaygroundSession {
+        PlaygroundSession {
+            vm: None,
+            task: None,
+            seed: Task::seed(),
+            testcase: None,
+            next_tc: 0,
+            results: vec![],
+            on_progress: None,
+        }
+    }
+
+    /// Parses script text in `format` ("wpk" or "wpkm") and restarts grading
+    #[wasm_bindgen(js_name = loadScript)]
+    pub fn load_script(&mut self, source: &str, format: &str) -> Result<(), JsError> {
+        let program = Program::parse(source.as_bytes(), format)?;
+        self.vm = Some(Vm::new(program.script.instructions));
+        self.reset();
+        Ok(())
+    }
+
+    /// Picks the task to grade against (e.g. "2a") and restarts grading
+    #[wasm_bindgen(js_name = setTask)]
+    pub fn set_task(&mut self, task: &str) -> Result<(), JsError> {
+        self.task = Some(Task::from_str(task)?);
+        self.reset();
+        Ok(())
+    }
+
+    /// Picks the seed testcases are generated from, e.g. per user, and restarts grading
+    #[wasm_bindgen(js_name = setSeed)]
+    pub fn set_seed(&mut self, seed: &str) {
+        self.seed = seed.to_string();
+        self.reset();
+    }
+
+    /// Called with a `CaseResult` after each testcase; Pass undefined to stop
+    #[wasm_bindgen(js_name = onProgress)]
+    pub fn on_progress(&mut self, callback: Option<js_sys::Function>) {
+        self.on_progress = callback;
+    }
+
+    /// Forgets all results and starts again from the first testcase
+    pub fn reset(&mut self) {
+        self.testcase = None;
+        self.next_tc = 0;
+        self.results.clear();
+    }
+
+    /// Runs at most `max_steps` VM instructions, returning whether every testcase is done
+    pub fn step(&mut self, max_steps: f64) -> Result<bool, JsError> {
+        let (vm, task) = match (self.vm.as_mut(), self.task) {
+            (Some(vm), Some(task)) => (vm, task),
+            (None, _) => return Err(JsError::new("No script loaded")),
+            (_, None) => return Err(JsError::new("No task picked")),
+        };
+
+        let mut steps = 0.0;
+        while self.results.len() < TESTCASES {
+            let testcase = match self.testcase.take() {
+                Some(testcase) => testcase,
+                None => {
+                    let testcase = load_testcase_seeded(task, &self.seed, vm, self.next_tc)?;
+                    self.next_tc += 1;
+                    testcase
+                }
+            };
+            while !vm.halted && steps < max_steps {
+                vm.step();
+                steps += 1.0;
+            }
+            if !vm.halted {
+                self.testcase = Some(testcase);
+                break;
+            }
+
+            check_memory_span(vm.memory_pointer.span())?;
+            let result = check_testcase(vm, &testcase);
+            self.results.push(result);
+            if let Some(callback) = self.on_progress.as_ref() {
+                callback
+                    .call1(&JsValue::NULL, &CaseResult { result }.into())
+                    .map_err(|_| JsError::new("Progress callback threw"))?;
+            }
+        }
+        Ok(self.done())
+    }
+
+    /// Runs every remaining testcase
+    pub fn run(&mut self) -> Result<(), JsError> {
+        self.step(f64::INFINITY)?;
+        Ok(())
+    }
+
+    /// Whether every testcase has finished
+    #[wasm_bindgen(getter)]
+    pub fn done(&self) -> bool {
+        self.results.len() == TESTCASES
+    }
+
+    /// Result of the `index`th finished testcase
+    pub fn result(&self, index: usize) -> Option<CaseResult> {
+        self.results.get(index).map(|&result| CaseResult { result })
+    }
+
+    /// Finished testcases so far
+    #[wasm_bindgen(getter)]
+    pub fn finished(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Correct testcases so far
+    #[wasm_bindgen(getter)]
+    pub fn score(&self) -> usize {
+        self.results.iter().filter(|result| result.correct).count()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn total(&self) -> usize {
+        TESTCASES
+    }
+}
+
+impl Default for PlaygroundSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,195 @@
+//! `wpkpp when`: At which step, and by which instruction, an output bit was last flipped in one
+//! testcase, for tracking down where a wrong answer comes from.
+
+use crate::{
+    error::{Result, WpkppError},
+    grader::{load_testcase_seeded, LoadedTestcase},
+    parse::{format_location, parse_script_as, ScriptFormat},
+    task::Task,
+    vm::{cell_index, Instruction, Vm},
+};
+
+/// Most flips listed by step; Earlier ones are only counted
+pub const MAX_LISTED_FLIPS: usize = 10;
+
+pub struct WhenOptions {
+    /// Script format, overriding the script path's extension
+    pub format: Option<ScriptFormat>,
+    /// Task to run, instead of the one in the script's metadata
+    pub task: Option<Task>,
+    /// Testcase to run
+    pub tc_id: i8,
+    /// Seed to generate the testcase from, instead of WPKPP_SEED
+    pub seed: Option<String>,
+    /// Output bit to ask about, counted from the start of the output
+    pub bit: usize,
+}
+
+/// An INV of the cell being asked about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flip {
+    /// Index of the INV instruction
+    pub idx: usize,
+    /// Steps run once the INV was done, as counted by the grader
+    pub step: i64,
+    /// Value the cell was flipped to
+    pub value: bool,
+}
+
+/// Every write of one memory cell over a testcase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellHistory {
+    pub cell: usize,
+    pub initial: bool,
+    /// Every INV of the cell, in the order they ran
+    pub flips: Vec<Flip>,
+}
+
+impl CellHistory {
+    /// Runs a loaded testcase to completion, noting every INV of `cell`
+    pub fn run(vm: &mut Vm, cell: usize) -> Self {
+        let mut history = Self {
+            cell,
+            initial: vm.memory[cell],
+            flips: vec![],
+        };
+        while !vm.halted {
+            let idx = vm.intsruction_pointer;
+            let flipping =
+                vm.program[idx] == Instruction::Inv && cell_index(vm.memory_pointer.ptr) == cell;
+            vm.step();
+            if flipping {
+                history.flips.push(Flip {
+                    idx,
+                    step: vm.runtime,
+                    value: vm.memory[cell],
+                });
+            }
+        }
+        history
+    }
+
+    pub fn last(&self) -> Option<&Flip> {
+        self.flips.last()
+    }
+
+    pub fn value(&self) -> bool {
+        self.last().map_or(self.initial, |flip| flip.value)
+    }
+}
+
+/// Cell holding output bit `bit` of a loaded testcase
+fn output_cell(testcase: &LoadedTestcase, bit: usize) -> Result<usize> {
+    let (output_start, output_end) = testcase.regions();
+    match output_start.checked_add(bit) {
+        Some(cell) if cell < output_end => Ok(cell),
+        _ => Err(WpkppError::InvalidArgument(format!(
+            "Output bit {} is out of range; the output has {} bit(s)",
+            bit,
+            output_end - output_start
+        ))),
+    }
+}
+
+/// Prints when and by which instruction one output bit of a testcase was last flipped
+pub fn do_when(path: &str, options: &WhenOptions) -> Result<()> {
+    let script = parse_script_as(path, options.format, true)?;
+    let task = match options.task {
+        Some(task) => task,
+        None => match script.meta.as_ref().and_then(|meta| meta.task()) {
+            Some(task) => Task::from_str(task)?,
+            None => {
+                return Err(WpkppError::InvalidArgument(format!(
+                    "{} has no \"task\" metadata; try --task",
+                    path
+                )))
+            }
+        },
+    };
+    let seed = options.seed.clone().unwrap_or_else(Task::seed);
+    let mut vm = Vm::new(script.instructions);
+    let testcase = load_testcase_seeded(task, &seed, &mut vm, options.tc_id)?;
+    let cell = output_cell(&testcase, options.bit)?;
+    let expected = testcase.answer_bit(options.bit).unwrap();
+    let history = CellHistory::run(&mut vm, cell);
+
+    println!(
+        "Task {} testcase {}, output bit {} (cell {})",
+        task.name(),
+        options.tc_id,
+        options.bit,
+        cell
+    );
+    match history.last() {
+        Some(flip) => println!(
+            "Last flipped at step {} of {} by instruction {}, to {}",
+            flip.step,
+            vm.runtime,
+            format_location(&script.labels, flip.idx),
+            flip.value as u8
+        ),
+        None => println!(
+            "Never flipped; it stays at {} from the start",
+            history.initial as u8
+        ),
+    }
+    if history.flips.len() > 1 {
+        let listed = &history.flips[history.flips.len().saturating_sub(MAX_LISTED_FLIPS)..];
+        let steps: Vec<String> = listed.iter().map(|flip| flip.step.to_string()).collect();
+        println!(
+            "Flipped {} times, {}at steps {}",
+            history.flips.len(),
+            match listed.len() == history.flips.len() {
+                true => String::new(),
+                false => format!("the last {} ", listed.len()),
+            },
+            steps.join(", ")
+        );
+    }
+    println!(
+        "Ends as {}, expected {}: {}",
+        history.value() as u8,
+        expected as u8,
+        match history.value() == expected {
+            true => "Correct",
+            false => "Wrong",
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grader::load_testcase_memory, vm::Instruction::*};
+
+    #[test]
+    fn flips_are_listed_in_the_order_they_ran() {
+        // Cell 2 is flipped at steps 3, 5 and 11; Cell 0 once in between
+        let program = vec![Inc(2), Inv, Load, Inv, Cdec(2), Inv, Inc(2), Inv, Load];
+        let mut vm = Vm::new(program.into());
+        let history = CellHistory::run(&mut vm, 2);
+
+        assert!(!history.initial);
+        let flips: Vec<(usize, i64, bool)> = history
+            .flips
+            .iter()
+            .map(|flip| (flip.idx, flip.step, flip.value))
+            .collect();
+        assert_eq!(flips, [(1, 3, true), (3, 5, false), (7, 11, true)]);
+        assert_eq!(history.last().map(|flip| flip.idx), Some(7));
+        assert!(history.value());
+        assert!(vm.halted);
+    }
+
+    #[test]
+    fn output_bits_past_the_output_are_refused() {
+        let (input, answer) = Task::ZeroXor.load_tc_seeded("when", 0).unwrap();
+        let mut vm = Vm::new(vec![Inv].into());
+        let testcase = load_testcase_memory(&mut vm, 0, &input, answer.clone());
+
+        assert_eq!(output_cell(&testcase, 0).unwrap(), input.len());
+        assert!(output_cell(&testcase, answer.len()).is_err());
+        assert!(output_cell(&testcase, usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! `grade --contest`: An online round's window and tasks. Submissions outside the window are
+//! still graded, but their verdict becomes "LATE"; Tasks outside the round are refused.
+//!
+//! ```toml
+//! start = 2024-05-01T12:00:00Z
+//! end = 2024-05-01T15:00:00+01:00
+//! tasks = ["3", "4", "5"]
+//! ```
+//!
+//! Timestamps are TOML datetimes with an offset, or seconds since the Unix epoch. Every key is
+//! optional.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    error::{Result, WpkppError},
+    task::Task,
+};
+
+/// When a round accepts submissions and for which tasks, in seconds since the Unix epoch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContestWindow {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    /// Tasks of the round; None allows every task
+    pub tasks: Option<Vec<Task>>,
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Days in a month of the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 timestamp such as "2024-05-01T12:00:00Z";
+/// Fractional seconds are dropped
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = text.get(range)?;
+        match digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    };
+    let separators = text.as_bytes();
+    if separators.len() < 20
+        || separators[4] != b'-'
+        || separators[7] != b'-'
+        || !matches!(separators[10], b'T' | b't' | b' ')
+        || separators[13] != b':'
+        || separators[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+    {
+        return None;
+    }
+    // Leap seconds are allowed by RFC 3339
+    if second > 60 {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let at = text.len() - rest.len();
+            let (hours, minutes) = (number(at + 1..at + 3)?, number(at + 4..at + 6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+        _ => return None,
+    };
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+impl ContestWindow {
+    /// Whether a submission at `time` is outside the window
+    pub fn is_late(&self, time: i64) -> bool {
+        self.start.is_some_and(|start| time < start) || self.end.is_some_and(|end| time > end)
+    }
+
+    /// Fails for tasks which are not part of the round
+    pub fn check_task(&self, task: Task) -> Result<()> {
+        match self.tasks.as_ref() {
+            Some(tasks) if !tasks.contains(&task) => Err(WpkppError::InvalidArgument(format!(
+                "Task {} is not part of this contest",
+                task.name()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl ContestWindow {
+    pub fn parse(text: &str, path: &str) -> Result<Self> {
+        let invalid = |message: String| WpkppError::InvalidConfig(format!("{}: {}", path, message));
+        let table = match text.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(invalid("expected a table of contest settings".to_string())),
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+        let timestamp = |key: &str| -> Result<Option<i64>> {
+            let time = match table.get(key) {
+                None => return Ok(None),
+                Some(toml::Value::Integer(seconds)) => Some(*seconds),
+                Some(toml::Value::Datetime(datetime)) => parse_timestamp(&datetime.to_string()),
+                Some(toml::Value::String(text)) => parse_timestamp(text),
+                Some(_) => None,
+            };
+            match time {
+                Some(time) => Ok(Some(time)),
+                None => Err(invalid(format!(
+                    "\"{}\" should be a datetime with an offset, e.g. 2024-05-01T12:00:00Z",
+                    key
+                ))),
+            }
+        };
+
+        let mut window = Self {
+            start: timestamp("start")?,
+            end: timestamp("end")?,
+            tasks: None,
+        };
+        if let Some(value) = table.get("tasks") {
+            let names = value
+                .as_array()
+                .ok_or_else(|| invalid("\"tasks\" should be a list of task numbers".to_string()))?;
+            let mut tasks = vec![];
+            for name in names {
+                let name = name.as_str().ok_or_else(|| {
+                    invalid("\"tasks\" should be a list of task numbers".to_string())
+                })?;
+                tasks.push(Task::from_str(name).map_err(|e| invalid(e.to_string()))?);
+            }
+            window.tasks = Some(tasks);
+        }
+        for key in table.keys() {
+            if !matches!(key.as_str(), "start" | "end" | "tasks") {
+                return Err(invalid(format!("unknown key \"{}\"", key)));
+            }
+        }
+        if let (Some(start), Some(end)) = (window.start, window.end) {
+            if start > end {
+                return Err(invalid("\"start\" is after \"end\"".to_string()));
+            }
+        }
+        Ok(window)
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_count_from_the_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+        assert_eq!(
+            days_from_civil(2024, 3, 1) - days_from_civil(2024, 2, 28),
+            2
+        );
+        assert_eq!(
+            days_from_civil(2023, 3, 1) - days_from_civil(2023, 2, 28),
+            1
+        );
+    }
+
+    #[test]
+    fn timestamps_with_offsets() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), Some(-1));
+        for text in [
+            "2024-05-01T12:00:00Z",
+            "2024-05-01t12:00:00z",
+            "2024-05-01 12:00:00Z",
+            "2024-05-01T13:00:00+01:00",
+            "2024-05-01T07:30:00-04:30",
+            "2024-05-01T12:00:00+00:00",
+            "2024-05-01T12:00:00.999Z",
+            "2024-05-01T13:00:00.5+01:00",
+        ] {
+            assert_eq!(parse_timestamp(text), Some(1714564800), "{}", text);
+        }
+        // A leap second runs into the next minute
+        assert_eq!(
+            parse_timestamp("2016-12-31T23:59:60Z"),
+            parse_timestamp("2017-01-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn leap_days_follow_the_gregorian_rules() {
+        assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(19782 * 86400));
+        assert!(parse_timestamp("2000-02-29T00:00:00Z").is_some());
+        assert_eq!(parse_timestamp("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("1900-02-29T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn invalid_timestamps_are_rejected() {
+        for text in [
+            "",
+            "2024-02-30T00:00:00Z",
+            "2024-02-31T00:00:00Z",
+            "2024-04-31T00:00:00Z",
+            "2024-00-10T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-05-00T00:00:00Z",
+            "2024-05-01T24:00:00Z",
+            "2024-05-01T12:60:00Z",
+            "2024-05-01T12:00:61Z",
+            "2024-05-01T12:00:00",
+            "2024-05-01T12:00:00.Z",
+            "2024-05-01T12:00:00+0100",
+            "2024-05-01T12:00:00+24:00",
+            "2024-05-01T12:00:00++1:00",
+            "2024-05-01X12:00:00Z",
+            "2024/05/01T12:00:00Z",
+            "+024-05-01T12:00:00Z",
+        ] {
+            assert_eq!(parse_timestamp(text), None, "{}", text);
+        }
+    }
+}